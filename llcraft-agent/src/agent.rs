@@ -1,27 +1,149 @@
 //! Agent implementation - orchestrates LLM <-> VM loop
 
 use llcraft_vm::{
-    BridgeProvider, ChatMessage, CompletionRequest, DefaultSyscallHandler, ExecutionResult,
-    Interpreter, LlmProvider, LlmRequest, LlmRequestType, MemoryPage, Opcode, PageIndex,
-    Program, Session, SessionManager, VmSchema,
+    Approval, ApprovalHook, BridgeProvider, ChatMessage, CompletionRequest, CompletionResponse, DefaultSyscallHandler,
+    ExecutionResult,
+    Interpreter, LlmProvider, ProviderError, LlmRequest, LlmRequestType, MemoryPage, Opcode, PageIndex,
+    Program, RunMetrics, Session, SessionManager, TokenCounter, Usage, UsageTracker, VmSchema,
 };
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// Error returned by an LLM call aborted by the run's cancellation token
+const CANCELLED: &str = "Run cancelled";
 
 /// Configuration for the agent
 #[derive(Debug, Clone)]
 pub struct AgentConfig {
-    /// Enable verbose logging
+    /// Print agent events as they happen (see `print_event`)
     pub verbose: bool,
     /// Session directory for persistence
     pub session_dir: String,
+    /// How many times to re-prompt the LLM when its program fails to parse or validate
+    pub max_parse_retries: usize,
+    /// Maximum LLM round-trips while running a program (not VM steps)
+    pub max_iterations: usize,
+    /// Token budget for the whole run (prompt + completion), if any
+    pub max_total_tokens: Option<usize>,
+    /// Extra guidance appended after the VM spec in the system prompt
+    pub system_prompt_suffix: Option<String>,
+    /// Replace the system prompt entirely (the VM spec is NOT included)
+    pub system_prompt_override: Option<String>,
+    /// Reproducible runs: temperature 0 and a fixed seed on every LLM call,
+    /// session pages listed in a stable order, and UUIDs derived from the
+    /// program instead of randomness
+    pub deterministic: bool,
+    /// Reuse the generated program when the same task is run again against
+    /// the same set of session pages (off by default, since a repeated task
+    /// may expect a fresh look at files that changed). The execution trace
+    /// from earlier runs is not part of the key: every run extends it, so
+    /// including it would mean a repeated task never hits the cache.
+    pub cache_programs: bool,
+    /// Hosts HTTP_REQUEST may contact (none by default)
+    pub allowed_hosts: Vec<String>,
+    /// Environment variables GET_ENV may read (none by default)
+    pub allowed_env: Vec<String>,
+    /// Once the accumulated trace grows past this many steps, the oldest
+    /// ones are folded into a single `COMPACTED` step (`None` keeps everything)
+    pub max_trace_steps: Option<usize>,
+    /// Most INFER_BATCH prompts sent to the LLM at once
+    pub max_batch_concurrency: usize,
+    /// Record which pages each step created, modified or freed in the trace
+    pub trace_memory: bool,
 }
 
+/// Seed sent with every request when `AgentConfig::deterministic` is set
+pub const DETERMINISTIC_SEED: u64 = 42;
+
+/// Default for `AgentConfig::max_trace_steps`
+pub const DEFAULT_MAX_TRACE_STEPS: usize = 100;
+
+/// Opcode name of the synthetic step that stands in for compacted steps
+pub const COMPACTED_OPCODE: &str = "COMPACTED";
+
 impl Default for AgentConfig {
     fn default() -> Self {
         Self {
             verbose: true,
             session_dir: ".llcraft_sessions".to_string(),
+            max_parse_retries: 2,
+            max_iterations: 50,
+            max_total_tokens: None,
+            system_prompt_suffix: None,
+            system_prompt_override: None,
+            deterministic: false,
+            cache_programs: false,
+            allowed_hosts: Vec::new(),
+            allowed_env: Vec::new(),
+            max_trace_steps: Some(DEFAULT_MAX_TRACE_STEPS),
+            max_batch_concurrency: 4,
+            trace_memory: false,
+        }
+    }
+}
+
+/// Shape the caller wants the final answer in
+#[derive(Debug, Clone, Default)]
+pub struct OutputFormat {
+    /// JSON schema the COMPLETE result must match
+    pub schema: Option<serde_json::Value>,
+}
+
+impl OutputFormat {
+    /// Require a final answer matching `schema`
+    pub fn from_schema(schema: serde_json::Value) -> Self {
+        Self { schema: Some(schema) }
+    }
+
+    /// The task, with instructions for producing the answer in this format
+    fn instruct(&self, task: &str) -> String {
+        match &self.schema {
+            Some(schema) => format!(
+                "{}\n\n## Output Format\nFinish with COMPLETE whose `result` is the answer itself \
+                 (or {{\"page\": \"<id>\"}} naming a page that holds it), as JSON matching this schema:\n{}",
+                task,
+                serde_json::to_string_pretty(schema).unwrap_or_default()
+            ),
+            None => task.to_string(),
+        }
+    }
+
+    /// Pull the answer out of a finished run and check it against the schema
+    fn extract(&self, result: &AgentResult) -> Result<Option<serde_json::Value>, String> {
+        let Some(schema) = &self.schema else {
+            return Ok(None);
+        };
+
+        let mut candidates = vec![result.result.clone()];
+        if let Some(page) = result.result.get("page").and_then(|p| p.as_str()).and_then(|id| result.pages.get(id)) {
+            candidates.push(page.clone());
+            // INFER pages wrap the reply as {"response": ...}
+            if let Some(response) = page.get("response") {
+                candidates.push(response.clone());
+            }
+        }
+        // Models often store JSON as a string; accept it parsed too
+        let parsed: Vec<serde_json::Value> = candidates
+            .iter()
+            .filter_map(|c| c.as_str().and_then(|s| serde_json::from_str(s.trim()).ok()))
+            .collect();
+        candidates.extend(parsed);
+
+        let mut first_error = None;
+        for candidate in candidates {
+            match llcraft_vm::json_schema::validate(&candidate, schema) {
+                Ok(()) => return Ok(Some(candidate)),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
         }
+        Err(format!(
+            "Final answer does not match the output schema: {}",
+            first_error.unwrap_or_default()
+        ))
     }
 }
 
@@ -31,11 +153,93 @@ pub struct AgentResult {
     pub result: serde_json::Value,
     /// All pages from the final interpreter state
     pub pages: HashMap<String, serde_json::Value>,
+    /// Tokens spent across every LLM call in the run
+    pub usage: Usage,
+    /// Number of LLM calls made (program generation included)
+    pub llm_calls: usize,
+    /// Number of VM steps executed
+    pub steps: usize,
+    /// Wall-clock time for the run
+    pub duration: Duration,
+    /// Opcode, tool and memory counts from the interpreter
+    pub metrics: RunMetrics,
+    /// The final answer, validated against `OutputFormat::schema` (only set
+    /// for runs given a schema)
+    pub output: Option<serde_json::Value>,
+    /// The run was stopped by its cancellation token; `pages` holds what it
+    /// had produced so far
+    pub cancelled: bool,
+}
+
+/// Events emitted while the agent runs
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    /// The LLM produced a program for the task
+    ProgramGenerated(Program),
+    /// The interpreter executed one opcode
+    StepExecuted(llcraft_vm::ExecutionStep),
+    /// A request is about to be sent to the LLM
+    LlmRequest {
+        /// The last message in the request (usually the prompt)
+        prompt: String,
+    },
+    /// The LLM answered a request
+    LlmResponse {
+        /// Response content (empty if the model returned none)
+        content: String,
+    },
+    /// A page was persisted to the session
+    PageSaved {
+        page_id: String,
+    },
+    /// The task completed with a result
+    Completed(serde_json::Value),
+    /// The task failed
+    Failed(String),
+    /// The run was cancelled before it finished
+    Cancelled,
+    /// A progress note for people watching the run, e.g. the session being
+    /// resumed or a LOG line from the program
+    Progress(String),
+}
+
+/// Callback invoked for every [`AgentEvent`]
+pub type EventHandler = Box<dyn FnMut(AgentEvent) + Send>;
+
+/// The default event handler, used when `AgentConfig::verbose` is set:
+/// prints progress notes, generated programs and LLM response sizes
+pub fn print_event(event: &AgentEvent) {
+    match event {
+        AgentEvent::Progress(text) => println!("{}", text),
+        AgentEvent::ProgramGenerated(program) => {
+            println!("Generated Program:");
+            program.pretty_print();
+        }
+        AgentEvent::LlmResponse { content } => println!("   Response: {} chars", content.len()),
+        AgentEvent::Completed(_) => println!("\nTask completed!"),
+        AgentEvent::Cancelled => println!("\nRun cancelled"),
+        AgentEvent::StepExecuted(_)
+        | AgentEvent::LlmRequest { .. }
+        | AgentEvent::PageSaved { .. }
+        | AgentEvent::Failed(_) => {}
+    }
+}
+
+/// Pass `event` to the default handler (if verbose) and then to `handler`
+fn dispatch(handler: &mut Option<EventHandler>, verbose: bool, event: AgentEvent) {
+    if verbose {
+        print_event(&event);
+    }
+    if let Some(handler) = handler {
+        handler(event);
+    }
 }
 
 /// The agent orchestrator - manages the LLM <-> VM loop
-pub struct Agent {
-    provider: BridgeProvider,
+///
+/// Generic over the LLM backend; defaults to the local Copilot bridge.
+pub struct Agent<P: LlmProvider = BridgeProvider> {
+    provider: P,
     schema: VmSchema,
     /// Accumulated trace across all programs
     full_trace: Vec<llcraft_vm::ExecutionStep>,
@@ -47,6 +251,24 @@ pub struct Agent {
     session_id: Option<String>,
     /// Page index from session (rich metadata - NOT content)
     page_index: HashMap<String, PageIndex>,
+    /// Pages produced by earlier runs of this agent, preloaded into the next run
+    pages: HashMap<String, serde_json::Value>,
+    /// Observer for agent events
+    event_handler: Option<EventHandler>,
+    /// Token usage for the current run
+    usage: UsageTracker,
+    /// Approval hook passed to every interpreter the agent runs
+    approval_hook: Option<ApprovalHook>,
+    /// Token counter for budgets and page sizes
+    token_counter: Arc<dyn TokenCounter>,
+    /// When the current run started
+    started_at: Instant,
+    /// Programs generated for earlier tasks, by task text
+    program_cache: HashMap<String, Program>,
+    /// Page signature the cached programs were generated against
+    program_cache_pages: String,
+    /// Token that stops the current run (see `run_with_cancel`)
+    cancel: CancellationToken,
 }
 
 impl Agent {
@@ -57,22 +279,93 @@ impl Agent {
 
     /// Create a new agent with custom configuration
     pub fn with_config(config: AgentConfig) -> Self {
+        Agent::with_provider_and_config(BridgeProvider::local(), config)
+    }
+}
+
+impl<P: LlmProvider> Agent<P> {
+    /// Create a new agent backed by the given provider
+    pub fn with_provider(provider: P) -> Self {
+        Self::with_provider_and_config(provider, AgentConfig::default())
+    }
+
+    /// Create a new agent backed by the given provider with custom configuration
+    pub fn with_provider_and_config(provider: P, config: AgentConfig) -> Self {
+        let token_counter = llcraft_vm::counter_for_model(provider.default_model());
         Self {
-            provider: BridgeProvider::local(),
+            provider,
             schema: VmSchema::new(),
             full_trace: Vec::new(),
             config,
             session_manager: None,
             session_id: None,
             page_index: HashMap::new(),
+            pages: HashMap::new(),
+            event_handler: None,
+            usage: UsageTracker::new(),
+            approval_hook: None,
+            token_counter,
+            started_at: Instant::now(),
+            program_cache: HashMap::new(),
+            program_cache_pages: String::new(),
+            cancel: CancellationToken::new(),
         }
     }
 
+    /// Count tokens with a specific tokenizer instead of the per-model default
+    pub fn with_token_counter(mut self, counter: impl TokenCounter + 'static) -> Self {
+        self.token_counter = Arc::new(counter);
+        self
+    }
+
+    /// Ask for approval before EXEC / WRITE_FILE run
+    pub fn with_approval_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Opcode) -> Approval + Send + Sync + 'static,
+    {
+        self.approval_hook = Some(std::sync::Arc::new(hook));
+        self
+    }
+
+    /// Stop every run once `token` is cancelled (see `run_with_cancel`)
+    pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Observe the agent as it runs. With `verbose` set, events are printed
+    /// by `print_event` before reaching the handler
+    pub fn on_event<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(AgentEvent) + Send + 'static,
+    {
+        self.event_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Get the underlying provider
+    pub fn provider(&self) -> &P {
+        &self.provider
+    }
+
     /// Get the execution trace
     pub fn trace(&self) -> &[llcraft_vm::ExecutionStep] {
         &self.full_trace
     }
 
+    /// Pages accumulated across runs of this agent
+    pub fn pages(&self) -> &HashMap<String, serde_json::Value> {
+        &self.pages
+    }
+
+    /// Forget accumulated pages and trace (session storage is untouched)
+    pub fn clear(&mut self) {
+        self.full_trace.clear();
+        self.pages.clear();
+        self.page_index.clear();
+        self.program_cache.clear();
+    }
+
     /// Enable session persistence
     pub fn with_session(mut self, session_id: Option<&str>) -> Result<Self, String> {
         let manager =
@@ -83,38 +376,29 @@ impl Agent {
                 let session = manager.load_session(id).map_err(|e| e.to_string())?;
                 let mut page_index = HashMap::new();
 
-                if self.config.verbose {
-                    println!("Resuming session: {}", id);
-                    println!("   Previous task: {}", session.metadata.task);
-                    println!("   Available pages (use LOAD_PAGE to fetch content):");
-                }
+                self.progress(format!("Resuming session: {}", id));
+                self.progress(format!("   Previous task: {}", session.metadata.task));
+                self.progress("   Available pages (use LOAD_PAGE to fetch content):");
 
                 let total_tokens: usize =
                     session.page_index.values().map(|idx| idx.tokens).sum();
 
                 for (page_id, idx) in &session.page_index {
-                    if self.config.verbose {
-                        println!(
-                            "     - {} (~{} tokens): {}",
-                            page_id, idx.tokens, idx.summary
-                        );
-                    }
+                    self.progress(format!("     - {} (~{} tokens): {}", page_id, idx.tokens, idx.summary));
                     page_index.insert(page_id.clone(), idx.clone());
                 }
 
-                if self.config.verbose && !page_index.is_empty() {
-                    println!(
+                if !page_index.is_empty() {
+                    self.progress(format!(
                         "   {} pages indexed (~{} total tokens, content NOT loaded)\n",
                         page_index.len(),
                         total_tokens
-                    );
+                    ));
                 }
 
                 (id.to_string(), page_index)
             } else {
-                if self.config.verbose {
-                    println!("Creating new session: {}", id);
-                }
+                self.progress(format!("Creating new session: {}", id));
                 let session = Session::new(id, "agent session");
                 manager.save_session(&session).map_err(|e| e.to_string())?;
                 (id.to_string(), HashMap::new())
@@ -123,9 +407,7 @@ impl Agent {
             let session = manager
                 .create_session("agent session")
                 .map_err(|e| e.to_string())?;
-            if self.config.verbose {
-                println!("Created new session: {}", session.metadata.id);
-            }
+            self.progress(format!("Created new session: {}", session.metadata.id));
             (session.metadata.id.clone(), HashMap::new())
         };
 
@@ -138,71 +420,291 @@ impl Agent {
 
     /// Run a task to completion
     pub async fn run(&mut self, task: &str) -> Result<AgentResult, String> {
-        if self.config.verbose {
-            println!("Task: {}\n", task);
-
-            if !self.page_index.is_empty() {
-                let total_tokens: usize = self.page_index.values().map(|idx| idx.tokens).sum();
-                println!(
-                    "Available pages in session (~{} tokens total, use LOAD_PAGE to fetch):",
-                    total_tokens
-                );
-                for (page_id, idx) in &self.page_index {
-                    println!("   - {} (~{} tokens): {}", page_id, idx.tokens, idx.summary);
-                }
-                println!();
+        self.run_with_format(task, &OutputFormat::default()).await
+    }
+
+    /// Run a task that stops early once `token` is cancelled. The token is
+    /// checked between LLM round-trips and aborts an LLM call in flight; a
+    /// cancelled run returns `Ok` with `AgentResult::cancelled` set and its
+    /// pages saved to the session like a finished one
+    pub async fn run_with_cancel(&mut self, task: &str, token: CancellationToken) -> Result<AgentResult, String> {
+        let previous = std::mem::replace(&mut self.cancel, token);
+        let result = self.run_with_format(task, &OutputFormat::default()).await;
+        self.cancel = previous;
+        result
+    }
+
+    /// Run a task whose final answer must take the given shape. With a
+    /// schema, the answer is validated and returned in `AgentResult::output`
+    pub async fn run_with_format(&mut self, task: &str, format: &OutputFormat) -> Result<AgentResult, String> {
+        let result = self.run_task(&format.instruct(task)).await.and_then(|mut result| {
+            if !result.cancelled {
+                result.output = format.extract(&result)?;
             }
+            Ok(result)
+        });
+        match &result {
+            Ok(r) if r.cancelled => self.emit(AgentEvent::Cancelled),
+            Ok(r) => self.emit(AgentEvent::Completed(r.result.clone())),
+            Err(e) => self.emit(AgentEvent::Failed(e.clone())),
         }
+        result
+    }
 
-        let program = self.generate_program(task).await?;
-
-        if self.config.verbose {
-            println!("Generated Program:");
-            program.pretty_print();
+    async fn run_task(&mut self, task: &str) -> Result<AgentResult, String> {
+        self.usage = UsageTracker::new();
+        self.started_at = Instant::now();
+
+        self.progress(format!("Task: {}\n", task));
+        if !self.page_index.is_empty() {
+            let total_tokens: usize = self.page_index.values().map(|idx| idx.tokens).sum();
+            let mut lines = vec![format!(
+                "Available pages in session (~{} tokens total, use LOAD_PAGE to fetch):",
+                total_tokens
+            )];
+            for (page_id, idx) in &self.page_index {
+                lines.push(format!("   - {} (~{} tokens): {}", page_id, idx.tokens, idx.summary));
+            }
+            lines.push(String::new());
+            self.progress(lines.join("\n"));
         }
 
+        let program = match self.generate_program(task).await {
+            Err(_) if self.cancel.is_cancelled() => return Ok(self.cancelled_result(HashMap::new(), RunMetrics::default())),
+            other => other?,
+        };
+        self.emit(AgentEvent::ProgramGenerated(program.clone()));
+
         self.run_program(program).await
     }
 
-    /// Generate a program from the LLM based on the task
+    /// Result of a run stopped by its cancellation token
+    fn cancelled_result(&self, pages: HashMap<String, serde_json::Value>, metrics: RunMetrics) -> AgentResult {
+        AgentResult {
+            result: serde_json::json!({"status": "cancelled"}),
+            pages,
+            usage: self.run_usage(),
+            llm_calls: self.usage.total_calls,
+            steps: metrics.total_steps,
+            duration: self.started_at.elapsed(),
+            metrics,
+            output: None,
+            cancelled: true,
+        }
+    }
+
+    /// Generate a program for the task without executing it
+    ///
+    /// Useful for inspecting (or hand-editing) what the model would do
+    /// before any opcode touches the filesystem.
+    pub async fn plan(&mut self, task: &str) -> Result<Program, String> {
+        self.usage = UsageTracker::new();
+
+        let program = self.generate_program(task).await?;
+        self.emit(AgentEvent::ProgramGenerated(program.clone()));
+
+        Ok(program)
+    }
+
+    /// Generate a program from the LLM based on the task, or reuse the one
+    /// generated for the same task if the page set hasn't changed since
     async fn generate_program(&mut self, task: &str) -> Result<Program, String> {
-        let system = self.schema.system_prompt().to_string();
-        let user = self.schema.user_prompt(task, self.page_index.iter(), &self.full_trace);
-
-        if self.config.verbose {
-            println!("Asking LLM to generate program...");
-            if !self.full_trace.is_empty() {
-                println!(
-                    "   (with {} previous execution steps as context)",
-                    self.full_trace.len()
-                );
+        if self.config.cache_programs {
+            let signature = self.page_signature();
+            if signature != self.program_cache_pages {
+                self.program_cache.clear();
+                self.program_cache_pages = signature;
             }
-            if !self.page_index.is_empty() {
-                println!(
-                    "   (with {} page summaries from session)",
-                    self.page_index.len()
-                );
+            if let Some(program) = self.program_cache.get(task).cloned() {
+                self.progress("Reusing cached program for this task");
+                return Ok(program);
             }
         }
 
-        let completion_request = CompletionRequest::new(vec![
-            ChatMessage::system(&system),
-            ChatMessage::user(&user),
-        ]);
+        let system = self.system_prompt();
+        let mut pages: Vec<_> = self.page_index.iter().collect();
+        if self.config.deterministic {
+            pages.sort_by(|a, b| a.0.cmp(b.0));
+        }
+        let user = self.schema.user_prompt(task, pages.into_iter(), &self.full_trace);
 
-        let response = self
-            .provider
-            .complete(completion_request)
-            .await
-            .map_err(|e| format!("LLM error: {:?}", e))?;
+        self.progress("Asking LLM to generate program...");
+        if !self.full_trace.is_empty() {
+            self.progress(format!("   (with {} previous execution steps as context)", self.full_trace.len()));
+        }
+        if !self.page_index.is_empty() {
+            self.progress(format!("   (with {} page summaries from session)", self.page_index.len()));
+        }
 
-        let content = response.content.ok_or("Empty LLM response")?;
+        let mut messages = vec![ChatMessage::system(&system), ChatMessage::user(&user)];
+        let mut attempt = 0;
+
+        loop {
+            let completion_request = CompletionRequest::new(messages.clone());
+
+            let response = self.complete(completion_request).await?;
+
+            let content = response.content.ok_or("Empty LLM response")?;
 
-        if self.config.verbose {
-            println!("   Response: {} chars", content.len());
+            let parsed = self.parse_program(&content).and_then(|program| {
+                program.validate().map(|_| program).map_err(|errors| {
+                    let lines: Vec<String> = errors.iter().map(|e| format!("  - {}", e)).collect();
+                    format!("Program failed validation:\n{}", lines.join("\n"))
+                })
+            });
+
+            match parsed {
+                Ok(program) => {
+                    if self.config.cache_programs {
+                        self.program_cache.insert(task.to_string(), program.clone());
+                    }
+                    return Ok(program);
+                }
+                Err(e) if attempt < self.config.max_parse_retries => {
+                    attempt += 1;
+                    self.progress(format!(
+                        "   Program was rejected, re-prompting ({}/{})",
+                        attempt, self.config.max_parse_retries
+                    ));
+                    messages.push(ChatMessage::assistant(content));
+                    messages.push(ChatMessage::user(format!(
+                        "Your program was rejected: {}\n\n\
+                         Return ONLY a valid JSON program object, with no extra text.",
+                        e
+                    )));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Stable fingerprint of the page index, used to invalidate cached programs
+    fn page_signature(&self) -> String {
+        let mut ids: Vec<String> = self
+            .page_index
+            .iter()
+            .map(|(id, idx)| format!("{}:{}", id, idx.tokens))
+            .collect();
+        ids.sort();
+        ids.join(",")
+    }
+
+    /// Build the system prompt: VM spec (or override) plus any configured suffix
+    fn system_prompt(&self) -> String {
+        let mut system = match &self.config.system_prompt_override {
+            Some(prompt) => prompt.clone(),
+            None => self.schema.system_prompt().to_string(),
+        };
+        if let Some(suffix) = &self.config.system_prompt_suffix {
+            system.push_str("\n\n");
+            system.push_str(suffix);
+        }
+        system
+    }
+
+    /// Send a request to the provider, emitting request/response events
+    async fn complete(&mut self, request: CompletionRequest) -> Result<CompletionResponse, String> {
+        let request = self.prepare_request(request)?;
+
+        let cancel = self.cancel.clone();
+        let response = tokio::select! {
+            response = self.provider.complete(request) => response,
+            _ = cancel.cancelled() => return Err(CANCELLED.to_string()),
+        };
+        Self::record_response(&mut self.usage, &mut self.event_handler, self.config.verbose, response)
+    }
+
+    /// Apply the deterministic settings and the token budget to a request
+    /// about to be sent, emitting its request event
+    fn prepare_request(&mut self, request: CompletionRequest) -> Result<CompletionRequest, String> {
+        let request = if self.config.deterministic {
+            request.with_temperature(0.0).with_seed(DETERMINISTIC_SEED)
+        } else {
+            request
+        };
+        let prompt = request
+            .messages
+            .last()
+            .and_then(|m| m.content.clone())
+            .unwrap_or_default();
+        self.check_token_budget(&request)?;
+        self.emit(AgentEvent::LlmRequest { prompt });
+        Ok(request)
+    }
+
+    /// Track a provider reply's usage and emit its response event. Takes the
+    /// fields it needs so it can run while the provider is still borrowed
+    fn record_response(
+        usage: &mut UsageTracker,
+        event_handler: &mut Option<EventHandler>,
+        verbose: bool,
+        response: Result<CompletionResponse, ProviderError>,
+    ) -> Result<CompletionResponse, String> {
+        let response = response.map_err(|e| format!("LLM error: {:?}", e))?;
+        usage.track(&response.model, &response.usage);
+
+        let content = response.content.clone().unwrap_or_default();
+        dispatch(event_handler, verbose, AgentEvent::LlmResponse { content });
+        response.reject_filtered().map_err(|e| format!("LLM error: {}", e))
+    }
+
+    /// Refuse to send a request whose prompt would push the run over budget
+    fn check_token_budget(&self, request: &CompletionRequest) -> Result<(), String> {
+        let Some(budget) = self.config.max_total_tokens else {
+            return Ok(());
+        };
+
+        let used = self.usage.total_tokens();
+        let estimated: usize = request
+            .messages
+            .iter()
+            .filter_map(|m| m.content.as_ref())
+            .map(|c| self.token_counter.count(c))
+            .sum();
+
+        if used + estimated > budget {
+            return Err(format!(
+                "Token budget exceeded: {} tokens used, next request needs ~{}, budget is {}",
+                used, estimated, budget
+            ));
         }
+        Ok(())
+    }
 
-        self.parse_program(&content)
+    /// Total usage for the current run
+    fn run_usage(&self) -> Usage {
+        Usage {
+            prompt_tokens: self.usage.total_prompt_tokens,
+            completion_tokens: self.usage.total_completion_tokens,
+            total_tokens: self.usage.total_tokens(),
+            latency_ms: (self.usage.latency.calls > 0).then_some(self.usage.latency.total_ms),
+        }
+    }
+
+    fn emit(&mut self, event: AgentEvent) {
+        dispatch(&mut self.event_handler, self.config.verbose, event);
+    }
+
+    fn progress(&mut self, text: impl Into<String>) {
+        self.emit(AgentEvent::Progress(text.into()));
+    }
+
+    /// Add a finished program's steps to the accumulated trace, compacting
+    /// it if it has grown past `max_trace_steps`
+    fn extend_trace(&mut self, steps: &[llcraft_vm::ExecutionStep]) {
+        self.full_trace.extend(steps.iter().cloned());
+        if let Some(max) = self.config.max_trace_steps {
+            compact_trace(&mut self.full_trace, max);
+        }
+    }
+
+    /// Emit a StepExecuted event for every trace step after `from`
+    fn emit_steps(&mut self, interp: &Interpreter<DefaultSyscallHandler>, from: &mut usize) {
+        let steps = interp.trace();
+        for step in &steps[(*from).min(steps.len())..] {
+            self.emit(AgentEvent::StepExecuted(step.clone()));
+        }
+        *from = steps.len();
     }
 
     /// Parse a program from LLM output (handles markdown fences)
@@ -230,7 +732,13 @@ impl Agent {
 
     /// Run a program, handling any LLM requests along the way
     async fn run_program(&mut self, program: Program) -> Result<AgentResult, String> {
-        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        let handler = DefaultSyscallHandler::default()
+            .with_allowed_hosts(self.config.allowed_hosts.clone())
+            .with_allowed_env(self.config.allowed_env.clone());
+        let mut interp = Interpreter::new(program, handler)
+            .with_token_counter(self.token_counter.clone())
+            .with_deterministic(self.config.deterministic)
+            .with_memory_trace(self.config.trace_memory);
 
         if let (Some(_), Some(ref session_id)) = (&self.session_manager, &self.session_id) {
             let interp_manager =
@@ -240,104 +748,181 @@ impl Agent {
                 .resume_session(session_id)
                 .map_err(|e| e.to_string())?;
 
-            if self.config.verbose {
-                println!(
-                    "   Session connected - LOAD_PAGE enabled for: {}",
-                    session_id
-                );
-            }
+            let note = format!("   Session connected - LOAD_PAGE enabled for: {}", session_id);
+            self.progress(note);
         }
 
-        if self.config.verbose {
-            interp = interp.with_log_callback(|level, msg| {
-                println!("   [{:?}] {}", level, msg);
-            });
+        if let Some(hook) = &self.approval_hook {
+            let hook = hook.clone();
+            interp = interp.with_approval_hook(move |op| hook(op));
         }
 
+        for (page_id, content) in &self.pages {
+            interp
+                .load_page(page_id.clone(), content.clone())
+                .map_err(|e| e.to_string())?;
+        }
+
+        // LOG lines are passed on as progress events after each stretch of
+        // execution
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let sink = logs.clone();
+        interp = interp.with_log_callback(move |level, msg| {
+            sink.lock().unwrap().push(format!("   [{:?}] {}", level, msg));
+        });
+
+        let mut iterations = 0;
+        let mut emitted_steps = 0;
+
         loop {
-            match interp.run().map_err(|e| e.to_string())? {
-                ExecutionResult::Complete(result) => {
-                    self.full_trace.extend(interp.trace().iter().cloned());
+            // EXEC, HTTP_REQUEST and SLEEP block, so run off the async workers
+            let (returned, outcome) = tokio::task::spawn_blocking(move || {
+                let outcome = interp.run();
+                (interp, outcome)
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+            interp = returned;
+            let lines = std::mem::take(&mut *logs.lock().unwrap());
+            for line in lines {
+                self.progress(line);
+            }
+            let outcome = outcome.map_err(|e| e.to_string())?;
+            self.emit_steps(&interp, &mut emitted_steps);
 
-                    if self.config.verbose {
-                        println!("\nTask completed!");
-                    }
+            match outcome {
+                ExecutionResult::Complete(result) => {
+                    self.extend_trace(interp.trace());
 
                     let pages = self.collect_pages(&interp);
-                    self.save_to_session(&pages)?;
-
-                    return Ok(AgentResult { result, pages });
+                    let labels = interp.page_labels();
+                    self.save_to_session(&pages, &labels)?;
+                    self.remember_pages(&pages, &labels);
+
+                    return Ok(AgentResult {
+                        result,
+                        pages,
+                        usage: self.run_usage(),
+                        llm_calls: self.usage.total_calls,
+                        steps: interp.trace().len(),
+                        duration: self.started_at.elapsed(),
+                        metrics: interp.metrics(),
+                        output: None,
+                        cancelled: false,
+                    });
                 }
                 ExecutionResult::Failed(error) => {
-                    self.full_trace.extend(interp.trace().iter().cloned());
+                    self.extend_trace(interp.trace());
                     return Err(error);
                 }
+                ExecutionResult::NeedsLlm(_) if self.cancel.is_cancelled() => {
+                    return self.cancel_run(&interp);
+                }
                 ExecutionResult::NeedsLlm(request) => {
-                    if let LlmRequestType::Inject { .. } = &request.request_type {
-                        let opcodes = self.handle_inject_request(&request, &interp).await?;
-                        let count = interp.inject_opcodes(opcodes).map_err(|e| e.to_string())?;
-                        if self.config.verbose {
-                            println!("   Injected {} opcodes", count);
-                        }
-                    } else if let LlmRequestType::InferBatch {
-                        prompts,
-                        context,
-                        store_prefix,
-                        store_combined,
-                        ..
-                    } = &request.request_type
-                    {
-                        let results = self
-                            .handle_infer_batch_request(prompts, context, store_prefix)
-                            .await?;
-
-                        for (i, result) in results.iter().enumerate() {
-                            let page_id = format!("{}_{}", store_prefix, i);
-                            interp
-                                .provide_llm_response(result.clone(), &page_id)
-                                .map_err(|e| e.to_string())?;
-                        }
-
-                        if let Some(combined_page) = store_combined {
-                            let combined = serde_json::json!({
-                                "results": results,
-                                "count": results.len(),
-                                "success": true
-                            });
-                            interp
-                                .provide_llm_response(combined, combined_page)
-                                .map_err(|e| e.to_string())?;
-                        }
-                    } else {
-                        let response = self.handle_llm_request(&request, &interp).await?;
-                        interp
-                            .provide_llm_response(response, &request.store_to)
-                            .map_err(|e| e.to_string())?;
+                    iterations += 1;
+                    if iterations > self.config.max_iterations {
+                        self.extend_trace(interp.trace());
+                        let mut partial: Vec<String> =
+                            self.collect_pages(&interp).into_keys().collect();
+                        partial.sort();
+                        return Err(format!(
+                            "Max iterations exceeded ({} LLM round-trips); partial pages: [{}]",
+                            self.config.max_iterations,
+                            partial.join(", ")
+                        ));
+                    }
+
+                    match self.answer_request(&request, &mut interp).await {
+                        Err(_) if self.cancel.is_cancelled() => return self.cancel_run(&interp),
+                        other => other?,
                     }
                 }
                 ExecutionResult::StepLimitExceeded => {
-                    self.full_trace.extend(interp.trace().iter().cloned());
+                    self.extend_trace(interp.trace());
                     return Err("Step limit exceeded".to_string());
                 }
             }
         }
     }
 
-    /// Save pages to session
+    /// Stop a cancelled run, keeping and persisting the pages it produced
+    fn cancel_run(&mut self, interp: &Interpreter<DefaultSyscallHandler>) -> Result<AgentResult, String> {
+        self.extend_trace(interp.trace());
+
+        let pages = self.collect_pages(interp);
+        let labels = interp.page_labels();
+        self.save_to_session(&pages, &labels)?;
+        self.remember_pages(&pages, &labels);
+        Ok(self.cancelled_result(pages, interp.metrics()))
+    }
+
+    /// Answer the interpreter's pending LLM request
+    async fn answer_request(
+        &mut self,
+        request: &LlmRequest,
+        interp: &mut Interpreter<DefaultSyscallHandler>,
+    ) -> Result<(), String> {
+        let llm_started = Instant::now();
+        if let LlmRequestType::Inject { .. } = &request.request_type {
+            let opcodes = self.handle_inject_request(request, interp).await?;
+            interp.record_llm_time(llm_started.elapsed());
+            let outcome = interp.inject_opcodes_lenient(opcodes).map_err(|e| e.to_string())?;
+            self.progress(format!("   Injected {} opcodes ({} dropped)", outcome.injected, outcome.dropped));
+        } else if let LlmRequestType::InferBatch {
+            prompts,
+            context,
+            store_prefix,
+            store_combined,
+            ..
+        } = &request.request_type
+        {
+            let results = self
+                .handle_infer_batch_request(prompts, context, store_prefix, interp)
+                .await?;
+            interp.record_llm_time(llm_started.elapsed());
+
+            // The per-prompt pages were stored as their results came in
+            let mut responses = Vec::new();
+            if let Some(combined_page) = store_combined {
+                let succeeded = results.iter().filter(|r| r["success"] == true).count();
+                let combined = serde_json::json!({
+                    "results": results,
+                    "count": results.len(),
+                    "succeeded": succeeded,
+                    "failed": results.len() - succeeded,
+                    "success": true
+                });
+                responses.push((combined_page.clone(), combined));
+            }
+
+            interp
+                .provide_llm_responses(responses)
+                .map_err(|e| e.to_string())?;
+        } else {
+            let response = self.handle_llm_request(request).await?;
+            interp.record_llm_time(llm_started.elapsed());
+            interp
+                .provide_llm_response(response, &request.store_to)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Save pages to session; `labels` gives their content types
     fn save_to_session(
         &mut self,
         pages: &HashMap<String, serde_json::Value>,
+        labels: &HashMap<String, String>,
     ) -> Result<(), String> {
         if let (Some(manager), Some(session_id)) = (&self.session_manager, &self.session_id) {
             let mut session = manager.load_session(session_id).map_err(|e| e.to_string())?;
 
+            let mut saved = Vec::new();
             for (page_id, content) in pages {
-                let page = MemoryPage::new(page_id, content.clone());
+                let mut page = MemoryPage::new(page_id, content.clone());
+                page.label = labels.get(page_id).cloned();
                 let summary = summarize_value(content);
                 session.index_page(&page, Some(summary.clone()));
-                manager
-                    .save_page(session_id, &page)
-                    .map_err(|e| e.to_string())?;
 
                 let now = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
@@ -347,23 +932,53 @@ impl Agent {
                     id: page_id.clone(),
                     summary,
                     tokens: page.size_tokens,
-                    content_type: None,
+                    content_type: page.label.clone(),
                     created_at: now,
                     accessed_at: now,
                     loaded: false,
                 };
                 self.page_index.insert(page_id.clone(), idx);
+                saved.push(page);
             }
 
+            manager.save_pages(session_id, &saved).map_err(|e| e.to_string())?;
             manager.save_session(&session).map_err(|e| e.to_string())?;
 
-            if self.config.verbose {
-                println!("   Saved {} pages to session", pages.len());
+            self.progress(format!("   Saved {} pages to session", pages.len()));
+
+            for page_id in pages.keys() {
+                self.emit(AgentEvent::PageSaved {
+                    page_id: page_id.clone(),
+                });
             }
         }
         Ok(())
     }
 
+    /// Keep pages in-process for the next run and make sure the LLM sees them
+    /// in the page index (LOAD_PAGE falls back to active memory)
+    fn remember_pages(
+        &mut self,
+        pages: &HashMap<String, serde_json::Value>,
+        labels: &HashMap<String, String>,
+    ) {
+        for (page_id, content) in pages {
+            self.pages.insert(page_id.clone(), content.clone());
+            self.page_index.entry(page_id.clone()).or_insert_with(|| {
+                let page = MemoryPage::new(page_id, content.clone());
+                PageIndex {
+                    id: page_id.clone(),
+                    summary: summarize_value(content),
+                    tokens: page.size_tokens,
+                    content_type: labels.get(page_id).cloned(),
+                    created_at: page.created_at,
+                    accessed_at: page.accessed_at,
+                    loaded: true,
+                }
+            });
+        }
+    }
+
     /// Collect all pages from interpreter for final result
     fn collect_pages(
         &self,
@@ -373,30 +988,32 @@ impl Agent {
     }
 
     /// Handle an LLM request from the interpreter
-    async fn handle_llm_request(
-        &self,
-        request: &LlmRequest,
-        interp: &Interpreter<DefaultSyscallHandler>,
-    ) -> Result<serde_json::Value, String> {
-        if self.config.verbose {
-            println!("\n   LLM Request ({:?})", request.request_type);
-            println!("      Prompt: {}", truncate(&request.prompt, 60));
-        }
-
+    async fn handle_llm_request(&mut self, request: &LlmRequest) -> Result<serde_json::Value, String> {
+        self.progress(format!(
+            "\n   LLM Request ({:?})\n      Prompt: {}",
+            request.request_type,
+            truncate(&request.prompt, 60)
+        ));
+
+        // Pages come from the request, which may be a forked child's
         let mut context = String::new();
         for page_id in &request.context_pages {
-            if let Some(content) = interp.get_page(page_id) {
+            if let Some(content) = request.page(page_id) {
                 context.push_str(&format!("### Page: {}\n{}\n\n", page_id, content));
             }
         }
 
         let prompt = match &request.request_type {
-            LlmRequestType::Infer => {
-                if context.is_empty() {
+            LlmRequestType::Infer { params, .. } => {
+                let mut prompt = if context.is_empty() {
                     request.prompt.clone()
                 } else {
                     format!("{}\n\n## Context:\n{}", request.prompt, context)
+                };
+                if let Some(schema) = &params.schema {
+                    prompt.push_str(&format!("\n\nRespond with only JSON matching this schema:\n{}", schema));
                 }
+                prompt
             }
             LlmRequestType::Plan => {
                 format!(
@@ -407,8 +1024,9 @@ impl Agent {
             }
             LlmRequestType::Reflect { include_trace } => {
                 let trace_text = if *include_trace {
-                    let trace: Vec<String> = interp
-                        .trace()
+                    let trace: Vec<String> = request
+                        .execution_state
+                        .trace
                         .iter()
                         .map(|s| format!("{}: {} -> {}", s.step, s.opcode, s.result))
                         .collect();
@@ -417,8 +1035,11 @@ impl Agent {
                     String::new()
                 };
                 format!(
-                    "# Reflection Request\n\n{}\n\n## Context:\n{}{}",
-                    request.prompt, context, trace_text
+                    "# Reflection Request\n\n{}\n\n## Context:\n{}{}{}",
+                    request.prompt,
+                    context,
+                    registers_text(&request.execution_state.registers),
+                    trace_text
                 )
             }
             LlmRequestType::Inject { .. } => {
@@ -429,19 +1050,20 @@ impl Agent {
             }
         };
 
-        let completion_request = CompletionRequest::new(vec![ChatMessage::user(prompt)]);
+        let mut messages = Vec::new();
+        if let LlmRequestType::Infer { history_page: Some(page_id), .. } = &request.request_type {
+            messages = request.history_messages(page_id).map_err(|e| e.to_string())?;
+        }
+        messages.push(ChatMessage::user(prompt));
 
-        let response = self
-            .provider
-            .complete(completion_request)
-            .await
-            .map_err(|e| format!("LLM error: {:?}", e))?;
+        let mut completion_request = CompletionRequest::new(messages);
+        if let LlmRequestType::Infer { params, .. } = &request.request_type {
+            completion_request = completion_request.with_infer_params(params);
+        }
 
-        let content = response.content.ok_or("Empty LLM response")?;
+        let response = self.complete(completion_request).await?;
 
-        if self.config.verbose {
-            println!("      Response: {} chars", content.len());
-        }
+        let content = response.content.ok_or("Empty LLM response")?;
 
         Ok(serde_json::json!({
             "response": content,
@@ -451,14 +1073,11 @@ impl Agent {
 
     /// Handle an INJECT request - LLM generates opcodes to insert
     async fn handle_inject_request(
-        &self,
+        &mut self,
         request: &LlmRequest,
         interp: &Interpreter<DefaultSyscallHandler>,
-    ) -> Result<Vec<Opcode>, String> {
-        if self.config.verbose {
-            println!("\n   INJECT Request");
-            println!("      Goal: {}", truncate(&request.prompt, 60));
-        }
+    ) -> Result<Vec<serde_json::Value>, String> {
+        self.progress(format!("\n   INJECT Request\n      Goal: {}", truncate(&request.prompt, 60)));
 
         let mut context = String::new();
         for page_id in &request.context_pages {
@@ -511,7 +1130,7 @@ You are the LLM CPU of a running VM program. The program has reached an INJECT p
 {}
 
 ## Current Context
-{}{}{}
+{}{}{}{}
 
 ## Tool Opcode Reference (EXACT field names required):
 - READ_FILE: {{"op": "READ_FILE", "path": "<file>", "store_to": "<page>"}}
@@ -535,28 +1154,59 @@ IMPORTANT: Return ONLY a valid JSON array. Example:
 ]
 
 Generate the opcodes now:"#,
-            request.prompt, context, trace_text, memory_text
+            request.prompt,
+            context,
+            registers_text(&request.execution_state.registers),
+            trace_text,
+            memory_text
         );
 
-        let completion_request = CompletionRequest::new(vec![ChatMessage::user(prompt)]);
-
-        let response = self
-            .provider
-            .complete(completion_request)
-            .await
-            .map_err(|e| format!("LLM error: {:?}", e))?;
+        let mut messages = vec![ChatMessage::user(prompt)];
+        let mut attempt = 0;
 
-        let content = response.content.ok_or("Empty LLM response")?;
+        loop {
+            let completion_request = CompletionRequest::new(messages.clone());
+
+            let response = self.complete(completion_request).await?;
+
+            let content = response.content.ok_or("Empty LLM response")?;
+
+            // Elements that don't parse are dropped at injection, so only
+            // the ones that do are checked here
+            let checked = self.parse_opcodes(&content).and_then(|values| {
+                let opcodes: Vec<Opcode> = values
+                    .iter()
+                    .filter_map(|v| serde_json::from_value(v.clone()).ok())
+                    .collect();
+                interp.validate_injection(&opcodes).map(|_| values).map_err(|errors| {
+                    let lines: Vec<String> = errors.iter().map(|e| format!("  - {}", e)).collect();
+                    format!("Injected opcodes failed validation:\n{}", lines.join("\n"))
+                })
+            });
 
-        if self.config.verbose {
-            println!("      Response: {} chars", content.len());
+            match checked {
+                Ok(values) => return Ok(values),
+                Err(e) if attempt < self.config.max_parse_retries => {
+                    attempt += 1;
+                    self.progress(format!(
+                        "      Opcodes were rejected, re-prompting ({}/{})",
+                        attempt, self.config.max_parse_retries
+                    ));
+                    messages.push(ChatMessage::assistant(content));
+                    messages.push(ChatMessage::user(format!(
+                        "Your opcodes were rejected: {}\n\n\
+                         Return ONLY a corrected JSON array of opcodes.",
+                        e
+                    )));
+                }
+                Err(e) => return Err(e),
+            }
         }
-
-        self.parse_opcodes(&content)
     }
 
-    /// Parse opcodes from LLM output (handles markdown fences)
-    fn parse_opcodes(&self, content: &str) -> Result<Vec<Opcode>, String> {
+    /// Parse the opcode array from LLM output (handles markdown fences).
+    /// Elements stay raw JSON so one malformed opcode doesn't sink the rest
+    fn parse_opcodes(&self, content: &str) -> Result<Vec<serde_json::Value>, String> {
         let json_str = if content.contains("```json") {
             content
                 .split("```json")
@@ -574,21 +1224,24 @@ Generate the opcodes now:"#,
             content.trim()
         };
 
-        serde_json::from_str::<Vec<Opcode>>(json_str)
+        serde_json::from_str::<Vec<serde_json::Value>>(json_str)
             .map_err(|e| format!("Failed to parse injected opcodes: {}\n\nContent:\n{}", e, json_str))
     }
 
-    /// Handle an INFER_BATCH request - run multiple LLM queries
+    /// Handle an INFER_BATCH request: run the prompts concurrently, at most
+    /// `max_batch_concurrency` at a time, and store each result in its
+    /// `{prefix}_{i}` page as soon as it arrives. A failed prompt gets an
+    /// error page and the rest carry on. Returns the results in prompt order.
     async fn handle_infer_batch_request(
-        &self,
+        &mut self,
         prompts: &[String],
         context: &[serde_json::Value],
         store_prefix: &str,
+        interp: &mut Interpreter<DefaultSyscallHandler>,
     ) -> Result<Vec<serde_json::Value>, String> {
-        if self.config.verbose {
-            println!("\n   INFER_BATCH Request");
-            println!("      Running {} prompts...", prompts.len());
-        }
+        use futures_util::StreamExt;
+
+        self.progress(format!("\n   INFER_BATCH Request\n      Running {} prompts...", prompts.len()));
 
         let context_text: String = context
             .iter()
@@ -602,62 +1255,56 @@ Generate the opcodes now:"#,
             })
             .collect();
 
-        let mut results = Vec::with_capacity(prompts.len());
-
+        let mut results = vec![serde_json::Value::Null; prompts.len()];
+        let mut requests = Vec::with_capacity(prompts.len());
         for (i, prompt) in prompts.iter().enumerate() {
             let full_prompt = if context_text.is_empty() {
                 prompt.clone()
             } else {
                 format!("{}\n\n## Context:\n{}", prompt, context_text)
             };
-
-            let req = CompletionRequest::new(vec![ChatMessage::user(full_prompt)]);
-            let result = match self.provider.complete(req).await {
-                Ok(resp) => {
-                    let content = resp.content.unwrap_or_default();
-                    serde_json::json!({
-                        "response": content,
-                        "success": true,
-                        "index": i
-                    })
-                }
-                Err(e) => {
-                    serde_json::json!({
-                        "error": format!("{:?}", e),
-                        "success": false,
-                        "index": i
-                    })
-                }
-            };
-            results.push(result);
-
-            if self.config.verbose {
-                println!(
-                    "      [{}/{}] {} → {}",
-                    i + 1,
-                    prompts.len(),
-                    store_prefix,
-                    if results
-                        .last()
-                        .map(|r| r["success"].as_bool().unwrap_or(false))
-                        .unwrap_or(false)
-                    {
-                        "ok"
-                    } else {
-                        "err"
-                    }
-                );
+            match self.prepare_request(CompletionRequest::new(vec![ChatMessage::user(full_prompt)])) {
+                Ok(request) => requests.push((i, request)),
+                Err(e) => results[i] = serde_json::json!({"error": e, "success": false, "index": i}),
             }
         }
 
-        if self.config.verbose {
-            let successes = results
-                .iter()
-                .filter(|r| r["success"].as_bool().unwrap_or(false))
-                .count();
-            println!("      Completed: {}/{} successful", successes, results.len());
+        let provider = &self.provider;
+        let mut pending = futures_util::stream::iter(requests)
+            .map(|(i, request)| async move { (i, provider.complete(request).await) })
+            .buffer_unordered(self.config.max_batch_concurrency.max(1));
+
+        let mut done = 0;
+        loop {
+            let next = tokio::select! {
+                next = pending.next() => next,
+                _ = self.cancel.cancelled() => return Err(CANCELLED.to_string()),
+            };
+            let Some((i, response)) = next else { break };
+
+            let result = match Self::record_response(&mut self.usage, &mut self.event_handler, self.config.verbose, response) {
+                Ok(resp) => serde_json::json!({
+                    "response": resp.content.unwrap_or_default(),
+                    "success": true,
+                    "index": i
+                }),
+                Err(e) => serde_json::json!({"error": e, "success": false, "index": i}),
+            };
+            interp
+                .load_page(format!("{}_{}", store_prefix, i), result.clone())
+                .map_err(|e| e.to_string())?;
+
+            done += 1;
+            let status = if result["success"] == true { "ok" } else { "err" };
+            let note = format!("      [{}/{}] {}_{} → {}", done, prompts.len(), store_prefix, i, status);
+            dispatch(&mut self.event_handler, self.config.verbose, AgentEvent::Progress(note));
+            results[i] = result;
         }
 
+        drop(pending);
+        let successes = results.iter().filter(|r| r["success"] == true).count();
+        self.progress(format!("      Completed: {}/{} successful", successes, results.len()));
+
         Ok(results)
     }
 }
@@ -668,11 +1315,71 @@ impl Default for Agent {
     }
 }
 
+/// Fold the oldest steps of `trace` into one `COMPACTED` step so at most
+/// `max` entries remain; the newest `max - 1` steps are kept verbatim. A
+/// `max` of 0 is treated as 1, leaving only the `COMPACTED` step.
+///
+/// The synthetic step's result is JSON: how many steps it covers, a count per
+/// opcode and the error count with the last error. An earlier `COMPACTED`
+/// step is merged into the new one rather than nested.
+pub fn compact_trace(trace: &mut Vec<llcraft_vm::ExecutionStep>, max: usize) {
+    let max = max.max(1);
+    if trace.len() <= max {
+        return;
+    }
+    // The COMPACTED step takes one of the `max` slots
+    let keep = max - 1;
+    let old: Vec<_> = trace.drain(..trace.len() - keep).collect();
+
+    let mut steps = 0;
+    let mut opcodes: std::collections::BTreeMap<String, u64> = Default::default();
+    let mut errors = 0;
+    let mut last_error = None;
+    for step in &old {
+        if step.opcode == COMPACTED_OPCODE {
+            let summary: serde_json::Value = serde_json::from_str(&step.result).unwrap_or_default();
+            steps += summary["steps"].as_u64().unwrap_or(0);
+            errors += summary["errors"].as_u64().unwrap_or(0);
+            if let Some(counts) = summary["opcodes"].as_object() {
+                for (op, count) in counts {
+                    *opcodes.entry(op.clone()).or_default() += count.as_u64().unwrap_or(0);
+                }
+            }
+            last_error = summary["last_error"].as_str().map(str::to_string).or(last_error);
+            continue;
+        }
+        steps += 1;
+        *opcodes.entry(step.opcode.clone()).or_default() += 1;
+        if let Some(err) = &step.error {
+            errors += 1;
+            last_error = Some(truncate(err, 100));
+        }
+    }
+
+    let llm_ms: f64 = old.iter().filter_map(|s| s.llm_ms).sum();
+    trace.insert(0, llcraft_vm::ExecutionStep {
+        step: old[0].step,
+        opcode: COMPACTED_OPCODE.to_string(),
+        result: serde_json::json!({
+            "steps": steps,
+            "opcodes": opcodes,
+            "errors": errors,
+            "last_error": last_error,
+        })
+        .to_string(),
+        error: None,
+        duration_ms: old.iter().map(|s| s.duration_ms).sum(),
+        llm_ms: (llm_ms > 0.0).then_some(llm_ms),
+        stack: Vec::new(),
+        memory: Vec::new(),
+    });
+}
+
+/// First `max_len` characters of `s`, with an ellipsis if anything was cut
 fn truncate(s: &str, max_len: usize) -> String {
-    if s.len() <= max_len {
-        s.to_string()
-    } else {
-        format!("{}…", &s[..max_len])
+    match s.char_indices().nth(max_len) {
+        Some((cut, _)) => format!("{}…", &s[..cut]),
+        None => s.to_string(),
     }
 }
 
@@ -694,3 +1401,757 @@ fn summarize_value(content: &serde_json::Value) -> String {
         _ => format!("{}", content),
     }
 }
+
+/// Render the goal/focus/thought registers for REFLECT and INJECT prompts
+fn registers_text(registers: &HashMap<String, serde_json::Value>) -> String {
+    let lines: Vec<String> = ["goal", "focus", "thought"]
+        .iter()
+        .filter_map(|name| {
+            registers.get(*name).map(|value| match value {
+                serde_json::Value::String(s) => format!("- {}: {}", name, s),
+                other => format!("- {}: {}", name, other),
+            })
+        })
+        .collect();
+    if lines.is_empty() {
+        String::new()
+    } else {
+        format!("\n\n## Registers:\n{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llcraft_vm::MockProvider;
+
+    fn quiet() -> AgentConfig {
+        AgentConfig {
+            verbose: false,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_with_mock_provider() {
+        let program = r#"```json
+{
+  "id": "test",
+  "name": "Test",
+  "description": "Store and complete",
+  "code": [
+    {"op": "STORE", "page_id": "greeting", "data": "hello"},
+    {"op": "COMPLETE", "result": {"page": "greeting"}}
+  ]
+}
+```"#;
+        let provider = MockProvider::with_responses([program]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("say hello").await.unwrap();
+
+        assert_eq!(result.result["page"], "greeting");
+        assert_eq!(result.pages.get("greeting"), Some(&serde_json::json!("hello")));
+        assert_eq!(agent.provider().call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_reprompts_on_parse_failure() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [{"op": "COMPLETE", "result": "done"}]}"#;
+        let provider = MockProvider::with_responses(["not json at all", program]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("anything").await.unwrap();
+        assert_eq!(result.result, "done");
+
+        let requests = agent.provider().requests();
+        assert_eq!(requests.len(), 2);
+        let retry = requests[1].messages.last().unwrap().content.as_deref().unwrap();
+        assert!(retry.contains("Failed to parse program"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_gives_up_after_parse_retries() {
+        let provider = MockProvider::with_responses(["bad", "worse"]);
+        let config = AgentConfig {
+            max_parse_retries: 1,
+            ..quiet()
+        };
+        let mut agent = Agent::with_provider_and_config(provider, config);
+
+        let err = agent.run("anything").await.err().unwrap();
+        assert!(err.contains("Failed to parse program"));
+        assert_eq!(agent.provider().call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_agent_reprompts_on_invalid_program() {
+        let invalid = r#"{"id": "t", "name": "T", "description": "", "code": [{"op": "JUMP", "target": "nowhere"}]}"#;
+        let valid = r#"{"id": "t", "name": "T", "description": "", "code": [{"op": "COMPLETE", "result": "done"}]}"#;
+        let provider = MockProvider::with_responses([invalid, valid]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        agent.run("anything").await.unwrap();
+
+        let requests = agent.provider().requests();
+        let retry = requests[1].messages.last().unwrap().content.as_deref().unwrap();
+        assert!(retry.contains("undefined label 'nowhere'"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_reprompts_on_invalid_injection() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "STORE", "page_id": "notes", "data": "text"},
+            {"op": "INJECT", "goal": "finish up"}
+        ]}"#;
+        let typo = r#"[{"op": "COMPLETE", "result": {"page": "x"}}, {"op": "COPY", "src": "note", "dst": "out"}]"#;
+        let fixed = r#"[{"op": "COPY", "src": "notes", "dst": "out"}, {"op": "COMPLETE", "result": {"page": "out"}}]"#;
+        let provider = MockProvider::with_responses([program, typo, fixed]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("anything").await.unwrap();
+        assert_eq!(result.pages.get("out"), Some(&serde_json::json!("text")));
+
+        let requests = agent.provider().requests();
+        assert_eq!(requests.len(), 3);
+        let retry = requests[2].messages.last().unwrap().content.as_deref().unwrap();
+        assert!(retry.contains("reads page 'note'"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_run_with_cancel_keeps_partial_pages() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "STORE", "page_id": "seed", "data": 1},
+            {"op": "INFER", "prompt": "expand", "store_to": "more"},
+            {"op": "COMPLETE", "result": {"page": "more"}}
+        ]}"#;
+        let provider = MockProvider::with_responses([program, "never sent"]);
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let mut agent = Agent::with_provider_and_config(provider, quiet()).on_event(move |event| {
+            if let AgentEvent::LlmResponse { .. } = event {
+                cancel.cancel();
+            }
+        });
+
+        let result = agent.run_with_cancel("anything", token).await.unwrap();
+        assert!(result.cancelled);
+        assert_eq!(result.pages.get("seed"), Some(&serde_json::json!(1)));
+        assert!(!result.pages.contains_key("more"));
+        assert_eq!(agent.provider().requests().len(), 1);
+        assert_eq!(agent.pages().get("seed"), Some(&serde_json::json!(1)));
+    }
+
+    #[tokio::test]
+    async fn test_agent_structured_output() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "INFER", "prompt": "count the files", "store_to": "count"},
+            {"op": "COMPLETE", "result": {"page": "count"}}
+        ]}"#;
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"files": {"type": "integer"}},
+            "required": ["files"]
+        });
+        let provider = MockProvider::with_responses([program, r#"{"files": 3}"#]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run_with_format("count files", &OutputFormat::from_schema(schema.clone())).await.unwrap();
+        assert_eq!(result.output, Some(serde_json::json!({"files": 3})));
+
+        let requests = agent.provider().requests();
+        let prompt = requests[0].messages.last().unwrap().content.as_deref().unwrap();
+        assert!(prompt.contains("## Output Format"));
+
+        let provider = MockProvider::with_responses([program, "three files"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+        let Err(err) = agent.run_with_format("count files", &OutputFormat::from_schema(schema)).await else {
+            panic!("expected a schema mismatch");
+        };
+        assert!(err.contains("does not match the output schema"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_max_iterations() {
+        let program = r#"{
+  "id": "spin",
+  "name": "Spin",
+  "description": "Never completes",
+  "code": [
+    {"op": "STORE", "page_id": "seed", "data": 1},
+    {"op": "LABEL", "name": "top"},
+    {"op": "INFER", "prompt": "again", "store_to": "out"},
+    {"op": "JUMP", "target": "top"}
+  ]
+}"#;
+        let provider = MockProvider::with_responses([program, "a", "b", "c", "d"]);
+        let config = AgentConfig {
+            max_iterations: 3,
+            ..quiet()
+        };
+        let mut agent = Agent::with_provider_and_config(provider, config);
+
+        let err = agent.run("spin").await.err().unwrap();
+        assert!(err.contains("Max iterations exceeded"));
+        assert!(err.contains("out, seed"));
+        // 1 program generation + 3 INFER round-trips
+        assert_eq!(agent.provider().call_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_agent_event_sequence() {
+        use std::sync::{Arc, Mutex};
+
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "STORE", "page_id": "x", "data": 1},
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+        let provider = MockProvider::with_responses([program]);
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let mut agent = Agent::with_provider_and_config(provider, quiet()).on_event(move |e| {
+            let name = match e {
+                AgentEvent::ProgramGenerated(_) => "program",
+                AgentEvent::StepExecuted(_) => "step",
+                AgentEvent::LlmRequest { .. } => "request",
+                AgentEvent::LlmResponse { .. } => "response",
+                AgentEvent::PageSaved { .. } => "saved",
+                AgentEvent::Completed(_) => "completed",
+                AgentEvent::Failed(_) => "failed",
+                AgentEvent::Cancelled => "cancelled",
+                AgentEvent::Progress(_) => return,
+            };
+            sink.lock().unwrap().push(name);
+        });
+
+        agent.run("trivial").await.unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["request", "response", "program", "step", "step", "completed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_lines_arrive_as_progress_events() {
+        use std::sync::{Arc, Mutex};
+
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "LOG", "level": "info", "message": "halfway there"},
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+        let notes = Arc::new(Mutex::new(Vec::new()));
+        let sink = notes.clone();
+        let mut agent = Agent::with_provider_and_config(MockProvider::with_responses([program]), quiet())
+            .on_event(move |e| {
+                if let AgentEvent::Progress(text) = e {
+                    sink.lock().unwrap().push(text);
+                }
+            });
+
+        agent.run("log something").await.unwrap();
+
+        let notes = notes.lock().unwrap();
+        assert!(notes.iter().any(|n| n.starts_with("Task: log something")));
+        assert!(notes.iter().any(|n| n.contains("[Info] halfway there")), "{:?}", notes);
+    }
+
+    fn with_usage(content: &str, prompt: usize, completion: usize) -> llcraft_vm::CompletionResponse {
+        let mut response = MockProvider::completion(content);
+        response.usage = Usage {
+            prompt_tokens: prompt,
+            completion_tokens: completion,
+            total_tokens: prompt + completion,
+            latency_ms: None,
+        };
+        response
+    }
+
+    #[tokio::test]
+    async fn test_agent_reports_usage() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "INFER", "prompt": "hi", "store_to": "a"},
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+        let provider = MockProvider::new();
+        provider.push_completion(with_usage(program, 100, 50));
+        provider.push_completion(with_usage("hello", 20, 10));
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("usage").await.unwrap();
+        assert_eq!(result.usage.prompt_tokens, 120);
+        assert_eq!(result.usage.completion_tokens, 60);
+        assert_eq!(result.usage.total_tokens, 180);
+    }
+
+    #[tokio::test]
+    async fn test_agent_result_stats() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "INFER", "prompt": "hi", "store_to": "a"},
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+        let provider = MockProvider::with_responses([program, "hello"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("stats").await.unwrap();
+        assert_eq!(result.llm_calls, 2);
+        // INFER + COMPLETE
+        assert_eq!(result.steps, 2);
+        assert!(result.duration > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_agent_token_budget() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [
+            {"op": "INFER", "prompt": "hi", "store_to": "a"},
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+        let provider = MockProvider::new();
+        provider.push_completion(with_usage(program, 9_950, 50));
+        provider.push_completion(with_usage("hello", 20, 10));
+        let config = AgentConfig {
+            max_total_tokens: Some(10_000),
+            ..quiet()
+        };
+        let mut agent = Agent::with_provider_and_config(provider, config);
+
+        let err = agent.run("budget").await.err().unwrap();
+        assert!(err.contains("Token budget exceeded"));
+        // The INFER call was never sent
+        assert_eq!(agent.provider().call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_custom_token_counter() {
+        struct Expensive;
+        impl TokenCounter for Expensive {
+            fn count(&self, _text: &str) -> usize {
+                1_000_000
+            }
+        }
+
+        let config = AgentConfig {
+            max_total_tokens: Some(10_000),
+            ..quiet()
+        };
+        let mut agent = Agent::with_provider_and_config(MockProvider::new(), config)
+            .with_token_counter(Expensive);
+
+        let err = agent.run("budget").await.err().unwrap();
+        assert!(err.contains("Token budget exceeded"));
+        assert_eq!(agent.provider().call_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_agent_plan_does_not_execute() {
+        let dir = std::env::temp_dir().join("llcraft_agent_plan_test");
+        let target = dir.join("should_not_exist.txt");
+        let _ = std::fs::remove_file(&target);
+
+        let program = serde_json::json!({
+            "id": "t",
+            "name": "T",
+            "description": "",
+            "code": [
+                {"op": "WRITE_FILE", "path": target.to_string_lossy(), "content": "x"},
+                {"op": "EXEC", "command": format!("touch {}", target.display()), "store_to": "out"},
+                {"op": "COMPLETE", "result": "done"}
+            ]
+        });
+        let provider = MockProvider::with_responses([program.to_string()]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let planned = agent.plan("write a file").await.unwrap();
+
+        assert_eq!(planned.code.len(), 3);
+        assert!(!target.exists());
+        assert!(agent.trace().is_empty());
+        assert_eq!(agent.provider().call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_system_prompt_suffix() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [{"op": "COMPLETE", "result": "done"}]}"#;
+        let provider = MockProvider::with_responses([program]);
+        let config = AgentConfig {
+            system_prompt_suffix: Some("You are a Rust refactoring assistant.".into()),
+            ..quiet()
+        };
+        let mut agent = Agent::with_provider_and_config(provider, config);
+        agent.run("refactor").await.unwrap();
+
+        let requests = agent.provider().requests();
+        let system = requests[0].messages[0].content.as_deref().unwrap();
+        assert!(system.starts_with(llcraft_vm::SYSTEM_PROMPT));
+        assert!(system.ends_with("You are a Rust refactoring assistant."));
+    }
+
+    #[tokio::test]
+    async fn test_agent_system_prompt_override() {
+        let program = r#"{"id": "t", "name": "T", "description": "", "code": [{"op": "COMPLETE", "result": "done"}]}"#;
+        let provider = MockProvider::with_responses([program]);
+        let config = AgentConfig {
+            system_prompt_override: Some("Custom spec".into()),
+            ..quiet()
+        };
+        let mut agent = Agent::with_provider_and_config(provider, config);
+        agent.run("task").await.unwrap();
+
+        let requests = agent.provider().requests();
+        assert_eq!(requests[0].messages[0].content.as_deref(), Some("Custom spec"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_map_over_array() {
+        let program = r#"{"id": "m", "name": "M", "description": "", "code": [
+            {"op": "STORE", "page_id": "langs", "data": ["rust", "go", "zig"]},
+            {"op": "MAP", "over": "langs", "prompt_template": "One word for {item}", "store_to": "words"},
+            {"op": "COMPLETE", "result": {"page": "words"}}
+        ]}"#;
+        let provider = MockProvider::with_responses([program, "fast", "simple", "small"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("map").await.unwrap();
+        let words: Vec<&str> = result.pages["words"]["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["response"].as_str().unwrap())
+            .collect();
+        assert_eq!(words, vec!["fast", "simple", "small"]);
+        assert_eq!(result.pages["words_2"]["response"], "small");
+        // COMPLETE ran after MAP rather than being skipped
+        assert_eq!(result.steps, 3);
+
+        let requests = agent.provider().requests();
+        assert_eq!(requests[2].messages[0].content.as_deref(), Some("One word for go"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_carries_pages_between_runs() {
+        let first = r#"{"id": "a", "name": "A", "description": "", "code": [
+            {"op": "STORE", "page_id": "notes", "data": "remember me"},
+            {"op": "COMPLETE", "result": "stored"}
+        ]}"#;
+        let second = r#"{"id": "b", "name": "B", "description": "", "code": [
+            {"op": "COPY", "src": "notes", "dst": "copy"},
+            {"op": "COMPLETE", "result": {"page": "copy"}}
+        ]}"#;
+        let provider = MockProvider::with_responses([first, second]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        agent.run("store").await.unwrap();
+        let result = agent.run("recall").await.unwrap();
+
+        assert_eq!(result.pages["copy"], "remember me");
+        // The second prompt advertised the page from the first run
+        let requests = agent.provider().requests();
+        let user = requests[1].messages[1].content.as_deref().unwrap();
+        assert!(user.contains("Page 'notes'"));
+
+        agent.clear();
+        assert!(agent.pages().is_empty());
+        assert!(agent.trace().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_agent_infer_goes_through_provider() {
+        let program = r#"{
+  "id": "test",
+  "name": "Test",
+  "description": "Infer once",
+  "code": [
+    {"op": "INFER", "prompt": "What is 2+2?", "context": [], "store_to": "answer"},
+    {"op": "COMPLETE", "result": {"page": "answer"}}
+  ]
+}"#;
+        let provider = MockProvider::with_responses([program, "4"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("add numbers").await.unwrap();
+
+        assert_eq!(result.pages["answer"]["response"], "4");
+        assert_eq!(agent.provider().call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_agent_infer_with_schema() {
+        let program = r#"{"id": "s", "name": "S", "description": "", "code": [
+            {"op": "INFER", "prompt": "Classify the review", "context": [], "store_to": "label",
+             "params": {"schema": {"type": "object", "required": ["sentiment"],
+                                   "properties": {"sentiment": {"type": "string", "enum": ["positive", "negative"]}}}}},
+            {"op": "BRANCH", "condition": "label.response.sentiment == \"positive\"", "if_true": "good", "if_false": "bad"},
+            {"op": "LABEL", "name": "good"},
+            {"op": "COMPLETE", "result": "good"},
+            {"op": "LABEL", "name": "bad"},
+            {"op": "COMPLETE", "result": "bad"}
+        ]}"#;
+        let provider = MockProvider::with_responses([program, "```json\n{\"sentiment\": \"positive\"}\n```"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("classify").await.unwrap();
+        assert_eq!(result.result, "good");
+        assert_eq!(result.pages["label"]["response"]["sentiment"], "positive");
+        let requests = agent.provider().requests();
+        assert_eq!(requests[1].response_schema.as_ref().unwrap()["required"][0], "sentiment");
+
+        let provider = MockProvider::with_responses([program, r#"{"sentiment": "meh"}"#]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+        match agent.run("classify").await {
+            Err(err) => assert!(err.contains("does not match schema: $.sentiment"), "{}", err),
+            Ok(_) => panic!("expected a schema mismatch"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_with_fn_provider() {
+        let program = r#"{"id": "f", "name": "F", "description": "", "code": [
+            {"op": "INFER", "prompt": "Say hello", "store_to": "greeting"},
+            {"op": "COMPLETE", "result": {"page": "greeting"}}
+        ]}"#;
+        let provider = llcraft_vm::FnProvider::new("scripted", move |request: CompletionRequest| {
+            let prompt = request.messages.last().and_then(|m| m.content.clone()).unwrap_or_default();
+            let reply = if prompt.starts_with("Say hello") { "hello" } else { program };
+            Ok(MockProvider::completion(reply))
+        });
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("greet").await.unwrap();
+        assert_eq!(result.pages["greeting"]["response"], "hello");
+    }
+
+    #[tokio::test]
+    async fn test_infer_batch_keeps_results_past_a_failure() {
+        let program = r#"{"id": "b", "name": "B", "description": "", "code": [
+            {"op": "INFER_BATCH", "prompts": ["part one", "part two", "part three"],
+             "store_prefix": "part", "store_combined": "parts"},
+            {"op": "COMPLETE", "result": {"page": "parts"}}
+        ]}"#;
+        let provider = llcraft_vm::FnProvider::new("scripted", move |request: CompletionRequest| {
+            let prompt = request.messages.last().and_then(|m| m.content.clone()).unwrap_or_default();
+            match prompt.as_str() {
+                "part two" => Err(llcraft_vm::ProviderError::Other("overloaded".into())),
+                p if p.starts_with("part") => Ok(MockProvider::completion(p.to_uppercase())),
+                _ => Ok(MockProvider::completion(program)),
+            }
+        });
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("batch").await.unwrap();
+        assert_eq!(result.pages["part_0"]["response"], "PART ONE");
+        assert_eq!(result.pages["part_1"]["success"], false);
+        assert_eq!(result.pages["part_2"]["response"], "PART THREE");
+        assert_eq!(result.pages["parts"]["succeeded"], 2);
+        assert_eq!(result.pages["parts"]["failed"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_reports_content_filter() {
+        let provider = MockProvider::new();
+        provider.push_completion(CompletionResponse {
+            content: None,
+            finish_reason: llcraft_vm::FinishReason::ContentFilter,
+            ..MockProvider::completion("")
+        });
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let Err(err) = agent.run("anything").await else {
+            panic!("a filtered response should fail the run");
+        };
+        assert!(err.contains("blocked by the provider's content filter"), "{}", err);
+        assert!(!err.contains("Empty LLM response"));
+    }
+
+    #[test]
+    fn test_compact_trace_bounds_size() {
+        let step = |i: usize| llcraft_vm::ExecutionStep {
+            step: i,
+            opcode: if i.is_multiple_of(2) { "STORE" } else { "INFER" }.to_string(),
+            result: format!("step {}", i),
+            error: (i == 7).then(|| "boom".to_string()),
+            duration_ms: 1.0,
+            llm_ms: None,
+            stack: Vec::new(),
+            memory: Vec::new(),
+        };
+        let mut trace: Vec<_> = (0..200).map(step).collect();
+
+        compact_trace(&mut trace, 50);
+        assert_eq!(trace.len(), 50);
+        assert_eq!(trace[0].opcode, COMPACTED_OPCODE);
+        let summary: serde_json::Value = serde_json::from_str(&trace[0].result).unwrap();
+        assert_eq!(summary["steps"], 151);
+        assert_eq!(summary["opcodes"]["STORE"], 76);
+        assert_eq!(summary["errors"], 1);
+        assert_eq!(summary["last_error"], "boom");
+        assert_eq!(trace[0].duration_ms, 151.0);
+        let recent: Vec<usize> = trace[1..].iter().map(|s| s.step).collect();
+        assert_eq!(recent, (151..200).collect::<Vec<_>>());
+
+        trace.extend((200..210).map(step));
+        compact_trace(&mut trace, 50);
+        assert_eq!(trace.len(), 50);
+        let summary: serde_json::Value = serde_json::from_str(&trace[0].result).unwrap();
+        assert_eq!(summary["steps"], 161);
+        assert_eq!(trace.last().unwrap().step, 209);
+
+        // With room for one entry, only the summary is left
+        compact_trace(&mut trace, 1);
+        assert_eq!(trace.len(), 1);
+        let summary: serde_json::Value = serde_json::from_str(&trace[0].result).unwrap();
+        assert_eq!(summary["steps"], 210);
+        trace.push(step(210));
+        compact_trace(&mut trace, 1);
+        assert_eq!(trace.len(), 1);
+        let summary: serde_json::Value = serde_json::from_str(&trace[0].result).unwrap();
+        assert_eq!(summary["steps"], 211);
+    }
+
+    #[test]
+    fn test_compact_trace_truncates_non_ascii_errors() {
+        // 'é' is two bytes, so a byte cut at 100 would split a character
+        let error = format!("x{}", "é".repeat(120));
+        let mut trace: Vec<_> = (0..3)
+            .map(|i| llcraft_vm::ExecutionStep {
+                step: i,
+                opcode: "EXEC".to_string(),
+                result: String::new(),
+                error: Some(error.clone()),
+                duration_ms: 0.0,
+                llm_ms: None,
+                stack: Vec::new(),
+                memory: Vec::new(),
+            })
+            .collect();
+
+        compact_trace(&mut trace, 2);
+        let summary: serde_json::Value = serde_json::from_str(&trace[0].result).unwrap();
+        let last_error = summary["last_error"].as_str().unwrap();
+        assert_eq!(last_error.chars().count(), 101);
+        assert!(last_error.starts_with("xé"));
+        assert!(last_error.ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn test_agent_infer_history_page() {
+        let program = r#"{"id": "h", "name": "H", "description": "", "code": [
+            {"op": "INFER", "prompt": "Name a colour", "store_to": "first", "history_page": "chat"},
+            {"op": "INFER", "prompt": "Another one", "store_to": "second", "history_page": "chat"},
+            {"op": "COMPLETE", "result": {"page": "second"}}
+        ]}"#;
+        let provider = MockProvider::with_responses([program, "red", "blue"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("chat").await.unwrap();
+        let chat = result.pages["chat"].as_array().unwrap();
+        assert_eq!(chat.len(), 4);
+        assert_eq!(chat[1], serde_json::json!({"role": "assistant", "content": "red"}));
+        assert_eq!(chat[3]["content"], "blue");
+
+        let requests = agent.provider().requests();
+        assert_eq!(requests[1].messages.len(), 1);
+        let second = &requests[2].messages;
+        assert_eq!(second.len(), 3);
+        assert_eq!(second[0].content.as_deref(), Some("Name a colour"));
+        assert_eq!(second[1].content.as_deref(), Some("red"));
+        assert_eq!(second[2].content.as_deref(), Some("Another one"));
+    }
+
+    #[tokio::test]
+    async fn test_agent_reflect_includes_registers() {
+        let program = r#"{"id": "r", "name": "R", "description": "", "code": [
+            {"op": "SET_REG", "reg": "goal", "value": "ship the parser"},
+            {"op": "SET_REG", "reg": {"custom": "attempts"}, "value": 2},
+            {"op": "GET_REG", "reg": {"custom": "attempts"}, "store_to": "attempts"},
+            {"op": "REFLECT", "question": "Are we on track?", "include_trace": false, "store_to": "review"},
+            {"op": "COMPLETE", "result": {"page": "review"}}
+        ]}"#;
+        let provider = MockProvider::with_responses([program, "yes"]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+
+        let result = agent.run("reflect").await.unwrap();
+        assert_eq!(result.pages["attempts"], 2);
+
+        let requests = agent.provider().requests();
+        let prompt = requests[1].messages[0].content.as_deref().unwrap();
+        assert!(prompt.contains("## Registers:\n- goal: ship the parser"));
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_runs_are_reproducible() {
+        let program = r#"{"id": "d", "name": "D", "description": "", "code": [
+            {"op": "STORE", "page_id": "topic", "data": "rust"},
+            {"op": "INFER", "prompt": "Describe it", "context": ["topic"], "store_to": "answer"},
+            {"op": "COMPLETE", "result": {"page": "answer"}}
+        ]}"#;
+        let config = AgentConfig {
+            deterministic: true,
+            ..quiet()
+        };
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let provider = MockProvider::with_responses([program, "a language"]);
+            let mut agent = Agent::with_provider_and_config(provider, config.clone());
+            agent.run("describe").await.unwrap();
+            runs.push((agent.trace().to_vec(), agent.provider().requests()));
+        }
+
+        let trace = |steps: &[llcraft_vm::ExecutionStep]| -> Vec<String> {
+            steps.iter().map(|s| format!("{} {} {}", s.step, s.opcode, s.result)).collect()
+        };
+        assert_eq!(trace(&runs[0].0), trace(&runs[1].0));
+        for request in &runs[0].1 {
+            assert_eq!(request.temperature, Some(0.0));
+            assert_eq!(request.seed, Some(DETERMINISTIC_SEED));
+        }
+        let prompts = |requests: &[CompletionRequest]| -> Vec<Option<String>> {
+            requests.iter().map(|r| r.messages.last().unwrap().content.clone()).collect()
+        };
+        assert_eq!(prompts(&runs[0].1), prompts(&runs[1].1));
+    }
+
+    #[tokio::test]
+    async fn test_agent_caches_program_for_repeated_task() {
+        let program = r#"{"id": "c", "name": "C", "description": "", "code": [
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+
+        let config = AgentConfig {
+            cache_programs: true,
+            ..quiet()
+        };
+        let provider = MockProvider::with_responses([program]);
+        let mut agent = Agent::with_provider_and_config(provider, config);
+        agent.run("same task").await.unwrap();
+        let result = agent.run("same task").await.unwrap();
+        assert_eq!(result.result, "done");
+        assert_eq!(agent.provider().call_count(), 1);
+
+        // Off by default
+        let provider = MockProvider::with_responses([program, program]);
+        let mut agent = Agent::with_provider_and_config(provider, quiet());
+        agent.run("same task").await.unwrap();
+        agent.run("same task").await.unwrap();
+        assert_eq!(agent.provider().call_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_agent_program_cache_invalidated_by_new_pages() {
+        let program = r#"{"id": "c", "name": "C", "description": "", "code": [
+            {"op": "STORE", "page_id": "notes", "data": "new"},
+            {"op": "COMPLETE", "result": "done"}
+        ]}"#;
+        let config = AgentConfig {
+            cache_programs: true,
+            ..quiet()
+        };
+        let provider = MockProvider::with_responses([program, program]);
+        let mut agent = Agent::with_provider_and_config(provider, config);
+
+        agent.run("same task").await.unwrap();
+        // The first run added a page, so the second run re-plans
+        agent.run("same task").await.unwrap();
+        assert_eq!(agent.provider().call_count(), 2);
+    }
+}
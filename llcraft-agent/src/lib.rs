@@ -12,7 +12,11 @@
 
 mod agent;
 
-pub use agent::{Agent, AgentConfig, AgentResult};
+pub use agent::{
+    compact_trace, print_event, Agent, AgentConfig, AgentEvent, AgentResult, EventHandler, OutputFormat, COMPACTED_OPCODE,
+    DEFAULT_MAX_TRACE_STEPS, DETERMINISTIC_SEED,
+};
+pub use tokio_util::sync::CancellationToken;
 
 /// Get a human-readable summary of the VM schema (available opcodes)
 pub fn schema_summary() -> &'static str {
@@ -0,0 +1,300 @@
+//! Config file support
+//!
+//! Settings are read from `.llcraft.toml`, searched from the current
+//! directory upward, and from `~/.config/llcraft/config.toml`. The nearest
+//! file wins field by field; CLI flags override both.
+//!
+//! ```toml
+//! provider = "openai"
+//! model = "gpt-4o-mini"
+//! session_dir = ".sessions"
+//! max_iterations = 20
+//! max_total_tokens = 200000
+//! ```
+
+use llcraft_agent::AgentConfig;
+use llcraft_vm::{
+    AnthropicProvider, BridgeProvider, CompletionRequest, CompletionResponse, LlmProvider,
+    OpenAIProvider, ProviderConfig, ProviderError, ProviderType, StreamReceiver,
+};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Project-local config file name
+pub const CONFIG_FILE: &str = ".llcraft.toml";
+
+/// Default endpoint for a local Ollama server (OpenAI-compatible API)
+pub const OLLAMA_BASE_URL: &str = "http://localhost:11434/v1";
+
+/// Settings loaded from a config file (every field is optional)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Provider name: openai, anthropic, bridge, ollama
+    pub provider: Option<String>,
+    /// Model name passed to the provider
+    pub model: Option<String>,
+    /// Override the provider's API base URL
+    pub base_url: Option<String>,
+    /// Directory for session storage
+    pub session_dir: Option<String>,
+    /// Maximum LLM round-trips per run
+    pub max_iterations: Option<usize>,
+    /// Token budget per run
+    pub max_total_tokens: Option<usize>,
+}
+
+impl FileConfig {
+    /// Parse a single config file
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Load config for the current directory and user
+    pub fn load() -> Result<Self, String> {
+        let cwd = std::env::current_dir().map_err(|e| e.to_string())?;
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        Self::discover(&cwd, home.as_deref())
+    }
+
+    /// Merge every `.llcraft.toml` from `start` upward, then the global file
+    /// under `home`. Nearer files take precedence.
+    pub fn discover(start: &Path, home: Option<&Path>) -> Result<Self, String> {
+        let mut config = Self::default();
+
+        for dir in start.ancestors() {
+            let path = dir.join(CONFIG_FILE);
+            if path.is_file() {
+                config = config.or(Self::from_path(&path)?);
+            }
+        }
+
+        if let Some(home) = home {
+            let path = home.join(".config").join("llcraft").join("config.toml");
+            if path.is_file() {
+                config = config.or(Self::from_path(&path)?);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Fill unset fields from `other`
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            provider: self.provider.or(other.provider),
+            model: self.model.or(other.model),
+            base_url: self.base_url.or(other.base_url),
+            session_dir: self.session_dir.or(other.session_dir),
+            max_iterations: self.max_iterations.or(other.max_iterations),
+            max_total_tokens: self.max_total_tokens.or(other.max_total_tokens),
+        }
+    }
+
+    /// Build an agent config from these settings
+    pub fn agent_config(&self) -> AgentConfig {
+        let mut config = AgentConfig::default();
+        if let Some(dir) = &self.session_dir {
+            config.session_dir = dir.clone();
+        }
+        if let Some(max) = self.max_iterations {
+            config.max_iterations = max;
+        }
+        if self.max_total_tokens.is_some() {
+            config.max_total_tokens = self.max_total_tokens;
+        }
+        config
+    }
+
+    /// Resolve the provider settings, reading API keys from the environment
+    pub fn provider_config(&self) -> Result<ProviderConfig, String> {
+        self.provider_config_with_env(|key| std::env::var(key).ok())
+    }
+
+    /// Resolve the provider settings using `env` to look up API keys
+    pub fn provider_config_with_env(
+        &self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<ProviderConfig, String> {
+        let require_key = |var: &str, name: &str| {
+            env(var)
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| format!("Provider '{}' requires an API key: set {}", name, var))
+        };
+
+        let name = self.provider.as_deref().unwrap_or("bridge");
+        let mut config = match name {
+            "openai" => ProviderConfig::openai(require_key("OPENAI_API_KEY", name)?),
+            "anthropic" => ProviderConfig::anthropic(require_key("ANTHROPIC_API_KEY", name)?),
+            "bridge" => ProviderConfig::bridge(),
+            "ollama" => {
+                let model = self
+                    .model
+                    .as_deref()
+                    .ok_or("Provider 'ollama' requires a model: pass --model <name>")?;
+                ProviderConfig::local(OLLAMA_BASE_URL, model)
+            }
+            other => {
+                return Err(format!(
+                    "Unknown provider '{}' (expected openai, anthropic, bridge or ollama)",
+                    other
+                ))
+            }
+        };
+
+        if let Some(model) = &self.model {
+            config = config.with_model(model);
+        }
+        if let Some(url) = &self.base_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("Invalid base URL '{}': expected http:// or https://", url));
+            }
+            config.base_url = Some(url.clone());
+        }
+        Ok(config)
+    }
+}
+
+/// Provider chosen at runtime from config
+pub enum CliProvider {
+    OpenAI(OpenAIProvider),
+    Anthropic(AnthropicProvider),
+    Bridge(BridgeProvider),
+}
+
+impl CliProvider {
+    pub fn new(config: ProviderConfig) -> Self {
+        match config.provider_type {
+            ProviderType::Anthropic => Self::Anthropic(AnthropicProvider::new(config)),
+            ProviderType::Bridge => Self::Bridge(BridgeProvider::new(config)),
+            _ => Self::OpenAI(OpenAIProvider::new(config)),
+        }
+    }
+}
+
+impl LlmProvider for CliProvider {
+    fn name(&self) -> &str {
+        match self {
+            Self::OpenAI(p) => p.name(),
+            Self::Anthropic(p) => p.name(),
+            Self::Bridge(p) => p.name(),
+        }
+    }
+
+    fn models(&self) -> Vec<String> {
+        match self {
+            Self::OpenAI(p) => p.models(),
+            Self::Anthropic(p) => p.models(),
+            Self::Bridge(p) => p.models(),
+        }
+    }
+
+    fn default_model(&self) -> &str {
+        match self {
+            Self::OpenAI(p) => p.default_model(),
+            Self::Anthropic(p) => p.default_model(),
+            Self::Bridge(p) => p.default_model(),
+        }
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        match self {
+            Self::OpenAI(p) => p.complete(request).await,
+            Self::Anthropic(p) => p.complete(request).await,
+            Self::Bridge(p) => p.complete(request).await,
+        }
+    }
+
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+        match self {
+            Self::OpenAI(p) => p.stream(request).await,
+            Self::Anthropic(p) => p.stream(request).await,
+            Self::Bridge(p) => p.stream(request).await,
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        match self {
+            Self::OpenAI(p) => p.list_models().await,
+            Self::Anthropic(p) => p.list_models().await,
+            Self::Bridge(p) => p.list_models().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_drives_agent_and_provider() {
+        let root = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        let nested = root.path().join("project").join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        std::fs::write(
+            root.path().join("project").join(CONFIG_FILE),
+            "provider = \"bridge\"\nmodel = \"local-model\"\nmax_iterations = 7\n",
+        )
+        .unwrap();
+        let global = home.path().join(".config").join("llcraft");
+        std::fs::create_dir_all(&global).unwrap();
+        std::fs::write(
+            global.join("config.toml"),
+            "model = \"global-model\"\nsession_dir = \"/tmp/sessions\"\nmax_total_tokens = 5000\n",
+        )
+        .unwrap();
+
+        let config = FileConfig::discover(&nested, Some(home.path())).unwrap();
+        assert_eq!(config.model.as_deref(), Some("local-model"));
+
+        let agent_config = config.agent_config();
+        assert_eq!(agent_config.max_iterations, 7);
+        assert_eq!(agent_config.session_dir, "/tmp/sessions");
+        assert_eq!(agent_config.max_total_tokens, Some(5000));
+
+        let provider = CliProvider::new(config.provider_config().unwrap());
+        assert_eq!(provider.name(), "bridge");
+        assert_eq!(provider.default_model(), "local-model");
+    }
+
+    #[test]
+    fn test_provider_validation() {
+        let no_env = |_: &str| None;
+        let config = |provider: &str| FileConfig {
+            provider: Some(provider.into()),
+            ..Default::default()
+        };
+
+        let err = config("openai").provider_config_with_env(no_env).unwrap_err();
+        assert!(err.contains("OPENAI_API_KEY"));
+        let err = config("ollama").provider_config_with_env(no_env).unwrap_err();
+        assert!(err.contains("--model"));
+        let err = config("gemini").provider_config_with_env(no_env).unwrap_err();
+        assert!(err.contains("Unknown provider 'gemini'"));
+
+        let resolved = config("anthropic")
+            .provider_config_with_env(|key| (key == "ANTHROPIC_API_KEY").then(|| "sk-test".into()))
+            .unwrap();
+        assert_eq!(resolved.provider_type, ProviderType::Anthropic);
+        assert_eq!(resolved.api_key.as_deref(), Some("sk-test"));
+
+        let bad_url = FileConfig {
+            base_url: Some("localhost:1234".into()),
+            ..Default::default()
+        };
+        assert!(bad_url.provider_config_with_env(no_env).is_err());
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        std::fs::write(&path, "max_iteration = 3\n").unwrap();
+
+        assert!(FileConfig::from_path(&path).unwrap_err().contains("max_iteration"));
+    }
+}
@@ -5,7 +5,21 @@
 //! Usage:
 //!   llcraft <task>
 //!   llcraft --session <id> <task>
-//!   llcraft program <file.json>
+//!   llcraft program <file.json> [--debug]
+//!   llcraft plan <task>
+//!   llcraft validate <file.json>
+//!   llcraft --json <task>
+//!   llcraft --no-color <task>
+//!   llcraft --output run.json <task>
+//!   llcraft --answer-schema answer.schema.json <task>
+//!   echo <task> | llcraft run -
+//!   llcraft --provider openai --model gpt-4o-mini <task>
+//!   llcraft session <show|info|delete> <id>
+//!   llcraft schema [--format text|json|markdown]
+//!   llcraft completions <bash|zsh|fish|powershell>
+//!   llcraft repl [--session <id>]
+//!
+//! Settings are also read from `.llcraft.toml` (see [`config`]).
 //!
 //! Examples:
 //!   llcraft "Read Cargo.toml and list the dependencies"
@@ -13,13 +27,21 @@
 //!   llcraft -s demo "What is the version of this package?"
 //!   llcraft program examples/ralph.json
 
-use clap::{Parser, Subcommand};
-use llcraft_agent::{Agent, AgentConfig};
+mod config;
+mod spinner;
+mod style;
+
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use config::{CliProvider, FileConfig};
+use spinner::Spinner;
+use llcraft_agent::{Agent, AgentConfig, AgentEvent, AgentResult, CancellationToken, OutputFormat};
 use llcraft_vm::{
-    BridgeProvider, DefaultSyscallHandler, ExecutionResult, Interpreter, LlmProvider,
-    LlmRequest, LlmRequestType, Program, ChatMessage, CompletionRequest,
+    DefaultSyscallHandler, ExecutionResult, ExecutionStep, Interpreter, LlmProvider,
+    LlmRequest, LlmRequestType, PageChange, PageChangeKind, Program, ChatMessage, CompletionRequest, RunMetrics, SessionManager, VmSchema,
 };
 use std::collections::HashMap;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "llcraft")]
@@ -43,6 +65,83 @@ struct Cli {
     /// Quiet mode - only show final answer
     #[arg(short, long, global = true)]
     quiet: bool,
+
+    /// Emit a single JSON object on stdout (for piping into jq or other tools)
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// LLM backend: openai, anthropic, bridge or ollama (default: bridge)
+    #[arg(long, global = true)]
+    provider: Option<String>,
+
+    /// Model name passed to the provider
+    #[arg(long, global = true)]
+    model: Option<String>,
+
+    /// Override the provider's API base URL
+    #[arg(long, global = true)]
+    base_url: Option<String>,
+
+    /// Always ask the LLM for a fresh program, even for a repeated task
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Disable colored output (also disabled by NO_COLOR or when piped)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Also write the full run (answer, result, pages, trace, usage) to this file as JSON
+    #[arg(short, long, global = true)]
+    output: Option<PathBuf>,
+
+    /// Let HTTP_REQUEST reach this host (repeatable; no hosts by default)
+    #[arg(long = "allow-host", value_name = "HOST", global = true)]
+    allow_hosts: Vec<String>,
+
+    /// Let GET_ENV read this variable (repeatable; none by default)
+    #[arg(long = "allow-env", value_name = "NAME", global = true)]
+    allow_env: Vec<String>,
+
+    /// JSON schema file the final answer must match; the answer is printed as JSON
+    #[arg(long, value_name = "FILE", global = true)]
+    answer_schema: Option<PathBuf>,
+}
+
+/// How a task run reports its results
+struct RunOptions<'a> {
+    session_id: Option<&'a str>,
+    verbose: bool,
+    quiet: bool,
+    json: bool,
+    /// With `--json`, the JSON goes here instead of stdout
+    output: Option<&'a Path>,
+    /// Schema the final answer must match
+    answer_schema: Option<&'a Path>,
+}
+
+impl Cli {
+    /// Overlay provider flags on top of the loaded config file
+    fn file_config(&self, file: FileConfig) -> FileConfig {
+        FileConfig {
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            base_url: self.base_url.clone(),
+            ..Default::default()
+        }
+        .or(file)
+    }
+
+    /// Output flags for a task run
+    fn run_options(&self) -> RunOptions<'_> {
+        RunOptions {
+            session_id: self.session.as_deref(),
+            verbose: self.verbose,
+            quiet: self.quiet,
+            json: self.json,
+            output: self.output.as_deref(),
+            answer_schema: self.answer_schema.as_deref(),
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -62,11 +161,80 @@ enum Commands {
         /// Maximum execution steps (default: 1000)
         #[arg(short, long, default_value = "1000")]
         max_steps: usize,
+
+        /// Step through the program one opcode at a time
+        #[arg(long)]
+        debug: bool,
+
+        /// Don't run EXEC, WRITE_FILE or HTTP_REQUEST; log what they would do
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check a program JSON file for errors without running it
+    Validate {
+        /// Path to the program JSON file
+        #[arg(required = true)]
+        file: String,
+    },
+    /// Generate a program for a task without running it
+    Plan {
+        /// The task description
+        #[arg(trailing_var_arg = true, required = true)]
+        task: Vec<String>,
     },
     /// List existing sessions
     Sessions,
+    /// Inspect or manage a single session
+    Session {
+        #[command(subcommand)]
+        action: SessionAction,
+    },
+    /// Interactive mode: read tasks line by line against one agent
+    Repl,
+    /// List the models the provider offers
+    Models,
     /// Show VM schema (available opcodes)
-    Schema,
+    Schema {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = SchemaFormat::Markdown)]
+        format: SchemaFormat,
+    },
+    /// Print a shell completion script
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaFormat {
+    /// Opcode names with one-line descriptions
+    Text,
+    /// Machine-readable opcode reference
+    Json,
+    /// Full VM specification (the agent's system prompt)
+    Markdown,
+}
+
+#[derive(Subcommand)]
+enum SessionAction {
+    /// Show the page index with summaries and token totals
+    Show {
+        /// Session ID
+        id: String,
+    },
+    /// Show session metadata (task, status, steps, LLM calls, timestamps)
+    Info {
+        /// Session ID
+        id: String,
+    },
+    /// Delete a session and all of its pages
+    Delete {
+        /// Session ID
+        id: String,
+    },
 }
 
 fn truncate(s: &str, max_len: usize) -> String {
@@ -155,21 +323,112 @@ fn extract_answer(
     }
 }
 
-async fn run_task(task: &str, session_id: Option<&str>, verbose: bool, quiet: bool) {
+/// Resolve the task from positional args, reading it from stdin when the
+/// args are empty or `-` and stdin is not a terminal
+fn resolve_task(args: &[String], stdin_is_tty: bool, mut stdin: impl Read) -> Option<String> {
+    let from_stdin = args.is_empty() || args == ["-"];
+    if !from_stdin {
+        return Some(args.join(" "));
+    }
+    if stdin_is_tty {
+        return None;
+    }
+
+    let mut task = String::new();
+    stdin.read_to_string(&mut task).ok()?;
+    let task = task.trim();
+    (!task.is_empty()).then(|| task.to_string())
+}
+
+/// Resolve the task against the process stdin
+fn task_from_args(args: &[String]) -> Option<String> {
+    let stdin = std::io::stdin();
+    resolve_task(args, stdin.is_terminal(), stdin.lock())
+}
+
+/// The schema-checked answer if the run had a schema, else the best-effort text
+fn final_answer(agent_result: &AgentResult) -> serde_json::Value {
+    match &agent_result.output {
+        Some(output) => output.clone(),
+        None => extract_answer(&agent_result.result, &agent_result.pages).into(),
+    }
+}
+
+/// Read the `--answer-schema` file, exiting on error
+fn read_output_format(path: Option<&Path>) -> OutputFormat {
+    let Some(path) = path else {
+        return OutputFormat::default();
+    };
+    let schema = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()));
+    match schema {
+        Ok(schema) => OutputFormat::from_schema(schema),
+        Err(e) => {
+            eprintln!("Error reading schema {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Build the `--json` output object for a finished run
+fn json_output(agent_result: &AgentResult, trace: &[ExecutionStep]) -> serde_json::Value {
+    serde_json::json!({
+        "answer": final_answer(agent_result),
+        "result": agent_result.result,
+        "pages": agent_result.pages,
+        "trace": trace,
+        "usage": agent_result.usage,
+        "cancelled": agent_result.cancelled,
+    })
+}
+
+/// Write a run's JSON to the `--output` file
+fn write_output(path: &Path, value: &serde_json::Value) {
+    let json = serde_json::to_string_pretty(value).unwrap_or_default();
+    if let Err(e) = std::fs::write(path, json) {
+        eprintln!("Error: failed to write {}: {}", path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/// Print a JSON error object and exit nonzero
+fn json_error(error: &str) -> ! {
+    println!("{}", serde_json::json!({ "error": error }));
+    std::process::exit(1);
+}
+
+async fn run_task(task: &str, config: AgentConfig, provider: CliProvider, options: RunOptions<'_>) {
+    let RunOptions { session_id, verbose, quiet, json, output, answer_schema } = options;
+    let quiet = quiet || json;
+    let format = read_output_format(answer_schema);
+
     if !quiet {
         println!();
     }
 
     let config = AgentConfig {
         verbose: !quiet,
-        session_dir: ".llcraft_sessions".to_string(),
+        trace_memory: verbose,
+        ..config
     };
 
-    let mut agent = Agent::with_config(config);
+    // Ctrl-C stops the run between LLM calls; pages produced so far are kept
+    let cancel = CancellationToken::new();
+    let on_ctrl_c = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            on_ctrl_c.cancel();
+        }
+    });
+
+    let agent = Agent::with_provider_and_config(provider, config).with_cancel_token(cancel);
+    let mut agent = with_spinner(agent, !quiet);
 
     if let Some(sid) = session_id {
         match agent.with_session(Some(sid)) {
             Ok(a) => agent = a,
+            Err(e) if json => json_error(&format!("Failed to initialize session: {}", e)),
             Err(e) => {
                 eprintln!("Failed to initialize session: {}", e);
                 return;
@@ -177,16 +436,57 @@ async fn run_task(task: &str, session_id: Option<&str>, verbose: bool, quiet: bo
         }
     }
 
-    match agent.run(task).await {
+    let outcome = agent.run_with_format(task, &format).await;
+    if let Some(path) = output {
+        match &outcome {
+            Ok(agent_result) => write_output(path, &json_output(agent_result, agent.trace())),
+            Err(e) => write_output(path, &serde_json::json!({ "error": e })),
+        }
+    }
+
+    if json && output.is_some() {
+        // Everything went to the file; keep stdout clean
+        if outcome.is_err() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    match outcome {
+        Ok(agent_result) if json => {
+            println!("{}", json_output(&agent_result, agent.trace()));
+            return;
+        }
+        Err(e) if json => json_error(&e),
+        Ok(agent_result) if agent_result.cancelled => {
+            let mut pages: Vec<_> = agent_result.pages.keys().map(String::as_str).collect();
+            pages.sort_unstable();
+            eprintln!("\n{} kept pages: [{}]", style::err().error("Run cancelled;"), pages.join(", "));
+            std::process::exit(130);
+        }
         Ok(agent_result) => {
             if !quiet {
-                println!("\n--- FINAL ANSWER ---\n");
+                println!("\n{}\n", style::out().header("--- FINAL ANSWER ---"));
+            }
+
+            match &agent_result.output {
+                Some(output) => println!("{}", serde_json::to_string_pretty(output).unwrap_or_default()),
+                None => println!("{}", extract_answer(&agent_result.result, &agent_result.pages)),
             }
 
-            let answer = extract_answer(&agent_result.result, &agent_result.pages);
-            println!("{}", answer);
+            if !quiet {
+                let stats = format!(
+                    "{} LLM calls, {} steps, {} tokens in {:.1}s",
+                    agent_result.llm_calls,
+                    agent_result.steps,
+                    agent_result.usage.total_tokens,
+                    agent_result.duration.as_secs_f64()
+                );
+                println!("\n{}", style::out().dim(&stats));
+            }
 
             if verbose {
+                print_metrics(&agent_result.metrics);
                 println!("\nRaw Result:");
                 println!(
                     "{}",
@@ -209,32 +509,245 @@ async fn run_task(task: &str, session_id: Option<&str>, verbose: bool, quiet: bo
             }
         }
         Err(e) => {
-            eprintln!("Error: {}", e);
+            drop(agent);
+            eprintln!("{} {}", style::err().error("Error:"), e);
             std::process::exit(1);
         }
     }
 
     if !quiet {
-        println!("\n--- Execution Trace ({} steps) ---", agent.trace().len());
+        print_trace_header(agent.trace().len());
         for step in agent.trace() {
-            let err = step
-                .error
-                .as_ref()
-                .map(|e| format!(" {}", e))
-                .unwrap_or_default();
-            println!(
-                "  {:3}. {} -> {}{}",
-                step.step,
-                step.opcode,
-                truncate(&step.result, 50),
-                err
-            );
+            print_step(step, verbose);
+        }
+        if verbose {
+            print_slowest_steps(agent.trace());
         }
     }
 }
 
-fn list_sessions() {
-    let session_dir = ".llcraft_sessions";
+fn print_trace_header(steps: usize) {
+    let header = format!("--- Execution Trace ({} steps) ---", steps);
+    println!("\n{}", style::out().header(&header));
+}
+
+/// Print one trace line, with the opcode highlighted and any error in red.
+/// Verbose lines also show how long the step took and the pages it changed.
+fn print_step(step: &ExecutionStep, verbose: bool) {
+    let palette = style::out();
+    let err = step
+        .error
+        .as_ref()
+        .map(|e| format!(" {}", palette.error(e)))
+        .unwrap_or_default();
+    let timing = if verbose {
+        format!(" {}", palette.dim(&format_timing(step)))
+    } else {
+        String::new()
+    };
+    println!(
+        "  {:3}. {} -> {}{}{}",
+        step.step,
+        palette.opcode(&step.opcode),
+        truncate(&step.result, 50),
+        err,
+        timing
+    );
+    if verbose && !step.memory.is_empty() {
+        println!("       {}", palette.dim(&format!("→ {}", format_page_changes(&step.memory))));
+    }
+}
+
+/// "modified `analysis` (+1.2k tokens), freed `tmp` (-40 tokens)"
+fn format_page_changes(changes: &[PageChange]) -> String {
+    changes
+        .iter()
+        .map(|change| {
+            let kind = match change.kind {
+                PageChangeKind::Created => "created",
+                PageChangeKind::Modified => "modified",
+                PageChangeKind::Freed => "freed",
+            };
+            let size = change.token_delta.unsigned_abs();
+            let size = if size >= 1000 { format!("{:.1}k", size as f64 / 1000.0) } else { size.to_string() };
+            let sign = if change.token_delta < 0 { "-" } else { "+" };
+            format!("{} `{}` ({}{} tokens)", kind, change.page_id, sign, size)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn format_timing(step: &ExecutionStep) -> String {
+    match step.llm_ms {
+        Some(llm_ms) => format!("({:.1}ms, llm {:.1}ms)", step.duration_ms, llm_ms),
+        None => format!("({:.1}ms)", step.duration_ms),
+    }
+}
+
+/// The `n` steps that took longest, counting time spent waiting on the LLM
+fn slowest_steps(trace: &[ExecutionStep], n: usize) -> Vec<&ExecutionStep> {
+    let total = |step: &ExecutionStep| step.duration_ms + step.llm_ms.unwrap_or(0.0);
+    let mut steps: Vec<&ExecutionStep> = trace.iter().collect();
+    steps.sort_by(|a, b| total(b).total_cmp(&total(a)));
+    steps.truncate(n);
+    steps
+}
+
+fn print_slowest_steps(trace: &[ExecutionStep]) {
+    let slowest = slowest_steps(trace, 3);
+    if slowest.is_empty() {
+        return;
+    }
+    println!("\n{}", style::out().header("--- Slowest Steps ---"));
+    for step in slowest {
+        println!(
+            "  {:3}. {} {}",
+            step.step,
+            style::out().opcode(&step.opcode),
+            format_timing(step)
+        );
+    }
+}
+
+fn print_metrics(metrics: &RunMetrics) {
+    println!("\n{}", style::out().header("--- Metrics ---"));
+    println!("  steps: {}, LLM calls: {}, tool calls: {}", metrics.total_steps, metrics.infer_calls, metrics.tool_calls);
+    println!("  bytes read: {}, bytes written: {}, peak pages: {}", metrics.bytes_read, metrics.bytes_written, metrics.peak_pages);
+
+    let mut counts: Vec<_> = metrics.opcode_counts.iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let counts: Vec<_> = counts.iter().map(|(op, n)| format!("{} {}", style::out().opcode(op), n)).collect();
+    if !counts.is_empty() {
+        println!("  opcodes: {}", counts.join(", "));
+    }
+}
+
+/// Show a spinner on stderr from each LLM request until the next agent event
+fn with_spinner<P: LlmProvider>(agent: Agent<P>, enabled: bool) -> Agent<P> {
+    if !enabled {
+        return agent;
+    }
+    let mut spinner: Option<Spinner> = None;
+    agent.on_event(move |event| match event {
+        AgentEvent::LlmRequest { .. } => {
+            drop(spinner.take());
+            spinner = Some(Spinner::start("Waiting for LLM", true));
+        }
+        _ => drop(spinner.take()),
+    })
+}
+
+/// Start the interactive REPL on stdin/stdout
+async fn repl(config: AgentConfig, provider: CliProvider, session_id: Option<&str>, quiet: bool) {
+    let config = AgentConfig {
+        verbose: !quiet,
+        ..config
+    };
+
+    let mut agent = with_spinner(Agent::with_provider_and_config(provider, config), !quiet);
+    if let Some(sid) = session_id {
+        match agent.with_session(Some(sid)) {
+            Ok(a) => agent = a,
+            Err(e) => {
+                eprintln!("Failed to initialize session: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if !quiet {
+        println!("LLcraft REPL - enter a task, or :pages, :trace, :clear, :quit");
+    }
+
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    if let Err(e) = run_repl(&mut agent, stdin.lock(), &mut stdout).await {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Read tasks line by line and run each against the same agent, so pages
+/// from earlier tasks stay available to later ones. Stops at EOF or `:quit`.
+async fn run_repl<P: LlmProvider, R: BufRead, W: Write>(
+    agent: &mut Agent<P>,
+    input: R,
+    out: &mut W,
+) -> std::io::Result<()> {
+    write!(out, "> ")?;
+    out.flush()?;
+
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        match line {
+            "" => {}
+            ":quit" | ":q" => break,
+            ":pages" => {
+                let mut ids: Vec<_> = agent.pages().keys().collect();
+                ids.sort();
+                if ids.is_empty() {
+                    writeln!(out, "(no pages)")?;
+                }
+                for id in ids {
+                    let content = serde_json::to_string(&agent.pages()[id]).unwrap_or_default();
+                    writeln!(out, "  {}: {}", id, truncate(&content, 80))?;
+                }
+            }
+            ":trace" => {
+                if agent.trace().is_empty() {
+                    writeln!(out, "(no trace)")?;
+                }
+                for step in agent.trace() {
+                    writeln!(out, "  {:3}. {} -> {}", step.step, step.opcode, truncate(&step.result, 50))?;
+                }
+            }
+            ":clear" => {
+                agent.clear();
+                writeln!(out, "Cleared pages and trace")?;
+            }
+            task if task.starts_with(':') => {
+                writeln!(out, "Unknown command: {} (try :pages, :trace, :clear, :quit)", task)?;
+            }
+            task => match agent.run(task).await {
+                Ok(agent_result) => {
+                    writeln!(out, "{}", extract_answer(&agent_result.result, &agent_result.pages))?;
+                }
+                Err(e) => writeln!(out, "Error: {}", e)?,
+            },
+        }
+
+        write!(out, "> ")?;
+        out.flush()?;
+    }
+
+    writeln!(out)?;
+    Ok(())
+}
+
+async fn plan_task(task: &str, config: AgentConfig, provider: CliProvider, quiet: bool) {
+    let config = AgentConfig {
+        verbose: !quiet,
+        ..config
+    };
+
+    let mut agent = with_spinner(Agent::with_provider_and_config(provider, config), !quiet);
+
+    match agent.plan(task).await {
+        Ok(program) => {
+            // JSON on stdout so the plan can be saved and run with `llcraft program`
+            println!("{}", serde_json::to_string_pretty(&program).unwrap_or_default());
+        }
+        Err(e) => {
+            drop(agent);
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn list_sessions(session_dir: &str) {
     match std::fs::read_dir(session_dir) {
         Ok(entries) => {
             println!("Sessions in {}:", session_dir);
@@ -257,13 +770,275 @@ fn list_sessions() {
     }
 }
 
-fn show_schema() {
-    let schema = llcraft_agent::schema_summary();
-    println!("{}", schema);
+/// Open the session store and make sure the session exists, exiting on error
+fn open_session(session_dir: &str, id: &str) -> SessionManager {
+    let manager = match SessionManager::new(session_dir) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Error opening sessions in {}: {}", session_dir, e);
+            std::process::exit(1);
+        }
+    };
+    if !manager.session_exists(id) {
+        eprintln!("Error: session not found: {}", id);
+        std::process::exit(1);
+    }
+    manager
+}
+
+fn handle_session(session_dir: &str, action: SessionAction) {
+    match action {
+        SessionAction::Show { id } => show_session(session_dir, &id),
+        SessionAction::Info { id } => session_info(session_dir, &id),
+        SessionAction::Delete { id } => delete_session(session_dir, &id),
+    }
 }
 
-async fn run_program_file(file: &str, max_steps: usize, verbose: bool, quiet: bool) {
-    // Read and parse program
+fn show_session(session_dir: &str, id: &str) {
+    let manager = open_session(session_dir, id);
+    let session = match manager.load_session(id) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error loading session {}: {}", id, e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Session {}: {}", id, session.metadata.task);
+
+    let mut pages: Vec<_> = session.page_index.values().collect();
+    pages.sort_by(|a, b| a.id.cmp(&b.id));
+
+    if pages.is_empty() {
+        println!("  (no pages)");
+        return;
+    }
+
+    for idx in &pages {
+        println!("  - {} (~{} tokens): {}", idx.id, idx.tokens, idx.summary);
+    }
+    let total: usize = pages.iter().map(|idx| idx.tokens).sum();
+    println!("{} pages, ~{} tokens total", pages.len(), total);
+}
+
+fn session_info(session_dir: &str, id: &str) {
+    let manager = open_session(session_dir, id);
+    match manager.get_session_info(id) {
+        Ok(meta) => {
+            println!("Session:    {}", meta.id);
+            println!("Task:       {}", meta.task);
+            println!("Status:     {:?}", meta.status);
+            println!("Steps:      {}", meta.total_steps);
+            println!("LLM calls:  {}", meta.llm_calls);
+            println!("Created at: {}", meta.created_at);
+            println!("Updated at: {}", meta.updated_at);
+        }
+        Err(e) => {
+            eprintln!("Error loading session {}: {}", id, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn delete_session(session_dir: &str, id: &str) {
+    let manager = open_session(session_dir, id);
+    match manager.delete_session(id) {
+        Ok(()) => println!("Deleted session {}", id),
+        Err(e) => {
+            eprintln!("Error deleting session {}: {}", id, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn list_models(provider: CliProvider, json: bool) {
+    let models = match provider.list_models().await {
+        Ok(models) => models,
+        Err(e) if json => json_error(&e.to_string()),
+        Err(e) => {
+            eprintln!("Error listing models: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::json!({ "provider": provider.name(), "models": models }));
+        return;
+    }
+
+    let default = provider.default_model();
+    println!("Models for {}:", provider.name());
+    for model in &models {
+        let marker = if model == default { " (default)" } else { "" };
+        println!("  - {}{}", model, marker);
+    }
+}
+
+/// Command tree for completion scripts. clap_complete lists hidden
+/// subcommands too, so they are left out here.
+fn completion_command() -> clap::Command {
+    let cli = Cli::command();
+    clap::Command::new("llcraft")
+        .version(env!("CARGO_PKG_VERSION"))
+        .args(cli.get_arguments().cloned())
+        .subcommands(cli.get_subcommands().filter(|sub| !sub.is_hide_set()).cloned())
+}
+
+fn show_schema(format: SchemaFormat) {
+    let schema = VmSchema::new();
+    match format {
+        SchemaFormat::Text => print!("{}", schema.to_text()),
+        SchemaFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(&schema.to_json()).unwrap_or_default())
+        }
+        SchemaFormat::Markdown => println!("{}", schema.to_prompt()),
+    }
+}
+
+fn validate_program_file(file: &str) {
+    let program = read_program_file(file);
+
+    if let Err(errors) = program.validate() {
+        eprintln!("{} is invalid ({} errors):", file, errors.len());
+        for error in &errors {
+            eprintln!("  {}", error);
+        }
+        std::process::exit(1);
+    }
+
+    let labels = program.labels();
+    println!("{} is valid", file);
+    println!("  Opcodes:  {}", program.code.len());
+    println!("  Labels:   {}", if labels.is_empty() { "(none)".to_string() } else { labels.join(", ") });
+    println!(
+        "  Terminal: {}",
+        if program.has_terminal() { "yes" } else { "no (ends with implicit completion)" }
+    );
+}
+
+/// Run a program under the interactive debugger on stdin/stdout
+async fn debug_program_file(
+    file: &str,
+    provider: CliProvider,
+    handler: DefaultSyscallHandler,
+    max_steps: usize,
+    dry_run: bool,
+) {
+    let program = read_program_file(file);
+    println!("Debugging program: {} ({} opcodes)", program.name, program.code.len());
+    println!("Commands: <enter>/s step, c continue, p [page] print page(s), q quit\n");
+
+    let mut interp = Interpreter::new(program, handler)
+        .with_max_steps(max_steps)
+        .with_dry_run(dry_run)
+        .with_output_callback(|text| println!("{}", text));
+
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    match debug_program(&mut interp, &provider, stdin.lock(), &mut stdout).await {
+        Ok(Some(ExecutionResult::Complete(result))) => {
+            println!("\n{}\n", style::out().success("=== PROGRAM COMPLETE ==="));
+            println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
+        }
+        Ok(Some(ExecutionResult::Failed(error))) => {
+            eprintln!("\n{}\n", style::err().error("=== PROGRAM FAILED ==="));
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
+        }
+        Ok(Some(_)) => {
+            eprintln!("\n{}", style::err().error("=== STEP LIMIT EXCEEDED ==="));
+            std::process::exit(1);
+        }
+        Ok(None) => println!("Stopped at opcode {}", interp.pc()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Step through a program, reading debugger commands from `input`.
+///
+/// Returns the final execution result, or `None` if the user quit.
+async fn debug_program<P: LlmProvider, R: BufRead, W: Write>(
+    interp: &mut Interpreter<DefaultSyscallHandler>,
+    provider: &P,
+    input: R,
+    out: &mut W,
+) -> Result<Option<ExecutionResult>, String> {
+    let io_err = |e: std::io::Error| e.to_string();
+    let mut commands = input.lines();
+    let mut stepping = true;
+
+    loop {
+        if stepping {
+            match interp.current_opcode() {
+                Some(op) => writeln!(out, "[{}] {}", interp.pc(), op).map_err(io_err)?,
+                None => writeln!(out, "[{}] (end of program)", interp.pc()).map_err(io_err)?,
+            }
+
+            // Read commands until the user steps or continues
+            loop {
+                write!(out, "(debug) ").map_err(io_err)?;
+                out.flush().map_err(io_err)?;
+
+                let Some(line) = commands.next() else {
+                    writeln!(out).map_err(io_err)?;
+                    return Ok(None);
+                };
+                let line = line.map_err(io_err)?;
+                let mut parts = line.split_whitespace();
+
+                match (parts.next(), parts.next()) {
+                    (None, _) | (Some("s"), _) => break,
+                    (Some("c"), _) => {
+                        stepping = false;
+                        break;
+                    }
+                    (Some("q"), _) => return Ok(None),
+                    (Some("p"), None) => {
+                        let mut ids: Vec<_> = interp.all_pages().into_keys().collect();
+                        ids.sort();
+                        writeln!(out, "pages: [{}]", ids.join(", ")).map_err(io_err)?;
+                    }
+                    (Some("p"), Some(page_id)) => match interp.get_page(page_id) {
+                        Some(content) => writeln!(
+                            out,
+                            "{}",
+                            serde_json::to_string_pretty(content).unwrap_or_default()
+                        )
+                        .map_err(io_err)?,
+                        None => writeln!(out, "No such page: {}", page_id).map_err(io_err)?,
+                    },
+                    (Some(other), _) => {
+                        writeln!(out, "Unknown command: {} (s, c, p [page], q)", other)
+                            .map_err(io_err)?
+                    }
+                }
+            }
+        }
+
+        match interp.step().map_err(|e| e.to_string())? {
+            None => {
+                if stepping {
+                    if let Some(step) = interp.trace().last() {
+                        let err = step.error.as_ref().map(|e| format!(" ({})", e)).unwrap_or_default();
+                        writeln!(out, "    -> {}{}", truncate(&step.result, 60), err).map_err(io_err)?;
+                    }
+                    writeln!(out, "    pages: {}", interp.all_pages().len()).map_err(io_err)?;
+                }
+            }
+            Some(ExecutionResult::NeedsLlm(request)) => {
+                writeln!(out, "    LLM request: {:?}", request.request_type).map_err(io_err)?;
+                answer_llm_request(provider, &request, interp, true).await?;
+            }
+            Some(result) => return Ok(Some(result)),
+        }
+    }
+}
+
+/// Read and parse a program file, exiting on error
+fn read_program_file(file: &str) -> Program {
     let content = match std::fs::read_to_string(file) {
         Ok(c) => c,
         Err(e) => {
@@ -279,17 +1054,51 @@ async fn run_program_file(file: &str, max_steps: usize, verbose: bool, quiet: bo
             std::process::exit(1);
         }
     };
+    if !program.has_includes() {
+        return program;
+    }
+
+    // Included programs are looked up among the JSON files next to this one
+    let dir = std::path::Path::new(file)
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(std::path::Path::new("."));
+    match Program::load_dir(dir).and_then(|registry| program.resolve_includes(&registry)) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Error resolving includes: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_program_file(
+    file: &str,
+    provider: CliProvider,
+    handler: DefaultSyscallHandler,
+    max_steps: usize,
+    dry_run: bool,
+    options: &RunOptions<'_>,
+) {
+    let (verbose, quiet, output) = (options.verbose, options.quiet, options.output);
+    let program = read_program_file(file);
 
     if !quiet {
         println!("Running program: {} ({})", program.name, program.id);
         println!("Description: {}", program.description.as_deref().unwrap_or("(no description)"));
         println!("Opcodes: {}", program.code.len());
         println!("Max steps: {}", max_steps);
+        if dry_run {
+            println!("Dry run: EXEC, WRITE_FILE and HTTP_REQUEST will not run");
+        }
         println!();
     }
 
     // Create interpreter
-    let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+    let mut interp = Interpreter::new(program, handler)
+        .with_max_steps(max_steps)
+        .with_dry_run(dry_run)
+        .with_memory_trace(verbose);
 
     if verbose {
         interp = interp.with_log_callback(|level, msg| {
@@ -297,29 +1106,27 @@ async fn run_program_file(file: &str, max_steps: usize, verbose: bool, quiet: bo
         });
     }
 
-    // Create LLM provider for handling INFER/PLAN/REFLECT/INJECT
-    let provider = BridgeProvider::local();
-
-    // Track steps manually
-    let mut total_steps = 0;
-
     // Run the program
     loop {
-        if total_steps >= max_steps {
-            eprintln!("\n=== STEP LIMIT EXCEEDED ===");
-            eprintln!("Program did not complete within {} steps", max_steps);
-            std::process::exit(1);
-        }
-
         match interp.run() {
             Ok(ExecutionResult::Complete(result)) => {
                 if !quiet {
-                    println!("\n=== PROGRAM COMPLETE ===\n");
+                    println!("\n{}\n", style::out().success("=== PROGRAM COMPLETE ==="));
                 }
                 println!("{}", serde_json::to_string_pretty(&result).unwrap_or_default());
 
+                if let Some(path) = output {
+                    let pages = interp.all_pages();
+                    write_output(path, &serde_json::json!({
+                        "answer": extract_answer(&result, &pages),
+                        "result": result,
+                        "pages": pages,
+                        "trace": interp.trace(),
+                    }));
+                }
+
                 if verbose {
-                    println!("\n--- Pages ---");
+                    println!("\n{}", style::out().header("--- Pages ---"));
                     for (id, content) in interp.all_pages() {
                         println!("  {}: {}", id, truncate(&serde_json::to_string(&content).unwrap_or_default(), 80));
                     }
@@ -327,51 +1134,22 @@ async fn run_program_file(file: &str, max_steps: usize, verbose: bool, quiet: bo
                 break;
             }
             Ok(ExecutionResult::Failed(error)) => {
-                eprintln!("\n=== PROGRAM FAILED ===\n");
+                if let Some(path) = output {
+                    write_output(path, &serde_json::json!({ "error": error, "trace": interp.trace() }));
+                }
+                eprintln!("\n{}\n", style::err().error("=== PROGRAM FAILED ==="));
                 eprintln!("Error: {}", error);
                 std::process::exit(1);
             }
             Ok(ExecutionResult::StepLimitExceeded) => {
-                eprintln!("\n=== STEP LIMIT EXCEEDED ===");
+                eprintln!("\n{}", style::err().error("=== STEP LIMIT EXCEEDED ==="));
                 eprintln!("Program did not complete within {} steps", max_steps);
                 std::process::exit(1);
             }
             Ok(ExecutionResult::NeedsLlm(request)) => {
-                if !quiet {
-                    println!("   LLM Request: {:?}", request.request_type);
-                    println!("      Prompt: {}", truncate(&request.prompt, 60));
-                }
-
-                // Handle the LLM request
-                let response = handle_llm_request(&provider, &request, &interp, quiet).await;
-
-                match response {
-                    Ok(value) => {
-                        if let LlmRequestType::Inject { .. } = &request.request_type {
-                            // For INJECT, parse and inject opcodes
-                            let opcodes = parse_opcodes(&value);
-                            match interp.inject_opcodes(opcodes) {
-                                Ok(count) => {
-                                    if !quiet {
-                                        println!("      Injected {} opcodes", count);
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Error injecting opcodes: {}", e);
-                                    std::process::exit(1);
-                                }
-                            }
-                        } else {
-                            if let Err(e) = interp.provide_llm_response(value, &request.store_to) {
-                                eprintln!("Error providing LLM response: {}", e);
-                                std::process::exit(1);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("LLM error: {}", e);
-                        std::process::exit(1);
-                    }
+                if let Err(e) = answer_llm_request(&provider, &request, &mut interp, quiet).await {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
                 }
             }
             Err(e) => {
@@ -379,27 +1157,60 @@ async fn run_program_file(file: &str, max_steps: usize, verbose: bool, quiet: bo
                 std::process::exit(1);
             }
         }
-        total_steps += 1;
     }
 
     if !quiet {
-        println!("\n--- Execution Trace ({} steps) ---", interp.trace().len());
+        print_trace_header(interp.trace().len());
         for step in interp.trace().iter().take(50) {
-            println!(
-                "  {:3}. {} -> {}",
-                step.step,
-                step.opcode,
-                truncate(&step.result, 50)
-            );
+            print_step(step, verbose);
         }
         if interp.trace().len() > 50 {
             println!("  ... ({} more steps)", interp.trace().len() - 50);
         }
+        if verbose {
+            print_slowest_steps(interp.trace());
+            print_metrics(&interp.metrics());
+        }
     }
 }
 
-async fn handle_llm_request(
-    provider: &BridgeProvider,
+/// Answer a pending LLM request and feed the result back into the interpreter
+async fn answer_llm_request<P: LlmProvider>(
+    provider: &P,
+    request: &LlmRequest,
+    interp: &mut Interpreter<DefaultSyscallHandler>,
+    quiet: bool,
+) -> Result<(), String> {
+    if !quiet {
+        println!("   LLM Request: {:?}", request.request_type);
+        println!("      Prompt: {}", truncate(&request.prompt, 60));
+    }
+
+    let started = std::time::Instant::now();
+    let value = handle_llm_request(provider, request, interp, quiet)
+        .await
+        .map_err(|e| format!("LLM error: {}", e))?;
+    interp.record_llm_time(started.elapsed());
+
+    if let LlmRequestType::Inject { .. } = &request.request_type {
+        // For INJECT, parse and inject opcodes
+        let opcodes = parse_opcodes(&value);
+        let count = interp
+            .inject_opcodes(opcodes)
+            .map_err(|e| format!("Error injecting opcodes: {}", e))?;
+        if !quiet {
+            println!("      Injected {} opcodes", count);
+        }
+    } else {
+        interp
+            .provide_llm_response(value, &request.store_to)
+            .map_err(|e| format!("Error providing LLM response: {}", e))?;
+    }
+    Ok(())
+}
+
+async fn handle_llm_request<P: LlmProvider>(
+    provider: &P,
     request: &LlmRequest,
     interp: &Interpreter<DefaultSyscallHandler>,
     quiet: bool,
@@ -407,18 +1218,22 @@ async fn handle_llm_request(
     // Build context from pages
     let mut context = String::new();
     for page_id in &request.context_pages {
-        if let Some(content) = interp.get_page(page_id) {
+        if let Some(content) = request.page(page_id) {
             context.push_str(&format!("### Page: {}\n{}\n\n", page_id, content));
         }
     }
 
     let prompt = match &request.request_type {
-        LlmRequestType::Infer => {
-            if context.is_empty() {
+        LlmRequestType::Infer { params, .. } => {
+            let mut prompt = if context.is_empty() {
                 request.prompt.clone()
             } else {
                 format!("{}\n\n## Context:\n{}", request.prompt, context)
+            };
+            if let Some(schema) = &params.schema {
+                prompt.push_str(&format!("\n\nRespond with only JSON matching this schema:\n{}", schema));
             }
+            prompt
         }
         LlmRequestType::Plan => {
             format!(
@@ -428,8 +1243,9 @@ async fn handle_llm_request(
         }
         LlmRequestType::Reflect { include_trace } => {
             let trace_text = if *include_trace {
-                let trace: Vec<String> = interp
-                    .trace()
+                let trace: Vec<String> = request
+                    .execution_state
+                    .trace
                     .iter()
                     .map(|s| format!("{}: {} -> {}", s.step, s.opcode, s.result))
                     .collect();
@@ -502,12 +1318,24 @@ Return ONLY a valid JSON array. No markdown, no explanation."#,
         }
     };
 
-    let completion_request = CompletionRequest::new(vec![ChatMessage::user(prompt)]);
+    let mut messages = Vec::new();
+    if let LlmRequestType::Infer { history_page: Some(page_id), .. } = &request.request_type {
+        messages = request.history_messages(page_id).map_err(|e| e.to_string())?;
+    }
+    messages.push(ChatMessage::user(prompt));
+
+    let mut completion_request = CompletionRequest::new(messages);
+    if let LlmRequestType::Infer { params, .. } = &request.request_type {
+        completion_request = completion_request.with_infer_params(params);
+    }
 
+    let spinner = Spinner::start("Waiting for LLM", !quiet);
     let response = provider
         .complete(completion_request)
         .await
-        .map_err(|e| format!("LLM error: {:?}", e))?;
+        .map_err(|e| format!("LLM error: {:?}", e));
+    drop(spinner);
+    let response = response?.reject_filtered().map_err(|e| format!("LLM error: {}", e))?;
 
     let content = response.content.ok_or("Empty LLM response")?;
 
@@ -579,55 +1407,329 @@ fn parse_opcodes(value: &serde_json::Value) -> Vec<llcraft_vm::Opcode> {
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
+    style::init(cli.no_color || cli.json);
+
+    let file_config = match FileConfig::load() {
+        Ok(file) => cli.file_config(file),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut agent_config = file_config.agent_config();
+    agent_config.cache_programs = !cli.no_cache;
+    agent_config.allowed_hosts = cli.allow_hosts.clone();
+    agent_config.allowed_env = cli.allow_env.clone();
+    let syscalls = || {
+        DefaultSyscallHandler::default()
+            .with_allowed_hosts(cli.allow_hosts.clone())
+            .with_allowed_env(cli.allow_env.clone())
+    };
+    let provider = || match file_config.provider_config() {
+        Ok(config) => CliProvider::new(config),
+        Err(e) if cli.json => json_error(&e),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // Handle subcommands
     match cli.command {
         Some(Commands::Sessions) => {
-            list_sessions();
+            list_sessions(&agent_config.session_dir);
+            return;
+        }
+        Some(Commands::Session { action }) => {
+            handle_session(&agent_config.session_dir, action);
+            return;
+        }
+        Some(Commands::Models) => {
+            list_models(provider(), cli.json).await;
+            return;
+        }
+        Some(Commands::Schema { format }) => {
+            show_schema(format);
+            return;
+        }
+        Some(Commands::Completions { shell }) => {
+            clap_complete::generate(shell, &mut completion_command(), "llcraft", &mut std::io::stdout());
             return;
         }
-        Some(Commands::Schema) => {
-            show_schema();
+        Some(Commands::Repl) => {
+            repl(agent_config, provider(), cli.session.as_deref(), cli.quiet).await;
             return;
         }
-        Some(Commands::Program { file, max_steps }) => {
+        Some(Commands::Program { file, max_steps, debug: true, dry_run }) => {
+            debug_program_file(&file, provider(), syscalls(), max_steps, dry_run).await;
+            return;
+        }
+        Some(Commands::Program { ref file, max_steps, dry_run, .. }) => {
             if !cli.quiet {
                 println!("LLcraft VM - Running program from file\n");
             }
-            run_program_file(&file, max_steps, cli.verbose, cli.quiet).await;
+            run_program_file(file, provider(), syscalls(), max_steps, dry_run, &cli.run_options()).await;
             return;
         }
-        Some(Commands::Run { task }) => {
-            let task_str = task.join(" ");
-            if !cli.quiet {
-                println!("LLcraft Agent - Your AI's operating system\n");
-            }
-            run_task(&task_str, cli.session.as_deref(), cli.verbose, cli.quiet).await;
+        Some(Commands::Validate { file }) => {
+            validate_program_file(&file);
+            return;
+        }
+        Some(Commands::Plan { task }) => {
+            plan_task(&task.join(" "), agent_config, provider(), cli.quiet).await;
             return;
         }
-        None => {
-            // Default: treat remaining args as task
-            if cli.task.is_empty() {
-                eprintln!("Error: No task provided.");
-                eprintln!("Usage: llcraft [OPTIONS] <TASK>...");
-                eprintln!("       llcraft run <TASK>...");
-                eprintln!("       llcraft program <FILE.json>");
-                eprintln!("       llcraft sessions");
-                eprintln!("       llcraft schema");
-                eprintln!("\nExamples:");
-                eprintln!("  llcraft \"Read Cargo.toml and list dependencies\"");
-                eprintln!("  llcraft -s demo \"Read Cargo.toml\"");
-                eprintln!("  llcraft program examples/ralph.json");
-                eprintln!("  llcraft --help");
+        Some(Commands::Run { ref task }) => {
+            let Some(task_str) = task_from_args(task) else {
+                if cli.json {
+                    json_error("No task provided");
+                }
+                eprintln!("Error: No task provided (stdin was empty).");
                 std::process::exit(1);
+            };
+            if !cli.quiet && !cli.json {
+                println!("LLcraft Agent - Your AI's operating system\n");
             }
+            run_task(&task_str, agent_config, provider(), cli.run_options())
+            .await;
+            return;
         }
+        None => {}
     }
 
-    // Default: run task from positional args
-    let task_str = cli.task.join(" ");
-    if !cli.quiet {
+    // Default: treat remaining args (or piped stdin) as task
+    let Some(task_str) = task_from_args(&cli.task) else {
+        if cli.json {
+            json_error("No task provided");
+        }
+        eprintln!("Error: No task provided.");
+        eprintln!("Usage: llcraft [OPTIONS] <TASK>...");
+        eprintln!("       llcraft run <TASK>...");
+        eprintln!("       echo <TASK> | llcraft run -");
+        eprintln!("       llcraft program <FILE.json>");
+        eprintln!("       llcraft plan <TASK>...");
+        eprintln!("       llcraft validate <FILE.json>");
+        eprintln!("       llcraft sessions");
+        eprintln!("       llcraft session <show|info|delete> <ID>");
+        eprintln!("       llcraft repl");
+        eprintln!("       llcraft schema");
+        eprintln!("\nExamples:");
+        eprintln!("  llcraft \"Read Cargo.toml and list dependencies\"");
+        eprintln!("  llcraft -s demo \"Read Cargo.toml\"");
+        eprintln!("  llcraft program examples/ralph.json");
+        eprintln!("  llcraft --help");
+        std::process::exit(1);
+    };
+
+    if !cli.quiet && !cli.json {
         println!("LLcraft Agent - Your AI's operating system\n");
     }
-    run_task(&task_str, cli.session.as_deref(), cli.verbose, cli.quiet).await;
-}
\ No newline at end of file
+    run_task(&task_str, agent_config, provider(), cli.run_options()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_output_shape() {
+        let mut pages = HashMap::new();
+        pages.insert("answer".to_string(), serde_json::json!({"response": "42"}));
+        let agent_result = AgentResult {
+            result: serde_json::json!({"page": "answer"}),
+            pages,
+            usage: Default::default(),
+            llm_calls: 2,
+            steps: 3,
+            duration: std::time::Duration::from_millis(5),
+            metrics: Default::default(),
+            output: None,
+            cancelled: false,
+        };
+        let trace = vec![ExecutionStep {
+            step: 0,
+            opcode: "INFER".into(),
+            result: "ok".into(),
+            error: None,
+            duration_ms: 1.5,
+            llm_ms: Some(120.0),
+            stack: Vec::new(),
+            memory: Vec::new(),
+        }];
+
+        let out = json_output(&agent_result, &trace);
+        let parsed: serde_json::Value = serde_json::from_str(&out.to_string()).unwrap();
+
+        assert!(parsed["answer"].as_str().unwrap().contains("42"));
+        assert_eq!(parsed["result"]["page"], "answer");
+        assert_eq!(parsed["pages"]["answer"]["response"], "42");
+        assert_eq!(parsed["trace"][0]["opcode"], "INFER");
+        assert_eq!(parsed["usage"]["total_tokens"], 0);
+        assert_eq!(parsed["trace"][0]["llm_ms"], 120.0);
+    }
+
+    #[test]
+    fn test_slowest_steps_include_llm_time() {
+        let step = |n: usize, duration_ms: f64, llm_ms: Option<f64>| ExecutionStep {
+            step: n,
+            opcode: format!("OP{}", n),
+            result: String::new(),
+            error: None,
+            duration_ms,
+            llm_ms,
+            stack: Vec::new(),
+            memory: Vec::new(),
+        };
+        let trace = vec![step(0, 5.0, None), step(1, 0.1, Some(900.0)), step(2, 40.0, None), step(3, 1.0, None)];
+
+        let slowest: Vec<usize> = slowest_steps(&trace, 3).iter().map(|s| s.step).collect();
+        assert_eq!(slowest, vec![1, 2, 0]);
+        assert_eq!(format_timing(&trace[1]), "(0.1ms, llm 900.0ms)");
+    }
+
+    #[test]
+    fn test_format_page_changes() {
+        let change = |page_id: &str, kind, token_delta| PageChange { page_id: page_id.into(), kind, token_delta };
+        let changes = vec![
+            change("analysis", PageChangeKind::Modified, 1234),
+            change("scratch", PageChangeKind::Freed, -8),
+        ];
+        assert_eq!(format_page_changes(&changes), "modified `analysis` (+1.2k tokens), freed `scratch` (-8 tokens)");
+    }
+
+    #[test]
+    fn test_provider_flags_override_config_file() {
+        let cli = Cli::try_parse_from([
+            "llcraft", "--provider", "ollama", "--model", "llama3", "run", "hello",
+        ])
+        .unwrap();
+        let file = FileConfig {
+            provider: Some("openai".into()),
+            model: Some("gpt-4o".into()),
+            max_iterations: Some(3),
+            ..Default::default()
+        };
+
+        let merged = cli.file_config(file);
+        assert_eq!(merged.max_iterations, Some(3));
+        let provider = merged.provider_config_with_env(|_| None).unwrap();
+        assert_eq!(provider.provider_type, llcraft_vm::ProviderType::Local);
+        assert_eq!(provider.default_model.as_deref(), Some("llama3"));
+        assert_eq!(provider.base_url.as_deref(), Some(config::OLLAMA_BASE_URL));
+
+        let cli = Cli::try_parse_from(["llcraft", "--base-url", "http://localhost:9000", "hi"]).unwrap();
+        let provider = cli
+            .file_config(FileConfig::default())
+            .provider_config_with_env(|_| None)
+            .unwrap();
+        assert_eq!(provider.provider_type, llcraft_vm::ProviderType::Bridge);
+        assert_eq!(provider.base_url.as_deref(), Some("http://localhost:9000"));
+    }
+
+    #[test]
+    fn test_resolve_task_from_stdin() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        // Args win over stdin
+        assert_eq!(
+            resolve_task(&args(&["list", "files"]), false, "ignored".as_bytes()).as_deref(),
+            Some("list files")
+        );
+        // Piped stdin with no args or `-`
+        assert_eq!(
+            resolve_task(&args(&[]), false, "  summarize README\n".as_bytes()).as_deref(),
+            Some("summarize README")
+        );
+        assert_eq!(
+            resolve_task(&args(&["-"]), false, "multi\nline".as_bytes()).as_deref(),
+            Some("multi\nline")
+        );
+        // Empty stdin or a terminal means no task
+        assert_eq!(resolve_task(&args(&[]), false, "\n".as_bytes()), None);
+        assert_eq!(resolve_task(&args(&["-"]), true, "task".as_bytes()), None);
+    }
+
+    #[tokio::test]
+    async fn test_debugger_scripted_commands() {
+        let program: Program = serde_json::from_str(
+            r#"{"id": "d", "name": "D", "description": "", "code": [
+                {"op": "STORE", "page_id": "a", "data": {"n": 1}},
+                {"op": "INFER", "prompt": "hi", "store_to": "answer"},
+                {"op": "STORE", "page_id": "b", "data": 2},
+                {"op": "COMPLETE", "result": "done"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        let provider = llcraft_vm::MockProvider::with_responses(["hello"]);
+
+        // step, inspect a page, poke at a bad command, then continue to the end
+        let commands = "s\np a\np missing\nx\np\nc\n";
+        let mut out = Vec::new();
+        let result = debug_program(&mut interp, &provider, commands.as_bytes(), &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(matches!(result, Some(ExecutionResult::Complete(_))));
+        assert!(out.contains("[0] STORE a"));
+        assert!(out.contains("\"n\": 1"));
+        assert!(out.contains("No such page: missing"));
+        assert!(out.contains("Unknown command: x"));
+        assert!(out.contains("pages: [a]"));
+        assert_eq!(interp.get_page("answer").unwrap()["response"], "hello");
+        assert_eq!(interp.get_page("b"), Some(&serde_json::json!(2)));
+    }
+
+    #[tokio::test]
+    async fn test_debugger_quit_stops_execution() {
+        let program = Program::new(
+            "q",
+            "Q",
+            vec![
+                llcraft_vm::Opcode::Store { page_id: "a".into(), data: serde_json::json!(1) },
+                llcraft_vm::Opcode::Store { page_id: "b".into(), data: serde_json::json!(2) },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        let provider = llcraft_vm::MockProvider::new();
+
+        let mut out = Vec::new();
+        let result = debug_program(&mut interp, &provider, "\nq\n".as_bytes(), &mut out)
+            .await
+            .unwrap();
+
+        assert!(result.is_none());
+        assert_eq!(interp.pc(), 1);
+        assert!(interp.get_page("b").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_repl_scripted_session() {
+        let store = r#"{"id": "a", "name": "A", "description": "", "code": [
+            {"op": "STORE", "page_id": "notes", "data": {"content": "hello"}},
+            {"op": "COMPLETE", "result": {"page": "notes"}}
+        ]}"#;
+        let recall = r#"{"id": "b", "name": "B", "description": "", "code": [
+            {"op": "COPY", "src": "notes", "dst": "again"},
+            {"op": "COMPLETE", "result": {"page": "again"}}
+        ]}"#;
+        let provider = llcraft_vm::MockProvider::with_responses([store, recall]);
+        let config = AgentConfig { verbose: false, ..Default::default() };
+        let mut agent = Agent::with_provider_and_config(provider, config);
+
+        let input = "store a note\n:pages\nrecall it\n:trace\n:clear\n:pages\n:quit\nnever run\n";
+        let mut out = Vec::new();
+        run_repl(&mut agent, input.as_bytes(), &mut out).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("--- notes ---\nhello"));
+        assert!(out.contains("  notes: {\"content\":\"hello\"}"));
+        assert!(out.contains("--- again ---\nhello"));
+        assert!(out.contains("COPY"));
+        assert!(out.contains("Cleared pages and trace"));
+        assert!(out.contains("(no pages)"));
+        // :quit stops before the last line reaches the provider
+        assert_eq!(agent.provider().call_count(), 2);
+    }
+}
@@ -0,0 +1,63 @@
+//! Progress spinner shown on stderr while waiting for the LLM
+//!
+//! Drawn only when stderr is a terminal, so piped output and `--json` runs
+//! never see it. The line is cleared when the spinner is dropped.
+
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const TICK: Duration = Duration::from_millis(100);
+
+/// A running spinner; stops and clears its line on drop
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start spinning with `message`, unless disabled or stderr isn't a terminal
+    pub fn start(message: &str, enabled: bool) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        if !enabled || !std::io::stderr().is_terminal() {
+            return Self { stop, handle: None };
+        }
+
+        let message = message.to_string();
+        let flag = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut stderr = std::io::stderr();
+            for frame in FRAMES.iter().cycle() {
+                if flag.load(Ordering::Relaxed) {
+                    break;
+                }
+                let _ = write!(
+                    stderr,
+                    "\r{} {} ({:.1}s)",
+                    frame,
+                    message,
+                    started.elapsed().as_secs_f64()
+                );
+                let _ = stderr.flush();
+                std::thread::sleep(TICK);
+            }
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        });
+
+        Self { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! ANSI colors for terminal output
+//!
+//! Color is used only when the stream is a terminal, `NO_COLOR` is unset and
+//! `--no-color` wasn't passed. `--json` output is never colored.
+
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static PALETTES: OnceLock<(Palette, Palette)> = OnceLock::new();
+
+/// Styling for one output stream (a no-op when color is off)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    /// Decide whether to color a stream
+    pub fn detect(no_color_flag: bool, no_color_env: Option<&str>, is_tty: bool) -> Self {
+        let env_disabled = no_color_env.is_some_and(|v| !v.is_empty());
+        Self { enabled: is_tty && !no_color_flag && !env_disabled }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Opcode names in traces
+    pub fn opcode(&self, text: &str) -> String {
+        self.paint("1;36", text)
+    }
+
+    /// Section headers like `--- FINAL ANSWER ---`
+    pub fn header(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn success(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    pub fn error(&self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    /// Secondary details like run stats
+    pub fn dim(&self, text: &str) -> String {
+        self.paint("2", text)
+    }
+}
+
+/// Detect colors for stdout and stderr; call once at startup
+pub fn init(no_color: bool) {
+    let env = std::env::var("NO_COLOR").ok();
+    let out = Palette::detect(no_color, env.as_deref(), std::io::stdout().is_terminal());
+    let err = Palette::detect(no_color, env.as_deref(), std::io::stderr().is_terminal());
+    let _ = PALETTES.set((out, err));
+}
+
+/// Palette for stdout (plain until `init` runs)
+pub fn out() -> Palette {
+    PALETTES.get().map(|p| p.0).unwrap_or(Palette { enabled: false })
+}
+
+/// Palette for stderr (plain until `init` runs)
+pub fn err() -> Palette {
+    PALETTES.get().map(|p| p.1).unwrap_or(Palette { enabled: false })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_detection() {
+        assert!(Palette::detect(false, None, true).enabled);
+        assert!(!Palette::detect(true, None, true).enabled);
+        assert!(!Palette::detect(false, Some("1"), true).enabled);
+        assert!(Palette::detect(false, Some(""), true).enabled);
+        assert!(!Palette::detect(false, None, false).enabled);
+
+        let on = Palette::detect(false, None, true);
+        assert_eq!(on.error("boom"), "\x1b[31mboom\x1b[0m");
+        assert_eq!(Palette::detect(true, None, true).error("boom"), "boom");
+    }
+}
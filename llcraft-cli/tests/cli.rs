@@ -0,0 +1,281 @@
+//! Integration tests for the `llcraft` binary
+
+use std::process::Command;
+
+fn llcraft() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_llcraft"))
+}
+
+#[test]
+fn test_json_error_is_valid_json() {
+    let output = llcraft().arg("--json").output().unwrap();
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let parsed: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert_eq!(parsed["error"], "No task provided");
+}
+
+#[test]
+fn test_blank_piped_stdin_is_no_task() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = llcraft()
+        .args(["--json", "run", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"  \n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(!output.status.success());
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(parsed["error"], "No task provided");
+}
+
+fn seed_session(dir: &std::path::Path, id: &str) {
+    use llcraft_vm::{MemoryPage, Session, SessionManager};
+
+    let manager = SessionManager::new(dir.join(".llcraft_sessions")).unwrap();
+    let mut session = Session::new(id, "demo task");
+    let page = MemoryPage::new("notes", serde_json::json!("some notes"));
+    session.index_page(&page, Some("Notes page".into()));
+    manager.save_page(id, &page).unwrap();
+    manager.save_session(&session).unwrap();
+}
+
+#[test]
+fn test_session_show_and_info() {
+    let dir = tempfile::tempdir().unwrap();
+    seed_session(dir.path(), "demo");
+
+    let output = llcraft()
+        .args(["session", "show", "demo"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("notes"));
+    assert!(stdout.contains("Notes page"));
+
+    let output = llcraft()
+        .args(["session", "info", "demo"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("demo task"));
+    assert!(stdout.contains("Active"));
+}
+
+#[test]
+fn test_session_delete() {
+    let dir = tempfile::tempdir().unwrap();
+    seed_session(dir.path(), "doomed");
+
+    let output = llcraft()
+        .args(["session", "delete", "doomed"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!dir.path().join(".llcraft_sessions/doomed").exists());
+}
+
+#[test]
+fn test_session_missing_id() {
+    let dir = tempfile::tempdir().unwrap();
+
+    for action in ["show", "info", "delete"] {
+        let output = llcraft()
+            .args(["session", action, "nope"])
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("session not found: nope"));
+    }
+}
+
+#[test]
+fn test_validate_valid_program() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("ok.json");
+    std::fs::write(
+        &file,
+        r#"{"id": "ok", "name": "OK", "code": [
+            {"op": "LABEL", "name": "start"},
+            {"op": "COMPLETE", "result": {}}
+        ]}"#,
+    )
+    .unwrap();
+
+    let output = llcraft().arg("validate").arg(&file).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("is valid"));
+    assert!(stdout.contains("Opcodes:  2"));
+}
+
+#[test]
+fn test_validate_broken_program() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("broken.json");
+    std::fs::write(
+        &file,
+        r#"{"id": "bad", "name": "Bad", "code": [
+            {"op": "STORE", "page_id": "x", "data": 1},
+            {"op": "JUMP", "target": "missing"}
+        ]}"#,
+    )
+    .unwrap();
+
+    let output = llcraft().arg("validate").arg(&file).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("opcode 1: jump to undefined label 'missing'"));
+}
+
+#[test]
+fn test_program_max_steps() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("spin.json");
+    std::fs::write(
+        &file,
+        r#"{"id": "spin", "name": "Spin", "code": [
+            {"op": "LABEL", "name": "top"},
+            {"op": "JUMP", "target": "top"}
+        ]}"#,
+    )
+    .unwrap();
+
+    let output = llcraft()
+        .current_dir(dir.path())
+        .args(["program", "--max-steps", "5"])
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Program did not complete within 5 steps"), "{}", stderr);
+}
+
+#[test]
+fn test_schema_json_format() {
+    let output = llcraft().args(["schema", "--format", "json"]).output().unwrap();
+
+    assert!(output.status.success());
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let opcodes = parsed["opcodes"].as_array().unwrap();
+    assert!(opcodes.iter().any(|op| op["name"] == "INFER"));
+}
+
+#[test]
+fn test_no_color_output_is_plain() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("hello.json");
+    std::fs::write(
+        &file,
+        r#"{"id": "hello", "name": "Hello", "code": [
+            {"op": "STORE", "page_id": "greeting", "data": "hi"},
+            {"op": "COMPLETE", "result": {"page": "greeting"}}
+        ]}"#,
+    )
+    .unwrap();
+
+    // Piped output is plain without any flag; --no-color forces it regardless
+    for args in [vec!["program"], vec!["--no-color", "program"]] {
+        let output = llcraft().args(args).arg(&file).output().unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.contains("PROGRAM COMPLETE"));
+        assert!(stdout.contains("STORE"));
+        assert!(!stdout.contains('\x1b'));
+    }
+}
+
+#[test]
+fn test_json_mode_has_no_spinner_output() {
+    // No LLM is reachable, so the run fails after one request attempt
+    let output = llcraft()
+        .args(["--json", "--base-url", "http://127.0.0.1:9", "summarize", "this"])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains('\r'));
+    assert!(!stdout.contains("Waiting for LLM"));
+    let parsed: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    assert!(parsed["error"].as_str().unwrap().contains("LLM error"));
+}
+
+#[test]
+fn test_output_file_records_run() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("hello.json");
+    let out = dir.path().join("run.json");
+    std::fs::write(
+        &file,
+        r#"{"id": "hello", "name": "Hello", "code": [
+            {"op": "STORE", "page_id": "greeting", "data": {"content": "hi"}},
+            {"op": "COMPLETE", "result": {"page": "greeting"}}
+        ]}"#,
+    )
+    .unwrap();
+
+    let output = llcraft()
+        .args(["--quiet", "--output"])
+        .arg(&out)
+        .arg("program")
+        .arg(&file)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    // The human result still goes to stdout
+    assert!(String::from_utf8(output.stdout).unwrap().contains("greeting"));
+
+    let run: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+    assert_eq!(run["result"]["page"], "greeting");
+    assert_eq!(run["pages"]["greeting"]["content"], "hi");
+    assert!(run["answer"].as_str().unwrap().contains("hi"));
+    assert_eq!(run["trace"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_json_with_output_keeps_stdout_clean() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("run.json");
+
+    let output = llcraft()
+        .args(["--json", "--base-url", "http://127.0.0.1:9", "--output"])
+        .arg(&out)
+        .arg("summarize this")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let run: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+    assert!(run["error"].as_str().unwrap().contains("LLM error"));
+}
+
+#[test]
+fn test_completions_for_each_shell() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = llcraft().args(["completions", shell]).output().unwrap();
+        assert!(output.status.success(), "{}", shell);
+        let script = String::from_utf8(output.stdout).unwrap();
+        assert!(script.contains("llcraft"), "{}", shell);
+        assert!(script.contains("schema"), "{}", shell);
+        if shell == "bash" {
+            // The hidden subcommand doesn't complete to itself
+            assert!(!script.contains("completions)"));
+        }
+    }
+}
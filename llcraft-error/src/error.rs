@@ -37,18 +37,15 @@ pub struct Error {
 }
 
 impl Error {
-    /// Create a new error with the given kind and message
+    /// Create a new error with the given kind and message.
+    ///
+    /// The status starts at `kind.default_status()`; override it with
+    /// `with_status`, `temporary` or `permanent`.
     pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
-        let status = if kind.is_retryable() {
-            ErrorStatus::Temporary
-        } else {
-            ErrorStatus::Permanent
-        };
-
         Self {
             kind,
             message: message.into(),
-            status,
+            status: kind.default_status(),
             operation: "",
             context: Vec::new(),
             source: None,
@@ -89,6 +86,22 @@ impl Error {
         self.source.as_ref()
     }
 
+    /// Iterate over this error and its causes, following `source()`
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn std::error::Error + 'static)> {
+        std::iter::successors(Some(self as &(dyn std::error::Error + 'static)), |err| err.source())
+    }
+
+    /// Context key-values accumulated across the chain, outermost first.
+    ///
+    /// Unlike [`Error::context`], this also includes the context of any
+    /// llcraft `Error` wrapped as a source.
+    pub fn contexts(&self) -> Vec<(&str, &str)> {
+        self.chain()
+            .filter_map(|err| err.downcast_ref::<Error>())
+            .flat_map(|err| err.context.iter().map(|(key, value)| (*key, value.as_str())))
+            .collect()
+    }
+
     // =========================================================================
     // Builders (chainable)
     // =========================================================================
@@ -182,6 +195,34 @@ impl fmt::Display for Error {
     }
 }
 
+// =============================================================================
+// JSON - structured format for machine-consumable logs
+// =============================================================================
+
+impl Error {
+    /// Serialize the error as a JSON object for structured logging.
+    ///
+    /// Shape: `{kind, status, operation, context: {..}, message, source}`.
+    /// `source` is `null` when no source error is set. Repeated context
+    /// keys keep the last value.
+    pub fn to_json(&self) -> serde_json::Value {
+        let context: serde_json::Map<String, serde_json::Value> = self
+            .context
+            .iter()
+            .map(|(key, value)| (key.to_string(), serde_json::Value::String(value.clone())))
+            .collect();
+
+        serde_json::json!({
+            "kind": self.kind.as_str(),
+            "status": self.status.as_str(),
+            "operation": self.operation,
+            "context": context,
+            "message": self.message,
+            "source": self.source.as_ref().map(|source| source.to_string()),
+        })
+    }
+}
+
 // =============================================================================
 // Debug - verbose, multi-line format for debugging
 // =============================================================================
@@ -390,6 +431,54 @@ mod tests {
         assert_eq!(err.kind(), ErrorKind::SyscallFailed);
     }
 
+    #[test]
+    fn test_to_json() {
+        let err = Error::new(ErrorKind::InferenceFailed, "model unavailable")
+            .with_operation("provider::infer")
+            .with_context("model", "claude-3")
+            .with_context("attempt", "3");
+
+        let json = err.to_json();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "InferenceFailed",
+                "status": "temporary",
+                "operation": "provider::infer",
+                "context": {"model": "claude-3", "attempt": "3"},
+                "message": "model unavailable",
+                "source": null,
+            })
+        );
+
+        let io_err = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        let err = Error::from(io_err);
+        assert_eq!(err.to_json()["source"], "missing");
+        assert_eq!(err.to_json()["kind"], "FileNotFound");
+    }
+
+    #[test]
+    fn test_chain_and_contexts() {
+        let io_err = std::io::Error::other("disk full");
+        let inner = Error::new(ErrorKind::StorageFailed, "write failed")
+            .with_context("key", "pages/notes")
+            .set_source(io_err);
+        let outer = Error::new(ErrorKind::Unexpected, "checkpoint failed")
+            .with_operation("interpreter::checkpoint")
+            .with_context("name", "before_edit")
+            .set_source(inner);
+
+        let chain: Vec<String> = outer.chain().map(|err| err.to_string()).collect();
+        assert_eq!(chain.len(), 3);
+        assert!(chain[1].contains("write failed"));
+        assert_eq!(chain[2], "disk full");
+
+        assert_eq!(
+            outer.contexts(),
+            vec![("name", "before_edit"), ("key", "pages/notes")]
+        );
+    }
+
     #[test]
     fn test_set_source() {
         let io_err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
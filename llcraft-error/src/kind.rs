@@ -1,5 +1,6 @@
 //! Error kinds for llcraft operations
 
+use crate::ErrorStatus;
 use std::fmt;
 
 /// The kind of error that occurred.
@@ -202,16 +203,55 @@ impl ErrorKind {
         }
     }
 
+    /// The status an error of this kind starts with.
+    ///
+    /// Transient failures (inference, network, IO, rate limits, timeouts)
+    /// are `Temporary`; everything else needs an external change to succeed.
+    pub fn default_status(&self) -> ErrorStatus {
+        match self {
+            // Transient
+            ErrorKind::InferenceFailed
+            | ErrorKind::ProviderUnavailable
+            | ErrorKind::RateLimited
+            | ErrorKind::SyscallTimeout
+            | ErrorKind::IoFailed
+            | ErrorKind::NetworkFailed => ErrorStatus::Temporary,
+
+            // Needs a fix to the program, input or environment
+            ErrorKind::Unexpected
+            | ErrorKind::Unsupported
+            | ErrorKind::ConfigInvalid
+            | ErrorKind::PageNotFound
+            | ErrorKind::PageOverflow
+            | ErrorKind::InvalidRange
+            | ErrorKind::StackOverflow
+            | ErrorKind::StackUnderflow
+            | ErrorKind::StorageNotFound
+            | ErrorKind::StorageFailed
+            | ErrorKind::SerializationFailed
+            | ErrorKind::ProgramNotFound
+            | ErrorKind::InvalidLabel
+            | ErrorKind::CallDepthExceeded
+            | ErrorKind::NoReturnAddress
+            | ErrorKind::InvalidOpcode
+            | ErrorKind::SyscallFailed
+            | ErrorKind::SyscallUnknown
+            | ErrorKind::ProcessNotFound
+            | ErrorKind::ChannelClosed
+            | ErrorKind::ForkFailed
+            | ErrorKind::ContextTooLarge
+            | ErrorKind::FileNotFound
+            | ErrorKind::PermissionDenied
+            | ErrorKind::ParseFailed
+            | ErrorKind::AssertionFailed
+            | ErrorKind::InvalidArgument
+            | ErrorKind::NotImplemented => ErrorStatus::Permanent,
+        }
+    }
+
     /// Check if this error kind is retryable by default
     pub fn is_retryable(&self) -> bool {
-        matches!(
-            self,
-            ErrorKind::InferenceFailed
-                | ErrorKind::NetworkFailed
-                | ErrorKind::RateLimited
-                | ErrorKind::SyscallTimeout
-                | ErrorKind::ProviderUnavailable
-        )
+        self.default_status().is_retryable()
     }
 }
 
@@ -238,4 +278,14 @@ mod tests {
         assert!(!ErrorKind::PageNotFound.is_retryable());
         assert!(!ErrorKind::StackUnderflow.is_retryable());
     }
+
+    #[test]
+    fn test_default_status() {
+        assert_eq!(ErrorKind::InferenceFailed.default_status(), ErrorStatus::Temporary);
+        assert_eq!(ErrorKind::NetworkFailed.default_status(), ErrorStatus::Temporary);
+        assert_eq!(ErrorKind::IoFailed.default_status(), ErrorStatus::Temporary);
+        assert_eq!(ErrorKind::PageNotFound.default_status(), ErrorStatus::Permanent);
+        assert_eq!(ErrorKind::FileNotFound.default_status(), ErrorStatus::Permanent);
+        assert_eq!(ErrorKind::InvalidOpcode.default_status(), ErrorStatus::Permanent);
+    }
 }
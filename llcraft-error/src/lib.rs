@@ -22,19 +22,33 @@
 //! }
 //! ```
 //!
+//! [`err!`] and [`bail!`] build the same thing more briefly:
+//!
+//! ```rust
+//! use llcraft_error::{bail, Result};
+//!
+//! fn example(page_id: &str) -> Result<()> {
+//!     bail!(PageNotFound, "page '{}' not loaded", page_id; "page_id" => page_id);
+//! }
+//! ```
+//!
 //! ## Principles
 //!
 //! - All functions return `Result<T, llcraft_error::Error>`
 //! - External errors are wrapped with `set_source(err)`
 //! - Same error handled once, subsequent ops only append context
 //! - Don't abuse `From<OtherError>` to prevent raw error leakage
+//! - Retry only `Temporary` errors, e.g. with [`retry_with_backoff`]
 
 mod error;
 mod kind;
+mod macros;
+mod retry;
 mod status;
 
 pub use error::Error;
 pub use kind::ErrorKind;
+pub use retry::{retry_with_backoff, RetryPolicy};
 pub use status::ErrorStatus;
 
 /// Result type alias using llcraft Error
@@ -0,0 +1,75 @@
+//! Shorthand for building errors
+
+/// Build an [`Error`](crate::Error) from a kind, a format string and
+/// optional `key => value` context pairs (after a `;`).
+///
+/// ```rust
+/// use llcraft_error::{err, Error, ErrorKind};
+///
+/// let page = "context";
+/// let short = err!(PageNotFound, "page '{}' not loaded", page; "page_id" => page, "attempt" => 2);
+/// let long = Error::new(ErrorKind::PageNotFound, format!("page '{}' not loaded", page))
+///     .with_context("page_id", page)
+///     .with_context("attempt", "2");
+///
+/// assert_eq!(short.to_json(), long.to_json());
+/// ```
+#[macro_export]
+macro_rules! err {
+    ($kind:ident, $fmt:literal $(, $arg:expr)* $(; $($key:literal => $value:expr),+)?) => {
+        $crate::Error::new($crate::ErrorKind::$kind, format!($fmt $(, $arg)*))
+            $($(.with_context($key, ($value).to_string()))+)?
+    };
+}
+
+/// Return early with an error built by [`err!`].
+///
+/// ```rust
+/// use llcraft_error::{bail, ErrorKind, Result};
+///
+/// fn check(tokens: usize) -> Result<()> {
+///     if tokens > 100 {
+///         bail!(ContextTooLarge, "{} tokens is over the limit", tokens; "limit" => 100);
+///     }
+///     Ok(())
+/// }
+///
+/// let err = check(150).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::ContextTooLarge);
+/// assert_eq!(err.context(), [("limit", "100".to_string())]);
+/// ```
+#[macro_export]
+macro_rules! bail {
+    ($($tt:tt)*) => {
+        return ::core::result::Result::Err($crate::err!($($tt)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, ErrorKind, Result};
+
+    #[test]
+    fn test_macros_match_manual_errors() {
+        let id = "summary";
+        assert_eq!(
+            err!(InferenceFailed, "empty response").to_json(),
+            Error::new(ErrorKind::InferenceFailed, "empty response").to_json()
+        );
+        assert_eq!(
+            err!(PageNotFound, "page '{id}' missing"; "page_id" => id).to_json(),
+            Error::new(ErrorKind::PageNotFound, "page 'summary' missing")
+                .with_context("page_id", "summary")
+                .to_json()
+        );
+
+        fn fails() -> Result<u32> {
+            bail!(InvalidArgument, "bad value {}", 7; "field" => "n", "max" => 5);
+        }
+        let err = fails().unwrap_err();
+        let manual = Error::new(ErrorKind::InvalidArgument, "bad value 7")
+            .with_context("field", "n")
+            .with_context("max", "5");
+        assert_eq!(err.to_json(), manual.to_json());
+    }
+}
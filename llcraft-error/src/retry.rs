@@ -0,0 +1,200 @@
+//! Retry helper driven by `ErrorStatus`
+
+use crate::{Error, Result};
+use std::time::Duration;
+
+/// How often and how patiently to retry a failing operation.
+///
+/// Delays grow exponentially: `initial_delay * multiplier^attempt`,
+/// capped at `max_delay`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub max_attempts: usize,
+    /// Delay before the first retry
+    pub initial_delay: Duration,
+    /// Upper bound for any single delay
+    pub max_delay: Duration,
+    /// Growth factor between retries
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy with the given number of attempts and default delays
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    /// Set the delay before the first retry
+    pub fn with_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Set the upper bound for any single delay
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Set the growth factor between retries; values below 1 (and NaN)
+    /// become 1, so delays never shrink
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Delay to wait after the given failed attempt (0-indexed). A delay too
+    /// large to represent, or one from a nonsensical multiplier, is
+    /// `max_delay`.
+    pub fn delay_for(&self, attempt: usize) -> Duration {
+        let factor = self.multiplier.powi(attempt.min(i32::MAX as usize) as i32);
+        Duration::try_from_secs_f64(self.initial_delay.as_secs_f64() * factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+/// Run `f` until it succeeds, its error is not retryable, or the policy
+/// runs out of attempts.
+///
+/// Whether an error is retried follows its status, which starts at
+/// `ErrorKind::default_status` unless the error overrides it.
+///
+/// A temporary error that survives every attempt is returned as
+/// `Persistent`, with the attempt count added to its context.
+pub fn retry_with_backoff<F, T>(policy: RetryPolicy, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let attempts = policy.max_attempts.max(1);
+    let mut attempt = 0;
+
+    loop {
+        let err: Error = match f() {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        attempt += 1;
+        if !err.is_retryable() {
+            return Err(err);
+        }
+        if attempt >= attempts {
+            return Err(err.persist().with_context("attempts", attempt.to_string()));
+        }
+
+        std::thread::sleep(policy.delay_for(attempt - 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorKind, ErrorStatus};
+
+    fn fast(max_attempts: usize) -> RetryPolicy {
+        RetryPolicy::new(max_attempts).with_initial_delay(Duration::ZERO)
+    }
+
+    #[test]
+    fn test_permanent_error_is_not_retried() {
+        let mut calls = 0;
+        let result: Result<()> = retry_with_backoff(fast(5), || {
+            calls += 1;
+            Err(Error::page_not_found("context"))
+        });
+
+        assert_eq!(calls, 1);
+        assert_eq!(result.unwrap_err().status(), ErrorStatus::Permanent);
+    }
+
+    #[test]
+    fn test_temporary_error_eventually_succeeds() {
+        let mut calls = 0;
+        let result = retry_with_backoff(fast(5), || {
+            calls += 1;
+            if calls < 3 {
+                Err(Error::new(ErrorKind::NetworkFailed, "connection reset"))
+            } else {
+                Ok(calls)
+            }
+        });
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_retries_follow_default_status_unless_overridden() {
+        let mut calls = 0;
+        let _: Result<()> = retry_with_backoff(fast(3), || {
+            calls += 1;
+            Err(Error::new(ErrorKind::IoFailed, "disk busy"))
+        });
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let _: Result<()> = retry_with_backoff(fast(3), || {
+            calls += 1;
+            Err(Error::new(ErrorKind::IoFailed, "disk full").permanent())
+        });
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_exhausted_retries_become_persistent() {
+        let mut calls = 0;
+        let result: Result<()> = retry_with_backoff(fast(2), || {
+            calls += 1;
+            Err(Error::new(ErrorKind::NetworkFailed, "connection reset"))
+        });
+
+        let err = result.unwrap_err();
+        assert_eq!(calls, 2);
+        assert_eq!(err.status(), ErrorStatus::Persistent);
+        assert!(err.context().contains(&("attempts", "2".to_string())));
+    }
+
+    #[test]
+    fn test_delay_grows_and_caps() {
+        let policy = RetryPolicy::new(10)
+            .with_initial_delay(Duration::from_millis(100))
+            .with_max_delay(Duration::from_millis(350));
+
+        assert_eq!(policy.delay_for(0), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(1), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_delay_does_not_overflow() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay_for(70), policy.max_delay);
+        assert_eq!(policy.delay_for(usize::MAX), policy.max_delay);
+
+        for multiplier in [-2.0, f64::NAN, 0.5] {
+            let policy = RetryPolicy::default().with_multiplier(multiplier);
+            assert_eq!(policy.multiplier, 1.0);
+            assert_eq!(policy.delay_for(5), policy.initial_delay);
+        }
+
+        // Set directly, a bad multiplier still can't panic
+        let policy = RetryPolicy { multiplier: -3.0, ..Default::default() };
+        assert_eq!(policy.delay_for(1), policy.max_delay);
+        let policy = RetryPolicy { multiplier: f64::INFINITY, ..Default::default() };
+        assert_eq!(policy.delay_for(3), policy.max_delay);
+    }
+}
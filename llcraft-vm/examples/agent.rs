@@ -376,7 +376,7 @@ impl Agent {
 
         // Build the full prompt based on request type
         let prompt = match &request.request_type {
-            LlmRequestType::Infer => {
+            LlmRequestType::Infer { .. } => {
                 if context.is_empty() {
                     request.prompt.clone()
                 } else {
@@ -0,0 +1,81 @@
+//! # Embeddings
+//!
+//! Semantic page search ranks stored pages by how close their summaries are
+//! to a query. `Embedder` abstracts how text becomes a vector so a real
+//! embedding model can be plugged in; `HashEmbedder` is the dependency-free
+//! default, a hashed bag of words.
+
+use crate::error::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Turns text into a vector for similarity search
+pub trait Embedder: Send + Sync {
+    /// Embedding of `text`
+    fn embed(&self, text: &str) -> Result<Vec<f32>>;
+}
+
+/// Bag-of-words embedder: each lowercased word is hashed into one of `dims`
+/// buckets. Finds pages that share words with the query, nothing deeper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashEmbedder {
+    dims: usize,
+}
+
+impl HashEmbedder {
+    /// Embedder with 256 buckets
+    pub fn new() -> Self {
+        Self { dims: 256 }
+    }
+
+    /// Embedder with a custom number of buckets
+    pub fn with_dims(dims: usize) -> Self {
+        Self { dims: dims.max(1) }
+    }
+}
+
+impl Default for HashEmbedder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Embedder for HashEmbedder {
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let mut vector = vec![0.0; self.dims];
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            let mut hasher = DefaultHasher::new();
+            word.to_lowercase().hash(&mut hasher);
+            vector[(hasher.finish() % self.dims as u64) as usize] += 1.0;
+        }
+        Ok(vector)
+    }
+}
+
+/// Cosine similarity of two vectors; 0 if either is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let (na, nb) = (norm(a), norm(b));
+    if na == 0.0 || nb == 0.0 {
+        0.0
+    } else {
+        dot / (na * nb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_embedder_similarity() {
+        let embedder = HashEmbedder::new();
+        let query = embedder.embed("parse the config file").unwrap();
+        let close = embedder.embed("Config file parsing notes").unwrap();
+        let far = embedder.embed("network retry timings").unwrap();
+
+        assert!(cosine_similarity(&query, &close) > cosine_similarity(&query, &far));
+        assert_eq!(cosine_similarity(&query, &vec![0.0; 256]), 0.0);
+    }
+}
@@ -3,7 +3,7 @@
 //! Re-exports llcraft-error and provides VM-specific conveniences.
 
 // Re-export the core error types
-pub use llcraft_error::{Error, ErrorKind, ErrorStatus, Result};
+pub use llcraft_error::{retry_with_backoff, Error, ErrorKind, ErrorStatus, Result, RetryPolicy};
 
 /// Legacy VmError alias - use Error instead in new code
 #[deprecated(since = "0.2.0", note = "Use llcraft_error::Error instead")]
@@ -28,11 +28,38 @@ pub fn stack_underflow() -> Error {
     Error::stack_underflow()
 }
 
+/// Create a StackOverflow error for a push past the stack's cap
+pub fn stack_full(max_size: usize) -> Error {
+    Error::new(ErrorKind::StackOverflow, format!("stack is full ({} values max)", max_size))
+        .with_context("max_size", max_size.to_string())
+}
+
+/// Create a StackUnderflow error naming the op and how deep it needed to reach
+pub fn stack_too_shallow(op: &str, needed: usize, depth: usize) -> Error {
+    Error::new(
+        ErrorKind::StackUnderflow,
+        format!("{} needs {} value(s) but the stack has {}", op, needed, depth),
+    )
+    .with_context("op", op)
+    .with_context("needed", needed.to_string())
+    .with_context("depth", depth.to_string())
+}
+
 /// Create a PageOverflow error
 pub fn page_overflow() -> Error {
     Error::new(ErrorKind::PageOverflow, "exceeds context window limit")
 }
 
+/// Create a PageOverflow error for a single page over its token budget
+pub fn page_too_large(page_id: &str, tokens: usize, budget: usize) -> Error {
+    Error::new(
+        ErrorKind::PageOverflow,
+        format!("page '{}' is {} tokens, over the {} token page budget", page_id, tokens, budget),
+    )
+    .with_context("page_id", page_id)
+    .with_context("tokens", tokens.to_string())
+}
+
 /// Create an InvalidRange error
 pub fn invalid_range(start: usize, end: usize) -> Error {
     Error::new(ErrorKind::InvalidRange, format!("invalid range: {}..{}", start, end))
@@ -128,6 +155,32 @@ pub fn invalid_opcode(position: usize) -> Error {
         .with_context("position", position.to_string())
 }
 
+/// Create an InvalidOpcode error for an opcode that can't run inside a block
+pub fn not_allowed_in_block(op: &str, block: &str) -> Error {
+    Error::new(ErrorKind::InvalidOpcode, format!("{} is not allowed inside {}", op, block))
+        .with_context("op", op)
+        .with_context("block", block)
+}
+
+/// Create a StorageNotFound error for a ROLLBACK to an unknown checkpoint
+pub fn checkpoint_not_found(name: &str) -> Error {
+    Error::new(ErrorKind::StorageNotFound, format!("checkpoint not found: {}", name))
+        .with_context("checkpoint", name)
+}
+
+/// Create an InvalidOpcode error for programs that include themselves;
+/// `chain` is the include path, ending with the repeated program
+pub fn include_cycle(chain: &[String]) -> Error {
+    Error::new(ErrorKind::InvalidOpcode, format!("include cycle: {}", chain.join(" -> ")))
+        .with_context("chain", chain.join(","))
+}
+
+/// Create an InvalidOpcode error for BREAK / CONTINUE with no enclosing LOOP
+pub fn outside_loop(op: &str) -> Error {
+    Error::new(ErrorKind::InvalidOpcode, format!("{} used outside of a LOOP", op))
+        .with_context("op", op)
+}
+
 /// Create an IoError error
 pub fn io_error(message: impl Into<String>) -> Error {
     Error::new(ErrorKind::IoFailed, message)
@@ -9,17 +9,63 @@
 //! - Manages session persistence for context efficiency
 
 use crate::error::{self, Result};
-use crate::memory::Memory;
-use crate::opcode::{Opcode, Program, LogLevel};
-use crate::schema::ExecutionStep;
+use crate::memory::{self, Memory, OversizePolicy, PageStats};
+use crate::opcode::{InferParams, Opcode, Program, LogLevel, Register, ValidationError};
+use crate::provider::ChatMessage;
+use crate::schema::{ExecutionStep, PageChange, PageChangeKind};
 use crate::session::{Session, SessionManager, SessionStatus};
 use crate::stack::Stack;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
 /// Maximum execution steps (prevents infinite loops)
 pub const MAX_STEPS: usize = 10_000;
 
+/// Longest SLEEP allowed unless changed with `Interpreter::with_max_sleep`
+pub const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Maximum forked children that can be waiting to be joined
+pub const MAX_CHILDREN: usize = 16;
+
+/// Maximum nesting of FORK (a child forking its own children)
+pub const MAX_FORK_DEPTH: usize = 4;
+
+/// Pid of the top-level interpreter
+pub const MAIN_PID: &str = "main";
+
+/// Decision returned by an approval hook for a side-effecting opcode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Approval {
+    /// Run the opcode as-is
+    Allow,
+    /// Skip the opcode; its result page records `{success: false, denied: true}`
+    Deny,
+    /// Run this opcode instead
+    Modify(Opcode),
+}
+
+/// Hook consulted before EXEC / WRITE_FILE run
+pub type ApprovalHook = Arc<dyn Fn(&Opcode) -> Approval + Send + Sync>;
+
+/// When an opcode hook is being called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPhase {
+    /// The opcode is about to execute
+    Before,
+    /// The opcode has executed (successfully or not)
+    After,
+}
+
+/// Hook called around every executed opcode
+pub type OpcodeHook = Box<dyn FnMut(&Opcode, HookPhase) + Send>;
+
+/// Where PRINT output goes
+pub type OutputCallback = Box<dyn Fn(&str) + Send + Sync>;
+
 /// Result of program execution
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ExecutionResult {
@@ -50,7 +96,13 @@ pub struct LlmRequest {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LlmRequestType {
-    Infer,
+    Infer {
+        #[serde(default)]
+        params: crate::opcode::InferParams,
+        /// Page holding the conversation so far (see `history_messages`)
+        #[serde(default)]
+        history_page: Option<String>,
+    },
     Plan,
     Reflect { include_trace: bool },
     /// JIT injection - LLM should return opcodes to insert
@@ -84,7 +136,99 @@ pub struct ExecutionState {
     pub steps: usize,
 }
 
+impl LlmRequest {
+    /// Content of a page in the requesting program's memory. A forked child's
+    /// request carries the child's memory, not the parent's.
+    pub fn page(&self, id: &str) -> Option<&serde_json::Value> {
+        self.execution_state.memory.get(id).map(|p| &p.content)
+    }
+
+    /// Messages stored in an INFER history page of the requesting program
+    pub fn history_messages(&self, page_id: &str) -> Result<Vec<ChatMessage>> {
+        history_messages(&self.execution_state.memory, page_id)
+    }
+}
+
+/// Messages stored in an INFER history page. An absent or null page is an
+/// empty conversation; anything but an array of `{role, content}` is an error.
+fn history_messages(memory: &Memory, page_id: &str) -> Result<Vec<ChatMessage>> {
+    match memory.get(page_id).map(|page| &page.content) {
+        None | Some(serde_json::Value::Null) => Ok(Vec::new()),
+        Some(turns) => serde_json::from_value(turns.clone()).map_err(|e| {
+            error::invalid_argument(format!(
+                "INFER: history page '{}' must be an array of {{role, content}} messages: {}",
+                page_id, e
+            ))
+        }),
+    }
+}
+
+/// Page that holds the error caught by a TRY block
+pub const ERROR_PAGE: &str = "_error";
+
+/// How many stack values each trace step shows
+const TRACE_STACK_DEPTH: usize = 5;
+
+/// Longest stack value shown in the trace, in characters
+const TRACE_STACK_VALUE_LEN: usize = 60;
+
+/// How many opcodes a lenient injection kept and how many it skipped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InjectOutcome {
+    /// Opcodes that parsed and were injected
+    pub injected: usize,
+    /// Elements that failed to parse and were skipped
+    pub dropped: usize,
+}
+
+/// Aggregate counts for a run, from `Interpreter::metrics`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunMetrics {
+    /// Steps taken (what `with_max_steps` is checked against)
+    pub total_steps: usize,
+    /// Opcodes executed by name, including those nested in blocks and loops
+    pub opcode_counts: HashMap<String, usize>,
+    /// LLM calls requested (each INFER_BATCH prompt counts once)
+    pub infer_calls: usize,
+    /// Syscalls made to the handler
+    pub tool_calls: usize,
+    /// Bytes of file content read by READ_FILE
+    pub bytes_read: usize,
+    /// Bytes of file content written by WRITE_FILE
+    pub bytes_written: usize,
+    /// Most pages held in memory at once
+    pub peak_pages: usize,
+}
+
+/// Checkpoint of a running interpreter, restored with `Interpreter::restore_state`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionSnapshot {
+    /// Current program counter
+    pub pc: usize,
+    /// Memory pages
+    pub memory: Memory,
+    /// Stack
+    pub stack: Stack,
+    /// Registers
+    pub registers: HashMap<String, serde_json::Value>,
+    /// Execution trace
+    pub trace: Vec<ExecutionStep>,
+    /// Step count
+    pub steps: usize,
+    /// LLM request the interpreter was waiting on, if any
+    pub pending_llm: Option<LlmRequest>,
+}
+
 /// Syscall handler trait - implement to provide external operations
+///
+/// The interpreter calls it with one of the names in `syscall::SYSCALLS` and
+/// the opcode's fields as JSON args (`{"path"}` for read_file, `{"path",
+/// "content"}` for write_file, `{"command", "cwd", "env", "stdin"}` for exec,
+/// and so on), then stores the returned value in the opcode's page. An
+/// operation that ran but failed should come back as
+/// `{"success": false, "error": ...}` so programs can branch on it; `Err` is
+/// for calls that can't be made at all (bad args, a refused syscall) and
+/// fails the step. See `DefaultSyscallHandler` for the reference behavior.
 pub trait SyscallHandler: Send + Sync {
     /// Execute a syscall and return the result
     fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value>;
@@ -93,22 +237,334 @@ pub trait SyscallHandler: Send + Sync {
     fn available(&self) -> Vec<&str>;
 }
 
+impl<S: SyscallHandler + ?Sized> SyscallHandler for &S {
+    fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        (**self).call(name, args)
+    }
+
+    fn available(&self) -> Vec<&str> {
+        (**self).available()
+    }
+}
+
+impl<S: SyscallHandler + ?Sized> SyscallHandler for Box<S> {
+    fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        (**self).call(name, args)
+    }
+
+    fn available(&self) -> Vec<&str> {
+        (**self).available()
+    }
+}
+
+impl<S: SyscallHandler + ?Sized> SyscallHandler for Arc<S> {
+    fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        (**self).call(name, args)
+    }
+
+    fn available(&self) -> Vec<&str> {
+        (**self).available()
+    }
+}
+
+/// A forked child running on its own thread
+struct ChildHandle {
+    /// Answers to the child's LLM requests, as (response, store_to)
+    answers: mpsc::Sender<(serde_json::Value, String)>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+/// What a forked child reports to the interpreter that forked it
+enum ChildEvent {
+    /// The child is waiting for the LLM; answer on its `answers` channel
+    Llm(String, LlmRequest),
+    /// The child finished with its `{success, result | error}` entry
+    Done(String, serde_json::Value),
+}
+
+/// How long JOIN waits on its children before checking for a panicked one
+const CHILD_POLL: Duration = Duration::from_millis(50);
+
+/// Per-process message queues shared by an interpreter and its forked children
+struct Mailboxes {
+    state: Mutex<MailboxState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct MailboxState {
+    /// Queued (sender, message) pairs per pid
+    queues: HashMap<String, VecDeque<(String, serde_json::Value)>>,
+    /// Live interpreters in the tree, including one waiting on the LLM
+    running: usize,
+    /// Processes that can't send right now: in RECV without a timeout, or in JOIN
+    blocked: usize,
+    /// LLM requests sent up to each pid and not yet picked up
+    llm_requests: HashMap<String, usize>,
+    /// Counter for assigning pids
+    next_pid: usize,
+}
+
+impl Mailboxes {
+    fn new() -> Self {
+        let mut state = MailboxState::default();
+        state.queues.insert(MAIN_PID.to_string(), VecDeque::new());
+        // The interpreter that owns the mailboxes
+        state.running = 1;
+        Self { state: Mutex::new(state), changed: Condvar::new() }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MailboxState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Assign a new pid with an empty queue
+    fn open(&self) -> String {
+        let mut state = self.lock();
+        state.next_pid += 1;
+        let pid = format!("pid_{}", state.next_pid);
+        state.queues.insert(pid.clone(), VecDeque::new());
+        pid
+    }
+
+    fn close(&self, pid: &str) {
+        self.lock().queues.remove(pid);
+    }
+
+    fn set_running(&self, delta: isize) {
+        let mut state = self.lock();
+        state.running = state.running.saturating_add_signed(delta);
+        self.changed.notify_all();
+    }
+
+    fn set_blocked(&self, delta: isize) {
+        let mut state = self.lock();
+        state.blocked = state.blocked.saturating_add_signed(delta);
+        self.changed.notify_all();
+    }
+
+    /// Count an LLM request sent up to `pid`, waking it if it's in RECV
+    fn set_llm_requests(&self, pid: &str, delta: isize) {
+        let mut state = self.lock();
+        let count = state.llm_requests.entry(pid.to_string()).or_default();
+        *count = count.saturating_add_signed(delta);
+        self.changed.notify_all();
+    }
+
+    fn send(&self, from: &str, to: &str, message: serde_json::Value) -> Result<()> {
+        let mut state = self.lock();
+        let queue = state.queues.get_mut(to).ok_or_else(|| error::process_not_found(to))?;
+        queue.push_back((from.to_string(), message));
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Wait for a message to `pid`. With a timeout, gives up when it expires;
+    /// without one, gives up once every other live process is itself
+    /// blocked, since then nobody is left to send. Returns `None` without
+    /// waiting further when one of `pid`'s children asks for the LLM.
+    fn recv(&self, pid: &str, timeout: Option<Duration>) -> Option<serde_json::Value> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.lock();
+        loop {
+            if let Some((from, message)) = state.queues.get_mut(pid).and_then(|q| q.pop_front()) {
+                return Some(serde_json::json!({ "received": true, "from": from, "message": message }));
+            }
+            if state.llm_requests.get(pid).is_some_and(|&n| n > 0) {
+                return None;
+            }
+
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Some(serde_json::json!({ "received": false, "timed_out": true }));
+                    }
+                    state = self.changed.wait_timeout(state, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner()).0;
+                }
+                None => {
+                    let others = state.running.saturating_sub(1);
+                    if others <= state.blocked {
+                        return Some(serde_json::json!({ "received": false, "timed_out": false, "closed": true }));
+                    }
+                    state.blocked += 1;
+                    self.changed.notify_all();
+                    state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+                    state.blocked -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Default timeout for HTTP_REQUEST
+pub const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Default syscall handler with basic file operations
 pub struct DefaultSyscallHandler {
     /// Working directory for file operations
     pub working_dir: std::path::PathBuf,
+    /// Hosts HTTP_REQUEST may contact; empty means no network access
+    pub allowed_hosts: Vec<String>,
+    /// Timeout for each HTTP request
+    pub http_timeout: Duration,
+    /// Syscalls that may run; `None` allows all of them
+    pub allowed_syscalls: Option<Vec<String>>,
+    /// Environment variables GET_ENV may read; empty means none
+    pub allowed_env: Vec<String>,
 }
 
 impl Default for DefaultSyscallHandler {
     fn default() -> Self {
+        Self::new(std::env::current_dir().unwrap_or_default())
+    }
+}
+
+impl DefaultSyscallHandler {
+    /// Handler rooted at `working_dir`, with no network access
+    pub fn new(working_dir: impl Into<std::path::PathBuf>) -> Self {
         Self {
-            working_dir: std::env::current_dir().unwrap_or_default(),
+            working_dir: working_dir.into(),
+            allowed_hosts: Vec::new(),
+            http_timeout: HTTP_TIMEOUT,
+            allowed_syscalls: None,
+            allowed_env: Vec::new(),
+        }
+    }
+
+    /// Only allow these syscalls (e.g. `["read_file", "list_dir"]` for a
+    /// read-only sandbox); others fail with an error
+    pub fn with_allowed_syscalls(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.allowed_syscalls = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Allow HTTP_REQUEST to contact these hosts (exact host names)
+    pub fn with_allowed_hosts(mut self, hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.allowed_hosts = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Allow GET_ENV to read these variables (exact names)
+    pub fn with_allowed_env(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.allowed_env = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the timeout for each HTTP request
+    pub fn with_http_timeout(mut self, timeout: Duration) -> Self {
+        self.http_timeout = timeout;
+        self
+    }
+
+    /// Check that `url` is http(s) and its host is on the allowlist
+    fn check_url(&self, url: &str) -> std::result::Result<reqwest::Url, String> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid url '{}': {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(format!("unsupported scheme '{}'", parsed.scheme()));
+        }
+        let host = parsed.host_str().unwrap_or_default();
+        if !self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+            return Err(format!("host '{}' is not in the allowlist", host));
+        }
+        Ok(parsed)
+    }
+
+    fn http_request(&self, url: reqwest::Url, args: &serde_json::Value) -> std::result::Result<serde_json::Value, String> {
+        let method = args.get("method").and_then(|v| v.as_str()).unwrap_or("GET").to_uppercase();
+        let method = reqwest::Method::from_bytes(method.as_bytes())
+            .map_err(|_| format!("invalid method '{}'", method))?;
+
+        // The blocking client must not run on an async runtime thread, and the
+        // interpreter is often driven from one, so send from a fresh thread
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let client = reqwest::blocking::Client::builder()
+                        .timeout(self.http_timeout)
+                        .build()
+                        .map_err(|e| e.to_string())?;
+                    let mut request = client.request(method, url);
+                    if let Some(headers) = args.get("headers").and_then(|v| v.as_object()) {
+                        for (name, value) in headers {
+                            request = request.header(name.as_str(), value.as_str().unwrap_or_default());
+                        }
+                    }
+                    if let Some(body) = args.get("body").and_then(|v| v.as_str()) {
+                        request = request.body(body.to_string());
+                    }
+
+                    let response = request.send().map_err(|e| e.to_string())?;
+                    let status = response.status();
+                    let headers: serde_json::Map<String, serde_json::Value> = response
+                        .headers()
+                        .iter()
+                        .map(|(name, value)| {
+                            (name.to_string(), serde_json::json!(String::from_utf8_lossy(value.as_bytes())))
+                        })
+                        .collect();
+                    let body = response.text().map_err(|e| e.to_string())?;
+                    Ok(serde_json::json!({
+                        "success": status.is_success(),
+                        "status": status.as_u16(),
+                        "headers": headers,
+                        "body": body
+                    }))
+                })
+                .join()
+                .unwrap_or_else(|_| Err("HTTP request thread panicked".to_string()))
+        })
+    }
+
+    /// Resolve an EXEC working directory, refusing paths outside `working_dir`
+    fn resolve_cwd(&self, cwd: Option<&str>) -> std::result::Result<std::path::PathBuf, String> {
+        let root = self.working_dir.canonicalize().map_err(|e| e.to_string())?;
+        let Some(cwd) = cwd else {
+            return Ok(root);
+        };
+        let resolved = root
+            .join(cwd)
+            .canonicalize()
+            .map_err(|e| format!("cwd '{}': {}", cwd, e))?;
+        if !resolved.starts_with(&root) {
+            return Err(format!("cwd '{}' is outside the workspace root", cwd));
+        }
+        Ok(resolved)
+    }
+}
+
+/// Spawn `command`, feed it `stdin` on a separate thread and collect its output
+fn run_with_stdin(mut command: std::process::Command, stdin: Option<&str>) -> std::io::Result<std::process::Output> {
+    use std::io::Write;
+
+    let mut child = command
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    let writer = match (stdin, child.stdin.take()) {
+        (Some(input), Some(mut pipe)) => {
+            let input = input.to_string();
+            Some(std::thread::spawn(move || pipe.write_all(input.as_bytes())))
         }
+        _ => None,
+    };
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        // A command that exits without reading stdin closes the pipe early
+        let _ = writer.join();
     }
+    Ok(output)
 }
 
 impl SyscallHandler for DefaultSyscallHandler {
     fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        if let Some(allowed) = &self.allowed_syscalls {
+            if !allowed.iter().any(|n| n == name) {
+                return Err(error::syscall_failed(name, "not in the syscall allowlist"));
+            }
+        }
+
         match name {
             "read_file" => {
                 let path = args.get("path")
@@ -137,16 +593,34 @@ impl SyscallHandler for DefaultSyscallHandler {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| error::invalid_argument("write_file requires 'content' argument"))?;
 
+                let backup = args.get("backup").and_then(|v| v.as_bool()).unwrap_or(false);
+
+                // Write beside the target and rename over it, so a crash
+                // leaves either the old file or the new one, never half of it
                 let full_path = self.working_dir.join(path);
-                match std::fs::write(&full_path, content) {
+                let backed_up = backup && full_path.is_file();
+                let written = if backed_up {
+                    let mut bak = full_path.clone().into_os_string();
+                    bak.push(".bak");
+                    std::fs::copy(&full_path, bak)
+                        .map_err(|e| error::io_error(format!("Failed to back up {}: {}", path, e)))
+                        .map(|_| ())
+                } else {
+                    Ok(())
+                }
+                .and_then(|_| crate::storage::write_files_atomically(&[(full_path, content.to_string())]));
+
+                match written {
                     Ok(()) => Ok(serde_json::json!({
                         "success": true,
-                        "path": path
+                        "path": path,
+                        "backup": backed_up
                     })),
                     Err(e) => Ok(serde_json::json!({
                         "success": false,
                         "error": e.to_string(),
-                        "path": path
+                        "path": path,
+                        "backup": false
                     })),
                 }
             }
@@ -180,21 +654,41 @@ impl SyscallHandler for DefaultSyscallHandler {
                     .and_then(|v| v.as_str())
                     .ok_or_else(|| error::invalid_argument("exec requires 'command' argument"))?;
 
-                match std::process::Command::new("sh")
-                    .arg("-c")
-                    .arg(cmd)
-                    .current_dir(&self.working_dir)
-                    .output()
-                {
+                let cwd = match self.resolve_cwd(args.get("cwd").and_then(|v| v.as_str())) {
+                    Ok(cwd) => cwd,
+                    Err(e) => return Ok(serde_json::json!({
+                        "success": false,
+                        "error": e,
+                        "cwd": args.get("cwd")
+                    })),
+                };
+
+                let mut command = std::process::Command::new("sh");
+                command.arg("-c").arg(cmd).current_dir(&cwd);
+                if let Some(env) = args.get("env").and_then(|v| v.as_object()) {
+                    for (key, value) in env {
+                        command.env(key, value.as_str().unwrap_or_default());
+                    }
+                }
+                let stdin = args.get("stdin").and_then(|v| v.as_str());
+                command.stdin(if stdin.is_some() {
+                    std::process::Stdio::piped()
+                } else {
+                    std::process::Stdio::null()
+                });
+
+                match run_with_stdin(command, stdin) {
                     Ok(output) => Ok(serde_json::json!({
                         "success": output.status.success(),
                         "stdout": String::from_utf8_lossy(&output.stdout),
                         "stderr": String::from_utf8_lossy(&output.stderr),
-                        "exit_code": output.status.code()
+                        "exit_code": output.status.code(),
+                        "cwd": cwd.display().to_string()
                     })),
                     Err(e) => Ok(serde_json::json!({
                         "success": false,
-                        "error": e.to_string()
+                        "error": e.to_string(),
+                        "cwd": cwd.display().to_string()
                     })),
                 }
             }
@@ -230,12 +724,45 @@ impl SyscallHandler for DefaultSyscallHandler {
                     })),
                 }
             }
+            "http_request" => {
+                let url = args.get("url")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| error::invalid_argument("http_request requires 'url' argument"))?;
+
+                let result = self.check_url(url).and_then(|parsed| self.http_request(parsed, args));
+                Ok(result.unwrap_or_else(|e| serde_json::json!({
+                    "success": false,
+                    "error": e,
+                    "url": url
+                })))
+            }
+            "get_env" => {
+                let var = args.get("name")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| error::invalid_argument("get_env requires 'name' argument"))?;
+                // Keys and tokens live in the environment, so nothing is
+                // readable unless the embedder lists it
+                if !self.allowed_env.iter().any(|allowed| allowed == var) {
+                    return Err(error::syscall_failed(
+                        "get_env",
+                        format!("'{}' is not in the environment allowlist", var),
+                    ));
+                }
+                Ok(serde_json::json!({
+                    "name": var,
+                    "value": std::env::var(var).ok()
+                }))
+            }
             _ => Err(error::unknown_syscall(name)),
         }
     }
 
     fn available(&self) -> Vec<&str> {
-        vec!["read_file", "write_file", "list_dir", "exec", "grep"]
+        let all = crate::syscall::SYSCALLS.to_vec();
+        match &self.allowed_syscalls {
+            Some(allowed) => all.into_iter().filter(|name| allowed.iter().any(|n| n == name)).collect(),
+            None => all,
+        }
     }
 }
 
@@ -253,25 +780,69 @@ pub struct Interpreter<S: SyscallHandler> {
     registers: HashMap<String, serde_json::Value>,
     /// Label to PC mapping
     labels: HashMap<String, usize>,
-    /// Syscall handler
-    syscall_handler: S,
+    /// Syscall handler, shared with forked children
+    syscall_handler: Arc<S>,
     /// Execution trace
     trace: Vec<ExecutionStep>,
     /// Step counter
     steps: usize,
     /// Max steps allowed
     max_steps: usize,
+    /// Longest SLEEP allowed
+    max_sleep: Duration,
     /// Log callback
     log_callback: Option<Box<dyn Fn(LogLevel, &str) + Send + Sync>>,
+    /// Output callback for PRINT
+    output_callback: Option<OutputCallback>,
     /// Pending spawned tasks (task_id -> opcode)
     pending_tasks: HashMap<String, Opcode>,
     /// Current session for persistence
     session: Option<Session>,
     /// Session manager for disk operations (None if initialization failed)
     session_manager: Option<SessionManager>,
+    /// Approval hook for side-effecting opcodes
+    approval_hook: Option<ApprovalHook>,
+    /// Hook called before and after each opcode
+    opcode_hook: Option<OpcodeHook>,
+    /// Skip side-effecting opcodes, storing a synthetic result instead
+    dry_run: bool,
+    /// Derive generated ids from the program instead of randomness
+    deterministic: bool,
+    /// COMPLETE replaces `{"page": id}` references with the page content
+    inline_results: bool,
+    /// Record each step's page changes in the trace
+    trace_memory: bool,
+    /// Counters reported by `metrics`
+    metrics: RunMetrics,
+    /// Loop variables of the LOOPs currently running, innermost last
+    loop_stack: Vec<String>,
+    /// Checkpoints taken by CHECKPOINT in this run, by name
+    checkpoints: HashMap<String, ExecutionSnapshot>,
+    /// LLM request returned by the last step and not yet answered
+    pending_llm: Option<LlmRequest>,
+    /// Programs that FORK can start, by id
+    programs: HashMap<String, Program>,
+    /// Forked children still running (pid -> child)
+    children: HashMap<String, ChildHandle>,
+    /// Channel the forked children report on; each child holds a sender
+    child_events: (mpsc::Sender<ChildEvent>, mpsc::Receiver<ChildEvent>),
+    /// Children's LLM requests not yet handed up, oldest first
+    child_llm: VecDeque<(String, LlmRequest)>,
+    /// Child whose LLM request is the pending one, if any
+    llm_child: Option<String>,
+    /// Results of finished children not yet collected by a JOIN
+    joined: serde_json::Map<String, serde_json::Value>,
+    /// This interpreter's pid ("main" unless forked)
+    pid: String,
+    /// Pid of the interpreter that forked this one
+    parent_pid: Option<String>,
+    /// Message queues shared with the whole process tree
+    mailboxes: Arc<Mailboxes>,
+    /// How many FORKs deep this interpreter is
+    fork_depth: usize,
 }
 
-impl<S: SyscallHandler> Interpreter<S> {
+impl<S: SyscallHandler + 'static> Interpreter<S> {
     /// Create a new interpreter for a program
     pub fn new(program: Program, syscall_handler: S) -> Self {
         let mut labels = HashMap::new();
@@ -290,14 +861,36 @@ impl<S: SyscallHandler> Interpreter<S> {
             stack: Stack::new(),
             registers: HashMap::new(),
             labels,
-            syscall_handler,
+            syscall_handler: Arc::new(syscall_handler),
             trace: Vec::new(),
             steps: 0,
             max_steps: MAX_STEPS,
+            max_sleep: MAX_SLEEP,
             log_callback: None,
+            output_callback: None,
             pending_tasks: HashMap::new(),
             session: None,
             session_manager: SessionManager::new(".llcraft/sessions").ok(),
+            approval_hook: None,
+            opcode_hook: None,
+            dry_run: false,
+            deterministic: false,
+            inline_results: false,
+            trace_memory: false,
+            metrics: RunMetrics::default(),
+            loop_stack: Vec::new(),
+            checkpoints: HashMap::new(),
+            pending_llm: None,
+            programs: HashMap::new(),
+            children: HashMap::new(),
+            child_events: mpsc::channel(),
+            child_llm: VecDeque::new(),
+            llm_child: None,
+            joined: serde_json::Map::new(),
+            pid: MAIN_PID.to_string(),
+            parent_pid: None,
+            mailboxes: Arc::new(Mailboxes::new()),
+            fork_depth: 0,
         }
     }
 
@@ -351,12 +944,102 @@ impl<S: SyscallHandler> Interpreter<S> {
         self
     }
 
+    /// Set where PRINT output goes. Without one, PRINT falls back to the log
+    /// callback at info level
+    pub fn with_output_callback<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.output_callback = Some(Box::new(callback) as OutputCallback);
+        self
+    }
+
+    /// Require approval before side-effecting opcodes (EXEC, WRITE_FILE) run
+    pub fn with_approval_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Opcode) -> Approval + Send + Sync + 'static,
+    {
+        self.approval_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with `Before` and `After` around every executed opcode
+    pub fn with_opcode_hook(mut self, hook: OpcodeHook) -> Self {
+        self.opcode_hook = Some(hook);
+        self
+    }
+
+    /// Preview mode: EXEC, WRITE_FILE and HTTP_REQUEST don't run; their
+    /// result page gets `{success: true, dry_run: true}` and a log line says
+    /// what would have happened
+    pub fn with_dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Reproducible runs: UUID derives its value from the program id and
+    /// step count, so identical runs generate identical ids
+    pub fn with_deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Make COMPLETE results self-contained: each `{"page": id}` in the
+    /// result is replaced by that page's content, and a result that is empty
+    /// after inlining fails the run
+    pub fn with_inline_results(mut self, enabled: bool) -> Self {
+        self.inline_results = enabled;
+        self
+    }
+
+    /// Record in each trace step which pages it created, modified or freed
+    /// and how their sizes changed. Costs a hash of every page per step.
+    pub fn with_memory_trace(mut self, enabled: bool) -> Self {
+        self.trace_memory = enabled;
+        self
+    }
+
+    /// Measure page sizes with the given token counter
+    pub fn with_token_counter(mut self, counter: Arc<dyn crate::tokens::TokenCounter>) -> Self {
+        self.memory.set_token_counter(counter);
+        self
+    }
+
+    /// Set the per-page token budget and what happens to pages over it
+    pub fn with_page_budget(mut self, max_tokens: usize, policy: OversizePolicy) -> Self {
+        self.memory.set_page_budget(max_tokens, policy);
+        self
+    }
+
+    /// Cap the stack at `max` values
+    pub fn with_max_stack(mut self, max: usize) -> Self {
+        self.stack = std::mem::take(&mut self.stack).with_max_size(max);
+        self
+    }
+
     /// Set max steps
     pub fn with_max_steps(mut self, max: usize) -> Self {
         self.max_steps = max;
         self
     }
 
+    /// Set the longest SLEEP a program may ask for; longer ones fail the step
+    pub fn with_max_sleep(mut self, max: Duration) -> Self {
+        self.max_sleep = max;
+        self
+    }
+
+    /// Register a program that FORK can start by id
+    pub fn with_program(mut self, program: Program) -> Self {
+        self.register_program(program);
+        self
+    }
+
+    /// Register a program that FORK can start by id
+    pub fn register_program(&mut self, program: Program) {
+        self.programs.insert(program.id.clone(), program);
+    }
+
     /// Pre-load a page into memory
     pub fn load_page(&mut self, id: impl Into<String>, content: serde_json::Value) -> Result<()> {
         self.memory.store(id, content)
@@ -367,6 +1050,20 @@ impl<S: SyscallHandler> Interpreter<S> {
         self.memory.get(id).map(|p| &p.content)
     }
 
+    /// Labels of the pages that have one, e.g. the content type inferred
+    /// for tool results (`file:rust`, `command_output`, ...)
+    pub fn page_labels(&self) -> HashMap<String, String> {
+        self.memory
+            .page_ids()
+            .filter_map(|id| Some((id.to_string(), self.memory.get(id)?.label.clone()?)))
+            .collect()
+    }
+
+    /// Read/write counts and last access of every page
+    pub fn page_stats(&self) -> HashMap<String, PageStats> {
+        self.memory.page_stats()
+    }
+
     /// Get all pages as a map of id -> content
     pub fn all_pages(&self) -> HashMap<String, serde_json::Value> {
         self.memory.pages_by_lru()
@@ -391,28 +1088,175 @@ impl<S: SyscallHandler> Interpreter<S> {
             stack: state.stack,
             registers: state.registers,
             labels,
-            syscall_handler,
+            syscall_handler: Arc::new(syscall_handler),
             trace: state.trace,
             steps: state.steps,
             max_steps: MAX_STEPS,
+            max_sleep: MAX_SLEEP,
             log_callback: None,
+            output_callback: None,
             pending_tasks: HashMap::new(),
             session: None,
             session_manager: SessionManager::new(".llcraft/sessions").ok(),
+            approval_hook: None,
+            opcode_hook: None,
+            dry_run: false,
+            deterministic: false,
+            inline_results: false,
+            trace_memory: false,
+            metrics: RunMetrics::default(),
+            loop_stack: Vec::new(),
+            checkpoints: HashMap::new(),
+            pending_llm: None,
+            programs: HashMap::new(),
+            children: HashMap::new(),
+            child_events: mpsc::channel(),
+            child_llm: VecDeque::new(),
+            llm_child: None,
+            joined: serde_json::Map::new(),
+            pid: MAIN_PID.to_string(),
+            parent_pid: None,
+            mailboxes: Arc::new(Mailboxes::new()),
+            fork_depth: 0,
+        }
+    }
+
+    /// Snapshot the interpreter so it can be restored later, e.g. after a restart
+    pub fn save_state(&self) -> ExecutionSnapshot {
+        ExecutionSnapshot {
+            pc: self.pc,
+            memory: self.memory.clone(),
+            stack: self.stack.clone(),
+            registers: self.registers.clone(),
+            trace: self.trace.clone(),
+            steps: self.steps,
+            pending_llm: self.pending_llm.clone(),
         }
     }
 
+    /// Rebuild an interpreter for `program` from a snapshot taken by `save_state`
+    pub fn restore_state(program: Program, snapshot: ExecutionSnapshot, syscall_handler: S) -> Self {
+        let state = ExecutionState {
+            pc: snapshot.pc,
+            program,
+            memory: snapshot.memory,
+            stack: snapshot.stack,
+            registers: snapshot.registers,
+            trace: snapshot.trace,
+            steps: snapshot.steps,
+        };
+        let mut interp = Self::resume(state, syscall_handler);
+        interp.pending_llm = snapshot.pending_llm;
+        interp
+    }
+
+    /// Checkpoint `name` as saved to the current session by an earlier run
+    fn load_persisted_checkpoint(&self, name: &str) -> Result<ExecutionSnapshot> {
+        let (Some(session), Some(manager)) = (&self.session, &self.session_manager) else {
+            return Err(error::checkpoint_not_found(name));
+        };
+        let data = manager
+            .load_checkpoint(&session.metadata.id, name)
+            .map_err(|_| error::checkpoint_not_found(name))?;
+        serde_json::from_value(data).map_err(|e| error::parse_error(format!("checkpoint {}: {}", name, e)))
+    }
+
+    /// The LLM request the interpreter is waiting on, if any
+    pub fn pending_llm_request(&self) -> Option<&LlmRequest> {
+        self.pending_llm.as_ref()
+    }
+
     /// Provide LLM response and continue execution
+    /// If the pending INFER has a schema, the response is parsed as JSON and
+    /// validated first; a mismatch fails without storing anything.
     pub fn provide_llm_response(&mut self, response: serde_json::Value, store_to: &str) -> Result<()> {
+        if let Some(pid) = self.llm_child.take() {
+            self.pending_llm = None;
+            let child = self.children.get(&pid).ok_or_else(|| error::process_not_found(&pid))?;
+            return child.answers
+                .send((response, store_to.to_string()))
+                .map_err(|_| error::process_not_found(&pid));
+        }
+        let response = match self.pending_llm.as_ref().map(|r| &r.request_type) {
+            Some(LlmRequestType::Infer { params: InferParams { schema: Some(schema), .. }, .. }) => {
+                structured_response(response, schema)?
+            }
+            _ => response,
+        };
+        let pages_before = self.trace_memory.then(|| self.page_fingerprints());
+        if let Some(LlmRequest { request_type: LlmRequestType::Infer { history_page: Some(page_id), .. }, prompt, .. }) =
+            self.pending_llm.clone()
+        {
+            let reply = match response.get("response").unwrap_or(&response) {
+                serde_json::Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            let mut history = self.history_messages(&page_id)?;
+            history.push(ChatMessage::user(prompt));
+            history.push(ChatMessage::assistant(reply));
+            let turns: Vec<serde_json::Value> = history
+                .iter()
+                .map(|m| serde_json::json!({"role": m.role, "content": m.content}))
+                .collect();
+            self.memory.store(&page_id, serde_json::Value::Array(turns))?;
+        }
         self.memory.store(store_to, response)?;
+        if let Some(LlmRequest { request_type: LlmRequestType::Infer { .. }, .. }) = &self.pending_llm {
+            self.memory.set_label(store_to, "analysis")?;
+        }
+        self.trace_llm_page_changes(pages_before);
+        self.pending_llm = None;
         self.pc += 1; // Move past the INFER/PLAN/REFLECT opcode
         Ok(())
     }
 
+    /// LLM answers land after their step ran, so add the pages they touched
+    /// to that step
+    fn trace_llm_page_changes(&mut self, before: Option<HashMap<String, (u64, usize)>>) {
+        if let Some(before) = before {
+            let changes = page_changes(&before, &self.page_fingerprints());
+            if let Some(step) = self.trace.last_mut() {
+                step.memory.extend(changes);
+            }
+        }
+    }
+
+    /// Record how long the LLM took to answer the pending request. Call this
+    /// before providing the response so it lands on the requesting step.
+    pub fn record_llm_time(&mut self, elapsed: Duration) {
+        if let Some(step) = self.trace.last_mut() {
+            step.llm_ms = Some(elapsed.as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// Messages stored in an INFER history page. An absent or null page is
+    /// an empty conversation; anything but an array of `{role, content}` is
+    /// an error.
+    pub fn history_messages(&self, page_id: &str) -> Result<Vec<ChatMessage>> {
+        history_messages(&self.memory, page_id)
+    }
+
+    /// Provide several LLM results for one opcode (INFER_BATCH, MAP) and
+    /// continue execution
+    pub fn provide_llm_responses(
+        &mut self,
+        responses: impl IntoIterator<Item = (String, serde_json::Value)>,
+    ) -> Result<()> {
+        let pages_before = self.trace_memory.then(|| self.page_fingerprints());
+        for (page_id, response) in responses {
+            self.memory.store(page_id, response)?;
+        }
+        self.trace_llm_page_changes(pages_before);
+        self.pending_llm = None;
+        self.pc += 1;
+        Ok(())
+    }
+
     /// Inject opcodes into the program at the current position (JIT)
     /// The new opcodes are inserted after the current INJECT instruction.
     /// Returns the number of opcodes injected.
     pub fn inject_opcodes(&mut self, opcodes: Vec<Opcode>) -> Result<usize> {
+        self.pending_llm = None;
         let count = opcodes.len();
         if count == 0 {
             self.pc += 1;
@@ -448,12 +1292,92 @@ impl<S: SyscallHandler> Interpreter<S> {
         Ok(count)
     }
 
-    /// Get current execution state (for serialization)
-    pub fn state(&self) -> ExecutionState {
-        ExecutionState {
-            pc: self.pc,
-            program: self.program.clone(),
-            memory: self.memory.clone(),
+    /// Like `inject_opcodes`, but takes raw JSON and skips any element that
+    /// doesn't parse as an opcode, logging a warning for each one.
+    pub fn inject_opcodes_lenient(&mut self, values: Vec<serde_json::Value>) -> Result<InjectOutcome> {
+        let mut opcodes = Vec::with_capacity(values.len());
+        let mut dropped = 0;
+        for (i, value) in values.into_iter().enumerate() {
+            match serde_json::from_value::<Opcode>(value.clone()) {
+                Ok(op) => opcodes.push(op),
+                Err(e) => {
+                    dropped += 1;
+                    if let Some(ref cb) = self.log_callback {
+                        cb(LogLevel::Warn, &format!("INJECT: skipping opcode {}: {} ({})", i, e, value));
+                    }
+                }
+            }
+        }
+
+        let injected = self.inject_opcodes(opcodes)?;
+        Ok(InjectOutcome { injected, dropped })
+    }
+
+    /// Check an INJECT batch against the running program before it's spliced in.
+    ///
+    /// Catches jumps to labels defined neither in the program nor the batch,
+    /// labels that clash with existing ones, zero-sized chunks, and reads of
+    /// pages that aren't in memory and that nothing in the program or batch
+    /// writes. Indices in the errors refer to positions in `opcodes`.
+    pub fn validate_injection(&self, opcodes: &[Opcode]) -> std::result::Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        let mut batch_labels = HashSet::new();
+        for (i, op) in opcodes.iter().enumerate() {
+            if let Opcode::Label { name } = op {
+                if self.labels.contains_key(name) || !batch_labels.insert(name.as_str()) {
+                    errors.push(ValidationError { index: i, message: format!("duplicate label '{}'", name) });
+                }
+            }
+        }
+
+        // Pages that exist now or that some opcode could produce
+        let mut known: HashSet<&str> = self.memory.page_ids().collect();
+        let mut prefixes: Vec<&str> = Vec::new();
+        let mut pending: Vec<&Opcode> = self.program.code.iter().chain(opcodes).collect();
+        while let Some(op) = pending.pop() {
+            known.extend(op.writes_pages().into_iter().map(memory::unscoped));
+            match op {
+                Opcode::Chunk { prefix, .. } => prefixes.push(prefix.as_deref().unwrap_or("chunk")),
+                Opcode::InferBatch { store_prefix, .. } => prefixes.push(store_prefix),
+                _ => {}
+            }
+            pending.extend(op.children());
+        }
+
+        for (i, op) in opcodes.iter().enumerate() {
+            for target in op.jump_targets() {
+                if !self.labels.contains_key(target) && !batch_labels.contains(target) {
+                    errors.push(ValidationError { index: i, message: format!("jump to undefined label '{}'", target) });
+                }
+            }
+            for page in op.reads_pages().into_iter().map(memory::unscoped) {
+                if !known.contains(page) && !prefixes.iter().any(|p| page.starts_with(p)) {
+                    errors.push(ValidationError {
+                        index: i,
+                        message: format!("reads page '{}', which doesn't exist and is never written", page),
+                    });
+                }
+            }
+            if let Opcode::Chunk { chunk_size: 0, .. } = op {
+                errors.push(ValidationError { index: i, message: "CHUNK chunk_size must be greater than 0".to_string() });
+            }
+        }
+
+        errors.sort_by_key(|e| e.index);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Get current execution state (for serialization)
+    pub fn state(&self) -> ExecutionState {
+        ExecutionState {
+            pc: self.pc,
+            program: self.program.clone(),
+            memory: self.memory.clone(),
             stack: self.stack.clone(),
             registers: self.registers.clone(),
             trace: self.trace.clone(),
@@ -466,47 +1390,392 @@ impl<S: SyscallHandler> Interpreter<S> {
         &self.trace
     }
 
+    /// The working stack, bottom first, without touching it
+    pub fn stack_snapshot(&self) -> &[serde_json::Value] {
+        self.stack.as_slice()
+    }
+
+    /// Content hash and token size of every page, for `page_changes`
+    fn page_fingerprints(&self) -> HashMap<String, (u64, usize)> {
+        self.memory
+            .page_ids()
+            .filter_map(|id| {
+                let page = self.memory.get(id)?;
+                let mut hasher = DefaultHasher::new();
+                page.content.to_string().hash(&mut hasher);
+                Some((id.to_string(), (hasher.finish(), page.size_tokens)))
+            })
+            .collect()
+    }
+
+    /// Short rendering of the top of the stack for the trace, top first
+    fn stack_view(&self) -> Vec<String> {
+        self.stack
+            .iter_top_down()
+            .take(TRACE_STACK_DEPTH)
+            .map(|value| {
+                let text = value.to_string();
+                if text.chars().count() > TRACE_STACK_VALUE_LEN {
+                    let cut: String = text.chars().take(TRACE_STACK_VALUE_LEN - 1).collect();
+                    format!("{}…", cut)
+                } else {
+                    text
+                }
+            })
+            .collect()
+    }
+
+    /// Copy of `value` with every `{"page": id}` replaced by the page's
+    /// content. Inlined content is taken as is, not searched again.
+    fn inline_pages(&self, value: &serde_json::Value) -> Result<serde_json::Value> {
+        use serde_json::Value;
+        match value {
+            Value::Object(obj) => {
+                if let (1, Some(Value::String(id))) = (obj.len(), obj.get("page")) {
+                    return self
+                        .memory
+                        .get(id)
+                        .map(|page| page.content.clone())
+                        .ok_or_else(|| error::page_not_found(id.as_str()));
+                }
+                obj.iter()
+                    .map(|(k, v)| Ok((k.clone(), self.inline_pages(v)?)))
+                    .collect::<Result<_>>()
+                    .map(Value::Object)
+            }
+            Value::Array(items) => items.iter().map(|v| self.inline_pages(v)).collect::<Result<_>>().map(Value::Array),
+            other => Ok(other.clone()),
+        }
+    }
+
+    /// Aggregate metrics for the run so far
+    pub fn metrics(&self) -> RunMetrics {
+        RunMetrics { total_steps: self.steps, ..self.metrics.clone() }
+    }
+
+    /// Call the syscall handler, counting the call and any file bytes moved
+    fn syscall(&mut self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        self.metrics.tool_calls += 1;
+        let result = self.syscall_handler.call(name, args)?;
+        let len = |v: &serde_json::Value| v.get("content").and_then(|c| c.as_str()).map_or(0, str::len);
+        match name {
+            "read_file" => self.metrics.bytes_read += len(&result),
+            "write_file" => self.metrics.bytes_written += len(args),
+            _ => {}
+        }
+        Ok(result)
+    }
+
+    /// Current program counter
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Opcodes executed so far (what `with_max_steps` is checked against)
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// The opcode that will execute next, if any
+    pub fn current_opcode(&self) -> Option<&Opcode> {
+        self.program.code.get(self.pc)
+    }
+
     /// Run until completion or LLM input needed
     pub fn run(&mut self) -> Result<ExecutionResult> {
-        while self.pc < self.program.code.len() {
-            if self.steps >= self.max_steps {
-                return Ok(ExecutionResult::StepLimitExceeded);
+        loop {
+            if let Some(result) = self.step()? {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Execute a single opcode. Returns `None` while execution can continue,
+    /// or the result that `run` would have stopped with.
+    pub fn step(&mut self) -> Result<Option<ExecutionResult>> {
+        // Forked children run alongside; pass their LLM requests up between
+        // opcodes, repeating the current one until it's answered
+        if let (Some(_), Some(request)) = (&self.llm_child, &self.pending_llm) {
+            return Ok(Some(ExecutionResult::NeedsLlm(request.clone())));
+        }
+        if let Some(request) = self.child_llm_request() {
+            self.metrics.infer_calls += 1;
+            self.pending_llm = Some(request.clone());
+            return Ok(Some(ExecutionResult::NeedsLlm(request)));
+        }
+
+        if self.pc >= self.program.code.len() {
+            // Implicit completion if we run off the end
+            return Ok(Some(ExecutionResult::Complete(serde_json::json!({
+                "status": "completed",
+                "message": "Program ended without explicit COMPLETE"
+            }))));
+        }
+
+        if self.steps >= self.max_steps {
+            return Ok(Some(ExecutionResult::StepLimitExceeded));
+        }
+
+        let opcode = self.program.code[self.pc].clone();
+        self.steps += 1;
+
+        if let Some(hook) = self.opcode_hook.as_mut() {
+            hook(&opcode, HookPhase::Before);
+        }
+        let first_step = self.trace.len();
+        let pages_before = self.trace_memory.then(|| self.page_fingerprints());
+        let started = Instant::now();
+        let outcome = self.execute_opcode(&opcode);
+        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+        if let Some(hook) = self.opcode_hook.as_mut() {
+            hook(&opcode, HookPhase::After);
+        }
+        self.metrics.peak_pages = self.metrics.peak_pages.max(self.memory.len());
+        let stack = self.stack_view();
+        for step in &mut self.trace[first_step..] {
+            step.duration_ms = elapsed_ms;
+            step.stack = stack.clone();
+        }
+        if let Some(before) = pages_before {
+            let changes = page_changes(&before, &self.page_fingerprints());
+            if let Some(step) = self.trace[first_step..].last_mut() {
+                step.memory = changes;
+            }
+        }
+        for warning in self.memory.take_warnings() {
+            if let Some(ref cb) = self.log_callback {
+                cb(LogLevel::Warn, &warning);
             }
+        }
 
-            let opcode = self.program.code[self.pc].clone();
-            self.steps += 1;
+        match outcome? {
+            StepResult::Continue => {
+                self.pc += 1;
+                Ok(None)
+            }
+            StepResult::Jump(target) => {
+                self.pc = self.labels.get(&target)
+                    .copied()
+                    .ok_or_else(|| error::label_not_found(&target))?;
+                Ok(None)
+            }
+            StepResult::JumpTo(target) => {
+                self.pc = target;
+                Ok(None)
+            }
+            StepResult::Complete(result) => {
+                self.record_step("COMPLETE", &format!("{:?}", result), None);
+                Ok(Some(ExecutionResult::Complete(result)))
+            }
+            StepResult::Fail(error) => {
+                self.record_step("FAIL", &error, Some(error.clone()));
+                Ok(Some(ExecutionResult::Failed(error)))
+            }
+            StepResult::NeedsLlm(request) => {
+                self.metrics.infer_calls += match &request.request_type {
+                    LlmRequestType::InferBatch { prompts, .. } => prompts.len(),
+                    _ => 1,
+                };
+                self.pending_llm = Some(request.clone());
+                Ok(Some(ExecutionResult::NeedsLlm(request)))
+            }
+            StepResult::Break | StepResult::NextIteration => Err(error::outside_loop(opcode.format_parts().0)),
+        }
+    }
 
-            match self.execute_opcode(&opcode)? {
-                StepResult::Continue => {
-                    self.pc += 1;
+    fn execute_opcode(&mut self, opcode: &Opcode) -> Result<StepResult> {
+        *self.metrics.opcode_counts.entry(opcode.format_parts().0.to_string()).or_default() += 1;
+        if self.dry_run && opcode.has_side_effects() {
+            return self.skip_opcode(opcode);
+        }
+        if opcode.has_side_effects() {
+            if let Some(hook) = self.approval_hook.clone() {
+                match hook(opcode) {
+                    Approval::Allow => {}
+                    Approval::Deny => return self.deny_opcode(opcode),
+                    Approval::Modify(replacement) => return self.dispatch_opcode(&replacement),
                 }
-                StepResult::Jump(target) => {
-                    self.pc = self.labels.get(&target)
-                        .copied()
-                        .ok_or_else(|| error::label_not_found(&target))?;
+            }
+        }
+        self.dispatch_opcode(opcode)
+    }
+
+    /// Interpreter for forked child `pid`, sharing this one's syscall
+    /// handler, program registry, approval hook and mailboxes
+    fn child_interpreter(&self, pid: &str, state: ExecutionState) -> Interpreter<Arc<dyn SyscallHandler>> {
+        let handler: Arc<dyn SyscallHandler> = self.syscall_handler.clone();
+        let mut interp = Interpreter::resume(state, handler);
+        interp.pid = pid.to_string();
+        interp.parent_pid = Some(self.pid.clone());
+        interp.mailboxes = self.mailboxes.clone();
+        interp.mailboxes.set_running(1);
+        interp.programs = self.programs.clone();
+        interp.approval_hook = self.approval_hook.clone();
+        interp.dry_run = self.dry_run;
+        interp.deterministic = self.deterministic;
+        interp.inline_results = self.inline_results;
+        interp.trace_memory = self.trace_memory;
+        interp.max_steps = self.max_steps;
+        interp.max_sleep = self.max_sleep;
+        interp.fork_depth = self.fork_depth + 1;
+        interp.session_manager = None;
+        interp
+    }
+
+    /// Body of a forked child's thread. LLM requests go up to the parent and
+    /// the child waits for the answer, staying live for its siblings' RECVs.
+    fn run_forked(
+        mut self,
+        events: mpsc::Sender<ChildEvent>,
+        answers: mpsc::Receiver<(serde_json::Value, String)>,
+    ) {
+        let pid = self.pid.clone();
+        let parent = self.parent_pid.clone().unwrap_or_default();
+        let result = loop {
+            match self.run() {
+                Ok(ExecutionResult::NeedsLlm(request)) => match request.request_type {
+                    LlmRequestType::Inject { .. } | LlmRequestType::InferBatch { .. } => break serde_json::json!({
+                        "success": false,
+                        "error": "forked programs cannot use INJECT or INFER_BATCH"
+                    }),
+                    _ => {
+                        self.mailboxes.set_llm_requests(&parent, 1);
+                        let answer = events.send(ChildEvent::Llm(pid.clone(), request))
+                            .ok()
+                            .and_then(|_| answers.recv().ok());
+                        let Some((response, store_to)) = answer else {
+                            break serde_json::json!({ "success": false, "error": "parent exited" });
+                        };
+                        if let Err(e) = self.provide_llm_response(response, &store_to) {
+                            break serde_json::json!({ "success": false, "error": e.to_string() });
+                        }
+                    }
+                },
+                Ok(ExecutionResult::Complete(result)) => break serde_json::json!({ "success": true, "result": result }),
+                Ok(ExecutionResult::Failed(error)) => break serde_json::json!({ "success": false, "error": error }),
+                Ok(ExecutionResult::StepLimitExceeded) => break serde_json::json!({
+                    "success": false,
+                    "error": "step limit exceeded"
+                }),
+                Err(e) => break serde_json::json!({ "success": false, "error": e.to_string() }),
+            }
+        };
+        // Leave the process tree before reporting, so siblings waiting in
+        // RECV see one fewer sender
+        drop(self);
+        let _ = events.send(ChildEvent::Done(pid, result));
+    }
+
+    /// Record what a forked child reported
+    fn handle_child_event(&mut self, event: ChildEvent) {
+        match event {
+            ChildEvent::Llm(pid, request) => {
+                self.mailboxes.set_llm_requests(&self.pid, -1);
+                self.child_llm.push_back((pid, request));
+            }
+            ChildEvent::Done(pid, result) => {
+                if let Some(child) = self.children.remove(&pid) {
+                    let _ = child.thread.join();
                 }
-                StepResult::Complete(result) => {
-                    self.record_step("COMPLETE", &format!("{:?}", result), None);
-                    return Ok(ExecutionResult::Complete(result));
+                self.mailboxes.close(&pid);
+                self.joined.insert(pid, result);
+            }
+        }
+    }
+
+    /// The next LLM request from a forked child, if one is waiting
+    fn child_llm_request(&mut self) -> Option<LlmRequest> {
+        while let Ok(event) = self.child_events.1.try_recv() {
+            self.handle_child_event(event);
+        }
+        let (pid, request) = self.child_llm.pop_front()?;
+        self.llm_child = Some(pid);
+        Some(request)
+    }
+
+    /// Wait for a forked child to report. While waiting this process can't
+    /// send, so it counts as blocked for RECV.
+    fn wait_for_child(&mut self) {
+        self.mailboxes.set_blocked(1);
+        let event = self.child_events.1.recv_timeout(CHILD_POLL);
+        self.mailboxes.set_blocked(-1);
+        match event {
+            Ok(event) => self.handle_child_event(event),
+            Err(_) => {
+                while let Ok(event) = self.child_events.1.try_recv() {
+                    self.handle_child_event(event);
                 }
-                StepResult::Fail(error) => {
-                    self.record_step("FAIL", &error, Some(error.clone()));
-                    return Ok(ExecutionResult::Failed(error));
+                // Done is sent before the thread ends, so a finished child
+                // still listed here panicked
+                let panicked: Vec<String> = self.children.iter()
+                    .filter(|(_, child)| child.thread.is_finished())
+                    .map(|(pid, _)| pid.clone())
+                    .collect();
+                for pid in panicked {
+                    let result = serde_json::json!({ "success": false, "error": "child panicked" });
+                    self.handle_child_event(ChildEvent::Done(pid, result));
                 }
-                StepResult::NeedsLlm(request) => {
-                    return Ok(ExecutionResult::NeedsLlm(request));
+            }
+        }
+    }
+
+    /// Run nested opcodes in place, returning early on COMPLETE/FAIL. Jumps and
+    /// LLM requests can't resume in the middle of a block, so they're rejected.
+    /// Errors come back with the name of the opcode that raised them.
+    fn run_block(
+        &mut self,
+        block: &str,
+        ops: &[Opcode],
+    ) -> std::result::Result<Option<StepResult>, (&'static str, error::Error)> {
+        for op in ops {
+            let name = op.format_parts().0;
+            match self.execute_opcode(op) {
+                Ok(StepResult::Continue) => {}
+                Ok(
+                    done @ (StepResult::Complete(_)
+                    | StepResult::Fail(_)
+                    | StepResult::Break
+                    | StepResult::NextIteration),
+                ) => return Ok(Some(done)),
+                Ok(StepResult::Jump(_) | StepResult::JumpTo(_) | StepResult::NeedsLlm(_)) => {
+                    return Err((name, error::not_allowed_in_block(name, block)))
                 }
+                Err(err) => return Err((name, err)),
             }
         }
+        Ok(None)
+    }
+
+    /// Skip a denied opcode, recording the denial in its result page
+    fn deny_opcode(&mut self, opcode: &Opcode) -> Result<StepResult> {
+        let result = serde_json::json!({
+            "success": false,
+            "denied": true,
+            "error": "Denied by approval hook"
+        });
+        for page_id in opcode.writes_pages() {
+            self.memory.store(page_id, result.clone())?;
+        }
+        let (name, details) = opcode.format_parts();
+        self.record_step(name, &details, Some("Denied by approval hook".to_string()));
+        Ok(StepResult::Continue)
+    }
 
-        // Implicit completion if we run off the end
-        Ok(ExecutionResult::Complete(serde_json::json!({
-            "status": "completed",
-            "message": "Program ended without explicit COMPLETE"
-        })))
+    fn skip_opcode(&mut self, opcode: &Opcode) -> Result<StepResult> {
+        let (name, details) = opcode.format_parts();
+        let message = format!("dry run: would {} {}", name, details);
+        if let Some(ref cb) = self.log_callback {
+            cb(LogLevel::Info, &message);
+        }
+        let result = serde_json::json!({ "success": true, "dry_run": true });
+        for page_id in opcode.writes_pages() {
+            self.memory.store(page_id, result.clone())?;
+        }
+        self.record_step(name, &message, None);
+        Ok(StepResult::Continue)
     }
 
-    fn execute_opcode(&mut self, opcode: &Opcode) -> Result<StepResult> {
+    fn dispatch_opcode(&mut self, opcode: &Opcode) -> Result<StepResult> {
         match opcode {
             // Labels are no-ops during execution
             Opcode::Label { name } => {
@@ -523,6 +1792,29 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Continue)
             }
 
+            Opcode::Print { source, message } => {
+                let text = match (source, message) {
+                    (Some(source), _) => match self.resolve_source(source)? {
+                        serde_json::Value::String(s) => s.clone(),
+                        value => serde_json::to_string_pretty(value).unwrap_or_default(),
+                    },
+                    (None, Some(message)) => message.clone(),
+                    (None, None) => {
+                        return Err(error::invalid_argument("PRINT needs a source or a message"));
+                    }
+                };
+                if let Some(ref cb) = self.output_callback {
+                    cb(&text);
+                } else if let Some(ref cb) = self.log_callback {
+                    cb(LogLevel::Info, &text);
+                }
+                if let Some(source) = source {
+                    self.memory.record_read(source.split('.').next().unwrap_or(source));
+                }
+                self.record_step("PRINT", &text, None);
+                Ok(StepResult::Continue)
+            }
+
             // Memory operations
             Opcode::Load { page_id, .. } => {
                 let _ = self.memory.load(page_id)?;
@@ -536,6 +1828,21 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Continue)
             }
 
+            Opcode::Append { page_id, value } => {
+                let mut items = match self.memory.get(page_id).map(|page| &page.content) {
+                    None | Some(serde_json::Value::Null) => Vec::new(),
+                    Some(serde_json::Value::Array(items)) => items.clone(),
+                    Some(_) => {
+                        return Err(error::invalid_argument(format!("APPEND: '{}' is not an array", page_id)));
+                    }
+                };
+                items.push(value.clone());
+                let count = items.len();
+                self.memory.store(page_id, serde_json::Value::Array(items))?;
+                self.record_step("APPEND", &format!("{} ({} items)", page_id, count), None);
+                Ok(StepResult::Continue)
+            }
+
             Opcode::Alloc { label, .. } => {
                 let id = self.memory.alloc(label.clone())?;
                 self.record_step("ALLOC", &id, None);
@@ -554,6 +1861,12 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Continue)
             }
 
+            Opcode::Pin { page_id } => {
+                self.memory.pin(page_id)?;
+                self.record_step("PIN", page_id, None);
+                Ok(StepResult::Continue)
+            }
+
             // Stack operations
             Opcode::Push { value } => {
                 self.stack.push(value.clone())?;
@@ -626,9 +1939,7 @@ impl<S: SyscallHandler> Interpreter<S> {
             }
 
             Opcode::Drop { n } => {
-                for _ in 0..*n {
-                    self.stack.pop()?;
-                }
+                self.stack.drop_n(*n)?;
                 self.record_step("DROP", &format!("{}", n), None);
                 Ok(StepResult::Continue)
             }
@@ -659,8 +1970,26 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Jump(target.clone()))
             }
 
+            Opcode::Switch { value, cases, default } => {
+                let actual = self.resolve_path(value).cloned().unwrap_or(serde_json::Value::Null);
+                let target = cases
+                    .iter()
+                    .find(|(case, _)| *case == actual)
+                    .map(|(_, label)| label)
+                    .unwrap_or(default);
+                self.record_step("SWITCH", &format!("{} = {} -> {}", value, actual, target), None);
+                Ok(StepResult::Jump(target.clone()))
+            }
+
             Opcode::Complete { result } => {
-                Ok(StepResult::Complete(result.clone()))
+                if !self.inline_results {
+                    return Ok(StepResult::Complete(result.clone()));
+                }
+                let result = self.inline_pages(result)?;
+                if is_empty_result(&result) {
+                    return Err(error::invalid_argument("COMPLETE result is empty"));
+                }
+                Ok(StepResult::Complete(result))
             }
 
             Opcode::Fail { error } => {
@@ -669,16 +1998,18 @@ impl<S: SyscallHandler> Interpreter<S> {
 
             // Tool operations - explicit file/exec tools
             Opcode::ReadFile { path, store_to } => {
-                let result = self.syscall_handler.call("read_file", &serde_json::json!({"path": path}))?;
+                let result = self.syscall("read_file", &serde_json::json!({"path": path}))?;
                 self.memory.store(store_to, result)?;
+                self.memory.set_label(store_to, file_content_type(path))?;
                 self.record_step("READ_FILE", path, None);
                 Ok(StepResult::Continue)
             }
 
-            Opcode::WriteFile { path, content, store_to } => {
-                let result = self.syscall_handler.call("write_file", &serde_json::json!({
+            Opcode::WriteFile { path, content, backup, store_to } => {
+                let result = self.syscall("write_file", &serde_json::json!({
                     "path": path,
-                    "content": content
+                    "content": content,
+                    "backup": backup
                 }))?;
                 if let Some(page_id) = store_to {
                     self.memory.store(page_id, result)?;
@@ -688,34 +2019,118 @@ impl<S: SyscallHandler> Interpreter<S> {
             }
 
             Opcode::ListDir { path, store_to } => {
-                let result = self.syscall_handler.call("list_dir", &serde_json::json!({"path": path}))?;
+                let result = self.syscall("list_dir", &serde_json::json!({"path": path}))?;
                 self.memory.store(store_to, result)?;
                 self.record_step("LIST_DIR", path, None);
                 Ok(StepResult::Continue)
             }
 
-            Opcode::Exec { command, store_to } => {
-                let result = self.syscall_handler.call("exec", &serde_json::json!({"command": command}))?;
+            Opcode::Exec { command, stdin, cwd, env, store_to } => {
+                let mut args = serde_json::json!({"command": command});
+                if let Some(stdin) = stdin {
+                    args["stdin"] = serde_json::json!(stdin);
+                }
+                if let Some(cwd) = cwd {
+                    args["cwd"] = serde_json::json!(cwd);
+                }
+                if !env.is_empty() {
+                    args["env"] = serde_json::json!(env);
+                }
+                let result = self.syscall("exec", &args)?;
                 self.memory.store(store_to, result)?;
+                self.memory.set_label(store_to, "command_output")?;
                 self.record_step("EXEC", command, None);
                 Ok(StepResult::Continue)
             }
 
+            Opcode::HttpRequest { method, url, headers, body, store_to } => {
+                let mut args = serde_json::json!({"method": method, "url": url, "headers": headers});
+                if let Some(body) = body {
+                    args["body"] = serde_json::json!(body);
+                }
+                let result = self.syscall("http_request", &args)?;
+                self.memory.store(store_to, result)?;
+                self.record_step("HTTP_REQUEST", &format!("{} {}", method, url), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::GetEnv { name, store_to, default } => {
+                let result = self.syscall("get_env", &serde_json::json!({"name": name}))?;
+                let value = match result.get("value") {
+                    Some(serde_json::Value::String(value)) => serde_json::json!(value),
+                    _ => serde_json::json!(default),
+                };
+                self.memory.store(store_to, value)?;
+                self.record_step("GET_ENV", &format!("{} -> {}", name, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Sleep { ms } => {
+                let duration = Duration::from_millis(*ms);
+                if duration > self.max_sleep {
+                    return Err(error::invalid_argument(format!(
+                        "SLEEP: {}ms is longer than the {}ms limit",
+                        ms,
+                        self.max_sleep.as_millis()
+                    )));
+                }
+                std::thread::sleep(duration);
+                self.record_step("SLEEP", &format!("{}ms", ms), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Timestamp { store_to } => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.memory.store(store_to, serde_json::json!(now))?;
+                self.record_step("TIMESTAMP", &format!("{} -> {}", now, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Uuid { store_to } => {
+                let id = if self.deterministic {
+                    let mut bytes = [0u8; 16];
+                    for (half, salt) in bytes.chunks_mut(8).zip([0u8, 1]) {
+                        let mut hasher = DefaultHasher::new();
+                        (&self.program.id, &self.pid, self.steps, salt).hash(&mut hasher);
+                        half.copy_from_slice(&hasher.finish().to_le_bytes());
+                    }
+                    uuid::Builder::from_random_bytes(bytes).into_uuid().to_string()
+                } else {
+                    uuid::Uuid::new_v4().to_string()
+                };
+                self.memory.store(store_to, serde_json::json!(id))?;
+                self.record_step("UUID", &format!("{} -> {}", id, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
             Opcode::Grep { pattern, path, store_to } => {
-                let result = self.syscall_handler.call("grep", &serde_json::json!({
+                let result = self.syscall("grep", &serde_json::json!({
                     "pattern": pattern,
                     "path": path
                 }))?;
                 self.memory.store(store_to, result)?;
+                self.memory.set_label(store_to, "search_results")?;
                 self.record_step("GREP", &format!("{} in {}", pattern, path), None);
                 Ok(StepResult::Continue)
             }
 
             // LLM operations - these pause execution
-            Opcode::Infer { prompt, context, store_to, .. } => {
+            Opcode::Infer { prompt, context, store_to, params, history_page } => {
+                if let Some(page_id) = history_page {
+                    self.history_messages(page_id)?;
+                }
+                for page_id in context {
+                    self.memory.record_read(page_id);
+                }
                 self.record_step("INFER", "awaiting LLM response", None);
                 Ok(StepResult::NeedsLlm(LlmRequest {
-                    request_type: LlmRequestType::Infer,
+                    request_type: LlmRequestType::Infer {
+                        params: params.clone(),
+                        history_page: history_page.clone(),
+                    },
                     prompt: prompt.clone(),
                     context_pages: context.clone(),
                     store_to: store_to.clone(),
@@ -724,6 +2139,9 @@ impl<S: SyscallHandler> Interpreter<S> {
             }
 
             Opcode::Plan { goal, context, store_to } => {
+                for page_id in context {
+                    self.memory.record_read(page_id);
+                }
                 self.record_step("PLAN", "awaiting LLM response", None);
                 Ok(StepResult::NeedsLlm(LlmRequest {
                     request_type: LlmRequestType::Plan,
@@ -747,6 +2165,9 @@ impl<S: SyscallHandler> Interpreter<S> {
 
             // JIT code injection - LLM generates opcodes at runtime
             Opcode::Inject { goal, context, include_trace, include_memory } => {
+                for page_id in context {
+                    self.memory.record_read(page_id);
+                }
                 self.record_step("INJECT", "awaiting LLM to generate opcodes", None);
                 Ok(StepResult::NeedsLlm(LlmRequest {
                     request_type: LlmRequestType::Inject {
@@ -765,6 +2186,7 @@ impl<S: SyscallHandler> Interpreter<S> {
                 // Gather content from pages
                 let mut content = String::new();
                 for page_id in pages {
+                    self.memory.record_read(page_id);
                     if let Some(page) = self.memory.get(page_id) {
                         content.push_str(&format!("## {}\n{}\n\n", page_id, page.content));
                     }
@@ -778,7 +2200,7 @@ impl<S: SyscallHandler> Interpreter<S> {
 
                 self.record_step("SUMMARIZE", "awaiting LLM response", None);
                 Ok(StepResult::NeedsLlm(LlmRequest {
-                    request_type: LlmRequestType::Infer,
+                    request_type: LlmRequestType::Infer { params: Default::default(), history_page: None },
                     prompt,
                     context_pages: vec![],
                     store_to: store_to.clone(),
@@ -791,6 +2213,7 @@ impl<S: SyscallHandler> Interpreter<S> {
                 let sep = separator.as_deref().unwrap_or("\n\n");
                 let mut parts = Vec::new();
                 for page_id in pages {
+                    self.memory.record_read(page_id);
                     if let Some(page) = self.memory.get(page_id) {
                         parts.push(page.content.to_string());
                     }
@@ -801,6 +2224,108 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Continue)
             }
 
+            Opcode::JsonParse { source, store_to } => {
+                let text = self.resolve_source(source)?
+                    .as_str()
+                    .ok_or_else(|| error::invalid_argument(format!("JSON_PARSE: '{}' is not a string", source)))?;
+                let value: serde_json::Value = serde_json::from_str(text)
+                    .map_err(|e| error::parse_error(format!("JSON_PARSE: invalid JSON in '{}': {}", source, e)))?;
+                self.memory.store(store_to, value)?;
+                self.record_step("JSON_PARSE", &format!("{} -> {}", source, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::JsonStringify { source, store_to, pretty } => {
+                let value = self.resolve_source(source)?;
+                let text = if *pretty {
+                    serde_json::to_string_pretty(value)
+                } else {
+                    serde_json::to_string(value)
+                }
+                .map_err(|e| error::serialization_error(e.to_string()))?;
+                self.memory.store(store_to, serde_json::json!(text))?;
+                self.record_step("JSON_STRINGIFY", &format!("{} -> {}", source, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Len { source, store_to } => {
+                let len = match self.resolve_path(source) {
+                    None | Some(serde_json::Value::Null) => 0,
+                    Some(serde_json::Value::Array(items)) => items.len(),
+                    Some(serde_json::Value::Object(map)) => map.len(),
+                    Some(serde_json::Value::String(text)) => text.chars().count(),
+                    Some(other) => {
+                        return Err(error::invalid_argument(format!("LEN: '{}' has no length ({})", source, other)));
+                    }
+                };
+                self.memory.store(store_to, serde_json::json!(len))?;
+                self.record_step("LEN", &format!("{} = {}", source, len), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::RegexReplace { source, pattern, replacement, store_to, all } => {
+                let text = self.resolve_source(source)?
+                    .as_str()
+                    .ok_or_else(|| error::invalid_argument(format!("REGEX_REPLACE: '{}' is not a string", source)))?;
+                let re = regex::Regex::new(pattern)
+                    .map_err(|e| error::invalid_argument(format!("REGEX_REPLACE: invalid pattern '{}': {}", pattern, e)))?;
+                let replaced = if *all {
+                    re.replace_all(text, replacement.as_str())
+                } else {
+                    re.replace(text, replacement.as_str())
+                }
+                .into_owned();
+                self.memory.store(store_to, serde_json::json!(replaced))?;
+                self.record_step("REGEX_REPLACE", &format!("{} -> {}", source, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Diff { old, new, store_to } => {
+                let old_text = diff_text(self.resolve_source(old)?);
+                let new_text = diff_text(self.resolve_source(new)?);
+                let diff = similar::TextDiff::from_lines(&old_text, &new_text);
+
+                let (mut added, mut removed) = (0, 0);
+                for change in diff.iter_all_changes() {
+                    match change.tag() {
+                        similar::ChangeTag::Insert => added += 1,
+                        similar::ChangeTag::Delete => removed += 1,
+                        similar::ChangeTag::Equal => {}
+                    }
+                }
+                let text = if added + removed == 0 {
+                    String::new()
+                } else {
+                    diff.unified_diff().header(old, new).to_string()
+                };
+
+                self.memory.store(store_to, serde_json::json!({
+                    "success": true,
+                    "diff": text,
+                    "added": added,
+                    "removed": removed
+                }))?;
+                self.record_step("DIFF", &format!("{} -> {}: +{} -{}", old, new, added, removed), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Filter { over, condition, store_to } => {
+                let items = self.resolve_source(over)?
+                    .as_array()
+                    .ok_or_else(|| error::invalid_argument(format!("FILTER: '{}' is not an array", over)))?;
+                let matches: Vec<serde_json::Value> = items
+                    .iter()
+                    .filter(|item| eval_condition(condition, |path| self.resolve_with_item(path, item)))
+                    .cloned()
+                    .collect();
+                let count = matches.len();
+
+                self.memory.store(store_to, serde_json::Value::Array(matches))?;
+                self.memory.store(format!("{}_count", store_to), serde_json::json!(count))?;
+                self.record_step("FILTER", &format!("{} matched in {} -> {}", count, over, store_to), None);
+                Ok(StepResult::Continue)
+            }
+
             // Chunk - split a page into smaller pieces
             Opcode::Chunk { source, chunk_size, prefix } => {
                 let content = self.memory.load(source)?.to_string();
@@ -820,34 +2345,76 @@ impl<S: SyscallHandler> Interpreter<S> {
 
             // Register operations
             Opcode::SetReg { reg, value } => {
-                let reg_name = format!("{:?}", reg).to_lowercase();
-                self.registers.insert(reg_name.clone(), value.clone());
-                self.record_step("SET_REG", &reg_name, None);
-                Ok(StepResult::Continue)
+                let as_index = || value.as_u64().map(|n| n as usize)
+                    .ok_or_else(|| error::invalid_argument(format!("register {} must be a non-negative integer", reg.name())));
+                self.record_step("SET_REG", reg.name(), None);
+                match reg {
+                    Register::Pc => {
+                        let target = as_index()?;
+                        if target >= self.program.code.len() {
+                            return Err(error::invalid_argument(format!(
+                                "pc {} is past the end of the program ({} opcodes)", target, self.program.code.len()
+                            )));
+                        }
+                        Ok(StepResult::JumpTo(target))
+                    }
+                    Register::Sp => {
+                        let depth = self.stack.len();
+                        let target = as_index()?;
+                        if target > depth {
+                            return Err(error::invalid_argument(format!(
+                                "sp {} is above the top of the stack (depth {})", target, depth
+                            )));
+                        }
+                        self.stack.drop_n(depth - target)?;
+                        Ok(StepResult::Continue)
+                    }
+                    _ => {
+                        self.registers.insert(reg.name().to_string(), value.clone());
+                        Ok(StepResult::Continue)
+                    }
+                }
             }
 
             Opcode::GetReg { reg, store_to } => {
-                let reg_name = format!("{:?}", reg).to_lowercase();
-                let value = self.registers.get(&reg_name).cloned().unwrap_or(serde_json::Value::Null);
+                let value = match reg {
+                    Register::Pc => serde_json::json!(self.pc),
+                    Register::Sp => serde_json::json!(self.stack.len()),
+                    _ => self.registers.get(reg.name()).cloned().unwrap_or(serde_json::Value::Null),
+                };
                 self.memory.store(store_to, value)?;
-                self.record_step("GET_REG", &format!("{} -> {}", reg_name, store_to), None);
+                self.record_step("GET_REG", &format!("{} -> {}", reg.name(), store_to), None);
                 Ok(StepResult::Continue)
             }
 
-            // Checkpoint/Rollback - simplified implementation
-            Opcode::Checkpoint { name } => {
-                // Store current state as a checkpoint
-                let state = self.state();
-                self.registers.insert(
-                    format!("__checkpoint_{}", name),
-                    serde_json::to_value(&state).unwrap_or_default(),
-                );
+            Opcode::Checkpoint { name, persist } => {
+                let snapshot = self.save_state();
+                if *persist {
+                    let (session, manager) = self.session.as_ref().zip(self.session_manager.as_ref())
+                        .ok_or_else(|| error::not_initialized("No active session or manager"))?;
+                    let data = serde_json::to_value(&snapshot)
+                        .map_err(|e| error::serialization_error(e.to_string()))?;
+                    manager.save_checkpoint(&session.metadata.id, name, data)?;
+                }
+                self.checkpoints.insert(name.clone(), snapshot);
                 self.record_step("CHECKPOINT", name, None);
                 Ok(StepResult::Continue)
             }
 
             Opcode::Rollback { name } => {
-                // For now, just log - full rollback would need more infrastructure
+                let snapshot = match self.checkpoints.get(name) {
+                    Some(snapshot) => snapshot.clone(),
+                    None => self.load_persisted_checkpoint(name)?,
+                };
+                // Data only: pc is not restored (the checkpoint may come from
+                // another program), so execution carries on after the ROLLBACK.
+                // The token counter and page budget stay as configured.
+                self.memory.clear();
+                for page in snapshot.memory.pages_by_lru() {
+                    self.memory.store_page(page.clone())?;
+                }
+                self.stack = snapshot.stack;
+                self.registers = snapshot.registers;
                 self.record_step("ROLLBACK", name, None);
                 Ok(StepResult::Continue)
             }
@@ -875,14 +2442,103 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Err(error::not_implemented("CALL"))
             }
 
+            Opcode::Include { program } => {
+                Err(error::invalid_argument(format!(
+                    "INCLUDE '{}' was not resolved; call Program::resolve_includes before running",
+                    program
+                )))
+            }
+
             Opcode::Return { .. } => {
                 self.record_step("RETURN", "", Some("Not implemented".to_string()));
                 Err(error::not_implemented("RETURN"))
             }
 
-            Opcode::Loop { .. } => {
-                self.record_step("LOOP", "", Some("Not implemented".to_string()));
-                Err(error::not_implemented("LOOP"))
+            Opcode::Loop { var, over, body } => {
+                let items = match self.resolve_path(over) {
+                    Some(serde_json::Value::Array(items)) => items.clone(),
+                    Some(_) => {
+                        return Err(error::invalid_argument(format!("LOOP: '{}' is not an array", over)))
+                    }
+                    None => match serde_json::from_str(over) {
+                        Ok(serde_json::Value::Array(items)) => items,
+                        _ => return Err(error::page_not_found(over)),
+                    },
+                };
+
+                self.loop_stack.push(var.clone());
+                let mut ran = 0;
+                let mut outcome = Ok(None);
+                for item in items {
+                    if let Err(e) = self.memory.store(var, item) {
+                        outcome = Err(e);
+                        break;
+                    }
+                    ran += 1;
+                    // Each iteration's own pages are dropped when it ends
+                    self.memory.push_scope();
+                    let iteration = self.run_block("LOOP", body);
+                    self.memory.pop_scope();
+                    match iteration {
+                        Ok(Some(StepResult::Break)) => break,
+                        Ok(None | Some(StepResult::NextIteration)) => {}
+                        other => {
+                            outcome = other.map_err(|(_, err)| err);
+                            break;
+                        }
+                    }
+                }
+                self.loop_stack.pop();
+
+                match outcome? {
+                    Some(done) => Ok(done),
+                    None => {
+                        self.record_step("LOOP", &format!("{} iterations over {}", ran, over), None);
+                        Ok(StepResult::Continue)
+                    }
+                }
+            }
+
+            Opcode::Break { condition } | Opcode::Continue { condition } => {
+                let (name, _) = opcode.format_parts();
+                let Some(var) = self.loop_stack.last().cloned() else {
+                    return Err(error::outside_loop(name));
+                };
+                if let Some(condition) = condition {
+                    if !self.evaluate_condition(condition)? {
+                        self.record_step(name, &format!("{} is false", condition), None);
+                        return Ok(StepResult::Continue);
+                    }
+                }
+                self.record_step(name, &format!("loop over {}", var), None);
+                Ok(match opcode {
+                    Opcode::Break { .. } => StepResult::Break,
+                    _ => StepResult::NextIteration,
+                })
+            }
+
+            Opcode::Try { body, catch } => {
+                let (op, err) = match self.run_block("TRY", body) {
+                    Ok(Some(done)) => return Ok(done),
+                    Ok(None) => {
+                        self.record_step("TRY", "body completed", None);
+                        return Ok(StepResult::Continue);
+                    }
+                    Err((op, err)) if is_catchable(&err) => (op, err),
+                    Err((_, err)) => return Err(err),
+                };
+
+                self.memory.store(ERROR_PAGE, serde_json::json!({
+                    "message": err.to_string(),
+                    "kind": err.kind().as_str(),
+                    "op": op,
+                }))?;
+                self.record_step("TRY", &format!("caught error in {}", op), Some(err.to_string()));
+
+                match self.run_block("CATCH", catch).map_err(|(_, err)| err)? {
+                    Some(done) => Ok(done),
+                    None => Ok(StepResult::Continue),
+                }
             }
 
             Opcode::Spawn { task_id, task } => {
@@ -892,17 +2548,79 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Continue)
             }
 
-            Opcode::Join { task_ids, store_to } => {
-                // Collect results from spawned tasks (or all if empty)
+            Opcode::Fork { program_id, args, store_to } => {
+                let program = self.programs.get(program_id).cloned()
+                    .ok_or_else(|| error::program_not_found(program_id))?;
+                if self.fork_depth >= MAX_FORK_DEPTH {
+                    return Err(error::call_depth_exceeded(MAX_FORK_DEPTH));
+                }
+                if self.children.len() >= MAX_CHILDREN {
+                    return Err(error::fork_failed(format!(
+                        "too many live children (max {}); JOIN some first", MAX_CHILDREN
+                    )));
+                }
+
+                // The child reads the parent's pages as they are now; its own
+                // writes stay in its scope
+                let pid = self.mailboxes.open();
+                let mut memory = self.memory.clone();
+                memory.push_scope();
+                let state = ExecutionState {
+                    pc: 0,
+                    program,
+                    memory,
+                    stack: Stack::new(),
+                    registers: HashMap::new(),
+                    trace: Vec::new(),
+                    steps: 0,
+                };
+                let mut child = self.child_interpreter(&pid, state);
+                child.memory.store("args", args.clone())?;
+                let events = self.child_events.0.clone();
+                let (answers, answer_rx) = mpsc::channel();
+                let thread = std::thread::spawn(move || child.run_forked(events, answer_rx));
+                self.children.insert(pid.clone(), ChildHandle { answers, thread });
+                self.memory.store(store_to, serde_json::json!(pid))?;
+                self.record_step("FORK", &format!("{} → {}", program_id, pid), None);
+                Ok(StepResult::Continue)
+            }
+
+            Opcode::Join { task_ids, store_to } => {
+                // Collect results from spawned tasks and forked children (or all if empty)
                 let ids: Vec<String> = if task_ids.is_empty() {
-                    self.pending_tasks.keys().cloned().collect()
+                    let mut ids: Vec<String> = self.pending_tasks.keys()
+                        .chain(self.children.keys())
+                        .chain(self.joined.keys())
+                        .cloned()
+                        .collect();
+                    ids.sort();
+                    ids
                 } else {
                     task_ids.clone()
                 };
 
-                // For now, execute tasks sequentially (async runtime would parallelize)
+                // Wait for the forked children, handing their LLM requests up
+                // as they come; this JOIN runs again once a request is answered
+                loop {
+                    if let Some(request) = self.child_llm_request() {
+                        let pid = self.llm_child.clone().unwrap_or_default();
+                        self.record_step("JOIN", &format!("{} awaiting LLM response", pid), None);
+                        return Ok(StepResult::NeedsLlm(request));
+                    }
+                    if !ids.iter().any(|id| self.children.contains_key(id)) {
+                        break;
+                    }
+                    self.wait_for_child();
+                }
+
                 let mut results = serde_json::Map::new();
                 let mut all_success = true;
+                for id in &ids {
+                    if let Some(result) = self.joined.remove(id) {
+                        all_success &= result["success"] == true;
+                        results.insert(id.clone(), result);
+                    }
+                }
 
                 for id in &ids {
                     if let Some(task) = self.pending_tasks.remove(id) {
@@ -1115,6 +2833,37 @@ impl<S: SyscallHandler> Interpreter<S> {
                 Ok(StepResult::Continue)
             }
 
+            Opcode::SearchPages { query, top_k, store_to } => {
+                let found = match (&self.session, &self.session_manager) {
+                    (Some(session), Some(manager)) => manager.rank_pages(session, query, *top_k),
+                    _ => Err(error::not_initialized("No active session or manager")),
+                };
+
+                match found {
+                    Ok(matches) => {
+                        let pages: Vec<_> = matches.iter().map(|(id, _)| id.clone()).collect();
+                        let matches: Vec<_> = matches
+                            .iter()
+                            .map(|(id, score)| serde_json::json!({"page_id": id, "score": score}))
+                            .collect();
+                        self.memory.store(store_to.as_str(), serde_json::json!({
+                            "success": true,
+                            "pages": pages,
+                            "matches": matches
+                        }))?;
+                        self.record_step("SEARCH_PAGES", &format!("\"{}\" -> {} pages", query, pages.len()), None);
+                    }
+                    Err(e) => {
+                        self.memory.store(store_to.as_str(), serde_json::json!({
+                            "success": false,
+                            "error": e.to_string()
+                        }))?;
+                        self.record_step("SEARCH_PAGES", query, Some(e.to_string()));
+                    }
+                }
+                Ok(StepResult::Continue)
+            }
+
             Opcode::SavePage { page_id, summary, content_type: _ } => {
                 if let (Some(ref mut session), Some(ref manager)) = (&mut self.session, &self.session_manager) {
                     // Get page from memory
@@ -1165,7 +2914,7 @@ impl<S: SyscallHandler> Interpreter<S> {
                         .iter()
                         .map(|p| serde_json::json!({
                             "id": p.id,
-                            "tokens": p.content.to_string().len() / 4,
+                            "tokens": p.size_tokens,
                             "loaded": true,
                         }))
                         .collect();
@@ -1278,57 +3027,88 @@ impl<S: SyscallHandler> Interpreter<S> {
                 }))
             }
 
-            Opcode::Send { .. } | Opcode::Recv { .. } | Opcode::Wait { .. } => {
-                Err(error::not_implemented("Process operations"))
-            }
-        }
-    }
+            // Map desugars to a batch with one prompt per element
+            Opcode::Map { over, prompt_template, store_to } => {
+                let items = self.resolve_source(over)?
+                    .as_array()
+                    .ok_or_else(|| error::invalid_argument(format!("MAP: '{}' is not an array", over)))?;
+                let prompts: Vec<String> = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| {
+                        let item = match item {
+                            serde_json::Value::String(s) => s.clone(),
+                            other => other.to_string(),
+                        };
+                        prompt_template.replace("{item}", &item).replace("{index}", &i.to_string())
+                    })
+                    .collect();
 
-    /// Evaluate a condition string
-    fn evaluate_condition(&self, condition: &str) -> Result<bool> {
-        // Simple condition evaluation
-        // Supports: page.field, page.success, page.error, comparisons
+                self.record_step("MAP", &format!("{} items from {} -> {}", prompts.len(), over, store_to), None);
 
-        let condition = condition.trim();
+                Ok(StepResult::NeedsLlm(LlmRequest {
+                    request_type: LlmRequestType::InferBatch {
+                        prompts,
+                        context: Vec::new(),
+                        store_prefix: store_to.clone(),
+                        store_combined: Some(store_to.clone()),
+                        params: Default::default(),
+                    },
+                    prompt: format!("MAP: {}", prompt_template),
+                    context_pages: Vec::new(),
+                    store_to: store_to.clone(),
+                    execution_state: self.state(),
+                }))
+            }
 
-        // Check for null/empty comparisons
-        if condition.contains("== null") || condition.contains("!= null") {
-            let parts: Vec<&str> = condition.split(|c| c == '=' || c == '!').collect();
-            if let Some(path) = parts.first() {
-                let path = path.trim();
-                let value = self.resolve_path(path);
-                let is_null = value.is_none() || value == Some(&serde_json::Value::Null);
-                return Ok(if condition.contains("!= null") { !is_null } else { is_null });
+            Opcode::Send { pid, message } => {
+                let target = match (pid.as_str(), &self.parent_pid) {
+                    ("parent", Some(parent)) => parent.clone(),
+                    _ => pid.clone(),
+                };
+                self.mailboxes.send(&self.pid, &target, message.clone())?;
+                self.record_step("SEND", &target, None);
+                Ok(StepResult::Continue)
             }
-        }
 
-        // Check for .error or .success
-        if condition.ends_with(".error") {
-            let page_id = condition.trim_end_matches(".error");
-            if let Some(page) = self.memory.get(page_id) {
-                let has_error = page.content.get("error").is_some()
-                    || page.content.get("success") == Some(&serde_json::json!(false));
-                return Ok(has_error);
+            Opcode::Recv { timeout_ms, store_to } => {
+                let timeout = timeout_ms.map(Duration::from_millis);
+                let Some(received) = self.mailboxes.recv(&self.pid, timeout) else {
+                    // A child asked for the LLM; pass it up and RECV again after
+                    while self.child_llm.is_empty() {
+                        match self.child_events.1.recv() {
+                            Ok(event) => self.handle_child_event(event),
+                            Err(_) => break,
+                        }
+                    }
+                    return match self.child_llm_request() {
+                        Some(request) => Ok(StepResult::NeedsLlm(request)),
+                        None => Ok(StepResult::JumpTo(self.pc)),
+                    };
+                };
+                self.memory.store(store_to, received)?;
+                self.record_step("RECV", store_to, None);
+                Ok(StepResult::Continue)
             }
-            return Ok(true); // Page not found = error
-        }
 
-        if condition.ends_with(".success") {
-            let page_id = condition.trim_end_matches(".success");
-            if let Some(page) = self.memory.get(page_id) {
-                let success = page.content.get("success") == Some(&serde_json::json!(true));
-                return Ok(success);
+            Opcode::Wait { .. } => {
+                Err(error::not_implemented("Process operations"))
             }
-            return Ok(false);
         }
+    }
 
-        // Check for truthiness of a path
-        if let Some(value) = self.resolve_path(condition) {
-            return Ok(is_truthy(value));
-        }
+    /// Evaluate a condition string
+    fn evaluate_condition(&self, condition: &str) -> Result<bool> {
+        Ok(eval_condition(condition, |path| self.resolve_path(path)))
+    }
 
-        // Default to false for unknown conditions
-        Ok(false)
+    /// Like `resolve_path`, but `item` / `item.field` refer to `item`
+    fn resolve_with_item<'a>(&'a self, path: &str, item: &'a serde_json::Value) -> Option<&'a serde_json::Value> {
+        match path.split_once('.') {
+            None if path == "item" => Some(item),
+            Some(("item", rest)) => rest.split('.').try_fold(item, |v, key| v.get(key)),
+            _ => self.resolve_path(path),
+        }
     }
 
     /// Resolve a path like "page.field.subfield" to a value
@@ -1348,6 +3128,11 @@ impl<S: SyscallHandler> Interpreter<S> {
         Some(current)
     }
 
+    /// Resolve a `page` or `page.field` path, erroring if it doesn't exist
+    fn resolve_source(&self, path: &str) -> Result<&serde_json::Value> {
+        self.resolve_path(path).ok_or_else(|| error::page_not_found(path))
+    }
+
     fn record_step(&mut self, opcode: &str, result: &str, error: Option<String>) {
         let step_num = self.trace.len();
 
@@ -1357,6 +3142,10 @@ impl<S: SyscallHandler> Interpreter<S> {
             opcode: opcode.to_string(),
             result: result.to_string(),
             error: error.clone(),
+            duration_ms: 0.0,
+            llm_ms: None,
+            stack: Vec::new(),
+            memory: Vec::new(),
         });
 
         // Also record to session if active
@@ -1366,21 +3155,176 @@ impl<S: SyscallHandler> Interpreter<S> {
     }
 }
 
+impl<S: SyscallHandler> Drop for Interpreter<S> {
+    fn drop(&mut self) {
+        // Nobody can get a message from this process any more
+        self.mailboxes.set_running(-1);
+    }
+}
+
+/// Pages that differ between two `page_fingerprints`, by page id
+fn page_changes(before: &HashMap<String, (u64, usize)>, after: &HashMap<String, (u64, usize)>) -> Vec<PageChange> {
+    let mut changes: Vec<PageChange> = after
+        .iter()
+        .filter_map(|(id, &(hash, tokens))| {
+            let (kind, old_tokens) = match before.get(id) {
+                None => (PageChangeKind::Created, 0),
+                Some(&(old_hash, old_tokens)) if old_hash != hash => (PageChangeKind::Modified, old_tokens),
+                Some(_) => return None,
+            };
+            Some(PageChange { page_id: id.clone(), kind, token_delta: tokens as i64 - old_tokens as i64 })
+        })
+        .chain(before.iter().filter(|(id, _)| !after.contains_key(*id)).map(|(id, &(_, tokens))| PageChange {
+            page_id: id.clone(),
+            kind: PageChangeKind::Freed,
+            token_delta: -(tokens as i64),
+        }))
+        .collect();
+    changes.sort_by(|a, b| a.page_id.cmp(&b.page_id));
+    changes
+}
+
+/// Content type of a page read from `path`: `file:<language>` when the
+/// extension is a known one, otherwise `file`
+fn file_content_type(path: &str) -> String {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let language = match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "go" => "go",
+        "java" => "java",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        "rb" => "ruby",
+        "sh" | "bash" => "shell",
+        "md" => "markdown",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "html" => "html",
+        "css" => "css",
+        "sql" => "sql",
+        _ => return "file".to_string(),
+    };
+    format!("file:{}", language)
+}
+
+/// Parse and validate a schema-constrained INFER response. Accepts the
+/// usual `{response, success}` wrapper or a bare value; the parsed JSON
+/// replaces the text so later opcodes can read its fields.
+fn structured_response(response: serde_json::Value, schema: &serde_json::Value) -> Result<serde_json::Value> {
+    let raw = match &response {
+        serde_json::Value::Object(map) if map.contains_key("response") => map["response"].clone(),
+        other => other.clone(),
+    };
+    let parsed = match raw {
+        serde_json::Value::String(text) => {
+            let text = text.trim();
+            let text = text
+                .strip_prefix("```json")
+                .or_else(|| text.strip_prefix("```"))
+                .and_then(|t| t.strip_suffix("```"))
+                .unwrap_or(text);
+            serde_json::from_str(text.trim())
+                .map_err(|e| error::parse_error(format!("INFER: response is not valid JSON: {}", e)))?
+        }
+        other => other,
+    };
+    crate::json_schema::validate(&parsed, schema)
+        .map_err(|e| error::parse_error(format!("INFER: response does not match schema: {}", e)))?;
+
+    Ok(match response {
+        serde_json::Value::Object(mut map) if map.contains_key("response") => {
+            map.insert("response".to_string(), parsed);
+            serde_json::Value::Object(map)
+        }
+        _ => parsed,
+    })
+}
+
 /// Result of executing a single opcode
 enum StepResult {
     /// Continue to next opcode
     Continue,
     /// Jump to a label
     Jump(String),
+    /// Jump to an opcode index (SET_REG pc)
+    JumpTo(usize),
     /// Program completed
     Complete(serde_json::Value),
     /// Program failed
     Fail(String),
     /// Needs LLM input
     NeedsLlm(LlmRequest),
+    /// BREAK: stop the innermost LOOP
+    Break,
+    /// CONTINUE: move the innermost LOOP to its next item
+    NextIteration,
 }
 
 /// Check if a JSON value is "truthy"
+/// Evaluate a condition, looking up paths with `resolve`
+///
+/// Supports `path.error`, `path.success`, comparisons against a literal or
+/// another path (`==`, `!=`, `>`, `>=`, `<`, `<=`) and plain truthiness.
+fn eval_condition<'a>(condition: &str, resolve: impl Fn(&str) -> Option<&'a serde_json::Value>) -> bool {
+    use std::cmp::Ordering;
+
+    let condition = condition.trim();
+
+    // Comparisons
+    for op in [">=", "<=", "==", "!=", ">", "<"] {
+        let Some((lhs, rhs)) = condition.split_once(op) else {
+            continue;
+        };
+        let (lhs, rhs) = (lhs.trim(), rhs.trim());
+        let null = serde_json::Value::Null;
+        let left = resolve(lhs).unwrap_or(&null);
+        let literal = serde_json::from_str::<serde_json::Value>(rhs).ok();
+        let right = literal.as_ref().or_else(|| resolve(rhs)).unwrap_or(&null);
+
+        let ordering = match (left, right) {
+            (serde_json::Value::Number(a), serde_json::Value::Number(b)) => {
+                a.as_f64().zip(b.as_f64()).and_then(|(a, b)| a.partial_cmp(&b))
+            }
+            (serde_json::Value::String(a), serde_json::Value::String(b)) => Some(a.cmp(b)),
+            (a, b) if a == b => Some(Ordering::Equal),
+            _ => None,
+        };
+        return match op {
+            "==" => ordering == Some(Ordering::Equal),
+            "!=" => ordering != Some(Ordering::Equal),
+            ">" => ordering == Some(Ordering::Greater),
+            ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            "<" => ordering == Some(Ordering::Less),
+            _ => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        };
+    }
+
+    // Check for .error or .success
+    if let Some(path) = condition.strip_suffix(".error") {
+        return match resolve(path) {
+            Some(value) => {
+                value.get("error").is_some() || value.get("success") == Some(&serde_json::json!(false))
+            }
+            None => true, // Page not found = error
+        };
+    }
+
+    if let Some(path) = condition.strip_suffix(".success") {
+        return resolve(path).is_some_and(|value| value.get("success") == Some(&serde_json::json!(true)));
+    }
+
+    // Truthiness of a path; unknown conditions are false
+    resolve(condition).is_some_and(is_truthy)
+}
+
 fn is_truthy(value: &serde_json::Value) -> bool {
     match value {
         serde_json::Value::Null => false,
@@ -1392,6 +3336,40 @@ fn is_truthy(value: &serde_json::Value) -> bool {
     }
 }
 
+/// Text DIFF compares for a value: a string as is, a READ_FILE result by
+/// its content, anything else as pretty JSON
+fn diff_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => match other.get("content").and_then(|c| c.as_str()) {
+            Some(content) => content.to_string(),
+            None => serde_json::to_string_pretty(other).unwrap_or_default(),
+        },
+    }
+}
+
+/// A COMPLETE result that carries nothing: null, or an empty string, array
+/// or object
+fn is_empty_result(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => true,
+        serde_json::Value::String(s) => s.trim().is_empty(),
+        serde_json::Value::Array(a) => a.is_empty(),
+        serde_json::Value::Object(o) => o.is_empty(),
+        _ => false,
+    }
+}
+
+/// Whether a TRY block may catch this error. Runtime failures are catchable;
+/// problems with the program itself are not.
+fn is_catchable(err: &error::Error) -> bool {
+    use crate::error::ErrorKind;
+    !matches!(
+        err.kind(),
+        ErrorKind::InvalidOpcode | ErrorKind::InvalidLabel | ErrorKind::NotImplemented
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1603,4 +3581,1764 @@ mod tests {
         assert_eq!(parallel_results.get("b1").unwrap().get("success"), Some(&serde_json::json!(true)));
         assert_eq!(parallel_results.get("b2").unwrap().get("success"), Some(&serde_json::json!(true)));
     }
+
+    #[test]
+    fn test_approval_hook_denies_exec() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = Program::new(
+            "test_approval",
+            "Test Approval",
+            vec![
+                Opcode::Exec {
+                    command: "touch created.txt".to_string(),
+                    stdin: None,
+                    cwd: None,
+                    env: HashMap::new(),
+                    store_to: "exec_result".to_string(),
+                },
+                Opcode::WriteFile {
+                    path: "written.txt".to_string(),
+                    content: "hello".to_string(),
+                    backup: false,
+                    store_to: Some("write_result".to_string()),
+                },
+                Opcode::Complete {
+                    result: serde_json::json!({"done": true}),
+                },
+            ],
+        );
+
+        let handler = DefaultSyscallHandler::new(dir.path());
+        let mut interp = Interpreter::new(program, handler).with_approval_hook(|op| match op {
+            Opcode::Exec { .. } => Approval::Deny,
+            _ => Approval::Allow,
+        });
+
+        match interp.run().unwrap() {
+            ExecutionResult::Complete(_) => {}
+            other => panic!("Expected Complete, got {:?}", other),
+        }
+
+        // EXEC was denied and never ran
+        assert!(!dir.path().join("created.txt").exists());
+        let exec_result = interp.get_page("exec_result").unwrap();
+        assert_eq!(exec_result["success"], serde_json::json!(false));
+        assert_eq!(exec_result["denied"], serde_json::json!(true));
+
+        // WRITE_FILE was allowed
+        assert!(dir.path().join("written.txt").exists());
+        assert_eq!(interp.get_page("write_result").unwrap()["success"], serde_json::json!(true));
+    }
+
+    #[test]
+    fn test_lenient_inject_skips_bad_opcodes() {
+        let program = Program::new(
+            "inject",
+            "Inject",
+            vec![
+                Opcode::Inject { goal: "plan".to_string(), context: vec![], include_trace: false, include_memory: false },
+                Opcode::Fail { error: "inject fell through".to_string() },
+            ],
+        );
+
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_log_callback(move |_, msg| sink.lock().unwrap().push(msg.to_string()));
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::NeedsLlm(_)));
+
+        let outcome = interp
+            .inject_opcodes_lenient(vec![
+                serde_json::json!({"op": "STORE", "page_id": "a", "data": 1}),
+                serde_json::json!({"op": "STORE", "page": "missing_fields"}),
+                serde_json::json!({"op": "NOT_AN_OPCODE"}),
+                serde_json::json!({"op": "COMPLETE", "result": {"page": "a"}}),
+            ])
+            .unwrap();
+
+        assert_eq!(outcome, InjectOutcome { injected: 2, dropped: 2 });
+        assert_eq!(warnings.lock().unwrap().len(), 2);
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        assert_eq!(interp.get_page("a").unwrap(), &serde_json::json!(1));
+    }
+
+    #[test]
+    fn test_validate_injection_catches_missing_page() {
+        let program = Program::new(
+            "inject",
+            "Inject",
+            vec![
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!("text") },
+                Opcode::Label { name: "done".to_string() },
+                Opcode::Inject { goal: "plan".to_string(), context: vec![], include_trace: false, include_memory: false },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::NeedsLlm(_)));
+
+        let good: Vec<Opcode> = serde_json::from_value(serde_json::json!([
+            {"op": "CHUNK", "source": "notes", "chunk_size": 2, "prefix": "part"},
+            {"op": "COPY", "src": "part_0", "dst": "first"},
+            {"op": "JUMP", "target": "done"}
+        ]))
+        .unwrap();
+        assert!(interp.validate_injection(&good).is_ok());
+
+        let bad: Vec<Opcode> = serde_json::from_value(serde_json::json!([
+            {"op": "COPY", "src": "note", "dst": "first"},
+            {"op": "LABEL", "name": "done"}
+        ]))
+        .unwrap();
+        let errors = interp.validate_injection(&bad).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].index, 0);
+        assert!(errors[0].message.contains("'note'"));
+        assert!(errors[1].message.contains("duplicate label 'done'"));
+    }
+
+    #[test]
+    fn test_stack_snapshot_tracks_pushes_and_pops() {
+        let long = "x".repeat(100);
+        let program = Program::new(
+            "stack",
+            "Stack",
+            vec![
+                Opcode::Push { value: serde_json::json!(1) },
+                Opcode::Push { value: serde_json::json!(long) },
+                Opcode::Pop,
+                Opcode::Push { value: serde_json::json!("top") },
+                Opcode::Complete { result: serde_json::json!(null) },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(interp.stack_snapshot().is_empty());
+
+        interp.step().unwrap();
+        interp.step().unwrap();
+        assert_eq!(interp.stack_snapshot(), &[serde_json::json!(1), serde_json::json!(long)]);
+        assert!(interp.trace()[1].stack[0].ends_with('…'));
+        assert_eq!(interp.trace()[1].stack[0].chars().count(), 60);
+
+        interp.run().unwrap();
+        assert_eq!(interp.stack_snapshot(), &[serde_json::json!(1), serde_json::json!("top")]);
+        assert_eq!(interp.trace()[2].stack, vec!["1"]);
+        assert_eq!(interp.trace()[3].stack, vec!["\"top\"", "1"]);
+    }
+
+    #[test]
+    fn test_deterministic_runs_generate_same_page_ids() {
+        let run = || {
+            let program = Program::new(
+                "ids",
+                "Ids",
+                vec![
+                    Opcode::Store { page_id: "text".to_string(), data: serde_json::json!("abcdefgh") },
+                    Opcode::Alloc { size_hint: None, label: None },
+                    Opcode::Alloc { size_hint: None, label: None },
+                    Opcode::Free { page_id: "page_1".to_string() },
+                    Opcode::Alloc { size_hint: None, label: None },
+                    Opcode::Chunk { source: "text".to_string(), chunk_size: 1, prefix: None },
+                    Opcode::Uuid { store_to: "run_id".to_string() },
+                    Opcode::Fork { program_id: "child".to_string(), args: serde_json::Value::Null, store_to: "pid".to_string() },
+                    Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+                    Opcode::Complete { result: serde_json::json!(null) },
+                ],
+            );
+            let child = Program::new(
+                "child",
+                "Child",
+                vec![
+                    Opcode::Uuid { store_to: "id".to_string() },
+                    Opcode::Complete { result: serde_json::json!("ok") },
+                ],
+            );
+            let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+                .with_program(child)
+                .with_deterministic(true);
+            assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+            let mut ids: Vec<String> = interp.all_pages().into_keys().collect();
+            ids.sort();
+            (ids, interp.get_page("run_id").cloned(), interp.get_page("pid").cloned())
+        };
+
+        let (ids, run_id, pid) = run();
+        assert_eq!((ids.clone(), run_id.clone(), pid), run());
+        // The ALLOC after FREE doesn't reuse a live page's id
+        assert!(ids.contains(&"page_2".to_string()) && ids.contains(&"page_3".to_string()));
+        assert!(uuid::Uuid::parse_str(run_id.unwrap().as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_print_goes_to_output_callback() {
+        let program = Program::new(
+            "print",
+            "Print",
+            vec![
+                Opcode::Store { page_id: "summary".to_string(), data: serde_json::json!("all good") },
+                Opcode::Store { page_id: "stats".to_string(), data: serde_json::json!({"files": 3}) },
+                Opcode::Print { source: Some("summary".to_string()), message: None },
+                Opcode::Print { source: Some("stats.files".to_string()), message: None },
+                Opcode::Print { source: None, message: Some("done".to_string()) },
+                Opcode::Log { level: LogLevel::Debug, message: "not output".to_string() },
+                Opcode::Complete { result: serde_json::json!(null) },
+            ],
+        );
+
+        let printed = Arc::new(Mutex::new(Vec::new()));
+        let sink = printed.clone();
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_log_callback(|_, _| {})
+            .with_output_callback(move |text| sink.lock().unwrap().push(text.to_string()));
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        assert_eq!(*printed.lock().unwrap(), vec!["all good", "3", "done"]);
+    }
+
+    #[test]
+    fn test_dry_run_skips_write_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let program = Program::new(
+            "test_dry_run",
+            "Test Dry Run",
+            vec![
+                Opcode::Store { page_id: "name".to_string(), data: serde_json::json!("notes.txt") },
+                Opcode::WriteFile {
+                    path: "notes.txt".to_string(),
+                    content: "hello".to_string(),
+                    backup: false,
+                    store_to: Some("write_result".to_string()),
+                },
+                Opcode::Complete { result: serde_json::json!({"page": "write_result"}) },
+            ],
+        );
+
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let sink = logged.clone();
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::new(dir.path()))
+            .with_dry_run(true)
+            .with_log_callback(move |_, msg| sink.lock().unwrap().push(msg.to_string()));
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        assert!(!dir.path().join("notes.txt").exists());
+        assert_eq!(interp.get_page("name").unwrap(), &serde_json::json!("notes.txt"));
+        assert_eq!(
+            interp.get_page("write_result").unwrap(),
+            &serde_json::json!({"success": true, "dry_run": true})
+        );
+        assert!(logged.lock().unwrap().iter().any(|m| m.starts_with("dry run: would WRITE_FILE")));
+    }
+
+    #[test]
+    fn test_approval_hook_modifies_opcode() {
+        let program = Program::new(
+            "test_modify",
+            "Test Modify",
+            vec![
+                Opcode::Exec {
+                    command: "rm -rf /".to_string(),
+                    stdin: None,
+                    cwd: None,
+                    env: HashMap::new(),
+                    store_to: "out".to_string(),
+                },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_approval_hook(|_| Approval::Modify(Opcode::Exec {
+                command: "echo safe".to_string(),
+                stdin: None,
+                cwd: None,
+                env: HashMap::new(),
+                store_to: "out".to_string(),
+            }));
+        interp.run().unwrap();
+
+        let out = interp.get_page("out").unwrap();
+        assert_eq!(out["stdout"], serde_json::json!("safe\n"));
+    }
+
+    #[test]
+    fn test_exec_stdin_cwd_and_env() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let exec = |command: &str, stdin: Option<&str>, cwd: Option<&str>, env: &[(&str, &str)], store_to: &str| {
+            Opcode::Exec {
+                command: command.to_string(),
+                stdin: stdin.map(str::to_string),
+                cwd: cwd.map(str::to_string),
+                env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+                store_to: store_to.to_string(),
+            }
+        };
+        let program = Program::new(
+            "exec_options",
+            "Exec Options",
+            vec![
+                exec("cat", Some("piped input"), None, &[], "cat"),
+                exec("echo $GREETING", None, None, &[("GREETING", "hello")], "env"),
+                exec("pwd", None, Some("sub"), &[], "sub"),
+                exec("pwd", None, Some(".."), &[], "escape"),
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let handler = DefaultSyscallHandler::new(dir.path());
+        let mut interp = Interpreter::new(program, handler);
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        assert_eq!(interp.get_page("cat").unwrap()["stdout"], "piped input");
+        assert_eq!(interp.get_page("env").unwrap()["stdout"], "hello\n");
+
+        let sub = interp.get_page("sub").unwrap();
+        let expected = dir.path().join("sub").canonicalize().unwrap();
+        assert_eq!(sub["cwd"], expected.display().to_string());
+        assert_eq!(sub["stdout"], format!("{}\n", expected.display()));
+
+        let escape = interp.get_page("escape").unwrap();
+        assert_eq!(escape["success"], false);
+        assert!(escape["error"].as_str().unwrap().contains("outside the workspace root"));
+    }
+
+    /// Serve `count` HTTP requests on localhost, answering each with
+    /// "<method> <path> <body>" and an `x-mock` header
+    fn mock_http_server(count: usize) -> (String, std::thread::JoinHandle<()>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            for _ in 0..count {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                let header_end = loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..header_end]).to_string();
+                let length = head
+                    .lines()
+                    .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                while request.len() < header_end + length {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let mut request_line = head.lines().next().unwrap().split_whitespace();
+                let body = format!(
+                    "{} {} {}",
+                    request_line.next().unwrap(),
+                    request_line.next().unwrap(),
+                    String::from_utf8_lossy(&request[header_end..])
+                );
+                write!(
+                    stream,
+                    "HTTP/1.1 201 Created\r\nx-mock: yes\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_http_request_get_and_post() {
+        let (base, server) = mock_http_server(2);
+        let request = |method: &str, body: Option<&str>, store_to: &str| Opcode::HttpRequest {
+            method: method.to_string(),
+            url: format!("{}/items", base),
+            headers: HashMap::from([("content-type".to_string(), "application/json".to_string())]),
+            body: body.map(str::to_string),
+            store_to: store_to.to_string(),
+        };
+        let program = Program::new(
+            "http",
+            "HTTP",
+            vec![
+                request("GET", None, "get"),
+                request("POST", Some(r#"{"name":"x"}"#), "post"),
+                Opcode::HttpRequest {
+                    method: "GET".to_string(),
+                    url: "http://example.com/".to_string(),
+                    headers: HashMap::new(),
+                    body: None,
+                    store_to: "blocked".to_string(),
+                },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let handler = DefaultSyscallHandler::default()
+            .with_allowed_hosts(["127.0.0.1"])
+            .with_http_timeout(Duration::from_secs(5));
+        let mut interp = Interpreter::new(program, handler);
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        server.join().unwrap();
+
+        let get = interp.get_page("get").unwrap();
+        assert_eq!(get["success"], true);
+        assert_eq!(get["status"], 201);
+        assert_eq!(get["headers"]["x-mock"], "yes");
+        assert_eq!(get["body"], "GET /items ");
+
+        let post = interp.get_page("post").unwrap();
+        assert_eq!(post["body"], r#"POST /items {"name":"x"}"#);
+
+        let blocked = interp.get_page("blocked").unwrap();
+        assert_eq!(blocked["success"], false);
+        assert!(blocked["error"].as_str().unwrap().contains("'example.com' is not in the allowlist"));
+    }
+
+    #[test]
+    fn test_get_env() {
+        std::env::set_var("LLCRAFT_TEST_GET_ENV", "from env");
+        std::env::remove_var("LLCRAFT_TEST_GET_ENV_UNSET");
+        let get_env = |name: &str, default: Option<&str>, store_to: &str| Opcode::GetEnv {
+            name: name.to_string(),
+            store_to: store_to.to_string(),
+            default: default.map(str::to_string),
+        };
+        let program = Program::new(
+            "env",
+            "Env",
+            vec![
+                get_env("LLCRAFT_TEST_GET_ENV", Some("fallback"), "set"),
+                get_env("LLCRAFT_TEST_GET_ENV_UNSET", Some("fallback"), "defaulted"),
+                get_env("LLCRAFT_TEST_GET_ENV_UNSET", None, "missing"),
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let handler = DefaultSyscallHandler::default()
+            .with_allowed_env(["LLCRAFT_TEST_GET_ENV", "LLCRAFT_TEST_GET_ENV_UNSET"]);
+        let mut interp = Interpreter::new(program.clone(), handler);
+        interp.run().unwrap();
+        assert_eq!(interp.get_page("set"), Some(&serde_json::json!("from env")));
+        assert_eq!(interp.get_page("defaulted"), Some(&serde_json::json!("fallback")));
+        assert_eq!(interp.get_page("missing"), Some(&serde_json::Value::Null));
+
+        let sandboxed = DefaultSyscallHandler::default().with_allowed_syscalls(["read_file"]);
+        assert_eq!(sandboxed.available(), vec!["read_file"]);
+        let err = Interpreter::new(program, sandboxed).run().unwrap_err();
+        assert!(err.to_string().contains("not in the syscall allowlist"));
+
+        // The default handler reads no variables at all
+        std::env::set_var("LLCRAFT_TEST_API_KEY", "secret");
+        let secret = Program::new("secret", "Secret", vec![get_env("LLCRAFT_TEST_API_KEY", None, "key")]);
+        let mut interp = Interpreter::new(secret, DefaultSyscallHandler::default());
+        let err = interp.run().unwrap_err();
+        assert!(err.to_string().contains("'LLCRAFT_TEST_API_KEY' is not in the environment allowlist"));
+        assert!(interp.get_page("key").is_none());
+    }
+
+    #[test]
+    fn test_filter_numbers_and_objects() {
+        let program = Program::new(
+            "filter",
+            "Filter",
+            vec![
+                Opcode::Store { page_id: "nums".to_string(), data: serde_json::json!([3, 8, 1, 10]) },
+                Opcode::Store {
+                    page_id: "results".to_string(),
+                    data: serde_json::json!([
+                        {"name": "a", "score": 0.9, "status": "ok"},
+                        {"name": "b", "score": 0.2, "status": "ok"},
+                        {"name": "c", "score": 0.7, "status": "error"},
+                    ]),
+                },
+                Opcode::Store { page_id: "limits".to_string(), data: serde_json::json!({"min": 5}) },
+                Opcode::Filter {
+                    over: "nums".to_string(),
+                    condition: "item >= limits.min".to_string(),
+                    store_to: "big".to_string(),
+                },
+                Opcode::Filter {
+                    over: "results".to_string(),
+                    condition: "item.score > 0.5".to_string(),
+                    store_to: "good".to_string(),
+                },
+                Opcode::Filter {
+                    over: "results".to_string(),
+                    condition: "item.status == \"ok\"".to_string(),
+                    store_to: "ok".to_string(),
+                },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+
+        assert_eq!(interp.get_page("big"), Some(&serde_json::json!([8, 10])));
+        assert_eq!(interp.get_page("big_count"), Some(&serde_json::json!(2)));
+        let names = |page: &str| -> Vec<String> {
+            interp.get_page(page).unwrap().as_array().unwrap()
+                .iter().map(|r| r["name"].as_str().unwrap().to_string()).collect()
+        };
+        assert_eq!(names("good"), vec!["a", "c"]);
+        assert_eq!(names("ok"), vec!["a", "b"]);
+        assert_eq!(interp.get_page("ok_count"), Some(&serde_json::json!(2)));
+    }
+
+    #[test]
+    fn test_json_parse_and_stringify() {
+        let program = Program::new(
+            "json",
+            "JSON",
+            vec![
+                Opcode::Store {
+                    page_id: "exec".to_string(),
+                    data: serde_json::json!({"stdout": "{\"count\": 3, \"items\": [1, 2]}"}),
+                },
+                Opcode::JsonParse { source: "exec.stdout".to_string(), store_to: "data".to_string() },
+                Opcode::JsonStringify { source: "data.items".to_string(), store_to: "text".to_string(), pretty: false },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+        assert_eq!(interp.get_page("data"), Some(&serde_json::json!({"count": 3, "items": [1, 2]})));
+        assert_eq!(interp.get_page("text"), Some(&serde_json::json!("[1,2]")));
+
+        let program = Program::new(
+            "bad_json",
+            "Bad JSON",
+            vec![
+                Opcode::Store { page_id: "raw".to_string(), data: serde_json::json!("{not json") },
+                Opcode::JsonParse { source: "raw".to_string(), store_to: "data".to_string() },
+            ],
+        );
+        let err = Interpreter::new(program, DefaultSyscallHandler::default()).run().unwrap_err();
+        assert!(err.to_string().contains("invalid JSON in 'raw'"));
+    }
+
+    #[test]
+    fn test_append_builds_array() {
+        let append = |value: serde_json::Value| Opcode::Append { page_id: "results".to_string(), value };
+        let program = Program::new(
+            "append",
+            "Append",
+            vec![
+                append(serde_json::json!(1)),
+                append(serde_json::json!({"two": 2})),
+                Opcode::Store { page_id: "nulled".to_string(), data: serde_json::Value::Null },
+                Opcode::Append { page_id: "nulled".to_string(), value: serde_json::json!("first") },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+        assert_eq!(interp.get_page("results"), Some(&serde_json::json!([1, {"two": 2}])));
+        assert_eq!(interp.get_page("nulled"), Some(&serde_json::json!(["first"])));
+
+        let program = Program::new(
+            "append_mismatch",
+            "Append Mismatch",
+            vec![
+                Opcode::Store { page_id: "results".to_string(), data: serde_json::json!({"not": "array"}) },
+                append(serde_json::json!(1)),
+            ],
+        );
+        let err = Interpreter::new(program, DefaultSyscallHandler::default()).run().unwrap_err();
+        assert!(err.to_string().contains("APPEND: 'results' is not an array"));
+    }
+
+    #[test]
+    fn test_len_of_each_type() {
+        let len = |source: &str, store_to: &str| Opcode::Len { source: source.to_string(), store_to: store_to.to_string() };
+        let program = Program::new(
+            "len",
+            "Len",
+            vec![
+                Opcode::Store {
+                    page_id: "data".to_string(),
+                    data: serde_json::json!({"items": [1, 2, 3], "name": "héllo", "empty": null}),
+                },
+                len("data.items", "array"),
+                len("data", "object"),
+                len("data.name", "string"),
+                len("data.empty", "null"),
+                len("missing", "absent"),
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+        assert_eq!(interp.get_page("array"), Some(&serde_json::json!(3)));
+        assert_eq!(interp.get_page("object"), Some(&serde_json::json!(3)));
+        assert_eq!(interp.get_page("string"), Some(&serde_json::json!(5)));
+        assert_eq!(interp.get_page("null"), Some(&serde_json::json!(0)));
+        assert_eq!(interp.get_page("absent"), Some(&serde_json::json!(0)));
+
+        let program = Program::new(
+            "len_number",
+            "Len Number",
+            vec![
+                Opcode::Store { page_id: "n".to_string(), data: serde_json::json!(42) },
+                len("n", "out"),
+            ],
+        );
+        let err = Interpreter::new(program, DefaultSyscallHandler::default()).run().unwrap_err();
+        assert!(err.to_string().contains("LEN: 'n' has no length"));
+    }
+
+    #[test]
+    fn test_sleep_timestamp_and_uuid() {
+        let program = Program::new(
+            "utils",
+            "Utilities",
+            vec![
+                Opcode::Timestamp { store_to: "before".to_string() },
+                Opcode::Sleep { ms: 20 },
+                Opcode::Uuid { store_to: "id1".to_string() },
+                Opcode::Uuid { store_to: "id2".to_string() },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+
+        let before = interp.get_page("before").unwrap().as_u64().unwrap();
+        assert!(before > 1_700_000_000);
+        assert!(interp.trace()[1].duration_ms >= 20.0);
+
+        let long_sleep = Program::new("nap", "Nap", vec![Opcode::Sleep { ms: u64::MAX }]);
+        let err = Interpreter::new(long_sleep, DefaultSyscallHandler::default())
+            .with_max_sleep(Duration::from_millis(10))
+            .run()
+            .unwrap_err();
+        assert!(err.to_string().contains("longer than the 10ms limit"));
+
+        let id1 = interp.get_page("id1").unwrap().as_str().unwrap().to_string();
+        let id2 = interp.get_page("id2").unwrap().as_str().unwrap();
+        assert_eq!(id1.len(), 36);
+        assert_eq!(&id1[14..15], "4");
+        assert!(matches!(&id1[19..20], "8" | "9" | "a" | "b"));
+        assert_ne!(id1, id2);
+    }
+
+    #[test]
+    fn test_regex_replace() {
+        let replace = |pattern: &str, replacement: &str, all: bool, store_to: &str| Opcode::RegexReplace {
+            source: "text".to_string(),
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            store_to: store_to.to_string(),
+            all,
+        };
+        let program = Program::new(
+            "regex",
+            "Regex",
+            vec![
+                Opcode::Store { page_id: "text".to_string(), data: serde_json::json!("a1  b22   c333") },
+                replace(r"\s+", " ", false, "first"),
+                replace(r"\s+", " ", true, "global"),
+                replace(r"([a-z])(\d+)", "$2$1", true, "swapped"),
+                replace(r"(?P<letter>[a-z])\d+", "<${letter}>", true, "named"),
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+        assert_eq!(interp.get_page("first"), Some(&serde_json::json!("a1 b22   c333")));
+        assert_eq!(interp.get_page("global"), Some(&serde_json::json!("a1 b22 c333")));
+        assert_eq!(interp.get_page("swapped"), Some(&serde_json::json!("1a  22b   333c")));
+        assert_eq!(interp.get_page("named"), Some(&serde_json::json!("<a>  <b>   <c>")));
+
+        let program = Program::new(
+            "bad_regex",
+            "Bad Regex",
+            vec![
+                Opcode::Store { page_id: "text".to_string(), data: serde_json::json!("abc") },
+                replace("(unclosed", "", false, "out"),
+            ],
+        );
+        let err = Interpreter::new(program, DefaultSyscallHandler::default()).run().unwrap_err();
+        assert!(err.to_string().contains("invalid pattern '(unclosed'"));
+    }
+
+    #[test]
+    fn test_switch_dispatch() {
+        let run = |status: serde_json::Value| {
+            let program = Program::new(
+                "switch",
+                "Switch",
+                vec![
+                    Opcode::Store { page_id: "result".to_string(), data: serde_json::json!({"status": status}) },
+                    Opcode::Switch {
+                        value: "result.status".to_string(),
+                        cases: vec![
+                            (serde_json::json!("ok"), "ok".to_string()),
+                            (serde_json::json!("retry"), "retry".to_string()),
+                        ],
+                        default: "other".to_string(),
+                    },
+                    Opcode::Label { name: "ok".to_string() },
+                    Opcode::Complete { result: serde_json::json!("ok") },
+                    Opcode::Label { name: "retry".to_string() },
+                    Opcode::Complete { result: serde_json::json!("retry") },
+                    Opcode::Label { name: "other".to_string() },
+                    Opcode::Complete { result: serde_json::json!("other") },
+                ],
+            );
+            match Interpreter::new(program, DefaultSyscallHandler::default()).run().unwrap() {
+                ExecutionResult::Complete(result) => result,
+                other => panic!("unexpected {:?}", other),
+            }
+        };
+
+        assert_eq!(run(serde_json::json!("retry")), "retry");
+        assert_eq!(run(serde_json::json!("ok")), "ok");
+        assert_eq!(run(serde_json::json!("timeout")), "other");
+    }
+
+    #[test]
+    fn test_try_catch_recovers() {
+        let program = Program::new(
+            "try",
+            "Try",
+            vec![
+                Opcode::Try {
+                    body: vec![
+                        Opcode::Store { page_id: "before".to_string(), data: serde_json::json!(1) },
+                        Opcode::PushPage { page_id: "missing".to_string() },
+                        Opcode::Store { page_id: "after".to_string(), data: serde_json::json!(2) },
+                    ],
+                    catch: vec![Opcode::Complete { result: serde_json::json!({"recovered": true}) }],
+                },
+                Opcode::Fail { error: "catch block should have completed".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        match interp.run().unwrap() {
+            ExecutionResult::Complete(result) => assert_eq!(result["recovered"], true),
+            other => panic!("expected completion, got {:?}", other),
+        }
+        assert!(interp.get_page("before").is_some());
+        assert!(interp.get_page("after").is_none());
+        let caught = interp.get_page(ERROR_PAGE).unwrap();
+        assert_eq!(caught["op"], "PUSH_PAGE");
+        assert_eq!(caught["kind"], "PageNotFound");
+
+        // Program errors are not catchable
+        let program = Program::new(
+            "try_jump",
+            "Try jump",
+            vec![
+                Opcode::Try {
+                    body: vec![Opcode::Jump { target: "end".to_string() }],
+                    catch: vec![],
+                },
+                Opcode::Label { name: "end".to_string() },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(interp.run().is_err());
+    }
+
+    #[test]
+    fn test_snapshot_restore_mid_run() {
+        let program = Program::new(
+            "snapshot",
+            "Snapshot",
+            vec![
+                Opcode::Store { page_id: "input".to_string(), data: serde_json::json!("data") },
+                Opcode::Push { value: serde_json::json!(7) },
+                Opcode::Infer {
+                    prompt: "summarize".to_string(),
+                    context: vec!["input".to_string()],
+                    store_to: "summary".to_string(),
+                    params: Default::default(),
+                    history_page: None,
+                },
+                Opcode::PopTo { store_to: "seven".to_string() },
+                Opcode::Complete { result: serde_json::json!({"page": "summary"}) },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program.clone(), DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::NeedsLlm(_)));
+
+        // Round-trip through JSON as if written to disk between processes
+        let json = serde_json::to_string(&interp.save_state()).unwrap();
+        drop(interp);
+        let snapshot: ExecutionSnapshot = serde_json::from_str(&json).unwrap();
+
+        let mut restored = Interpreter::restore_state(program, snapshot, DefaultSyscallHandler::default());
+        assert_eq!(restored.pc(), 2);
+        let pending = restored.pending_llm_request().unwrap();
+        assert_eq!(pending.prompt, "summarize");
+        let store_to = pending.store_to.clone();
+
+        restored.provide_llm_response(serde_json::json!("a summary"), &store_to).unwrap();
+        assert!(restored.pending_llm_request().is_none());
+        assert!(matches!(restored.run().unwrap(), ExecutionResult::Complete(_)));
+        assert_eq!(restored.get_page("summary"), Some(&serde_json::json!("a summary")));
+        assert_eq!(restored.get_page("seven"), Some(&serde_json::json!(7)));
+        assert_eq!(restored.get_page("input"), Some(&serde_json::json!("data")));
+    }
+
+    #[test]
+    fn test_page_stats_count_reads_and_writes() {
+        let program = Program::new(
+            "stats",
+            "Stats",
+            vec![
+                Opcode::Store { page_id: "input".to_string(), data: serde_json::json!("data") },
+                Opcode::Load { page_id: "input".to_string(), range: None },
+                Opcode::Load { page_id: "input".to_string(), range: None },
+                Opcode::Infer {
+                    prompt: "summarize".to_string(),
+                    context: vec!["input".to_string()],
+                    store_to: "summary".to_string(),
+                    params: Default::default(),
+                    history_page: None,
+                },
+                Opcode::Store { page_id: "input".to_string(), data: serde_json::json!("more") },
+                Opcode::Complete { result: serde_json::json!({"page": "summary"}) },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::NeedsLlm(_)));
+        interp.provide_llm_response(serde_json::json!("a summary"), "summary").unwrap();
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        let stats = interp.page_stats();
+        assert_eq!((stats["input"].reads, stats["input"].writes), (3, 2));
+        assert_eq!((stats["summary"].reads, stats["summary"].writes), (0, 1));
+        assert!(stats["input"].last_access > 0);
+    }
+
+    #[test]
+    fn test_tool_results_get_content_types() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(dir.path().join("notes.xyz"), "todo\n").unwrap();
+
+        let program = Program::new(
+            "types",
+            "Types",
+            vec![
+                Opcode::ReadFile { path: "main.rs".to_string(), store_to: "source".to_string() },
+                Opcode::ReadFile { path: "notes.xyz".to_string(), store_to: "notes".to_string() },
+                Opcode::Exec {
+                    command: "echo hi".to_string(),
+                    stdin: None,
+                    cwd: None,
+                    env: HashMap::new(),
+                    store_to: "output".to_string(),
+                },
+                Opcode::Grep { pattern: "main".to_string(), path: "main.rs".to_string(), store_to: "matches".to_string() },
+                Opcode::Infer {
+                    prompt: "explain".to_string(),
+                    context: vec!["source".to_string()],
+                    store_to: "explanation".to_string(),
+                    params: Default::default(),
+                    history_page: None,
+                },
+                Opcode::Store { page_id: "plain".to_string(), data: serde_json::json!(1) },
+                Opcode::Complete { result: serde_json::json!({"done": true}) },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::new(dir.path()));
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::NeedsLlm(_)));
+        interp.provide_llm_response(serde_json::json!({"response": "it's empty"}), "explanation").unwrap();
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        let labels = interp.page_labels();
+        assert_eq!(labels["source"], "file:rust");
+        assert_eq!(labels["notes"], "file");
+        assert_eq!(labels["output"], "command_output");
+        assert_eq!(labels["matches"], "search_results");
+        assert_eq!(labels["explanation"], "analysis");
+        assert!(!labels.contains_key("plain"));
+    }
+
+    #[test]
+    fn test_stack_limits_surface_as_errors() {
+        use crate::error::ErrorKind;
+
+        let program = Program::new(
+            "overflow",
+            "Overflow",
+            vec![
+                Opcode::Push { value: serde_json::json!(1) },
+                Opcode::Dup,
+                Opcode::Dup,
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default()).with_max_stack(2);
+        let err = interp.run().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StackOverflow);
+        assert_eq!(interp.pc(), 2);
+
+        for op in [
+            Opcode::Pop,
+            Opcode::PopTo { store_to: "x".to_string() },
+            Opcode::Peek { store_to: "x".to_string() },
+            Opcode::Swap,
+        ] {
+            let program = Program::new("underflow", "Underflow", vec![op]);
+            let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+            let err = interp.run().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::StackUnderflow);
+            assert!(err.to_string().contains("but the stack has 0"));
+        }
+    }
+
+    #[test]
+    fn test_oversized_page_logs_warning() {
+        let program = Program::new(
+            "budget",
+            "Budget",
+            vec![
+                Opcode::Store { page_id: "big".to_string(), data: serde_json::json!("x".repeat(400)) },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_page_budget(50, OversizePolicy::Warn)
+            .with_log_callback(move |level, msg| {
+                if level == LogLevel::Warn {
+                    sink.lock().unwrap().push(msg.to_string());
+                }
+            });
+
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("page 'big'"));
+    }
+
+    #[test]
+    fn test_step_executes_one_opcode() {
+        let program = Program::new(
+            "test_step",
+            "Test Step",
+            vec![
+                Opcode::Store { page_id: "a".to_string(), data: serde_json::json!(1) },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert_eq!(interp.current_opcode().unwrap().to_string(), "STORE a");
+
+        assert!(interp.step().unwrap().is_none());
+        assert_eq!(interp.pc(), 1);
+        assert_eq!(interp.get_page("a"), Some(&serde_json::json!(1)));
+
+        match interp.step().unwrap() {
+            Some(ExecutionResult::Complete(result)) => assert_eq!(result, "done"),
+            other => panic!("expected completion, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fork_and_join_children() {
+        let child = |id: &str, op: Opcode| Program::new(id, id, vec![op]);
+        let program = Program::new(
+            "parent",
+            "Parent",
+            vec![
+                Opcode::Fork { program_id: "greet".to_string(), args: serde_json::json!({"name": "a"}), store_to: "p1".to_string() },
+                Opcode::Fork { program_id: "broken".to_string(), args: serde_json::Value::Null, store_to: "p2".to_string() },
+                Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_program(child("greet", Opcode::Complete { result: serde_json::json!("hi") }))
+            .with_program(child("broken", Opcode::Fail { error: "boom".to_string() }));
+        interp.run().unwrap();
+
+        assert_eq!(interp.get_page("p1").unwrap(), "pid_1");
+        let results = interp.get_page("results").unwrap();
+        assert_eq!(results["pid_1"], serde_json::json!({"success": true, "result": "hi"}));
+        assert_eq!(results["pid_2"], serde_json::json!({"success": false, "error": "boom"}));
+        assert_eq!(results["success"], false);
+        assert!(interp.children.is_empty());
+
+        let missing = Opcode::Fork {
+            program_id: "missing".to_string(),
+            args: serde_json::Value::Null,
+            store_to: "p3".to_string(),
+        };
+        match interp.execute_opcode(&missing) {
+            Err(e) => assert_eq!(e.kind(), crate::error::ErrorKind::ProgramNotFound),
+            Ok(_) => panic!("expected program_not_found"),
+        }
+    }
+
+    #[test]
+    fn test_child_sends_to_parent() {
+        let reporter = Program::new(
+            "reporter",
+            "Reporter",
+            vec![
+                Opcode::Send { pid: "parent".to_string(), message: serde_json::json!({"answer": 42}) },
+                Opcode::Complete { result: serde_json::json!("sent") },
+            ],
+        );
+        let program = Program::new(
+            "parent",
+            "Parent",
+            vec![
+                Opcode::Fork { program_id: "reporter".to_string(), args: serde_json::Value::Null, store_to: "pid".to_string() },
+                Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+                Opcode::Recv { timeout_ms: None, store_to: "msg".to_string() },
+                Opcode::Recv { timeout_ms: None, store_to: "closed".to_string() },
+                Opcode::Recv { timeout_ms: Some(10), store_to: "late".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default()).with_program(reporter);
+        interp.run().unwrap();
+
+        assert_eq!(
+            interp.get_page("msg").unwrap(),
+            &serde_json::json!({"received": true, "from": "pid_1", "message": {"answer": 42}})
+        );
+        assert_eq!(interp.get_page("closed").unwrap()["closed"], true);
+        assert_eq!(interp.get_page("late").unwrap(), &serde_json::json!({"received": false, "timed_out": true}));
+
+        // The child's queue is gone once it has been joined
+        let send = Opcode::Send { pid: "pid_1".to_string(), message: serde_json::json!("hi") };
+        assert!(interp.execute_opcode(&send).is_err());
+    }
+
+    #[test]
+    fn test_children_waiting_on_each_other_are_closed() {
+        let listener = Program::new(
+            "listener",
+            "Listener",
+            vec![
+                Opcode::Recv { timeout_ms: None, store_to: "msg".to_string() },
+                Opcode::Complete { result: serde_json::json!({"page": "msg"}) },
+            ],
+        );
+        let program = Program::new(
+            "parent",
+            "Parent",
+            vec![
+                Opcode::Fork { program_id: "listener".to_string(), args: serde_json::Value::Null, store_to: "p1".to_string() },
+                Opcode::Fork { program_id: "listener".to_string(), args: serde_json::Value::Null, store_to: "p2".to_string() },
+                Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_program(listener)
+            .with_inline_results(true);
+        interp.run().unwrap();
+
+        // Neither child can ever get a message, so both RECVs report closed
+        // instead of waiting forever
+        let results = interp.get_page("results").unwrap();
+        for pid in ["pid_1", "pid_2"] {
+            assert_eq!(results[pid]["result"], serde_json::json!({"received": false, "timed_out": false, "closed": true}));
+        }
+    }
+
+    #[test]
+    fn test_forked_children_ask_llm_through_parent() {
+        let asker = Program::new(
+            "asker",
+            "Asker",
+            vec![
+                Opcode::Infer {
+                    prompt: "greet".to_string(),
+                    context: vec!["args".to_string()],
+                    store_to: "answer".to_string(),
+                    params: Default::default(),
+                    history_page: None,
+                },
+                Opcode::Complete { result: serde_json::json!({"page": "answer"}) },
+            ],
+        );
+        let program = Program::new(
+            "parent",
+            "Parent",
+            vec![
+                Opcode::Fork { program_id: "asker".to_string(), args: serde_json::json!("a"), store_to: "p1".to_string() },
+                Opcode::Fork { program_id: "asker".to_string(), args: serde_json::json!("b"), store_to: "p2".to_string() },
+                Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+                Opcode::Complete { result: serde_json::json!({"page": "results"}) },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_program(asker)
+            .with_inline_results(true);
+
+        let mut asked = Vec::new();
+        let result = loop {
+            match interp.run().unwrap() {
+                ExecutionResult::NeedsLlm(request) => {
+                    // Each request carries the asking child's memory
+                    let name = request.page("args").unwrap().as_str().unwrap().to_string();
+                    interp.provide_llm_response(serde_json::json!(format!("hi {}", name)), &request.store_to).unwrap();
+                    asked.push(name);
+                }
+                ExecutionResult::Complete(result) => break result,
+                other => panic!("unexpected result: {:?}", other),
+            }
+        };
+
+        // The children run at the same time, so either may ask first
+        asked.sort();
+        assert_eq!(asked, vec!["a", "b"]);
+        assert_eq!(result["pid_1"], serde_json::json!({"success": true, "result": "hi a"}));
+        assert_eq!(result["pid_2"], serde_json::json!({"success": true, "result": "hi b"}));
+        assert_eq!(result["success"], true);
+        assert!(interp.children.is_empty());
+        // The parent's own memory never saw the children's answers
+        assert!(interp.get_page("answer").is_none());
+    }
+
+    #[test]
+    fn test_child_waiting_on_llm_can_still_send() {
+        let listener = Program::new(
+            "listener",
+            "Listener",
+            vec![
+                Opcode::Recv { timeout_ms: None, store_to: "msg".to_string() },
+                Opcode::Complete { result: serde_json::json!({"page": "msg"}) },
+            ],
+        );
+        let asker = Program::new(
+            "asker",
+            "Asker",
+            vec![
+                Opcode::Infer {
+                    prompt: "what to send?".to_string(),
+                    context: vec![],
+                    store_to: "answer".to_string(),
+                    params: Default::default(),
+                    history_page: None,
+                },
+                Opcode::Send { pid: "pid_1".to_string(), message: serde_json::json!("from asker") },
+                Opcode::Complete { result: serde_json::json!("sent") },
+            ],
+        );
+        let program = Program::new(
+            "parent",
+            "Parent",
+            vec![
+                Opcode::Fork { program_id: "listener".to_string(), args: serde_json::Value::Null, store_to: "p1".to_string() },
+                Opcode::Fork { program_id: "asker".to_string(), args: serde_json::Value::Null, store_to: "p2".to_string() },
+                Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+                Opcode::Complete { result: serde_json::json!({"page": "results"}) },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_program(listener)
+            .with_program(asker)
+            .with_inline_results(true);
+
+        // The listener is blocked in RECV the whole time the asker waits on
+        // the LLM; it must not be told the asker is gone
+        let request = match interp.run().unwrap() {
+            ExecutionResult::NeedsLlm(request) => request,
+            other => panic!("expected the asker's INFER, got {:?}", other),
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        interp.provide_llm_response(serde_json::json!("go"), &request.store_to).unwrap();
+        let result = match interp.run().unwrap() {
+            ExecutionResult::Complete(result) => result,
+            other => panic!("expected completion, got {:?}", other),
+        };
+
+        assert_eq!(
+            result["pid_1"],
+            serde_json::json!({"success": true, "result": {"received": true, "from": "pid_2", "message": "from asker"}})
+        );
+        assert_eq!(result["pid_2"], serde_json::json!({"success": true, "result": "sent"}));
+        assert_eq!(interp.metrics().infer_calls, 1);
+    }
+
+    #[test]
+    fn test_registers() {
+        let program = Program::new(
+            "regs",
+            "Registers",
+            vec![
+                Opcode::SetReg { reg: Register::Custom("attempts".to_string()), value: serde_json::json!(3) },
+                Opcode::GetReg { reg: Register::Custom("attempts".to_string()), store_to: "attempts".to_string() },
+                Opcode::Push { value: serde_json::json!(1) },
+                Opcode::Push { value: serde_json::json!(2) },
+                Opcode::SetReg { reg: Register::Sp, value: serde_json::json!(1) },
+                Opcode::GetReg { reg: Register::Sp, store_to: "sp".to_string() },
+                Opcode::SetReg { reg: Register::Pc, value: serde_json::json!(8) },
+                Opcode::Fail { error: "pc was not honoured".to_string() },
+                Opcode::GetReg { reg: Register::Pc, store_to: "pc".to_string() },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        assert_eq!(interp.get_page("attempts").unwrap(), 3);
+        assert_eq!(interp.get_page("sp").unwrap(), 1);
+        assert_eq!(interp.get_page("pc").unwrap(), 8);
+        assert_eq!(interp.state().registers["attempts"], 3);
+
+        let bad = Opcode::SetReg { reg: Register::Pc, value: serde_json::json!("start") };
+        assert!(interp.execute_opcode(&bad).is_err());
+        let past_end = Opcode::SetReg { reg: Register::Pc, value: serde_json::json!(99) };
+        assert!(interp.execute_opcode(&past_end).is_err());
+    }
+    fn loop_program(body: Vec<Opcode>) -> Program {
+        Program::new(
+            "loop",
+            "Loop",
+            vec![
+                Opcode::Store { page_id: "nums".to_string(), data: serde_json::json!([1, 2, 3, 4, 5]) },
+                Opcode::Loop { var: "n".to_string(), over: "nums".to_string(), body },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        )
+    }
+
+    #[test]
+    fn test_loop_break_on_condition() {
+        let program = loop_program(vec![
+            Opcode::Break { condition: Some("n > 3".to_string()) },
+            Opcode::PushPage { page_id: "n".to_string() },
+        ]);
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        assert_eq!(interp.state().stack.as_slice(), &[serde_json::json!(1), serde_json::json!(2), serde_json::json!(3)]);
+        assert_eq!(interp.get_page("n"), Some(&serde_json::json!(4)));
+    }
+
+    #[test]
+    fn test_loop_continue_skips_iteration() {
+        let program = loop_program(vec![
+            Opcode::Continue { condition: Some("n == 2".to_string()) },
+            Opcode::PushPage { page_id: "n".to_string() },
+        ]);
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        let pushed: Vec<i64> = interp.state().stack.as_slice().iter().filter_map(|v| v.as_i64()).collect();
+        assert_eq!(pushed, vec![1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_nested_loop_break_is_innermost() {
+        let inner = Opcode::Loop {
+            var: "m".to_string(),
+            over: "[10, 20, 30]".to_string(),
+            body: vec![
+                Opcode::Break { condition: Some("m == 20".to_string()) },
+                Opcode::PushPage { page_id: "m".to_string() },
+            ],
+        };
+        let program = loop_program(vec![Opcode::Break { condition: Some("n == 3".to_string()) }, inner]);
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        assert_eq!(interp.state().stack.len(), 2);
+    }
+
+    #[test]
+    fn test_loop_body_pages_are_scoped() {
+        let program = Program::new(
+            "scoped",
+            "Scoped",
+            vec![
+                Opcode::Store { page_id: "acc".to_string(), data: serde_json::json!("untouched") },
+                Opcode::Store { page_id: "seen".to_string(), data: serde_json::json!([]) },
+                Opcode::Loop {
+                    var: "n".to_string(),
+                    over: "[1, 2, 3]".to_string(),
+                    body: vec![
+                        Opcode::Store { page_id: "acc".to_string(), data: serde_json::json!("clobbered") },
+                        Opcode::Store { page_id: "tmp".to_string(), data: serde_json::json!(true) },
+                        Opcode::Append { page_id: "parent:seen".to_string(), value: serde_json::json!("x") },
+                        Opcode::Copy { src: "n".to_string(), dst: "parent:last".to_string(), range: None },
+                    ],
+                },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        assert_eq!(interp.get_page("acc").unwrap(), "untouched");
+        assert_eq!(interp.get_page("seen").unwrap(), &serde_json::json!(["x", "x", "x"]));
+        assert_eq!(interp.get_page("last").unwrap(), 3);
+        assert!(interp.get_page("tmp").is_none());
+        assert_eq!(interp.state().memory.scope_depth(), 0);
+    }
+
+    #[test]
+    fn test_loop_body_accumulates_through_parent_prefix() {
+        let program = Program::new(
+            "accumulate",
+            "Accumulate",
+            vec![
+                Opcode::Store { page_id: "acc".to_string(), data: serde_json::json!([]) },
+                Opcode::Store { page_id: "total".to_string(), data: serde_json::json!(0) },
+                Opcode::Loop {
+                    var: "n".to_string(),
+                    over: "[1, 2, 3]".to_string(),
+                    body: vec![
+                        // Unprefixed writes stay in the iteration's scope
+                        Opcode::Append { page_id: "acc".to_string(), value: serde_json::json!("local") },
+                        Opcode::Store { page_id: "total".to_string(), data: serde_json::json!(99) },
+                        Opcode::Append { page_id: "parent:acc".to_string(), value: serde_json::json!(1) },
+                        Opcode::Copy { src: "n".to_string(), dst: "global:total".to_string(), range: None },
+                    ],
+                },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+
+        assert_eq!(interp.get_page("acc").unwrap(), &serde_json::json!([1, 1, 1]));
+        assert_eq!(interp.get_page("total").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_forked_child_reads_parent_pages() {
+        let child = Program::new(
+            "child",
+            "Child",
+            vec![
+                Opcode::Assert { condition: "config == 7".to_string(), message: "parent page not visible".to_string() },
+                Opcode::Store { page_id: "config".to_string(), data: serde_json::json!(8) },
+                Opcode::Complete { result: serde_json::json!("ok") },
+            ],
+        );
+        let program = Program::new(
+            "parent",
+            "Parent",
+            vec![
+                Opcode::Store { page_id: "config".to_string(), data: serde_json::json!(7) },
+                Opcode::Fork { program_id: "child".to_string(), args: serde_json::Value::Null, store_to: "pid".to_string() },
+                Opcode::Join { task_ids: vec![], store_to: "results".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default()).with_program(child);
+        interp.run().unwrap();
+
+        assert_eq!(interp.get_page("results").unwrap()["success"], true);
+        assert_eq!(interp.get_page("config").unwrap(), 7);
+    }
+
+    #[test]
+    fn test_search_pages_finds_saved_page() {
+        let program = Program::new(
+            "search",
+            "Search",
+            vec![
+                Opcode::Store { page_id: "deps".to_string(), data: serde_json::json!({"serde": "1.0"}) },
+                Opcode::SavePage { page_id: "deps".to_string(), summary: Some("crate dependency versions".to_string()), content_type: None },
+                Opcode::Store { page_id: "todo".to_string(), data: serde_json::json!(["tests"]) },
+                Opcode::SavePage { page_id: "todo".to_string(), summary: Some("remaining work items".to_string()), content_type: None },
+                Opcode::SearchPages { query: "which dependency versions".to_string(), top_k: 1, store_to: "found".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_session_manager(SessionManager::in_memory());
+        interp.start_session("search").unwrap();
+        interp.run().unwrap();
+
+        let found = interp.get_page("found").unwrap();
+        assert_eq!(found["success"], true);
+        assert_eq!(found["pages"], serde_json::json!(["deps"]));
+    }
+
+    #[test]
+    fn test_rollback_to_checkpoint_from_earlier_run() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let first = Program::new(
+            "first",
+            "First",
+            vec![
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!({"draft": 1}) },
+                Opcode::Push { value: serde_json::json!("saved") },
+                Opcode::Checkpoint { name: "before_edit".to_string(), persist: true },
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!({"draft": 2}) },
+            ],
+        );
+        let mut interp = Interpreter::new(first, DefaultSyscallHandler::default()).with_session_path(dir.path());
+        let session_id = interp.start_session("checkpoints").unwrap();
+        interp.run().unwrap();
+
+        let second = Program::new(
+            "second",
+            "Second",
+            vec![
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!({"draft": 3}) },
+                Opcode::Store { page_id: "scratch".to_string(), data: serde_json::json!(true) },
+                Opcode::Rollback { name: "before_edit".to_string() },
+                Opcode::Peek { store_to: "top".to_string() },
+            ],
+        );
+        let mut interp = Interpreter::new(second, DefaultSyscallHandler::default()).with_session_path(dir.path());
+        interp.resume_session(&session_id).unwrap();
+        interp.run().unwrap();
+
+        assert_eq!(interp.get_page("notes").unwrap()["draft"], 1);
+        assert!(interp.get_page("scratch").is_none());
+        assert_eq!(interp.get_page("top").unwrap(), &serde_json::json!("saved"));
+
+        // Without a session there is nothing to fall back on
+        let missing = Program::new("missing", "Missing", vec![Opcode::Rollback { name: "nope".to_string() }]);
+        let mut interp = Interpreter::new(missing, DefaultSyscallHandler::default());
+        assert!(interp.run().is_err());
+    }
+
+    #[test]
+    fn test_rollback_restores_data_not_pc() {
+        let program = Program::new(
+            "retry",
+            "Retry",
+            vec![
+                Opcode::Store { page_id: "attempt".to_string(), data: serde_json::json!(1) },
+                Opcode::Checkpoint { name: "start".to_string(), persist: false },
+                Opcode::Store { page_id: "attempt".to_string(), data: serde_json::json!(2) },
+                Opcode::Rollback { name: "start".to_string() },
+                Opcode::GetReg { reg: Register::Pc, store_to: "pc".to_string() },
+                Opcode::Append { page_id: "after".to_string(), value: serde_json::json!("ran") },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        assert_eq!(interp.get_page("attempt").unwrap(), 1);
+        // Execution went on from the ROLLBACK, not back to the CHECKPOINT
+        assert_eq!(interp.get_page("pc").unwrap(), 4);
+        assert_eq!(interp.get_page("after").unwrap(), &serde_json::json!(["ran"]));
+        assert_eq!(interp.trace().iter().filter(|s| s.opcode == "CHECKPOINT").count(), 1);
+    }
+
+    #[test]
+    fn test_memory_trace_records_page_changes() {
+        let program = Program::new(
+            "diffs",
+            "Diffs",
+            vec![
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!("short") },
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!("a much longer note than before") },
+                Opcode::Store { page_id: "notes".to_string(), data: serde_json::json!("a much longer note than before") },
+                Opcode::Free { page_id: "notes".to_string() },
+            ],
+        );
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default()).with_memory_trace(true);
+        interp.run().unwrap();
+
+        let trace = interp.trace();
+        assert_eq!(trace[0].memory.len(), 1);
+        assert_eq!(trace[0].memory[0].page_id, "notes");
+        assert_eq!(trace[0].memory[0].kind, PageChangeKind::Created);
+        assert!(trace[0].memory[0].token_delta > 0);
+        assert_eq!(trace[1].memory[0].kind, PageChangeKind::Modified);
+        assert!(trace[1].memory[0].token_delta > 0);
+        assert!(trace[2].memory.is_empty());
+        assert_eq!(trace[3].memory[0].kind, PageChangeKind::Freed);
+
+        // Off by default
+        let plain = Program::new("plain", "Plain", vec![Opcode::Store { page_id: "x".to_string(), data: serde_json::json!(1) }]);
+        let mut interp = Interpreter::new(plain, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+        assert!(interp.trace()[0].memory.is_empty());
+    }
+
+    #[test]
+    fn test_complete_inlines_page_references() {
+        let opcodes = || vec![
+            Opcode::Store { page_id: "summary".to_string(), data: serde_json::json!({"response": "all good"}) },
+            Opcode::Complete { result: serde_json::json!({"answer": {"page": "summary"}, "steps": 1}) },
+        ];
+
+        let mut interp = Interpreter::new(Program::new("ref", "Ref", opcodes()), DefaultSyscallHandler::default());
+        match interp.run().unwrap() {
+            ExecutionResult::Complete(result) => assert_eq!(result["answer"], serde_json::json!({"page": "summary"})),
+            other => panic!("expected Complete, got {:?}", other),
+        }
+
+        let mut interp = Interpreter::new(Program::new("inl", "Inline", opcodes()), DefaultSyscallHandler::default())
+            .with_inline_results(true);
+        match interp.run().unwrap() {
+            ExecutionResult::Complete(result) => {
+                assert_eq!(result["answer"], serde_json::json!({"response": "all good"}));
+                assert_eq!(result["steps"], 1);
+            }
+            other => panic!("expected Complete, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_complete_inline_rejects_empty_and_missing() {
+        for result in [serde_json::json!({}), serde_json::json!(""), serde_json::json!({"page": "nope"})] {
+            let program = Program::new("bad", "Bad", vec![Opcode::Complete { result }]);
+            let mut interp = Interpreter::new(program, DefaultSyscallHandler::default()).with_inline_results(true);
+            assert!(interp.run().is_err());
+        }
+    }
+
+    #[test]
+    fn test_metrics_count_opcodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt").to_string_lossy().to_string();
+        let program = Program::new(
+            "metrics",
+            "Metrics",
+            vec![
+                Opcode::Store { page_id: "a".to_string(), data: serde_json::json!(1) },
+                Opcode::Store { page_id: "b".to_string(), data: serde_json::json!(2) },
+                Opcode::WriteFile { path: path.clone(), content: "hello".to_string(), backup: false, store_to: None },
+                Opcode::ReadFile { path, store_to: "text".to_string() },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        interp.run().unwrap();
+
+        let metrics = interp.metrics();
+        assert_eq!(metrics.total_steps, 5);
+        assert_eq!(metrics.opcode_counts["STORE"], 2);
+        assert_eq!(metrics.opcode_counts["READ_FILE"], 1);
+        assert_eq!(metrics.opcode_counts["COMPLETE"], 1);
+        assert_eq!(metrics.tool_calls, 2);
+        assert_eq!((metrics.bytes_written, metrics.bytes_read), (5, 5));
+        assert_eq!(metrics.peak_pages, 3);
+        assert_eq!(metrics.infer_calls, 0);
+    }
+
+    #[test]
+    fn test_write_file_is_atomic_and_backs_up() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.rs"), "old").unwrap();
+        std::fs::create_dir(dir.path().join("taken")).unwrap();
+        let write = |path: &str, backup: bool, store_to: &str| Opcode::WriteFile {
+            path: path.to_string(),
+            content: "new".to_string(),
+            backup,
+            store_to: Some(store_to.to_string()),
+        };
+        let program = Program::new(
+            "atomic",
+            "Atomic",
+            vec![
+                write("lib.rs", true, "edited"),
+                write("fresh.rs", true, "created"),
+                // A directory is in the way: the rename fails
+                write("taken", false, "blocked"),
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::new(dir.path()));
+        interp.run().unwrap();
+
+        assert_eq!(interp.get_page("edited").unwrap()["backup"], true);
+        assert_eq!(std::fs::read_to_string(dir.path().join("lib.rs")).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(dir.path().join("lib.rs.bak")).unwrap(), "old");
+        assert_eq!(interp.get_page("created").unwrap()["backup"], false);
+        assert!(!dir.path().join("fresh.rs.bak").exists());
+
+        assert_eq!(interp.get_page("blocked").unwrap()["success"], false);
+        assert!(dir.path().join("taken").is_dir());
+        let mut left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["fresh.rs", "lib.rs", "lib.rs.bak", "taken"]);
+    }
+
+    #[test]
+    fn test_write_file_keeps_siblings_and_permissions() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data"), "old").unwrap();
+        std::fs::write(dir.path().join("data.tmp"), "the user's own file").unwrap();
+        let write = |path: &str| Opcode::WriteFile {
+            path: path.to_string(),
+            content: "new".to_string(),
+            backup: false,
+            store_to: None,
+        };
+        let mut ops = vec![write("data")];
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = dir.path().join("run.sh");
+            std::fs::write(&script, "#!/bin/sh").unwrap();
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+            std::os::unix::fs::symlink("data", dir.path().join("link")).unwrap();
+            ops.extend([write("run.sh"), write("link")]);
+        }
+
+        let program = Program::new("siblings", "Siblings", ops);
+        Interpreter::new(program, DefaultSyscallHandler::new(dir.path())).run().unwrap();
+
+        assert_eq!(std::fs::read_to_string(dir.path().join("data")).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(dir.path().join("data.tmp")).unwrap(), "the user's own file");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(dir.path().join("run.sh")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o755);
+            assert!(std::fs::symlink_metadata(dir.path().join("link")).unwrap().file_type().is_symlink());
+        }
+
+        let mut left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name != "run.sh" && name != "link")
+            .collect();
+        left.sort();
+        assert_eq!(left, ["data", "data.tmp"]);
+    }
+
+    #[test]
+    fn test_diff_pages() {
+        let diff = |old: &str, new: &str| {
+            let program = Program::new(
+                "diff",
+                "Diff",
+                vec![
+                    Opcode::Store { page_id: "before".to_string(), data: serde_json::json!(old) },
+                    Opcode::Store { page_id: "after".to_string(), data: serde_json::json!({"success": true, "content": new}) },
+                    Opcode::Diff { old: "before".to_string(), new: "after".to_string(), store_to: "changes".to_string() },
+                ],
+            );
+            let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+            interp.run().unwrap();
+            interp.get_page("changes").unwrap().clone()
+        };
+
+        let same = diff("a\nb\n", "a\nb\n");
+        assert_eq!(same["diff"], "");
+        assert_eq!((same["added"].as_u64(), same["removed"].as_u64()), (Some(0), Some(0)));
+
+        let changed = diff("fn main() {\n    old();\n}\n", "fn main() {\n    new();\n    more();\n}\n");
+        assert_eq!((changed["added"].as_u64(), changed["removed"].as_u64()), (Some(2), Some(1)));
+        let text = changed["diff"].as_str().unwrap();
+        assert!(text.starts_with("--- before\n+++ after\n@@"));
+        assert!(text.contains("-    old();\n+    new();\n+    more();\n"));
+    }
+
+    #[test]
+    fn test_break_outside_loop_errors() {
+        let program = Program::new("b", "B", vec![Opcode::Break { condition: None }]);
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        let err = interp.run().unwrap_err();
+        assert!(err.to_string().contains("BREAK used outside of a LOOP"));
+        assert!(Program::new("c", "C", vec![Opcode::Continue { condition: None }]).validate().is_err());
+    }
+
+    #[test]
+    fn test_max_steps_stops_infinite_loop() {
+        let program = Program::new(
+            "spin",
+            "Spin",
+            vec![
+                Opcode::Label { name: "top".to_string() },
+                Opcode::Jump { target: "top".to_string() },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default()).with_max_steps(10);
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::StepLimitExceeded));
+        assert_eq!(interp.steps(), 10);
+    }
+
+    #[test]
+    fn test_opcode_hook_fires_before_and_after() {
+        let program = Program::new(
+            "hooked",
+            "Hooked",
+            vec![
+                Opcode::Store { page_id: "a".to_string(), data: serde_json::json!(1) },
+                Opcode::Label { name: "end".to_string() },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default())
+            .with_opcode_hook(Box::new(move |op, phase| {
+                seen.lock().unwrap().push((op.format_parts().0.to_string(), phase));
+            }));
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            vec![
+                ("STORE".to_string(), HookPhase::Before),
+                ("STORE".to_string(), HookPhase::After),
+                ("LABEL".to_string(), HookPhase::Before),
+                ("LABEL".to_string(), HookPhase::After),
+                ("COMPLETE".to_string(), HookPhase::Before),
+                ("COMPLETE".to_string(), HookPhase::After),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_durations_recorded() {
+        let program = Program::new(
+            "timed",
+            "Timed",
+            vec![
+                Opcode::Exec {
+                    command: "sleep 0.02".to_string(),
+                    stdin: None,
+                    cwd: None,
+                    env: HashMap::new(),
+                    store_to: "out".to_string(),
+                },
+                Opcode::Infer {
+                    prompt: "hi".to_string(),
+                    context: vec![],
+                    store_to: "answer".to_string(),
+                    params: Default::default(),
+                    history_page: None,
+                },
+                Opcode::Complete { result: serde_json::json!("done") },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, DefaultSyscallHandler::default());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::NeedsLlm(_)));
+        interp.record_llm_time(Duration::from_millis(250));
+        interp.provide_llm_response(serde_json::json!("hello"), "answer").unwrap();
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+
+        let trace = interp.trace();
+        assert!(trace.iter().all(|step| step.duration_ms >= 0.0));
+        assert!(trace[0].duration_ms >= 20.0);
+        assert_eq!(trace[1].opcode, "INFER");
+        assert_eq!(trace[1].llm_ms, Some(250.0));
+        assert!(trace[0].llm_ms.is_none());
+    }
 }
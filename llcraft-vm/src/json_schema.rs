@@ -0,0 +1,126 @@
+//! # JSON Schema Validation
+//!
+//! A small validator for the JSON Schema subset used to constrain LLM
+//! output: `type`, `enum`, `properties`, `required`,
+//! `additionalProperties: false` and `items`. Unknown keywords are ignored,
+//! so full schemas can be passed to the provider unchanged.
+
+use serde_json::Value;
+
+/// Check `value` against `schema`. The error names the failing location,
+/// e.g. `$.items[2].name: expected string, got number`
+pub fn validate(value: &Value, schema: &Value) -> Result<(), String> {
+    check(value, schema, "$")
+}
+
+fn check(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Ok(());
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            return Err(format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value)));
+        }
+    }
+
+    if let Some(options) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !options.contains(value) {
+            return Err(format!("{}: {} is not one of {}", path, value, Value::Array(options.clone())));
+        }
+    }
+
+    if let Value::Object(map) = value {
+        if let Some(required) = schema.get("required").and_then(|r| r.as_array()) {
+            for key in required.iter().filter_map(|k| k.as_str()) {
+                if !map.contains_key(key) {
+                    return Err(format!("{}: missing required field '{}'", path, key));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for (key, field) in map {
+            match properties.and_then(|p| p.get(key)) {
+                Some(field_schema) => check(field, field_schema, &format!("{}.{}", path, key))?,
+                None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                    return Err(format!("{}: unexpected field '{}'", path, key));
+                }
+                None => {}
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            check(item, item_schema, &format!("{}[{}]", path, i))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_subset() {
+        let schema = json!({
+            "type": "object",
+            "required": ["label", "score"],
+            "additionalProperties": false,
+            "properties": {
+                "label": {"type": "string", "enum": ["good", "bad"]},
+                "score": {"type": "integer"},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            }
+        });
+
+        assert!(validate(&json!({"label": "good", "score": 3, "tags": ["a"]}), &schema).is_ok());
+        assert_eq!(
+            validate(&json!({"label": "good"}), &schema).unwrap_err(),
+            "$: missing required field 'score'"
+        );
+        assert_eq!(
+            validate(&json!({"label": "meh", "score": 1}), &schema).unwrap_err(),
+            "$.label: \"meh\" is not one of [\"good\",\"bad\"]"
+        );
+        assert_eq!(
+            validate(&json!({"label": "bad", "score": 1.5}), &schema).unwrap_err(),
+            "$.score: expected integer, got number"
+        );
+        assert_eq!(
+            validate(&json!({"label": "bad", "score": 1, "tags": ["a", 2]}), &schema).unwrap_err(),
+            "$.tags[1]: expected string, got number"
+        );
+        assert_eq!(
+            validate(&json!({"label": "bad", "score": 1, "extra": true}), &schema).unwrap_err(),
+            "$: unexpected field 'extra'"
+        );
+    }
+}
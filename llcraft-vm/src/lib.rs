@@ -17,29 +17,42 @@ pub mod storage;
 pub mod schema;
 pub mod provider;
 pub mod interpreter;
+pub mod syscall;
 pub mod session;
+pub mod tokens;
+pub mod embedding;
+pub mod json_schema;
 
-pub use opcode::{Opcode, Program, Range, InferParams, LogLevel, Register, ParallelBranch};
+pub use opcode::{Opcode, Program, Range, InferParams, LogLevel, Register, ParallelBranch, ValidationError};
 pub use error::{Error, ErrorKind, ErrorStatus, Result};
 pub use stack::Stack;
-pub use memory::{Memory, MemoryPage};
+pub use memory::{Memory, MemoryPage, OversizePolicy, PageBudget, PageStats, GLOBAL_SCOPE, PARENT_SCOPE};
+pub use tokens::{TokenCounter, ApproxCounter, counter_for_model};
+pub use embedding::{Embedder, HashEmbedder, cosine_similarity};
 pub use storage::{Storage, StorageBackend, MemoryStorage, FileStorage};
-pub use schema::{VmSchema, ExecutionStep, SYSTEM_PROMPT, USER_PROMPT_TEMPLATE, format_pages_section, format_trace_section};
+#[cfg(feature = "redis")]
+pub use storage::RedisStorage;
+#[cfg(feature = "tiktoken")]
+pub use tokens::TiktokenCounter;
+pub use schema::{VmSchema, ExecutionStep, PageChange, PageChangeKind, OpcodeSpec, OPCODES, SYSTEM_PROMPT, USER_PROMPT_TEMPLATE, format_pages_section, format_trace_section};
 pub use provider::{
-    LlmProvider, ProviderConfig, ProviderType, ProviderError,
+    LlmProvider, DynLlmProvider, BoxFuture, ProviderConfig, ProviderType, ProviderError,
     ChatMessage, Role, CompletionRequest, CompletionResponse,
     ToolDefinition, ToolCall, ToolChoice,
-    StreamChunk, StreamReceiver, FinishReason, Usage, UsageTracker,
-    OpenAIProvider, AnthropicProvider, BridgeProvider,
+    StreamChunk, StreamReceiver, FinishReason, Usage, UsageTracker, LatencyStats,
+    OpenAIProvider, OpenAICompatibleProvider, AnthropicProvider, BridgeProvider, MockProvider, CoalescingProvider,
+    FnProvider, create_provider,
 };
 pub use interpreter::{
-    Interpreter, ExecutionResult, ExecutionState,
+    Interpreter, ExecutionResult, ExecutionState, ExecutionSnapshot, InjectOutcome, RunMetrics, ERROR_PAGE,
     LlmRequest, LlmRequestType,
     SyscallHandler, DefaultSyscallHandler,
+    Approval, ApprovalHook, HookPhase, OpcodeHook, OutputCallback,
 };
+pub use syscall::{RecordingSyscallHandler, SandboxedSyscallHandler, SYSCALLS};
 pub use session::{
     Session, SessionManager, SessionStatus, PageIndex, TraceSummary,
-    SessionBackend, FileBackend, MemoryBackend,
+    SessionBackend, FileBackend, MemoryBackend, WorkingSetEntry, INLINE_PAGE_TOKENS,
     ProgressEntry, ProgressLog,
 };
 
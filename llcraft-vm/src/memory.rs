@@ -3,14 +3,29 @@
 //! Page-based memory system for the LLM Virtual Machine.
 //! Memory is organized as named pages that can hold any JSON data.
 //! This is the working memory during execution.
+//!
+//! ## Scopes
+//!
+//! LOOP bodies and forked children run in a child scope. Writes there stay
+//! local and are dropped when the scope ends; reads fall through to outer
+//! scopes. Prefix a page id with `parent:` to read or write the enclosing
+//! scope's page, or `global:` for the top-level one.
 
 use crate::error::{self, Result};
+use crate::tokens::{ApproxCounter, SharedCounter, TokenCounter};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Maximum number of pages (prevents unbounded memory growth)
 pub const MAX_PAGES: usize = 1024;
 
+/// Page id prefix that addresses the enclosing scope
+pub const PARENT_SCOPE: &str = "parent:";
+
+/// Page id prefix that addresses the top-level scope
+pub const GLOBAL_SCOPE: &str = "global:";
+
 /// Approximate max tokens per page (for context window management)
 pub const DEFAULT_PAGE_SIZE: usize = 4096;
 
@@ -31,6 +46,15 @@ pub struct MemoryPage {
     pub created_at: u64,
     /// Last access timestamp
     pub accessed_at: u64,
+    /// Pinned pages are never evicted
+    #[serde(default)]
+    pub pinned: bool,
+    /// Times the page has been read
+    #[serde(default)]
+    pub reads: u64,
+    /// Times the page has been written
+    #[serde(default)]
+    pub writes: u64,
 }
 
 impl MemoryPage {
@@ -47,6 +71,9 @@ impl MemoryPage {
             label: None,
             created_at: now,
             accessed_at: now,
+            pinned: false,
+            reads: 0,
+            writes: 0,
         }
     }
 
@@ -60,6 +87,7 @@ impl MemoryPage {
         self.content = content;
         self.size_tokens = estimate_tokens(&self.content);
         self.dirty = true;
+        self.writes += 1;
         self.accessed_at = current_timestamp();
     }
 
@@ -72,6 +100,57 @@ impl MemoryPage {
     pub fn mark_clean(&mut self) {
         self.dirty = false;
     }
+
+    /// Read/write counts and last access time
+    pub fn stats(&self) -> PageStats {
+        PageStats {
+            reads: self.reads,
+            writes: self.writes,
+            last_access: self.accessed_at,
+        }
+    }
+}
+
+/// How a page has been used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageStats {
+    /// Times the page was read (LOAD, INFER context, ...)
+    pub reads: u64,
+    /// Times the page was written
+    pub writes: u64,
+    /// Last read or write (seconds since the epoch)
+    pub last_access: u64,
+}
+
+/// What to do when a stored page is over the per-page token budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    /// Store the page and emit a warning
+    #[default]
+    Warn,
+    /// Split the content into `{id}_{n}` pages; `{id}` lists the chunks
+    Chunk,
+    /// Refuse to store the page
+    Reject,
+}
+
+/// Soft per-page token limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageBudget {
+    /// Token budget for a single page
+    pub max_tokens: usize,
+    /// What to do with pages over the budget
+    pub policy: OversizePolicy,
+}
+
+impl Default for PageBudget {
+    fn default() -> Self {
+        Self {
+            max_tokens: DEFAULT_PAGE_SIZE,
+            policy: OversizePolicy::Warn,
+        }
+    }
 }
 
 /// LLM-VM Memory - collection of named pages
@@ -82,6 +161,34 @@ pub struct Memory {
     total_tokens: usize,
     /// Maximum tokens allowed
     max_tokens: usize,
+    /// Counter used for page sizes
+    #[serde(skip)]
+    counter: SharedCounter,
+    /// Per-page token budget
+    #[serde(default)]
+    page_budget: PageBudget,
+    /// Budget warnings not yet reported
+    #[serde(skip)]
+    warnings: Vec<String>,
+    /// Number of child scopes entered (0 = top level)
+    #[serde(default)]
+    scope_depth: usize,
+}
+
+/// A page id with any `parent:`/`global:` prefix removed
+pub fn unscoped(id: &str) -> &str {
+    id.strip_prefix(PARENT_SCOPE)
+        .or_else(|| id.strip_prefix(GLOBAL_SCOPE))
+        .unwrap_or(id)
+}
+
+/// Storage key of page `id` in the scope at `depth`
+fn scoped_key(depth: usize, id: &str) -> String {
+    if depth == 0 {
+        id.to_string()
+    } else {
+        format!("~{}/{}", depth, id)
+    }
 }
 
 impl Memory {
@@ -91,6 +198,10 @@ impl Memory {
             pages: HashMap::new(),
             total_tokens: 0,
             max_tokens: 128_000, // Default context window
+            counter: SharedCounter::default(),
+            page_budget: PageBudget::default(),
+            warnings: Vec::new(),
+            scope_depth: 0,
         }
     }
 
@@ -100,9 +211,43 @@ impl Memory {
             pages: HashMap::new(),
             total_tokens: 0,
             max_tokens,
+            counter: SharedCounter::default(),
+            page_budget: PageBudget::default(),
+            warnings: Vec::new(),
+            scope_depth: 0,
         }
     }
 
+    /// Use a different token counter and re-measure every page
+    pub fn set_token_counter(&mut self, counter: Arc<dyn TokenCounter>) {
+        self.counter = SharedCounter(counter);
+        self.total_tokens = 0;
+        for page in self.pages.values_mut() {
+            page.size_tokens = self.counter.count_value(&page.content);
+            self.total_tokens += page.size_tokens;
+        }
+    }
+
+    /// Set the per-page token budget and what happens to pages over it
+    pub fn set_page_budget(&mut self, max_tokens: usize, policy: OversizePolicy) {
+        self.page_budget = PageBudget { max_tokens, policy };
+    }
+
+    /// Current per-page token budget
+    pub fn page_budget(&self) -> PageBudget {
+        self.page_budget
+    }
+
+    /// Take the budget warnings raised since the last call
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// The token counter used for page sizes
+    pub fn token_counter(&self) -> &dyn TokenCounter {
+        &*self.counter
+    }
+
     /// Number of pages
     pub fn len(&self) -> usize {
         self.pages.len()
@@ -118,43 +263,155 @@ impl Memory {
         self.total_tokens
     }
 
+    /// Enter a child scope
+    pub fn push_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    /// Leave the current child scope, dropping the pages written in it
+    pub fn pop_scope(&mut self) {
+        if self.scope_depth == 0 {
+            return;
+        }
+        let prefix = scoped_key(self.scope_depth, "");
+        let local: Vec<String> = self.pages.keys().filter(|k| k.starts_with(&prefix)).cloned().collect();
+        for key in local {
+            self.remove_key(&key);
+        }
+        self.scope_depth -= 1;
+    }
+
+    /// Number of child scopes entered (0 = top level)
+    pub fn scope_depth(&self) -> usize {
+        self.scope_depth
+    }
+
+    /// Key a read of `id` resolves to: the innermost scope holding the page,
+    /// starting from the enclosing scope for `parent:`
+    fn read_key(&self, id: &str) -> String {
+        if let Some(id) = id.strip_prefix(GLOBAL_SCOPE) {
+            return id.to_string();
+        }
+        let (id, from) = match id.strip_prefix(PARENT_SCOPE) {
+            Some(id) => (id, self.scope_depth.saturating_sub(1)),
+            None => (id, self.scope_depth),
+        };
+        (0..=from)
+            .rev()
+            .map(|depth| scoped_key(depth, id))
+            .find(|key| self.pages.contains_key(key))
+            .unwrap_or_else(|| scoped_key(from, id))
+    }
+
+    /// Key a write of `id` resolves to: the current scope, or for `parent:`
+    /// the nearest outer scope holding the page
+    fn write_key(&self, id: &str) -> String {
+        match id.strip_prefix(PARENT_SCOPE) {
+            Some(_) => self.read_key(id),
+            None if id.starts_with(GLOBAL_SCOPE) => self.read_key(id),
+            None => scoped_key(self.scope_depth, id),
+        }
+    }
+
     /// Check if a page exists
     pub fn has_page(&self, id: &str) -> bool {
-        self.pages.contains_key(id)
+        self.pages.contains_key(&self.read_key(id))
     }
 
     /// Get a page by ID (immutable)
     pub fn get(&self, id: &str) -> Option<&MemoryPage> {
-        self.pages.get(id)
+        self.pages.get(&self.read_key(id))
     }
 
     /// Get a page by ID (mutable)
     pub fn get_mut(&mut self, id: &str) -> Option<&mut MemoryPage> {
-        let page = self.pages.get_mut(id)?;
+        let key = self.read_key(id);
+        let page = self.pages.get_mut(&key)?;
         page.touch();
         Some(page)
     }
 
     /// Load page content
     pub fn load(&mut self, id: &str) -> Result<&serde_json::Value> {
-        let page = self.pages.get_mut(id).ok_or_else(|| error::page_not_found(id))?;
+        let key = self.read_key(id);
+        let page = self.pages.get_mut(&key).ok_or_else(|| error::page_not_found(id))?;
         page.touch();
+        page.reads += 1;
         Ok(&page.content)
     }
 
-    /// Store content to a page (creates if not exists)
+    /// Count a read of a page used without `load` (e.g. as LLM context)
+    pub fn record_read(&mut self, id: &str) {
+        let key = self.read_key(id);
+        if let Some(page) = self.pages.get_mut(&key) {
+            page.touch();
+            page.reads += 1;
+        }
+    }
+
+    /// Usage of every page
+    pub fn page_stats(&self) -> HashMap<String, PageStats> {
+        self.pages.iter().map(|(id, page)| (id.clone(), page.stats())).collect()
+    }
+
+    /// Store content to a page (creates if not exists), applying the page budget
     pub fn store(&mut self, id: impl Into<String>, content: serde_json::Value) -> Result<()> {
         let id = id.into();
+        let key = self.write_key(&id);
+        let tokens = self.counter.count_value(&content);
+        let PageBudget { max_tokens, policy } = self.page_budget;
+
+        if tokens <= max_tokens {
+            return self.store_unchecked(key, content);
+        }
+
+        match policy {
+            OversizePolicy::Warn => {
+                self.warnings.push(format!(
+                    "page '{}' is {} tokens, over the {} token page budget",
+                    id, tokens, max_tokens
+                ));
+                self.store_unchecked(key, content)
+            }
+            OversizePolicy::Reject => Err(error::page_too_large(&id, tokens, max_tokens)),
+            OversizePolicy::Chunk => {
+                let text = match content {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                };
+                let chars: Vec<char> = text.chars().collect();
+                let parts = tokens.div_ceil(max_tokens.max(1));
+                let per_chunk = chars.len().div_ceil(parts).max(1);
+
+                let mut chunk_ids = Vec::new();
+                for (i, chunk) in chars.chunks(per_chunk).enumerate() {
+                    let chunk_id = format!("{}_{}", id, i);
+                    let chunk_key = self.write_key(&chunk_id);
+                    self.store_unchecked(chunk_key, serde_json::json!(chunk.iter().collect::<String>()))?;
+                    chunk_ids.push(chunk_id);
+                }
+                self.warnings.push(format!(
+                    "page '{}' is {} tokens, over the {} token page budget; split into {} chunks",
+                    id, tokens, max_tokens, chunk_ids.len()
+                ));
+                self.store_unchecked(key, serde_json::json!({ "chunks": chunk_ids }))
+            }
+        }
+    }
+
+    /// Store content without checking the page budget
+    fn store_unchecked(&mut self, id: String, content: serde_json::Value) -> Result<()> {
 
         if let Some(page) = self.pages.get_mut(&id) {
             let old_tokens = page.size_tokens;
             page.set_content(content);
+            page.size_tokens = self.counter.count_value(&page.content);
             self.total_tokens = self.total_tokens - old_tokens + page.size_tokens;
         } else {
-            if self.pages.len() >= MAX_PAGES {
-                return Err(error::page_overflow());
-            }
-            let page = MemoryPage::new(&id, content);
+            self.make_room()?;
+            let mut page = MemoryPage::new(&id, content);
+            page.size_tokens = self.counter.count_value(&page.content);
+            page.writes = 1;
             self.total_tokens += page.size_tokens;
             self.pages.insert(id, page);
         }
@@ -163,10 +420,11 @@ impl Memory {
     }
 
     /// Store a pre-built page directly (used when loading from session)
-    pub fn store_page(&mut self, page: MemoryPage) -> Result<()> {
-        if !self.pages.contains_key(&page.id) && self.pages.len() >= MAX_PAGES {
-            return Err(error::page_overflow());
+    pub fn store_page(&mut self, mut page: MemoryPage) -> Result<()> {
+        if !self.pages.contains_key(&page.id) {
+            self.make_room()?;
         }
+        page.size_tokens = self.counter.count_value(&page.content);
 
         let old_tokens = self.pages.get(&page.id).map(|p| p.size_tokens).unwrap_or(0);
         self.total_tokens = self.total_tokens - old_tokens + page.size_tokens;
@@ -177,11 +435,13 @@ impl Memory {
 
     /// Allocate a new empty page
     pub fn alloc(&mut self, label: Option<String>) -> Result<String> {
-        if self.pages.len() >= MAX_PAGES {
-            return Err(error::page_overflow());
-        }
+        self.make_room()?;
 
-        let id = format!("page_{}", self.pages.len());
+        // First free `page_<n>`, so ids depend only on what's in memory
+        let id = (self.pages.len()..)
+            .map(|n| format!("page_{}", n))
+            .find(|id| !self.pages.contains_key(id))
+            .unwrap_or_default();
         let mut page = MemoryPage::empty(&id);
         page.label = label;
         self.total_tokens += page.size_tokens;
@@ -190,16 +450,78 @@ impl Memory {
         Ok(id)
     }
 
+    /// Set a page's label (its content type in the page index)
+    pub fn set_label(&mut self, id: &str, label: impl Into<String>) -> Result<()> {
+        let page = self.resolve_mut(id).ok_or_else(|| error::page_not_found(id))?;
+        page.label = Some(label.into());
+        Ok(())
+    }
+
+    /// Pin a page so eviction never drops it
+    pub fn pin(&mut self, id: &str) -> Result<()> {
+        let page = self.resolve_mut(id).ok_or_else(|| error::page_not_found(id))?;
+        page.pinned = true;
+        Ok(())
+    }
+
+    /// Allow a pinned page to be evicted again
+    pub fn unpin(&mut self, id: &str) -> Result<()> {
+        let page = self.resolve_mut(id).ok_or_else(|| error::page_not_found(id))?;
+        page.pinned = false;
+        Ok(())
+    }
+
+    /// Check if a page is pinned
+    pub fn is_pinned(&self, id: &str) -> bool {
+        self.get(id).is_some_and(|p| p.pinned)
+    }
+
+    /// Make room for one more page, evicting the LRU unpinned page at the cap.
+    /// Errors if every page is pinned.
+    fn make_room(&mut self) -> Result<()> {
+        if self.pages.len() < MAX_PAGES {
+            return Ok(());
+        }
+        match self.lru_unpinned() {
+            Some(id) => {
+                self.remove_key(&id);
+                Ok(())
+            }
+            None => Err(error::page_overflow()),
+        }
+    }
+
+    /// Least recently used page that is not pinned
+    fn lru_unpinned(&self) -> Option<String> {
+        self.pages
+            .values()
+            .filter(|p| !p.pinned)
+            .min_by_key(|p| p.accessed_at)
+            .map(|p| p.id.clone())
+    }
+
     /// Free a page
     pub fn free(&mut self, id: &str) -> Result<()> {
-        let page = self.pages.remove(id).ok_or_else(|| error::page_not_found(id))?;
+        let key = self.read_key(id);
+        self.remove_key(&key).ok_or_else(|| error::page_not_found(id))
+    }
+
+    /// Remove the page stored under `key`, bypassing scope resolution
+    fn remove_key(&mut self, key: &str) -> Option<()> {
+        let page = self.pages.remove(key)?;
         self.total_tokens = self.total_tokens.saturating_sub(page.size_tokens);
-        Ok(())
+        Some(())
+    }
+
+    /// Page `id` resolves to, without touching it
+    fn resolve_mut(&mut self, id: &str) -> Option<&mut MemoryPage> {
+        let key = self.read_key(id);
+        self.pages.get_mut(&key)
     }
 
     /// Copy content from one page to another
     pub fn copy(&mut self, src: &str, dst: &str) -> Result<()> {
-        let content = self.pages.get(src)
+        let content = self.get(src)
             .ok_or_else(|| error::page_not_found(src))?
             .content
             .clone();
@@ -230,19 +552,17 @@ impl Memory {
         pages
     }
 
-    /// Evict least recently used pages until under token limit
+    /// Evict least recently used pages until under token limit.
+    /// Pinned pages are skipped, so the limit may not be reached.
     pub fn evict_to_limit(&mut self, target_tokens: usize) -> Vec<String> {
         let mut evicted = Vec::new();
 
         while self.total_tokens > target_tokens && !self.pages.is_empty() {
             // Find LRU page
-            let lru_id = self.pages
-                .values()
-                .min_by_key(|p| p.accessed_at)
-                .map(|p| p.id.clone());
+            let lru_id = self.lru_unpinned();
 
             if let Some(id) = lru_id {
-                if let Ok(()) = self.free(&id) {
+                if self.remove_key(&id).is_some() {
                     evicted.push(id);
                 }
             } else {
@@ -254,11 +574,9 @@ impl Memory {
     }
 }
 
-/// Estimate token count for a JSON value (rough approximation)
+/// Estimate token count for a JSON value with the default heuristic
 fn estimate_tokens(value: &serde_json::Value) -> usize {
-    let s = value.to_string();
-    // Rough estimate: 4 chars per token
-    s.len() / 4 + 1
+    ApproxCounter::new().count_value(value)
 }
 
 /// Get current timestamp (mock for now)
@@ -275,6 +593,35 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    #[test]
+    fn test_scopes_isolate_writes() {
+        let mut mem = Memory::new();
+        mem.store("acc", json!(0)).unwrap();
+        mem.store("config", json!("top")).unwrap();
+
+        mem.push_scope();
+        mem.push_scope();
+        assert_eq!(mem.get("config").unwrap().content, json!("top"));
+
+        mem.store("acc", json!(99)).unwrap();
+        mem.store("tmp", json!(1)).unwrap();
+        assert_eq!(mem.get("acc").unwrap().content, json!(99));
+        assert_eq!(mem.get("parent:acc").unwrap().content, json!(0));
+
+        mem.store("parent:acc", json!(1)).unwrap();
+        mem.store("global:config", json!("changed")).unwrap();
+        assert_eq!(mem.get("global:acc").unwrap().content, json!(1));
+
+        mem.pop_scope();
+        assert!(mem.get("tmp").is_none());
+        assert_eq!(mem.get("acc").unwrap().content, json!(1));
+
+        mem.pop_scope();
+        assert_eq!(mem.scope_depth(), 0);
+        assert_eq!(mem.len(), 2);
+        assert_eq!(mem.get("config").unwrap().content, json!("changed"));
+    }
+
     #[test]
     fn test_store_load() {
         let mut mem = Memory::new();
@@ -330,6 +677,92 @@ mod tests {
         assert!(mem.get("page1").unwrap().dirty);
     }
 
+    #[test]
+    fn test_pinned_page_survives_eviction() {
+        let mut mem = Memory::new();
+
+        mem.store("goal", json!("the task goal")).unwrap();
+        mem.pin("goal").unwrap();
+        mem.store("scratch", json!("temporary notes")).unwrap();
+
+        let evicted = mem.evict_to_limit(0);
+        assert_eq!(evicted, vec!["scratch".to_string()]);
+        assert!(mem.has_page("goal"));
+        assert!(mem.is_pinned("goal"));
+
+        mem.unpin("goal").unwrap();
+        mem.evict_to_limit(0);
+        assert!(mem.is_empty());
+    }
+
+    #[test]
+    fn test_page_cap_evicts_unpinned_then_errors() {
+        use crate::error::ErrorKind;
+        let mut mem = Memory::new();
+
+        for i in 0..MAX_PAGES {
+            mem.store(format!("p{}", i), json!(i)).unwrap();
+        }
+        mem.pin("p0").unwrap();
+
+        // At the cap an unpinned page makes room; the pinned one stays
+        mem.store("extra", json!("new")).unwrap();
+        assert_eq!(mem.len(), MAX_PAGES);
+        assert!(mem.has_page("p0"));
+
+        for id in mem.page_ids().map(String::from).collect::<Vec<_>>() {
+            mem.pin(&id).unwrap();
+        }
+        let result = mem.store("overflow", json!("lost?"));
+        assert!(result.is_err_and(|e| e.kind() == ErrorKind::PageOverflow));
+        assert!(mem.alloc(None).is_err());
+    }
+
+    #[test]
+    fn test_custom_token_counter() {
+        struct Words;
+        impl TokenCounter for Words {
+            fn count(&self, text: &str) -> usize {
+                text.split_whitespace().count()
+            }
+        }
+
+        let mut mem = Memory::new();
+        mem.store("a", json!("one two three four five six seven eight")).unwrap();
+        assert_eq!(mem.get("a").unwrap().size_tokens, 11);
+
+        mem.set_token_counter(Arc::new(Words));
+        assert_eq!(mem.get("a").unwrap().size_tokens, 8);
+        mem.store("b", json!("nine ten")).unwrap();
+        assert_eq!(mem.total_tokens(), 10);
+    }
+
+    #[test]
+    fn test_page_budget_policies() {
+        let big = json!("word ".repeat(100));
+
+        let mut mem = Memory::new();
+        mem.set_page_budget(50, OversizePolicy::Warn);
+        mem.store("big", big.clone()).unwrap();
+        assert!(mem.has_page("big"));
+        let warnings = mem.take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("page 'big'"));
+        assert!(mem.take_warnings().is_empty());
+
+        mem.set_page_budget(50, OversizePolicy::Reject);
+        assert!(mem.store("rejected", big.clone()).is_err());
+        assert!(!mem.has_page("rejected"));
+
+        mem.set_page_budget(50, OversizePolicy::Chunk);
+        mem.store("doc", big).unwrap();
+        let chunks = mem.get("doc").unwrap().content["chunks"].as_array().unwrap().clone();
+        assert_eq!(chunks.len(), 3);
+        for id in &chunks {
+            assert!(mem.get(id.as_str().unwrap()).unwrap().size_tokens <= 50);
+        }
+    }
+
     #[test]
     fn test_total_tokens() {
         let mut mem = Memory::new();
@@ -10,6 +10,7 @@
 //! - Syscalls provide controlled access to external tools
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// LLM-VM Opcode - the instruction set for LLM cognition
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -38,6 +39,15 @@ pub enum Opcode {
         data: serde_json::Value,
     },
 
+    /// Append a value to an array page
+    /// Starts a new array if the page is absent or null
+    Append {
+        /// Page holding the array
+        page_id: String,
+        /// Value to append
+        value: serde_json::Value,
+    },
+
     /// Allocate a new page
     /// Returns the page ID of the newly allocated page
     Alloc {
@@ -67,6 +77,12 @@ pub enum Opcode {
         range: Option<Range>,
     },
 
+    /// Pin a page so memory eviction never drops it
+    Pin {
+        /// Page identifier to pin
+        page_id: String,
+    },
+
     // =========================================================================
     // CONTROL FLOW - Process and execution management
     // =========================================================================
@@ -81,6 +97,13 @@ pub enum Opcode {
         args: serde_json::Value,
     },
 
+    /// Splice another program's code in here. Resolved before the program
+    /// runs by `Program::resolve_includes`
+    Include {
+        /// Id of the program to inline
+        program: String,
+    },
+
     /// Return from a subprogram (pop frame from stack)
     /// Restores previous state and continues execution
     Return {
@@ -117,6 +140,16 @@ pub enum Opcode {
         if_false: String,
     },
 
+    /// Multi-way branch: jump to the label of the first case equal to `value`
+    Switch {
+        /// Path to the value to match (e.g. "result.status")
+        value: String,
+        /// `[value, label]` pairs, checked in order
+        cases: Vec<(serde_json::Value, String)>,
+        /// Label to jump to when no case matches
+        default: String,
+    },
+
     /// Unconditional jump to a label
     Jump {
         /// Label to jump to
@@ -140,6 +173,30 @@ pub enum Opcode {
         body: Vec<Opcode>,
     },
 
+    /// Stop the innermost enclosing LOOP
+    Break {
+        /// Only break when this condition holds (same syntax as BRANCH)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        condition: Option<String>,
+    },
+
+    /// Skip to the next item of the innermost enclosing LOOP
+    Continue {
+        /// Only skip when this condition holds (same syntax as BRANCH)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        condition: Option<String>,
+    },
+
+    /// Run `body`; if an opcode in it errors, run `catch` instead of failing
+    /// the program. The error is stored in the `_error` page.
+    Try {
+        /// Opcodes to attempt
+        body: Vec<Opcode>,
+        /// Opcodes to run after a caught error
+        #[serde(default)]
+        catch: Vec<Opcode>,
+    },
+
     // =========================================================================
     // TOOLS - Explicit external tool operations
     // =========================================================================
@@ -158,7 +215,10 @@ pub enum Opcode {
         path: String,
         /// Content to write
         content: String,
-        /// Page to store result {success, path}
+        /// Keep the previous content as `{path}.bak`
+        #[serde(default)]
+        backup: bool,
+        /// Page to store result {success, path, backup}
         #[serde(default)]
         store_to: Option<String>,
     },
@@ -175,7 +235,16 @@ pub enum Opcode {
     Exec {
         /// Shell command to execute
         command: String,
-        /// Page to store result {success, stdout, stderr, exit_code}
+        /// Text piped to the command's stdin
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stdin: Option<String>,
+        /// Directory to run in, relative to the workspace root
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<String>,
+        /// Extra environment variables
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        env: HashMap<String, String>,
+        /// Page to store result {success, stdout, stderr, exit_code, cwd}
         store_to: String,
     },
 
@@ -189,6 +258,34 @@ pub enum Opcode {
         store_to: String,
     },
 
+    /// Make an HTTP request to an allowlisted host
+    HttpRequest {
+        /// HTTP method (GET, POST, ...)
+        #[serde(default = "default_http_method")]
+        method: String,
+        /// Full http(s) URL
+        url: String,
+        /// Request headers
+        #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+        headers: HashMap<String, String>,
+        /// Request body
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<String>,
+        /// Page to store result {success, status, headers, body}
+        store_to: String,
+    },
+
+    /// Read an environment variable into a page
+    GetEnv {
+        /// Variable name
+        name: String,
+        /// Page to store the value
+        store_to: String,
+        /// Value to use when the variable is unset (null if omitted)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default: Option<String>,
+    },
+
     /// Wait for an async syscall to complete
     Wait {
         /// Handle returned by async syscall
@@ -198,6 +295,25 @@ pub enum Opcode {
         timeout_ms: Option<u64>,
     },
 
+    /// Pause execution. Counts as one step, so a polling loop still stops
+    /// at the step limit
+    Sleep {
+        /// Milliseconds to sleep (capped by the interpreter's max sleep)
+        ms: u64,
+    },
+
+    /// Store the current unix time in seconds
+    Timestamp {
+        /// Page to store the timestamp
+        store_to: String,
+    },
+
+    /// Store a fresh random (v4) UUID string
+    Uuid {
+        /// Page to store the UUID
+        store_to: String,
+    },
+
     // =========================================================================
     // PARALLEL EXECUTION - Spawn and join concurrent tasks
     // =========================================================================
@@ -230,6 +346,19 @@ pub enum Opcode {
         store_to: String,
     },
 
+    /// Start a registered program in a child interpreter on its own thread;
+    /// its pid is stored in store_to. The child runs alongside the parent
+    /// until a JOIN collects its result.
+    Fork {
+        /// ID of a program registered with the interpreter
+        program_id: String,
+        /// Value stored in the child's "args" page
+        #[serde(default)]
+        args: serde_json::Value,
+        /// Page to store the child's pid
+        store_to: String,
+    },
+
     /// Send a message to another process
     Send {
         /// Target process ID
@@ -240,7 +369,8 @@ pub enum Opcode {
 
     /// Receive a message from the message queue
     Recv {
-        /// Timeout in milliseconds (None = block forever)
+        /// Timeout in milliseconds (None = wait until a message arrives or
+        /// every other running process is also waiting in RECV)
         #[serde(default)]
         timeout_ms: Option<u64>,
         /// Page to store received message
@@ -264,6 +394,10 @@ pub enum Opcode {
         /// Model parameters
         #[serde(default)]
         params: InferParams,
+        /// Page holding earlier `{role, content}` turns. They are sent ahead
+        /// of the prompt, and the new exchange is appended afterwards
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        history_page: Option<String>,
     },
 
     /// Yield control back to the LLM for planning
@@ -339,6 +473,18 @@ pub enum Opcode {
         params: InferParams,
     },
 
+    /// Run an LLM prompt for every element of an array page
+    /// Runs as an INFER_BATCH: each result goes to `{store_to}_{i}` and the
+    /// combined `{results, count, success}` to `store_to`
+    Map {
+        /// Page (or `page.field` path) holding the array
+        over: String,
+        /// Prompt with `{item}` and `{index}` placeholders
+        prompt_template: String,
+        /// Page for the collected results
+        store_to: String,
+    },
+
     /// Chunk a large page into smaller pages
     /// For processing large contexts incrementally
     Chunk {
@@ -362,6 +508,77 @@ pub enum Opcode {
         separator: Option<String>,
     },
 
+    // =========================================================================
+    // DATA - Convert between JSON values and strings
+    // =========================================================================
+
+    /// Parse a JSON string into a structured value
+    JsonParse {
+        /// Page (or `page.field` path) holding the string
+        source: String,
+        /// Page to store the parsed value
+        store_to: String,
+    },
+
+    /// Serialize a value into a JSON string
+    JsonStringify {
+        /// Page (or `page.field` path) holding the value
+        source: String,
+        /// Page to store the string
+        store_to: String,
+        /// Pretty-print with indentation
+        #[serde(default)]
+        pretty: bool,
+    },
+
+    /// Store the length of a value: array elements, object keys or string
+    /// characters. An absent or null source has length 0
+    Len {
+        /// Page (or `page.field` path) holding the value
+        source: String,
+        /// Page to store the length
+        store_to: String,
+    },
+
+    /// Replace regex matches in a string. `replacement` may refer to capture
+    /// groups as `$1` or `${name}`
+    RegexReplace {
+        /// Page (or `page.field` path) holding the string
+        source: String,
+        /// Regular expression to match
+        pattern: String,
+        /// Replacement text
+        replacement: String,
+        /// Page to store the result
+        store_to: String,
+        /// Replace every match instead of only the first
+        #[serde(default)]
+        all: bool,
+    },
+
+    /// Unified diff between the text of two pages. A page holding a READ_FILE
+    /// result is compared by its `content`
+    Diff {
+        /// Page (or `page.field` path) with the original text
+        old: String,
+        /// Page (or `page.field` path) with the changed text
+        new: String,
+        /// Page to store {success, diff, added, removed}
+        store_to: String,
+    },
+
+    /// Keep the elements of an array page that satisfy a condition
+    /// The element is bound as `item` (e.g. `item.score > 0.5`); the number
+    /// of matches is stored in `{store_to}_count`
+    Filter {
+        /// Page (or `page.field` path) holding the array
+        over: String,
+        /// Condition evaluated for each element
+        condition: String,
+        /// Page for the matching elements
+        store_to: String,
+    },
+
     // =========================================================================
     // DEBUGGING AND INTROSPECTION
     // =========================================================================
@@ -377,14 +594,29 @@ pub enum Opcode {
         message: String,
     },
 
+    /// Print user-facing output: a page's content or a literal message
+    Print {
+        /// Page (or dotted path) whose content to print
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+        /// Literal message to print
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+
     /// Checkpoint the current state
     /// Allows rollback to this point
     Checkpoint {
         /// Checkpoint name
         name: String,
+        /// Also save it to the session, so a later run can roll back to it
+        #[serde(default)]
+        persist: bool,
     },
 
-    /// Rollback to a checkpoint
+    /// Rollback to a checkpoint: pages, stack and registers go back to how
+    /// they were, and execution carries on after the ROLLBACK (pc is not
+    /// restored)
     Rollback {
         /// Checkpoint name to rollback to
         name: String,
@@ -453,6 +685,18 @@ pub enum Opcode {
         store_to: Option<String>,
     },
 
+    /// Find the session pages most relevant to a query, by similarity of
+    /// their index summaries
+    SearchPages {
+        /// What to look for
+        query: String,
+        /// Maximum number of pages to return
+        #[serde(default = "default_top_k")]
+        top_k: usize,
+        /// Page for the matches
+        store_to: String,
+    },
+
     /// Save a page to session storage
     /// Page is indexed for later retrieval
     SavePage {
@@ -584,6 +828,16 @@ fn default_one() -> usize {
     1
 }
 
+/// SEARCH_PAGES returns 5 pages unless told otherwise
+fn default_top_k() -> usize {
+    5
+}
+
+/// HTTP_REQUEST defaults to GET
+fn default_http_method() -> String {
+    "GET".to_string()
+}
+
 /// A branch in a PARALLEL operation
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ParallelBranch {
@@ -614,6 +868,10 @@ pub struct InferParams {
     /// Model to use (if different from default)
     #[serde(default)]
     pub model: Option<String>,
+    /// JSON schema the response must match. Sent to the provider as the
+    /// response format; the reply is parsed and validated before it's stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
 }
 
 /// Log levels for debugging
@@ -646,6 +904,21 @@ pub enum Register {
     Custom(String),
 }
 
+impl Register {
+    /// Name the register is stored under ("goal", or the custom name)
+    pub fn name(&self) -> &str {
+        match self {
+            Register::Pc => "pc",
+            Register::Goal => "goal",
+            Register::Focus => "focus",
+            Register::Thought => "thought",
+            Register::Flags => "flags",
+            Register::Sp => "sp",
+            Register::Custom(name) => name,
+        }
+    }
+}
+
 impl Opcode {
     /// Check if this opcode is a terminal instruction
     pub fn is_terminal(&self) -> bool {
@@ -659,8 +932,12 @@ impl Opcode {
             Opcode::Call { .. }
                 | Opcode::Return { .. }
                 | Opcode::Branch { .. }
+                | Opcode::Switch { .. }
                 | Opcode::Jump { .. }
                 | Opcode::Loop { .. }
+                | Opcode::Break { .. }
+                | Opcode::Continue { .. }
+                | Opcode::Try { .. }
                 | Opcode::Complete { .. }
                 | Opcode::Fail { .. }
         )
@@ -675,12 +952,20 @@ impl Opcode {
                 | Opcode::WriteFile { .. }
                 | Opcode::ListDir { .. }
                 | Opcode::Exec { .. }
+                | Opcode::HttpRequest { .. }
+                | Opcode::GetEnv { .. }
                 | Opcode::Grep { .. }
                 | Opcode::Send { .. }
                 | Opcode::Recv { .. }
         )
     }
 
+    /// Check if this opcode has side effects outside the VM
+    /// (writes files, runs commands). These are subject to approval hooks.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, Opcode::WriteFile { .. } | Opcode::Exec { .. } | Opcode::HttpRequest { .. })
+    }
+
     /// Check if this opcode is a stack operation
     pub fn is_stack_op(&self) -> bool {
         matches!(
@@ -707,11 +992,26 @@ impl Opcode {
         match self {
             Opcode::Load { page_id, .. } => vec![page_id.as_str()],
             Opcode::Copy { src, .. } => vec![src.as_str()],
-            Opcode::Infer { context, .. } => context.iter().map(|s| s.as_str()).collect(),
+            Opcode::Pin { page_id } => vec![page_id.as_str()],
+            Opcode::Append { page_id, .. } => vec![page_id.as_str()],
+            Opcode::Infer { context, history_page, .. } => {
+                context.iter().chain(history_page).map(|s| s.as_str()).collect()
+            }
             Opcode::Summarize { pages, .. } => pages.iter().map(|s| s.as_str()).collect(),
             Opcode::Chunk { source, .. } => vec![source.as_str()],
             Opcode::Merge { pages, .. } => pages.iter().map(|s| s.as_str()).collect(),
+            Opcode::Map { over, .. } | Opcode::Filter { over, .. } => vec![over.split('.').next().unwrap_or(over)],
             Opcode::PushPage { page_id } => vec![page_id.as_str()],
+            Opcode::Print { source: Some(source), .. } => vec![source.split('.').next().unwrap_or(source)],
+            Opcode::JsonParse { source, .. }
+            | Opcode::JsonStringify { source, .. }
+            | Opcode::Len { source, .. }
+            | Opcode::RegexReplace { source, .. } => {
+                vec![source.split('.').next().unwrap_or(source)]
+            }
+            Opcode::Diff { old, new, .. } => {
+                vec![old.split('.').next().unwrap_or(old), new.split('.').next().unwrap_or(new)]
+            }
             _ => vec![],
         }
     }
@@ -720,22 +1020,68 @@ impl Opcode {
     pub fn writes_pages(&self) -> Vec<&str> {
         match self {
             Opcode::Store { page_id, .. } => vec![page_id.as_str()],
+            Opcode::Append { page_id, .. } => vec![page_id.as_str()],
             Opcode::Alloc { label, .. } => label.as_ref().map(|s| vec![s.as_str()]).unwrap_or_default(),
             Opcode::Copy { dst, .. } => vec![dst.as_str()],
             Opcode::ReadFile { store_to, .. } => vec![store_to.as_str()],
             Opcode::WriteFile { store_to, .. } => store_to.as_ref().map(|s| vec![s.as_str()]).unwrap_or_default(),
             Opcode::ListDir { store_to, .. } => vec![store_to.as_str()],
             Opcode::Exec { store_to, .. } => vec![store_to.as_str()],
+            Opcode::HttpRequest { store_to, .. } => vec![store_to.as_str()],
+            Opcode::GetEnv { store_to, .. } => vec![store_to.as_str()],
             Opcode::Grep { store_to, .. } => vec![store_to.as_str()],
-            Opcode::Infer { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Infer { store_to, history_page, .. } => {
+                std::iter::once(store_to).chain(history_page).map(|s| s.as_str()).collect()
+            }
+            Opcode::Map { store_to, .. } => vec![store_to.as_str()],
             Opcode::Summarize { store_to, .. } => vec![store_to.as_str()],
             Opcode::Merge { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Fork { store_to, .. } => vec![store_to.as_str()],
             Opcode::Recv { store_to, .. } => vec![store_to.as_str()],
             Opcode::GetReg { store_to, .. } => vec![store_to.as_str()],
             Opcode::PopTo { store_to } => vec![store_to.as_str()],
             Opcode::Peek { store_to } => vec![store_to.as_str()],
             Opcode::PeekAt { store_to, .. } => vec![store_to.as_str()],
             Opcode::Depth { store_to } => vec![store_to.as_str()],
+            Opcode::JsonParse { store_to, .. } => vec![store_to.as_str()],
+            Opcode::JsonStringify { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Filter { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Len { store_to, .. } => vec![store_to.as_str()],
+            Opcode::RegexReplace { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Diff { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Timestamp { store_to } | Opcode::Uuid { store_to } => vec![store_to.as_str()],
+            Opcode::SearchPages { store_to, .. } => vec![store_to.as_str()],
+            Opcode::Join { store_to, .. }
+            | Opcode::Parallel { store_to, .. }
+            | Opcode::LoadSession { store_to, .. } => vec![store_to.as_str()],
+            Opcode::InferBatch { store_combined, .. } => store_combined.iter().map(|s| s.as_str()).collect(),
+            Opcode::Loop { var, .. } => vec![var.as_str()],
+            _ => vec![],
+        }
+    }
+
+    /// Labels this opcode may jump to
+    pub fn jump_targets(&self) -> Vec<&str> {
+        match self {
+            Opcode::Jump { target } => vec![target.as_str()],
+            Opcode::Branch { if_true, if_false, .. } => vec![if_true.as_str(), if_false.as_str()],
+            Opcode::Switch { cases, default, .. } => cases
+                .iter()
+                .map(|(_, label)| label.as_str())
+                .chain(std::iter::once(default.as_str()))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Opcodes nested inside this one (LOOP and TRY bodies, SPAWN tasks,
+    /// PARALLEL branches)
+    pub fn children(&self) -> Vec<&Opcode> {
+        match self {
+            Opcode::Loop { body, .. } => body.iter().collect(),
+            Opcode::Try { body, catch } => body.iter().chain(catch).collect(),
+            Opcode::Spawn { task, .. } => vec![task.as_ref()],
+            Opcode::Parallel { branches, .. } => branches.iter().flat_map(|b| &b.ops).collect(),
             _ => vec![],
         }
     }
@@ -813,12 +1159,207 @@ impl Program {
     }
 }
 
+/// A problem found by [`Program::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Index of the offending opcode in `Program::code`
+    pub index: usize,
+    /// What is wrong with it
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "opcode {}: {}", self.index, self.message)
+    }
+}
+
+impl Program {
+    /// Statically check the program before running it
+    ///
+    /// Catches problems that would otherwise abort mid-run: jumps to
+    /// undefined labels, duplicate labels, a missing entry label, and
+    /// zero-sized chunks. Returns every problem found, not just the first.
+    pub fn validate(&self) -> std::result::Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+        let mut labels: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+
+        for (i, op) in self.code.iter().enumerate() {
+            if let Opcode::Label { name } = op {
+                if let Some(first) = labels.insert(name.as_str(), i) {
+                    errors.push(ValidationError {
+                        index: i,
+                        message: format!("duplicate label '{}' (first defined at opcode {})", name, first),
+                    });
+                }
+            }
+        }
+
+        for (i, op) in self.code.iter().enumerate() {
+            for target in op.jump_targets() {
+                if !labels.contains_key(target) {
+                    errors.push(ValidationError {
+                        index: i,
+                        message: format!("jump to undefined label '{}'", target),
+                    });
+                }
+            }
+
+            if matches!(op, Opcode::Break { .. } | Opcode::Continue { .. }) {
+                errors.push(ValidationError {
+                    index: i,
+                    message: format!("{} outside of a LOOP", op.format_parts().0),
+                });
+            }
+
+            if let Opcode::Chunk { chunk_size: 0, .. } = op {
+                errors.push(ValidationError {
+                    index: i,
+                    message: "CHUNK chunk_size must be greater than 0".to_string(),
+                });
+            }
+        }
+
+        if let Some(entry) = &self.entry {
+            if !labels.contains_key(entry.as_str()) {
+                errors.push(ValidationError {
+                    index: 0,
+                    message: format!("entry label '{}' is not defined", entry),
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Labels defined in the program, in order
+    pub fn labels(&self) -> Vec<&str> {
+        self.code
+            .iter()
+            .filter_map(|op| match op {
+                Opcode::Label { name } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the program contains an explicit COMPLETE or FAIL
+    pub fn has_terminal(&self) -> bool {
+        self.code.iter().any(|op| op.is_terminal())
+    }
+
+    /// Whether any INCLUDE is left to resolve, at any depth
+    pub fn has_includes(&self) -> bool {
+        fn any_include(ops: &[Opcode]) -> bool {
+            ops.iter().any(|op| match op {
+                Opcode::Include { .. } => true,
+                Opcode::Loop { body, .. } => any_include(body),
+                Opcode::Try { body, catch } => any_include(body) || any_include(catch),
+                Opcode::Parallel { branches, .. } => branches.iter().any(|b| any_include(&b.ops)),
+                _ => false,
+            })
+        }
+        any_include(&self.code)
+    }
+
+    /// Copy of the program with every INCLUDE (top level and inside LOOP,
+    /// TRY and PARALLEL bodies) replaced by the code of the program it names,
+    /// recursively. Fails if a program is missing or includes itself, directly
+    /// or through others. Included code is spliced as is, so its labels must
+    /// not clash with the includer's.
+    pub fn resolve_includes(&self, registry: &HashMap<String, Program>) -> crate::error::Result<Program> {
+        let mut chain = vec![self.id.clone()];
+        Ok(Program { code: inline_includes(&self.code, registry, &mut chain)?, ..self.clone() })
+    }
+
+    /// Registry of the programs in `dir` (every `*.json` file), by id
+    pub fn load_dir(dir: impl AsRef<std::path::Path>) -> crate::error::Result<HashMap<String, Program>> {
+        use crate::error;
+
+        let entries = std::fs::read_dir(dir.as_ref())
+            .map_err(|e| error::io_error(format!("Failed to read {}: {}", dir.as_ref().display(), e)))?;
+        let mut registry = HashMap::new();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| error::io_error(format!("Failed to read {}: {}", path.display(), e)))?;
+            let program: Program = serde_json::from_str(&text)
+                .map_err(|e| error::parse_error(format!("{}: {}", path.display(), e)))?;
+            registry.insert(program.id.clone(), program);
+        }
+        Ok(registry)
+    }
+}
+
+/// `ops` with includes expanded; `chain` holds the programs being expanded,
+/// outermost first
+fn inline_includes(
+    ops: &[Opcode],
+    registry: &HashMap<String, Program>,
+    chain: &mut Vec<String>,
+) -> crate::error::Result<Vec<Opcode>> {
+    let mut out = Vec::with_capacity(ops.len());
+    for op in ops {
+        match op {
+            Opcode::Include { program } => {
+                if chain.contains(program) {
+                    chain.push(program.clone());
+                    return Err(crate::error::include_cycle(chain));
+                }
+                let included = registry.get(program).ok_or_else(|| crate::error::program_not_found(program))?;
+                chain.push(program.clone());
+                out.extend(inline_includes(&included.code, registry, chain)?);
+                chain.pop();
+            }
+            Opcode::Loop { var, over, body } => out.push(Opcode::Loop {
+                var: var.clone(),
+                over: over.clone(),
+                body: inline_includes(body, registry, chain)?,
+            }),
+            Opcode::Try { body, catch } => out.push(Opcode::Try {
+                body: inline_includes(body, registry, chain)?,
+                catch: inline_includes(catch, registry, chain)?,
+            }),
+            Opcode::Parallel { branches, store_to } => out.push(Opcode::Parallel {
+                branches: branches
+                    .iter()
+                    .map(|b| Ok(ParallelBranch { id: b.id.clone(), ops: inline_includes(&b.ops, registry, chain)? }))
+                    .collect::<crate::error::Result<_>>()?,
+                store_to: store_to.clone(),
+            }),
+            other => out.push(other.clone()),
+        }
+    }
+    Ok(out)
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (name, details) = self.format_parts();
+        if details.is_empty() {
+            write!(f, "{}", name)
+        } else {
+            write!(f, "{} {}", name, details)
+        }
+    }
+}
+
 impl Opcode {
     /// Format opcode into (name, details) for pretty printing
-    fn format_parts(&self) -> (&'static str, String) {
+    pub(crate) fn format_parts(&self) -> (&'static str, String) {
         match self {
             Opcode::Label { name } => ("LABEL", format!(":{}", name)),
             Opcode::Log { level, message } => ("LOG", format!("[{:?}] \"{}\"", level, truncate(message, 30))),
+            Opcode::Print { source, message } => match (source, message) {
+                (Some(source), _) => ("PRINT", source.clone()),
+                (None, message) => ("PRINT", format!("\"{}\"", truncate(message.as_deref().unwrap_or(""), 30))),
+            },
             Opcode::Infer { prompt, context, store_to, .. } => {
                 let ctx = if context.is_empty() { String::new() } else { format!(" [{}]", context.join(", ")) };
                 ("INFER", format!("\"{}\"{}  → {}", truncate(prompt, 25), ctx, store_to))
@@ -826,6 +1367,9 @@ impl Opcode {
             Opcode::Branch { condition, if_true, if_false } => {
                 ("BRANCH", format!("{} ? {} : {}", truncate(condition, 15), if_true, if_false))
             }
+            Opcode::Switch { value, cases, default } => {
+                ("SWITCH", format!("{} [{} cases] else {}", value, cases.len(), default))
+            }
             Opcode::Jump { target } => ("JUMP", format!("→ {}", target)),
             Opcode::Push { value } => ("PUSH", format_value_brief(value)),
             Opcode::PushPage { page_id } => ("PUSH_PAGE", page_id.clone()),
@@ -834,20 +1378,34 @@ impl Opcode {
             Opcode::Complete { result } => ("COMPLETE", format_value_brief(result)),
             Opcode::Fail { error } => ("FAIL", format!("\"{}\"", truncate(error, 40))),
             Opcode::Call { program_id, args } => ("CALL", format!("{}({})", program_id, format_args_brief(args))),
+            Opcode::Include { program } => ("INCLUDE", program.clone()),
             Opcode::Return { value } => ("RETURN", format_value_brief(value)),
             Opcode::Yield => ("YIELD", String::new()),
             Opcode::Load { page_id, .. } => ("LOAD", page_id.clone()),
             Opcode::Store { page_id, .. } => ("STORE", page_id.clone()),
+            Opcode::Append { page_id, value } => ("APPEND", format!("{} += {}", page_id, format_value_brief(value))),
             Opcode::Alloc { label, .. } => ("ALLOC", label.clone().unwrap_or_default()),
             Opcode::Free { page_id } => ("FREE", page_id.clone()),
             Opcode::Copy { src, dst, .. } => ("COPY", format!("{} → {}", src, dst)),
+            Opcode::Pin { page_id } => ("PIN", page_id.clone()),
             Opcode::Summarize { pages, store_to, .. } => ("SUMMARIZE", format!("[{}] → {}", pages.join(", "), store_to)),
             Opcode::Chunk { source, chunk_size, .. } => ("CHUNK", format!("{} / {}", source, chunk_size)),
             Opcode::Merge { pages, store_to, .. } => ("MERGE", format!("[{}] → {}", pages.join(", "), store_to)),
+            Opcode::JsonParse { source, store_to } => ("JSON_PARSE", format!("{} → {}", source, store_to)),
+            Opcode::JsonStringify { source, store_to, .. } => ("JSON_STRINGIFY", format!("{} → {}", source, store_to)),
+            Opcode::Len { source, store_to } => ("LEN", format!("{} → {}", source, store_to)),
+            Opcode::Diff { old, new, store_to } => ("DIFF", format!("{} ↔ {} → {}", old, new, store_to)),
+            Opcode::RegexReplace { source, pattern, store_to, .. } => {
+                ("REGEX_REPLACE", format!("{} /{}/ → {}", source, truncate(pattern, 25), store_to))
+            }
+            Opcode::Filter { over, condition, store_to } => {
+                ("FILTER", format!("{} where {} → {}", over, truncate(condition, 25), store_to))
+            }
             Opcode::Spawn { task_id, task } => {
                 let (task_name, _) = task.format_parts();
                 ("SPAWN", format!("{} ← {}", task_id, task_name))
             }
+            Opcode::Fork { program_id, store_to, .. } => ("FORK", format!("{} → {}", program_id, store_to)),
             Opcode::Join { task_ids, store_to } => {
                 if task_ids.is_empty() {
                     ("JOIN", format!("all → {}", store_to))
@@ -862,8 +1420,12 @@ impl Opcode {
             Opcode::Send { pid, .. } => ("SEND", format!("→ {}", pid)),
             Opcode::Recv { store_to, .. } => ("RECV", format!("→ {}", store_to)),
             Opcode::Wait { handle, .. } => ("WAIT", handle.clone()),
+            Opcode::Sleep { ms } => ("SLEEP", format!("{}ms", ms)),
+            Opcode::Timestamp { store_to } => ("TIMESTAMP", format!("→ {}", store_to)),
+            Opcode::Uuid { store_to } => ("UUID", format!("→ {}", store_to)),
             Opcode::Nop => ("NOP", String::new()),
-            Opcode::Checkpoint { name } => ("CHECKPOINT", name.clone()),
+            Opcode::Checkpoint { name, persist: true } => ("CHECKPOINT", format!("{} (persist)", name)),
+            Opcode::Checkpoint { name, .. } => ("CHECKPOINT", name.clone()),
             Opcode::Rollback { name } => ("ROLLBACK", name.clone()),
             Opcode::Assert { condition, .. } => ("ASSERT", truncate(condition, 40)),
             Opcode::SetReg { reg, .. } => ("SET_REG", format!("{:?}", reg)),
@@ -877,6 +1439,9 @@ impl Opcode {
             Opcode::Peek { store_to } => ("PEEK", format!("→ {}", store_to)),
             Opcode::PeekAt { depth, store_to } => ("PEEK_AT", format!("[{}] → {}", depth, store_to)),
             Opcode::Loop { var, over, .. } => ("LOOP", format!("{} in {}", var, over)),
+            Opcode::Break { condition } => ("BREAK", condition.clone().unwrap_or_default()),
+            Opcode::Continue { condition } => ("CONTINUE", condition.clone().unwrap_or_default()),
+            Opcode::Try { body, catch } => ("TRY", format!("{} ops, catch {} ops", body.len(), catch.len())),
             Opcode::Depth { store_to } => ("DEPTH", format!("→ {}", store_to)),
             Opcode::Clear => ("CLEAR", String::new()),
             Opcode::Plan { goal, context, store_to } => {
@@ -899,16 +1464,22 @@ impl Opcode {
             Opcode::ReadFile { path, store_to } => {
                 ("READ_FILE", format!("\"{}\" → {}", path, store_to))
             }
-            Opcode::WriteFile { path, store_to, .. } => {
+            Opcode::WriteFile { path, backup, store_to, .. } => {
                 let store = store_to.as_ref().map(|s| format!(" → {}", s)).unwrap_or_default();
-                ("WRITE_FILE", format!("\"{}\"{}",  path, store))
+                let backup = if *backup { " (backup)" } else { "" };
+                ("WRITE_FILE", format!("\"{}\"{}{}", path, backup, store))
             }
             Opcode::ListDir { path, store_to } => {
                 ("LIST_DIR", format!("\"{}\" → {}", path, store_to))
             }
-            Opcode::Exec { command, store_to } => {
-                ("EXEC", format!("\"{}\" → {}", truncate(command, 30), store_to))
+            Opcode::Exec { command, cwd, store_to, .. } => {
+                let cwd = cwd.as_ref().map(|c| format!(" in \"{}\"", c)).unwrap_or_default();
+                ("EXEC", format!("\"{}\"{} → {}", truncate(command, 30), cwd, store_to))
+            }
+            Opcode::HttpRequest { method, url, store_to, .. } => {
+                ("HTTP_REQUEST", format!("{} \"{}\" → {}", method, truncate(url, 40), store_to))
             }
+            Opcode::GetEnv { name, store_to, .. } => ("GET_ENV", format!("{} → {}", name, store_to)),
             Opcode::Grep { pattern, path, store_to } => {
                 ("GREP", format!("\"{}\" in \"{}\" → {}", pattern, path, store_to))
             }
@@ -926,6 +1497,9 @@ impl Opcode {
                 let store = store_to.as_ref().map(|s| format!(" → {}", s)).unwrap_or_default();
                 ("LOAD_PAGE", format!("{}{}", page_id, store))
             }
+            Opcode::SearchPages { query, top_k, store_to } => {
+                ("SEARCH_PAGES", format!("\"{}\" top {} → {}", truncate(query, 25), top_k, store_to))
+            }
             Opcode::SavePage { page_id, summary, .. } => {
                 let sum = summary.as_ref().map(|s| format!(" \"{}\"", truncate(s, 20))).unwrap_or_default();
                 ("SAVE_PAGE", format!("{}{}", page_id, sum))
@@ -948,6 +1522,9 @@ impl Opcode {
                 let combined = store_combined.as_ref().map(|c| format!(", combined → {}", c)).unwrap_or_default();
                 ("INFER_BATCH", format!("{} prompts → {}_0..{}{}", prompts.len(), store_prefix, prompts.len().saturating_sub(1), combined))
             }
+            Opcode::Map { over, prompt_template, store_to } => {
+                ("MAP", format!("\"{}\" over {} → {}", truncate(prompt_template, 25), over, store_to))
+            }
         }
     }
 }
@@ -1024,6 +1601,7 @@ mod tests {
                     context: vec!["content".to_string()],
                     store_to: "analysis".to_string(),
                     params: InferParams::default(),
+                    history_page: None,
                 },
                 Opcode::Complete {
                     result: serde_json::json!({"page": "analysis"}),
@@ -1053,9 +1631,145 @@ mod tests {
             context: vec!["page1".to_string(), "page2".to_string()],
             store_to: "output".to_string(),
             params: InferParams::default(),
+            history_page: None,
         };
 
         assert_eq!(op.reads_pages(), vec!["page1", "page2"]);
         assert_eq!(op.writes_pages(), vec!["output"]);
     }
+
+    #[test]
+    fn test_validate_valid_program() {
+        let program = Program::new(
+            "ok",
+            "OK",
+            vec![
+                Opcode::Label { name: "start".to_string() },
+                Opcode::Branch {
+                    condition: "x.success".to_string(),
+                    if_true: "done".to_string(),
+                    if_false: "start".to_string(),
+                },
+                Opcode::Label { name: "done".to_string() },
+                Opcode::Complete { result: serde_json::json!({}) },
+            ],
+        );
+
+        assert!(program.validate().is_ok());
+        assert_eq!(program.labels(), vec!["start", "done"]);
+        assert!(program.has_terminal());
+    }
+
+    #[test]
+    fn test_json_ops_serde() {
+        let op: Opcode = serde_json::from_str(r#"{"op": "JSON_PARSE", "source": "exec.stdout", "store_to": "data"}"#).unwrap();
+        assert_eq!(op.reads_pages(), vec!["exec"]);
+        assert_eq!(op.writes_pages(), vec!["data"]);
+
+        let op: Opcode = serde_json::from_str(r#"{"op": "JSON_STRINGIFY", "source": "data", "store_to": "text"}"#).unwrap();
+        assert!(matches!(op, Opcode::JsonStringify { pretty: false, .. }));
+        let json = serde_json::to_value(&op).unwrap();
+        assert_eq!(json["op"], "JSON_STRINGIFY");
+    }
+
+    #[test]
+    fn test_utility_ops_serde() {
+        for json in [
+            r#"{"op":"SLEEP","ms":250}"#,
+            r#"{"op":"TIMESTAMP","store_to":"now"}"#,
+            r#"{"op":"UUID","store_to":"id"}"#,
+        ] {
+            let op: Opcode = serde_json::from_str(json).unwrap();
+            assert_eq!(serde_json::to_string(&op).unwrap(), json);
+        }
+        let op: Opcode = serde_json::from_str(r#"{"op": "UUID", "store_to": "id"}"#).unwrap();
+        assert_eq!(op.writes_pages(), vec!["id"]);
+    }
+
+    #[test]
+    fn test_switch_serde_and_validation() {
+        let json = r#"{"op": "SWITCH", "value": "result.status", "cases": [["ok", "done"], [404, "retry"]], "default": "fail"}"#;
+        let op: Opcode = serde_json::from_str(json).unwrap();
+        assert!(op.is_control_flow());
+        assert_eq!(op.to_string(), "SWITCH result.status [2 cases] else fail");
+
+        let program = Program::new("switch", "Switch", vec![op, Opcode::Label { name: "done".to_string() }]);
+        let errors = program.validate().unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("'retry'"));
+        assert!(messages[1].contains("'fail'"));
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let mut program = Program::new(
+            "broken",
+            "Broken",
+            vec![
+                Opcode::Label { name: "a".to_string() },
+                Opcode::Label { name: "a".to_string() },
+                Opcode::Jump { target: "missing".to_string() },
+                Opcode::Chunk {
+                    source: "src".to_string(),
+                    chunk_size: 0,
+                    prefix: None,
+                },
+            ],
+        );
+        program.entry = Some("nowhere".to_string());
+
+        let errors = program.validate().unwrap_err();
+        let indices: Vec<usize> = errors.iter().map(|e| e.index).collect();
+        assert_eq!(indices, vec![1, 2, 3, 0]);
+        assert!(errors[1].to_string().contains("undefined label 'missing'"));
+        assert!(!program.has_terminal());
+    }
+
+    #[test]
+    fn test_resolve_includes_inlines_nested_programs() {
+        let setup = Program::new("setup", "Setup", vec![
+            Opcode::Store { page_id: "config".to_string(), data: serde_json::json!({"debug": true}) },
+            Opcode::Include { program: "log".to_string() },
+        ]);
+        let log = Program::new("log", "Log", vec![Opcode::Log { level: LogLevel::Info, message: "ready".to_string() }]);
+        let main = Program::new("main", "Main", vec![
+            Opcode::Include { program: "setup".to_string() },
+            Opcode::Loop {
+                var: "item".to_string(),
+                over: "items".to_string(),
+                body: vec![Opcode::Include { program: "log".to_string() }],
+            },
+            Opcode::Complete { result: serde_json::json!({"page": "config"}) },
+        ]);
+        let registry: HashMap<_, _> = [setup, log].into_iter().map(|p| (p.id.clone(), p)).collect();
+
+        assert!(main.has_includes());
+        let resolved = main.resolve_includes(&registry).unwrap();
+        assert!(!resolved.has_includes());
+        assert_eq!(resolved.id, "main");
+        assert_eq!(resolved.code.len(), 4);
+        assert!(matches!(&resolved.code[0], Opcode::Store { page_id, .. } if page_id == "config"));
+        assert!(matches!(&resolved.code[1], Opcode::Log { message, .. } if message == "ready"));
+        assert!(matches!(&resolved.code[2], Opcode::Loop { body, .. } if matches!(body[..], [Opcode::Log { .. }])));
+
+        let missing = Program::new("m", "M", vec![Opcode::Include { program: "nope".to_string() }]);
+        assert!(missing.resolve_includes(&registry).is_err());
+    }
+
+    #[test]
+    fn test_resolve_includes_rejects_cycles() {
+        let a = Program::new("a", "A", vec![Opcode::Include { program: "b".to_string() }]);
+        let b = Program::new("b", "B", vec![Opcode::Try {
+            body: vec![Opcode::Include { program: "a".to_string() }],
+            catch: vec![],
+        }]);
+        let registry: HashMap<_, _> = [a.clone(), b].into_iter().map(|p| (p.id.clone(), p)).collect();
+
+        let err = a.resolve_includes(&registry).unwrap_err();
+        assert!(err.to_string().contains("include cycle: a -> b -> a"), "{}", err);
+
+        let own = Program::new("self", "Self", vec![Opcode::Include { program: "self".to_string() }]);
+        assert!(own.resolve_includes(&HashMap::new()).is_err());
+    }
 }
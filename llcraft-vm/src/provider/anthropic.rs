@@ -23,6 +23,49 @@ impl AnthropicProvider {
     fn base_url(&self) -> &str {
         self.config.base_url.as_deref().unwrap_or("https://api.anthropic.com/v1")
     }
+
+    /// Build the request body. The system message moves to `system`, and
+    /// extended thinking (which requires the default temperature and a
+    /// `max_tokens` above the budget) is enabled when a budget is set.
+    fn api_request(&self, request: &CompletionRequest, stream: bool) -> AnthropicRequest {
+        let mut system = None;
+        let mut messages = Vec::new();
+        for msg in &request.messages {
+            if msg.role == Role::System {
+                system = msg.content.clone();
+            } else {
+                messages.push(AnthropicMessage::from(msg.clone()));
+            }
+        }
+
+        let mut max_tokens = request.max_tokens.unwrap_or(4096);
+        let mut temperature = request.temperature;
+        let thinking = request.thinking_budget.map(|budget| {
+            if max_tokens <= budget {
+                max_tokens += budget;
+            }
+            temperature = None;
+            serde_json::json!({"type": "enabled", "budget_tokens": budget})
+        });
+
+        AnthropicRequest {
+            model: request.model.as_deref().unwrap_or(self.default_model()).to_string(),
+            messages,
+            system,
+            max_tokens,
+            temperature,
+            stream: Some(stream),
+            tools: request.tools.as_ref().map(|tools| {
+                tools.iter().map(|t| AnthropicTool {
+                    name: t.name.clone(),
+                    description: t.description.clone(),
+                    input_schema: t.parameters.clone(),
+                }).collect()
+            }),
+            stop_sequences: request.stop.clone(),
+            thinking,
+        }
+    }
 }
 
 impl LlmProvider for AnthropicProvider {
@@ -44,39 +87,26 @@ impl LlmProvider for AnthropicProvider {
         self.config.default_model.as_deref().unwrap_or("claude-sonnet-4-20250514")
     }
 
-    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
-        let model = request.model.as_deref().unwrap_or(self.default_model());
-        
-        // Extract system message
-        let (system, messages): (Option<String>, Vec<_>) = {
-            let mut sys = None;
-            let mut msgs = Vec::new();
-            for msg in &request.messages {
-                if msg.role == Role::System {
-                    sys = msg.content.clone();
-                } else {
-                    msgs.push(AnthropicMessage::from(msg.clone()));
-                }
-            }
-            (sys, msgs)
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let Some(api_key) = &self.config.api_key else {
+            return Ok(self.models());
         };
 
-        let api_request = AnthropicRequest {
-            model: model.to_string(),
-            messages,
-            system,
-            max_tokens: request.max_tokens.unwrap_or(4096),
-            temperature: request.temperature,
-            stream: Some(false),
-            tools: request.tools.as_ref().map(|tools| {
-                tools.iter().map(|t| AnthropicTool {
-                    name: t.name.clone(),
-                    description: t.description.clone(),
-                    input_schema: t.parameters.clone(),
-                }).collect()
-            }),
-            stop_sequences: request.stop,
-        };
+        let mut req = self.client
+            .get(format!("{}/models", self.base_url()))
+            .header("x-api-key", api_key)
+            .header("anthropic-version", "2023-06-01");
+
+        for (key, value) in &self.config.headers {
+            req = req.header(key, value);
+        }
+
+        Ok(fetch_models(req).await.unwrap_or_else(|_| self.models()))
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        let started = Instant::now();
+        let api_request = self.api_request(&request, false);
 
         let api_key = self.config.api_key.as_ref()
             .ok_or(ProviderError::AuthenticationFailed)?;
@@ -88,6 +118,10 @@ impl LlmProvider for AnthropicProvider {
             .header("content-type", "application/json")
             .json(&api_request);
 
+        if let Some(timeout) = request.timeout {
+            req = req.timeout(timeout);
+        }
+
         for (key, value) in &self.config.headers {
             req = req.header(key, value);
         }
@@ -111,81 +145,14 @@ impl LlmProvider for AnthropicProvider {
         let api_response: AnthropicResponse = response.json().await
             .map_err(|e| ProviderError::Parse(e.to_string()))?;
 
-        // Extract text content and tool calls
-        let mut content = String::new();
-        let mut tool_calls = Vec::new();
-
-        for block in &api_response.content {
-            match block {
-                ContentBlock::Text { text } => {
-                    content.push_str(text);
-                }
-                ContentBlock::ToolUse { id, name, input } => {
-                    tool_calls.push(ToolCall {
-                        id: id.clone(),
-                        name: name.clone(),
-                        arguments: serde_json::to_string(input).unwrap_or_default(),
-                    });
-                }
-            }
-        }
-
-        let finish_reason = match api_response.stop_reason.as_deref() {
-            Some("end_turn") => FinishReason::Stop,
-            Some("max_tokens") => FinishReason::Length,
-            Some("tool_use") => FinishReason::ToolCalls,
-            _ => FinishReason::Unknown,
-        };
-
-        let usage = Usage {
-            prompt_tokens: api_response.usage.input_tokens,
-            completion_tokens: api_response.usage.output_tokens,
-            total_tokens: api_response.usage.input_tokens + api_response.usage.output_tokens,
-        };
-
-        Ok(CompletionResponse {
-            id: api_response.id,
-            model: api_response.model,
-            content: if content.is_empty() { None } else { Some(content) },
-            tool_calls,
-            finish_reason,
-            usage,
-        })
+        let mut response = api_response.into_completion();
+        response.usage = response.usage.with_latency(started);
+        Ok(response)
     }
 
     async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
-        let model = request.model.as_deref().unwrap_or(self.default_model());
-        
-        // Extract system message
-        let (system, messages): (Option<String>, Vec<_>) = {
-            let mut sys = None;
-            let mut msgs = Vec::new();
-            for msg in &request.messages {
-                if msg.role == Role::System {
-                    sys = msg.content.clone();
-                } else {
-                    msgs.push(AnthropicMessage::from(msg.clone()));
-                }
-            }
-            (sys, msgs)
-        };
-
-        let api_request = AnthropicRequest {
-            model: model.to_string(),
-            messages,
-            system,
-            max_tokens: request.max_tokens.unwrap_or(4096),
-            temperature: request.temperature,
-            stream: Some(true),
-            tools: request.tools.as_ref().map(|tools| {
-                tools.iter().map(|t| AnthropicTool {
-                    name: t.name.clone(),
-                    description: t.description.clone(),
-                    input_schema: t.parameters.clone(),
-                }).collect()
-            }),
-            stop_sequences: request.stop,
-        };
+        let started = Instant::now();
+        let api_request = self.api_request(&request, true);
 
         let api_key = self.config.api_key.as_ref()
             .ok_or(ProviderError::AuthenticationFailed)?;
@@ -197,6 +164,10 @@ impl LlmProvider for AnthropicProvider {
             .header("content-type", "application/json")
             .json(&api_request);
 
+        if let Some(timeout) = request.timeout {
+            req = req.timeout(timeout);
+        }
+
         for (key, value) in &self.config.headers {
             req = req.header(key, value);
         }
@@ -213,92 +184,25 @@ impl LlmProvider for AnthropicProvider {
         // Create async stream from SSE response
         let stream = async_stream::stream! {
             use futures_util::StreamExt;
-            
+
             let mut byte_stream = response.bytes_stream();
             let mut buffer = String::new();
-            let mut current_tool_index = 0;
-            
+            let mut state = StreamState::default();
+            let mut chunks = Vec::new();
+
             while let Some(chunk_result) = byte_stream.next().await {
                 match chunk_result {
                     Ok(bytes) => {
                         buffer.push_str(&String::from_utf8_lossy(&bytes));
-                        
+
                         // Process complete SSE events
                         while let Some(pos) = buffer.find("\n\n") {
                             let event = buffer[..pos].to_string();
                             buffer = buffer[pos + 2..].to_string();
-                            
-                            let mut event_type = None;
-                            let mut event_data = None;
-                            
-                            for line in event.lines() {
-                                if let Some(t) = line.strip_prefix("event: ") {
-                                    event_type = Some(t.to_string());
-                                } else if let Some(d) = line.strip_prefix("data: ") {
-                                    event_data = Some(d.to_string());
-                                }
-                            }
-                            
-                            if let (Some(etype), Some(data)) = (event_type, event_data) {
-                                match etype.as_str() {
-                                    "content_block_delta" => {
-                                        if let Ok(delta) = serde_json::from_str::<ContentBlockDelta>(&data) {
-                                            match delta.delta {
-                                                DeltaContent::TextDelta { text } => {
-                                                    yield StreamChunk::Text(text);
-                                                }
-                                                DeltaContent::InputJsonDelta { partial_json } => {
-                                                    yield StreamChunk::ToolCallDelta {
-                                                        index: current_tool_index,
-                                                        id: None,
-                                                        name: None,
-                                                        arguments_delta: Some(partial_json),
-                                                    };
-                                                }
-                                            }
-                                        }
-                                    }
-                                    "content_block_start" => {
-                                        if let Ok(start) = serde_json::from_str::<ContentBlockStart>(&data) {
-                                            if let Some(tool_use) = start.content_block.tool_use {
-                                                yield StreamChunk::ToolCallDelta {
-                                                    index: start.index,
-                                                    id: Some(tool_use.id),
-                                                    name: Some(tool_use.name),
-                                                    arguments_delta: None,
-                                                };
-                                                current_tool_index = start.index;
-                                            }
-                                        }
-                                    }
-                                    "message_stop" => {
-                                        yield StreamChunk::Done {
-                                            finish_reason: FinishReason::Stop,
-                                            usage: None,
-                                        };
-                                    }
-                                    "message_delta" => {
-                                        if let Ok(delta) = serde_json::from_str::<MessageDelta>(&data) {
-                                            if let Some(reason) = delta.delta.stop_reason {
-                                                let fr = match reason.as_str() {
-                                                    "end_turn" => FinishReason::Stop,
-                                                    "max_tokens" => FinishReason::Length,
-                                                    "tool_use" => FinishReason::ToolCalls,
-                                                    _ => FinishReason::Unknown,
-                                                };
-                                                yield StreamChunk::Done {
-                                                    finish_reason: fr,
-                                                    usage: delta.usage.map(|u| Usage {
-                                                        prompt_tokens: 0, // Not available in delta
-                                                        completion_tokens: u.output_tokens,
-                                                        total_tokens: u.output_tokens,
-                                                    }),
-                                                };
-                                            }
-                                        }
-                                    }
-                                    _ => {}
-                                }
+
+                            state.event(&event, started, &mut chunks);
+                            for chunk in chunks.drain(..) {
+                                yield chunk;
                             }
                         }
                     }
@@ -314,6 +218,106 @@ impl LlmProvider for AnthropicProvider {
     }
 }
 
+/// Turns SSE events into chunks. Input tokens are only reported in
+/// `message_start`, so they're kept until the `Done` chunk.
+#[derive(Default)]
+struct StreamState {
+    current_tool_index: usize,
+    input_tokens: usize,
+    done: bool,
+}
+
+impl StreamState {
+    /// Handle one SSE event, pushing any chunks it produces
+    fn event(&mut self, event: &str, started: Instant, chunks: &mut Vec<StreamChunk>) {
+        let mut event_type = None;
+        let mut event_data = None;
+
+        for line in event.lines() {
+            if let Some(t) = line.strip_prefix("event: ") {
+                event_type = Some(t);
+            } else if let Some(d) = line.strip_prefix("data: ") {
+                event_data = Some(d);
+            }
+        }
+
+        let (Some(etype), Some(data)) = (event_type, event_data) else { return };
+        match etype {
+            "message_start" => {
+                if let Ok(start) = serde_json::from_str::<MessageStart>(data) {
+                    self.input_tokens = start.message.usage.input_tokens;
+                }
+            }
+            "content_block_delta" => {
+                if let Ok(delta) = serde_json::from_str::<ContentBlockDelta>(data) {
+                    match delta.delta {
+                        DeltaContent::TextDelta { text } => {
+                            chunks.push(StreamChunk::Text(text));
+                        }
+                        DeltaContent::InputJsonDelta { partial_json } => {
+                            chunks.push(StreamChunk::ToolCallDelta {
+                                index: self.current_tool_index,
+                                id: None,
+                                name: None,
+                                arguments_delta: Some(partial_json),
+                            });
+                        }
+                    }
+                }
+            }
+            "content_block_start" => {
+                if let Ok(start) = serde_json::from_str::<ContentBlockStart>(data) {
+                    if let Some(tool_use) = start.content_block.tool_use {
+                        chunks.push(StreamChunk::ToolCallDelta {
+                            index: start.index,
+                            id: Some(tool_use.id),
+                            name: Some(tool_use.name),
+                            arguments_delta: None,
+                        });
+                        self.current_tool_index = start.index;
+                    }
+                }
+            }
+            "message_delta" => {
+                if let Ok(delta) = serde_json::from_str::<MessageDelta>(data) {
+                    if let Some(reason) = delta.delta.stop_reason {
+                        let fr = match reason.as_str() {
+                            "end_turn" => FinishReason::Stop,
+                            "max_tokens" => FinishReason::Length,
+                            "tool_use" => FinishReason::ToolCalls,
+                            "refusal" => FinishReason::ContentFilter,
+                            _ => FinishReason::Unknown,
+                        };
+                        let output_tokens = delta.usage.map(|u| u.output_tokens).unwrap_or(0);
+                        self.done = true;
+                        chunks.push(StreamChunk::Done {
+                            finish_reason: fr,
+                            usage: Some(Usage {
+                                prompt_tokens: self.input_tokens,
+                                completion_tokens: output_tokens,
+                                total_tokens: self.input_tokens + output_tokens,
+                                latency_ms: None,
+                            }.with_latency(started)),
+                        });
+                    }
+                }
+            }
+            "message_stop" if !self.done => {
+                self.done = true;
+                chunks.push(StreamChunk::Done {
+                    finish_reason: FinishReason::Stop,
+                    usage: Some(Usage {
+                        prompt_tokens: self.input_tokens,
+                        total_tokens: self.input_tokens,
+                        ..Usage::default()
+                    }.with_latency(started)),
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
 // ============================================================================
 // Anthropic API Types
 // ============================================================================
@@ -333,6 +337,8 @@ struct AnthropicRequest {
     tools: Option<Vec<AnthropicTool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -400,6 +406,55 @@ struct AnthropicResponse {
     usage: AnthropicUsage,
 }
 
+impl AnthropicResponse {
+    fn into_completion(self) -> CompletionResponse {
+        let mut content = String::new();
+        let mut reasoning: Option<String> = None;
+        let mut tool_calls = Vec::new();
+
+        for block in self.content {
+            match block {
+                ContentBlock::Text { text } => content.push_str(&text),
+                ContentBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(ToolCall {
+                        id,
+                        name,
+                        arguments: serde_json::to_string(&input).unwrap_or_default(),
+                    });
+                }
+                ContentBlock::Thinking { thinking } => reasoning.get_or_insert_with(String::new).push_str(&thinking),
+                ContentBlock::RedactedThinking => {
+                    reasoning.get_or_insert_with(String::new).push_str("[redacted thinking]")
+                }
+                ContentBlock::Other => {}
+            }
+        }
+
+        let finish_reason = match self.stop_reason.as_deref() {
+            Some("end_turn") => FinishReason::Stop,
+            Some("max_tokens") => FinishReason::Length,
+            Some("tool_use") => FinishReason::ToolCalls,
+            Some("refusal") => FinishReason::ContentFilter,
+            _ => FinishReason::Unknown,
+        };
+
+        CompletionResponse {
+            id: self.id,
+            model: self.model,
+            content: if content.is_empty() { None } else { Some(content) },
+            tool_calls,
+            finish_reason,
+            usage: Usage {
+                prompt_tokens: self.usage.input_tokens,
+                completion_tokens: self.usage.output_tokens,
+                total_tokens: self.usage.input_tokens + self.usage.output_tokens,
+                latency_ms: None,
+            },
+            reasoning,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 enum ContentBlock {
@@ -411,6 +466,15 @@ enum ContentBlock {
         name: String,
         input: serde_json::Value,
     },
+    /// Extended-thinking reasoning
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String },
+    /// Reasoning the API returns encrypted
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking,
+    /// Block types this client doesn't know about
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Debug, Deserialize)]
@@ -420,6 +484,16 @@ struct AnthropicUsage {
 }
 
 // Streaming types
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    message: MessageStartInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStartInfo {
+    usage: AnthropicUsage,
+}
+
 #[derive(Debug, Deserialize)]
 struct ContentBlockDelta {
     delta: DeltaContent,
@@ -467,3 +541,86 @@ struct MessageDeltaContent {
 struct DeltaUsage {
     output_tokens: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_thinking_response() {
+        let captured = r#"{
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-20250514",
+            "content": [
+                {"type": "thinking", "thinking": "The user wants 2+2. ", "signature": "EqQBCgIYAhIM"},
+                {"type": "redacted_thinking", "data": "EmwKAhgBEgy3va3pzix"},
+                {"type": "server_tool_use", "id": "srv_1", "name": "web_search", "input": {}},
+                {"type": "text", "text": "4"}
+            ],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 12, "output_tokens": 40}
+        }"#;
+
+        let response: AnthropicResponse = serde_json::from_str(captured).unwrap();
+        let response = response.into_completion();
+        assert_eq!(response.content.as_deref(), Some("4"));
+        assert_eq!(response.reasoning.as_deref(), Some("The user wants 2+2. [redacted thinking]"));
+        assert_eq!(response.usage.total_tokens, 52);
+    }
+
+    #[test]
+    fn test_thinking_budget_in_request() {
+        let provider = AnthropicProvider::new(ProviderConfig::anthropic("sk-ant-test"));
+        let request = CompletionRequest::new(vec![ChatMessage::system("be brief"), ChatMessage::user("hi")])
+            .with_temperature(0.2)
+            .with_max_tokens(1000)
+            .with_thinking_budget(2048);
+
+        let body = serde_json::to_value(provider.api_request(&request, false)).unwrap();
+        assert_eq!(body["thinking"], serde_json::json!({"type": "enabled", "budget_tokens": 2048}));
+        assert_eq!(body["max_tokens"], 3048);
+        assert!(body.get("temperature").is_none());
+        assert_eq!(body["system"], "be brief");
+
+        let body = serde_json::to_value(provider.api_request(&CompletionRequest::default(), false)).unwrap();
+        assert!(body.get("thinking").is_none());
+    }
+
+    #[test]
+    fn test_stream_usage_carried_to_done() {
+        let captured = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_01\",\"type\":\"message\",\"role\":\"assistant\",\"content\":[],\"model\":\"claude-sonnet-4-20250514\",\"stop_reason\":null,\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":15}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        );
+
+        let mut state = StreamState::default();
+        let mut chunks = Vec::new();
+        for event in captured.split("\n\n") {
+            state.event(event, Instant::now(), &mut chunks);
+        }
+
+        assert_eq!(chunks.len(), 2, "one text chunk and a single Done: {:?}", chunks);
+        assert!(matches!(&chunks[0], StreamChunk::Text(t) if t == "Hello"));
+        match &chunks[1] {
+            StreamChunk::Done { finish_reason, usage: Some(usage) } => {
+                assert_eq!(*finish_reason, FinishReason::Stop);
+                assert_eq!(usage.prompt_tokens, 25);
+                assert_eq!(usage.completion_tokens, 15);
+                assert_eq!(usage.total_tokens, 40);
+            }
+            other => panic!("expected Done with usage, got {:?}", other),
+        }
+    }
+}
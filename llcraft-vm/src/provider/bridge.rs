@@ -39,6 +39,7 @@ impl BridgeProvider {
             default_model: Some("claude-opus-4".into()),
             headers: std::collections::HashMap::new(),
             timeout_secs: Some(300),
+            auth_header: None,
         })
     }
 
@@ -56,27 +57,6 @@ impl BridgeProvider {
 
         Ok(response.status().is_success())
     }
-
-    /// Get available models from the bridge
-    pub async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
-        let response = self.client
-            .get(format!("{}/v1/models", self.base_url()))
-            .send()
-            .await
-            .map_err(|e| ProviderError::Network(e.to_string()))?;
-
-        if !response.status().is_success() {
-            return Err(ProviderError::Api {
-                status: response.status().as_u16(),
-                message: response.text().await.unwrap_or_default(),
-            });
-        }
-
-        let models: ModelsResponse = response.json().await
-            .map_err(|e| ProviderError::Parse(e.to_string()))?;
-
-        Ok(models.data.into_iter().map(|m| m.id).collect())
-    }
 }
 
 impl LlmProvider for BridgeProvider {
@@ -101,7 +81,13 @@ impl LlmProvider for BridgeProvider {
         self.config.default_model.as_deref().unwrap_or("claude-opus-4")
     }
 
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let req = self.client.get(format!("{}/v1/models", self.base_url()));
+        Ok(fetch_models(req).await.unwrap_or_else(|_| self.models()))
+    }
+
     async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        let started = Instant::now();
         let model = request.model.as_deref().unwrap_or(self.default_model());
 
         let api_request = BridgeRequest {
@@ -130,6 +116,8 @@ impl LlmProvider for BridgeProvider {
                 }),
             }),
             stop: request.stop,
+            seed: request.seed,
+            response_format: request.response_schema.map(json_schema_format),
         };
 
         let response = self.client
@@ -178,6 +166,7 @@ impl LlmProvider for BridgeProvider {
             prompt_tokens: u.prompt_tokens,
             completion_tokens: u.completion_tokens,
             total_tokens: u.total_tokens,
+            latency_ms: None,
         }).unwrap_or_default();
 
         Ok(CompletionResponse {
@@ -186,11 +175,13 @@ impl LlmProvider for BridgeProvider {
             content: choice.message.content.clone(),
             tool_calls,
             finish_reason,
-            usage,
+            usage: usage.with_latency(started),
+            reasoning: None,
         })
     }
 
     async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+        let started = Instant::now();
         let model = request.model.as_deref().unwrap_or(self.default_model());
 
         let api_request = BridgeRequest {
@@ -211,6 +202,8 @@ impl LlmProvider for BridgeProvider {
             }),
             tool_choice: None,
             stop: request.stop,
+            seed: request.seed,
+            response_format: request.response_schema.map(json_schema_format),
         };
 
         let response = self.client
@@ -248,7 +241,7 @@ impl LlmProvider for BridgeProvider {
                                     if data == "[DONE]" {
                                         yield StreamChunk::Done {
                                             finish_reason: FinishReason::Stop,
-                                            usage: None,
+                                            usage: Some(Usage::default().with_latency(started)),
                                         };
                                         return;
                                     }
@@ -279,7 +272,7 @@ impl LlmProvider for BridgeProvider {
                                                 };
                                                 yield StreamChunk::Done {
                                                     finish_reason: fr,
-                                                    usage: None,
+                                                    usage: Some(Usage::default().with_latency(started)),
                                                 };
                                             }
                                         }
@@ -320,6 +313,10 @@ struct BridgeRequest {
     tool_choice: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -437,16 +434,6 @@ struct BridgeFunctionDelta {
     arguments: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct ModelsResponse {
-    data: Vec<ModelInfo>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ModelInfo {
-    id: String,
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,207 @@
+//! Coalescing provider - shares one in-flight call between identical requests
+//!
+//! While a deterministic request (temperature 0) is being answered, an
+//! identical request waits for that answer instead of being sent again.
+//! Other requests, and all streams, pass straight through.
+
+use super::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+type Waiters = Vec<oneshot::Sender<Result<CompletionResponse, ProviderError>>>;
+
+/// Wraps a provider so identical concurrent deterministic requests hit it once
+pub struct CoalescingProvider<P> {
+    inner: P,
+    in_flight: Mutex<HashMap<u64, Waiters>>,
+}
+
+impl<P: LlmProvider> CoalescingProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self { inner, in_flight: Mutex::new(HashMap::new()) }
+    }
+
+    /// The wrapped provider
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Key for requests that may share a call, or `None` if this one can't
+    fn key(request: &CompletionRequest) -> Option<u64> {
+        if request.temperature != Some(0.0) || request.stream {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        format!("{:?}", request).hash(&mut hasher);
+        Some(hasher.finish())
+    }
+}
+
+/// Removes the in-flight entry when the leading call ends, even if it is
+/// cancelled; waiters whose sender is dropped send their own request
+struct InFlight<'a> {
+    map: &'a Mutex<HashMap<u64, Waiters>>,
+    key: u64,
+    /// Set by `finish`; the entry may already belong to a newer leader
+    finished: bool,
+}
+
+impl InFlight<'_> {
+    fn finish(mut self, result: &Result<CompletionResponse, ProviderError>) {
+        self.finished = true;
+        let waiters = self.map.lock().unwrap().remove(&self.key).unwrap_or_default();
+        for waiter in waiters {
+            let _ = waiter.send(result.clone());
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.map.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+impl<P: LlmProvider> LlmProvider for CoalescingProvider<P> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn models(&self) -> Vec<String> {
+        self.inner.models()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        let Some(key) = Self::key(&request) else {
+            return self.inner.complete(request).await;
+        };
+
+        let waiting = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get_mut(&key) {
+                Some(waiters) => {
+                    let (tx, rx) = oneshot::channel();
+                    waiters.push(tx);
+                    Some(rx)
+                }
+                None => {
+                    in_flight.insert(key, Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(rx) = waiting {
+            return match rx.await {
+                Ok(result) => result,
+                Err(_) => self.inner.complete(request).await,
+            };
+        }
+
+        let guard = InFlight { map: &self.in_flight, key, finished: false };
+        let result = self.inner.complete(request).await;
+        guard.finish(&result);
+        result
+    }
+
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+        self.inner.stream(request).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        self.inner.list_models().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Answers after a short delay so concurrent callers overlap
+    #[derive(Default)]
+    struct SlowProvider {
+        calls: AtomicUsize,
+    }
+
+    impl LlmProvider for SlowProvider {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn models(&self) -> Vec<String> {
+            vec!["slow".into()]
+        }
+
+        fn default_model(&self) -> &str {
+            "slow"
+        }
+
+        async fn complete(&self, _request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(MockProvider::completion(format!("answer {}", n)))
+        }
+
+        async fn stream(&self, _request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+            Err(ProviderError::Other("not supported".into()))
+        }
+    }
+
+    fn request(prompt: &str, temperature: f32) -> CompletionRequest {
+        CompletionRequest::new(vec![ChatMessage::user(prompt)]).with_temperature(temperature)
+    }
+
+    #[tokio::test]
+    async fn test_identical_requests_share_one_call() {
+        let provider = CoalescingProvider::new(SlowProvider::default());
+
+        let (a, b) = tokio::join!(
+            provider.complete(request("same", 0.0)),
+            provider.complete(request("same", 0.0)),
+        );
+        assert_eq!(a.unwrap().content, b.unwrap().content);
+        assert_eq!(provider.inner().calls.load(Ordering::SeqCst), 1);
+
+        let (a, b) = tokio::join!(
+            provider.complete(request("same", 0.7)),
+            provider.complete(request("other", 0.0)),
+        );
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(provider.inner().calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_second_wave_keeps_its_leader() {
+        for round in 0..20 {
+            let provider = Arc::new(CoalescingProvider::new(SlowProvider::default()));
+
+            // Each caller asks again as soon as the first answer arrives, so the
+            // second wave starts while the first leader is still finishing
+            let tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let provider = provider.clone();
+                    tokio::spawn(async move {
+                        provider.complete(request("same", 0.0)).await.unwrap();
+                        provider.complete(request("same", 0.0)).await.unwrap()
+                    })
+                })
+                .collect();
+            for task in tasks {
+                assert_eq!(task.await.unwrap().content.as_deref(), Some("answer 1"), "round {}", round);
+            }
+            assert_eq!(provider.inner().calls.load(Ordering::SeqCst), 2, "round {}", round);
+            assert!(provider.in_flight.lock().unwrap().is_empty());
+        }
+    }
+}
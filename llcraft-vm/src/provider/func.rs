@@ -0,0 +1,91 @@
+//! Function provider - an `LlmProvider` backed by a closure
+//!
+//! Handy for prototypes and tests, or for plugging in a bespoke backend
+//! without writing a provider struct. Streaming replays the completion as a
+//! single text chunk.
+
+use super::*;
+
+type CompleteFn = dyn Fn(CompletionRequest) -> Result<CompletionResponse, ProviderError> + Send + Sync;
+
+/// Provider that answers every request by calling a closure
+pub struct FnProvider {
+    name: String,
+    model: String,
+    complete: Box<CompleteFn>,
+}
+
+impl FnProvider {
+    /// Create a provider named `name`; its default model has the same name
+    pub fn new<F>(name: impl Into<String>, complete: F) -> Self
+    where
+        F: Fn(CompletionRequest) -> Result<CompletionResponse, ProviderError> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        Self { model: name.clone(), name, complete: Box::new(complete) }
+    }
+
+    /// Report a different default model
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+}
+
+impl LlmProvider for FnProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn models(&self) -> Vec<String> {
+        vec![self.model.clone()]
+    }
+
+    fn default_model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        (self.complete)(request)
+    }
+
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+        let response = (self.complete)(request)?;
+
+        let stream = async_stream::stream! {
+            if let Some(content) = response.content {
+                yield StreamChunk::Text(content);
+            }
+            yield StreamChunk::Done {
+                finish_reason: response.finish_reason,
+                usage: Some(response.usage),
+            };
+        };
+
+        Ok(StreamReceiver::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fn_provider_complete_and_stream() {
+        let provider = FnProvider::new("echo", |request: CompletionRequest| {
+            let prompt = request.messages.last().and_then(|m| m.content.clone()).unwrap_or_default();
+            Ok(MockProvider::completion(prompt.to_uppercase()))
+        });
+
+        assert_eq!(provider.name(), "echo");
+        assert_eq!(provider.prompt("hi").await.unwrap(), "HI");
+        let streamed = provider
+            .stream(CompletionRequest::new(vec![ChatMessage::user("there")]))
+            .await
+            .unwrap()
+            .collect_text()
+            .await
+            .unwrap();
+        assert_eq!(streamed, "THERE");
+    }
+}
@@ -0,0 +1,151 @@
+//! Mock provider - scripted responses for tests
+//!
+//! Returns queued responses in order and records every request it
+//! receives, so agent and interpreter code can be exercised without
+//! a live LLM backend.
+
+use super::*;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Mock provider - replays scripted responses
+#[derive(Default)]
+pub struct MockProvider {
+    responses: Mutex<VecDeque<Result<CompletionResponse, ProviderError>>>,
+    requests: Mutex<Vec<CompletionRequest>>,
+}
+
+impl MockProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a mock that returns each string as a response, in order
+    pub fn with_responses<I, S>(responses: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let provider = Self::new();
+        for content in responses {
+            provider.push_response(content);
+        }
+        provider
+    }
+
+    /// Queue a plain text response
+    pub fn push_response(&self, content: impl Into<String>) {
+        self.push_completion(Self::completion(content));
+    }
+
+    /// Queue a full completion response
+    pub fn push_completion(&self, response: CompletionResponse) {
+        self.responses.lock().unwrap().push_back(Ok(response));
+    }
+
+    /// Queue an error
+    pub fn push_error(&self, error: ProviderError) {
+        self.responses.lock().unwrap().push_back(Err(error));
+    }
+
+    /// All requests received so far
+    pub fn requests(&self) -> Vec<CompletionRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Number of requests received so far
+    pub fn call_count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// Build a completion response with the given content
+    pub fn completion(content: impl Into<String>) -> CompletionResponse {
+        CompletionResponse {
+            id: "mock".into(),
+            model: "mock".into(),
+            content: Some(content.into()),
+            tool_calls: Vec::new(),
+            finish_reason: FinishReason::Stop,
+            usage: Usage::default(),
+            reasoning: None,
+        }
+    }
+
+    /// Pop the next scripted response. Its latency is the scripted one if
+    /// set, otherwise the (tiny) time spent here
+    fn next_response(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        let started = Instant::now();
+        self.requests.lock().unwrap().push(request);
+        let mut response = self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Err(ProviderError::Other("MockProvider: no scripted responses left".into())))?;
+        if response.usage.latency_ms.is_none() {
+            response.usage = response.usage.with_latency(started);
+        }
+        Ok(response)
+    }
+}
+
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn models(&self) -> Vec<String> {
+        vec!["mock".into()]
+    }
+
+    fn default_model(&self) -> &str {
+        "mock"
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        self.next_response(request)
+    }
+
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+        let response = self.next_response(request)?;
+
+        let stream = async_stream::stream! {
+            if let Some(content) = response.content {
+                yield StreamChunk::Text(content);
+            }
+            yield StreamChunk::Done {
+                finish_reason: response.finish_reason,
+                usage: Some(response.usage),
+            };
+        };
+
+        Ok(StreamReceiver::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_replays_in_order() {
+        let provider = MockProvider::with_responses(["first", "second"]);
+
+        assert_eq!(provider.prompt("a").await.unwrap(), "first");
+        assert_eq!(provider.prompt("b").await.unwrap(), "second");
+        assert!(provider.prompt("c").await.is_err());
+        assert_eq!(provider.call_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_mock_records_requests() {
+        let provider = MockProvider::with_responses(["ok"]);
+        provider
+            .chat(vec![ChatMessage::system("sys"), ChatMessage::user("hi")])
+            .await
+            .unwrap();
+
+        let requests = provider.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].messages[0].content.as_deref(), Some("sys"));
+    }
+}
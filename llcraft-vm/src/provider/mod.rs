@@ -6,21 +6,44 @@
 //! ## Design
 //! - `LlmProvider` trait defines the core interface
 //! - Implementations for OpenAI, Anthropic, Bridge (local Copilot), and local models
+//! - `MockProvider` with scripted responses for tests
+//! - `CoalescingProvider` to share in-flight identical requests
+//! - `FnProvider` to answer requests with a closure
 //! - Streaming via async iterators
 //! - Tool/function calling support
 //! - Usage tracking
+//!
+//! ## Trait objects
+//! `LlmProvider` uses `async fn`, so `dyn LlmProvider` isn't possible. To
+//! pick a provider at runtime, hold a `Box<dyn DynLlmProvider>` instead:
+//! every `LlmProvider` implements `DynLlmProvider`, and the box implements
+//! `LlmProvider` again, so it can be passed anywhere a provider is expected
+//! (e.g. `Agent<Box<dyn DynLlmProvider>>`).
+//!
+//! ## Cancellation
+//! Providers don't spawn background tasks: the HTTP call lives inside the
+//! returned future, and a stream's response body inside its `StreamReceiver`.
+//! Dropping either one (say, from `tokio::time::timeout`) aborts the request
+//! instead of leaking it. Wrappers must keep that property.
 
 pub mod openai;
 pub mod anthropic;
 pub mod bridge;
+pub mod mock;
+pub mod coalesce;
+pub mod func;
 
-pub use openai::OpenAIProvider;
+pub use openai::{OpenAIProvider, OpenAICompatibleProvider};
 pub use anthropic::AnthropicProvider;
 pub use bridge::BridgeProvider;
+pub use mock::MockProvider;
+pub use coalesce::CoalescingProvider;
+pub use func::FnProvider;
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 
 // ============================================================================
 // Core Types
@@ -35,6 +58,9 @@ pub struct ChatMessage {
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
+    /// Participant name, for multi-agent conversations (OpenAI only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
 }
 
 impl ChatMessage {
@@ -44,6 +70,7 @@ impl ChatMessage {
             content: Some(content.into()),
             tool_calls: None,
             tool_call_id: None,
+            name: None,
         }
     }
 
@@ -53,6 +80,15 @@ impl ChatMessage {
             content: Some(content.into()),
             tool_calls: None,
             tool_call_id: None,
+            name: None,
+        }
+    }
+
+    /// A user message from a named participant
+    pub fn user_named(name: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            ..Self::user(content)
         }
     }
 
@@ -62,6 +98,7 @@ impl ChatMessage {
             content: Some(content.into()),
             tool_calls: None,
             tool_call_id: None,
+            name: None,
         }
     }
 
@@ -71,6 +108,7 @@ impl ChatMessage {
             content: Some(content.into()),
             tool_calls: None,
             tool_call_id: Some(tool_call_id.into()),
+            name: None,
         }
     }
 
@@ -160,6 +198,13 @@ pub struct CompletionRequest {
     pub tool_choice: Option<ToolChoice>,
     pub stream: bool,
     pub stop: Option<Vec<String>>,
+    pub seed: Option<u64>,
+    /// JSON schema for a structured response
+    pub response_schema: Option<serde_json::Value>,
+    /// Overrides the client's timeout for this request only
+    pub timeout: Option<Duration>,
+    /// Token budget for extended thinking (Anthropic only)
+    pub thinking_budget: Option<usize>,
 }
 
 impl CompletionRequest {
@@ -194,6 +239,60 @@ impl CompletionRequest {
         self.stream = stream;
         self
     }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Stop generating at any of these strings
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Let the model reason for up to `budget` tokens before answering
+    pub fn with_thinking_budget(mut self, budget: usize) -> Self {
+        self.thinking_budget = Some(budget);
+        self
+    }
+
+    /// Give up on this request after `timeout` instead of the client default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Ask for a JSON response matching `schema`
+    pub fn with_response_schema(mut self, schema: serde_json::Value) -> Self {
+        self.response_schema = Some(schema);
+        self
+    }
+
+    /// Apply an INFER opcode's parameters
+    pub fn with_infer_params(mut self, params: &crate::opcode::InferParams) -> Self {
+        if let Some(model) = &params.model {
+            self.model = Some(model.clone());
+        }
+        if let Some(temperature) = params.temperature {
+            self.temperature = Some(temperature);
+        }
+        if let Some(max_tokens) = params.max_tokens {
+            self.max_tokens = Some(max_tokens);
+        }
+        if let Some(schema) = &params.schema {
+            self.response_schema = Some(schema.clone());
+        }
+        self
+    }
+}
+
+/// OpenAI-style `response_format` for a JSON schema
+pub(crate) fn json_schema_format(schema: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "type": "json_schema",
+        "json_schema": { "name": "response", "schema": schema }
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -214,6 +313,19 @@ pub struct CompletionResponse {
     pub tool_calls: Vec<ToolCall>,
     pub finish_reason: FinishReason,
     pub usage: Usage,
+    /// The model's reasoning, when the provider returns it separately
+    pub reasoning: Option<String>,
+}
+
+impl CompletionResponse {
+    /// Turn a content-filtered completion into `ProviderError::ContentFiltered`,
+    /// so it isn't mistaken for an empty answer
+    pub fn reject_filtered(self) -> Result<Self, ProviderError> {
+        if self.finish_reason == FinishReason::ContentFilter {
+            return Err(ProviderError::ContentFiltered { model: self.model });
+        }
+        Ok(self)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -226,11 +338,22 @@ pub enum FinishReason {
 }
 
 /// Token usage information
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
     pub total_tokens: usize,
+    /// Wall-clock time of the request, set by the provider that made it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+impl Usage {
+    /// Record the time elapsed since `started` as the request latency
+    pub fn with_latency(mut self, started: Instant) -> Self {
+        self.latency_ms = Some(started.elapsed().as_millis() as u64);
+        self
+    }
 }
 
 /// A streaming chunk from the model
@@ -259,7 +382,7 @@ pub enum StreamChunk {
 // ============================================================================
 
 /// Error type for provider operations
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ProviderError {
     /// Network/connection error
     Network(String),
@@ -275,6 +398,8 @@ pub enum ProviderError {
     ModelNotFound(String),
     /// Authentication failed
     AuthenticationFailed,
+    /// The provider's content filter blocked the response
+    ContentFiltered { model: String },
     /// Other error
     Other(String),
 }
@@ -295,6 +420,9 @@ impl std::fmt::Display for ProviderError {
             Self::InvalidRequest(e) => write!(f, "Invalid request: {}", e),
             Self::ModelNotFound(m) => write!(f, "Model not found: {}", m),
             Self::AuthenticationFailed => write!(f, "Authentication failed"),
+            Self::ContentFiltered { model } => {
+                write!(f, "Response from {} was blocked by the provider's content filter", model)
+            }
             Self::Other(e) => write!(f, "{}", e),
         }
     }
@@ -308,18 +436,29 @@ pub trait LlmProvider: Send + Sync {
     /// Get the provider name (e.g., "openai", "anthropic")
     fn name(&self) -> &str;
 
-    /// Get available models
+    /// Get available models (a built-in list; see `list_models`)
     fn models(&self) -> Vec<String>;
 
     /// Get the default model
     fn default_model(&self) -> &str;
 
     /// Send a completion request and get a full response
+    ///
+    /// Dropping the returned future cancels the request.
     async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError>;
 
     /// Send a completion request and stream the response
+    ///
+    /// Dropping the future, or the `StreamReceiver` it returns, cancels the
+    /// request and closes the connection.
     async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError>;
 
+    /// Ask the API which models are available, falling back to `models()`
+    /// when it can't be reached
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        Ok(self.models())
+    }
+
     /// Simple prompt -> response helper
     async fn prompt(&self, prompt: &str) -> Result<String, ProviderError> {
         let request = CompletionRequest::new(vec![ChatMessage::user(prompt)]);
@@ -335,6 +474,106 @@ pub trait LlmProvider: Send + Sync {
     }
 }
 
+/// Boxed future returned by [`DynLlmProvider`]
+pub type BoxFuture<'a, T> = Pin<Box<dyn std::future::Future<Output = T> + 'a>>;
+
+/// Object-safe version of [`LlmProvider`], implemented for every provider
+///
+/// Method names carry a `dyn_` prefix so both traits can be in scope at once.
+pub trait DynLlmProvider: Send + Sync {
+    fn dyn_name(&self) -> &str;
+
+    fn dyn_models(&self) -> Vec<String>;
+
+    fn dyn_default_model(&self) -> &str;
+
+    fn dyn_complete(&self, request: CompletionRequest) -> BoxFuture<'_, Result<CompletionResponse, ProviderError>>;
+
+    fn dyn_stream(&self, request: CompletionRequest) -> BoxFuture<'_, Result<StreamReceiver, ProviderError>>;
+
+    fn dyn_list_models(&self) -> BoxFuture<'_, Result<Vec<String>, ProviderError>>;
+}
+
+impl<P: LlmProvider> DynLlmProvider for P {
+    fn dyn_name(&self) -> &str {
+        self.name()
+    }
+
+    fn dyn_models(&self) -> Vec<String> {
+        self.models()
+    }
+
+    fn dyn_default_model(&self) -> &str {
+        self.default_model()
+    }
+
+    fn dyn_complete(&self, request: CompletionRequest) -> BoxFuture<'_, Result<CompletionResponse, ProviderError>> {
+        Box::pin(self.complete(request))
+    }
+
+    fn dyn_stream(&self, request: CompletionRequest) -> BoxFuture<'_, Result<StreamReceiver, ProviderError>> {
+        Box::pin(self.stream(request))
+    }
+
+    fn dyn_list_models(&self) -> BoxFuture<'_, Result<Vec<String>, ProviderError>> {
+        Box::pin(self.list_models())
+    }
+}
+
+impl LlmProvider for Box<dyn DynLlmProvider> {
+    fn name(&self) -> &str {
+        (**self).dyn_name()
+    }
+
+    fn models(&self) -> Vec<String> {
+        (**self).dyn_models()
+    }
+
+    fn default_model(&self) -> &str {
+        (**self).dyn_default_model()
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        (**self).dyn_complete(request).await
+    }
+
+    async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
+        (**self).dyn_stream(request).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        (**self).dyn_list_models().await
+    }
+}
+
+/// Model ids from a `GET /models` style endpoint (`{"data": [{"id": ...}]}`)
+pub(crate) async fn fetch_models(req: reqwest::RequestBuilder) -> Result<Vec<String>, ProviderError> {
+    #[derive(Deserialize)]
+    struct ModelsResponse {
+        data: Vec<ModelInfo>,
+    }
+
+    #[derive(Deserialize)]
+    struct ModelInfo {
+        id: String,
+    }
+
+    let response = req.send().await
+        .map_err(|e| ProviderError::Network(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(ProviderError::Api {
+            status: response.status().as_u16(),
+            message: response.text().await.unwrap_or_default(),
+        });
+    }
+
+    let models: ModelsResponse = response.json().await
+        .map_err(|e| ProviderError::Parse(e.to_string()))?;
+
+    Ok(models.data.into_iter().map(|m| m.id).collect())
+}
+
 /// Receiver for streaming responses
 pub struct StreamReceiver {
     inner: Pin<Box<dyn futures_core::Stream<Item = StreamChunk> + Send>>,
@@ -350,6 +589,12 @@ impl StreamReceiver {
         }
     }
 
+    /// Wait for the next chunk; `None` once the stream has ended
+    pub async fn next_chunk(&mut self) -> Option<StreamChunk> {
+        use futures_util::StreamExt;
+        self.inner.next().await
+    }
+
     /// Collect all text chunks into a single string
     pub async fn collect_text(mut self) -> Result<String, ProviderError> {
         use futures_core::Stream;
@@ -392,6 +637,8 @@ pub struct ProviderConfig {
     pub default_model: Option<String>,
     pub headers: HashMap<String, String>,
     pub timeout_secs: Option<u64>,
+    /// Header that carries the API key as-is; `None` sends `Authorization: Bearer`
+    pub auth_header: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -412,6 +659,7 @@ impl ProviderConfig {
             default_model: Some("gpt-4o".into()),
             headers: HashMap::new(),
             timeout_secs: Some(120),
+            auth_header: None,
         }
     }
 
@@ -426,6 +674,7 @@ impl ProviderConfig {
             default_model: Some("claude-sonnet-4-20250514".into()),
             headers,
             timeout_secs: Some(120),
+            auth_header: None,
         }
     }
 
@@ -439,6 +688,7 @@ impl ProviderConfig {
             default_model: Some("claude-opus-4".into()),
             headers: HashMap::new(),
             timeout_secs: Some(300),
+            auth_header: None,
         }
     }
 
@@ -451,6 +701,7 @@ impl ProviderConfig {
             default_model: Some("claude-opus-4".into()),
             headers: HashMap::new(),
             timeout_secs: Some(300),
+            auth_header: None,
         }
     }
 
@@ -462,20 +713,95 @@ impl ProviderConfig {
             default_model: Some(model.into()),
             headers: HashMap::new(),
             timeout_secs: Some(300),
+            auth_header: None,
         }
     }
 
+    /// Any other OpenAI-compatible endpoint (OpenRouter, Together, vLLM, ...)
+    pub fn custom(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            provider_type: ProviderType::Custom,
+            ..Self::local(base_url, model)
+        }
+    }
+
+    /// Guess the provider from the endpoint, falling back to the model name
+    ///
+    /// Known endpoints win (api.openai.com, api.anthropic.com, Ollama on
+    /// :11434, the bridge on :5168); otherwise `gpt-` and `claude-` models
+    /// pick OpenAI or Anthropic. Anything else is treated as an
+    /// OpenAI-compatible endpoint (`ProviderType::Custom`). No API key is
+    /// set: add one with `with_api_key`.
+    pub fn auto(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        let base_url = base_url.into();
+        let model = model.into();
+        let url = base_url.to_lowercase();
+
+        let mut config = if url.contains("api.openai.com") {
+            Self::openai("")
+        } else if url.contains("api.anthropic.com") {
+            Self::anthropic("")
+        } else if url.contains(":11434") {
+            Self::local(&base_url, &model)
+        } else if url.contains(":5168") {
+            Self::bridge()
+        } else if model.starts_with("gpt-") {
+            Self::openai("")
+        } else if model.starts_with("claude-") {
+            Self::anthropic("")
+        } else {
+            Self::custom(&base_url, &model)
+        };
+
+        config.api_key = None;
+        if !base_url.is_empty() {
+            config.base_url = Some(base_url);
+        }
+        if !model.is_empty() {
+            config.default_model = Some(model);
+        }
+        config
+    }
+
     pub fn with_model(mut self, model: impl Into<String>) -> Self {
         self.default_model = Some(model.into());
         self
     }
 
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Send the API key in `header` instead of `Authorization: Bearer`
+    pub fn with_auth_header(mut self, header: impl Into<String>) -> Self {
+        self.auth_header = Some(header.into());
+        self
+    }
+
+    pub fn with_header(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(key.into(), value.into());
+        self
+    }
+
     pub fn with_timeout(mut self, secs: u64) -> Self {
         self.timeout_secs = Some(secs);
         self
     }
 }
 
+/// Create the provider a config describes; OpenAI-compatible types
+/// (`OpenAI`, `Local`, `Custom`) share `OpenAIProvider`
+pub fn create_provider(config: ProviderConfig) -> Box<dyn DynLlmProvider> {
+    match config.provider_type {
+        ProviderType::Anthropic => Box::new(AnthropicProvider::new(config)),
+        ProviderType::Bridge => Box::new(BridgeProvider::new(config)),
+        ProviderType::OpenAI | ProviderType::Local | ProviderType::Custom => {
+            Box::new(OpenAIProvider::new(config))
+        }
+    }
+}
+
 // ============================================================================
 // Usage Tracking
 // ============================================================================
@@ -487,6 +813,31 @@ pub struct UsageTracker {
     pub total_prompt_tokens: usize,
     pub total_completion_tokens: usize,
     pub by_model: HashMap<String, Usage>,
+    /// Latency of every call that reported one
+    pub latency: LatencyStats,
+    /// The same, per model
+    pub latency_by_model: HashMap<String, LatencyStats>,
+}
+
+/// Accumulated request latency
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyStats {
+    /// Calls that reported a latency
+    pub calls: usize,
+    /// Sum of their latencies
+    pub total_ms: u64,
+}
+
+impl LatencyStats {
+    fn add(&mut self, ms: u64) {
+        self.calls += 1;
+        self.total_ms += ms;
+    }
+
+    /// Mean latency, if any call was timed
+    pub fn average_ms(&self) -> Option<f64> {
+        (self.calls > 0).then(|| self.total_ms as f64 / self.calls as f64)
+    }
 }
 
 impl UsageTracker {
@@ -503,6 +854,12 @@ impl UsageTracker {
         entry.prompt_tokens += usage.prompt_tokens;
         entry.completion_tokens += usage.completion_tokens;
         entry.total_tokens += usage.total_tokens;
+
+        if let Some(ms) = usage.latency_ms {
+            *entry.latency_ms.get_or_insert(0) += ms;
+            self.latency.add(ms);
+            self.latency_by_model.entry(model.to_string()).or_default().add(ms);
+        }
     }
 
     pub fn total_tokens(&self) -> usize {
@@ -571,6 +928,68 @@ mod tests {
         assert!(config.headers.contains_key("anthropic-version"));
     }
 
+    #[test]
+    fn test_auto_detects_provider() {
+        let detect = |url: &str, model: &str| {
+            let config = ProviderConfig::auto(url, model);
+            (config.provider_type, config.base_url.unwrap(), config.default_model.unwrap())
+        };
+
+        let (kind, url, _) = detect("https://api.openai.com/v1", "o3-mini");
+        assert_eq!((kind, url.as_str()), (ProviderType::OpenAI, "https://api.openai.com/v1"));
+        assert_eq!(detect("https://api.anthropic.com/v1", "any").0, ProviderType::Anthropic);
+        assert_eq!(detect("http://localhost:11434/v1", "llama3").0, ProviderType::Local);
+        assert_eq!(detect("http://localhost:5168", "claude-opus-4").0, ProviderType::Bridge);
+
+        // Unknown or missing endpoint: go by the model name
+        let (kind, url, model) = detect("", "gpt-4o-mini");
+        assert_eq!((kind, url.as_str(), model.as_str()), (ProviderType::OpenAI, "https://api.openai.com/v1", "gpt-4o-mini"));
+        let (kind, url, _) = detect("", "claude-sonnet-4-20250514");
+        assert_eq!((kind, url.as_str()), (ProviderType::Anthropic, "https://api.anthropic.com/v1"));
+        assert!(ProviderConfig::auto("", "claude-3-haiku").headers.contains_key("anthropic-version"));
+
+        // Anything else is an OpenAI-compatible endpoint
+        for url in ["https://openrouter.ai/api/v1", "https://api.deepseek.com/v1", "https://api.together.xyz/v1"] {
+            let (kind, base, _) = detect(url, "meta-llama/llama-3-70b");
+            assert_eq!((kind, base.as_str()), (ProviderType::Custom, url));
+        }
+
+        let config = ProviderConfig::auto("https://api.openai.com/v1", "gpt-4o");
+        assert_eq!(config.api_key, None);
+        assert_eq!(config.with_api_key("sk-test").api_key.as_deref(), Some("sk-test"));
+
+        let names: Vec<String> = [
+            ProviderConfig::auto("", "gpt-4o"),
+            ProviderConfig::auto("", "claude-opus-4"),
+            ProviderConfig::auto("http://localhost:5168", ""),
+            ProviderConfig::auto("https://openrouter.ai/api/v1", "x"),
+        ]
+        .into_iter()
+        .map(|config| create_provider(config).dyn_name().to_string())
+        .collect();
+        assert_eq!(names, ["openai", "anthropic", "bridge", "openai"]);
+    }
+
+    #[tokio::test]
+    async fn test_dyn_providers_in_a_vec() {
+        let providers: Vec<Box<dyn DynLlmProvider>> = vec![
+            Box::new(MockProvider::with_responses(["from mock"])),
+            Box::new(FnProvider::new("echo", |_| Ok(MockProvider::completion("from fn")))),
+        ];
+
+        let mut answers = Vec::new();
+        for provider in &providers {
+            let request = CompletionRequest::new(vec![ChatMessage::user("hi")]);
+            let response = provider.dyn_complete(request).await.unwrap();
+            answers.push((provider.dyn_name().to_string(), response.content.unwrap()));
+        }
+        assert_eq!(answers, [("mock".to_string(), "from mock".to_string()), ("echo".to_string(), "from fn".to_string())]);
+
+        // A boxed provider is itself an LlmProvider
+        let boxed = providers.into_iter().nth(1).unwrap();
+        assert_eq!(boxed.prompt("again").await.unwrap(), "from fn");
+    }
+
     #[test]
     fn test_usage_tracker() {
         let mut tracker = UsageTracker::new();
@@ -579,12 +998,14 @@ mod tests {
             prompt_tokens: 100,
             completion_tokens: 50,
             total_tokens: 150,
+            latency_ms: None,
         });
 
         tracker.track("gpt-4o", &Usage {
             prompt_tokens: 200,
             completion_tokens: 100,
             total_tokens: 300,
+            latency_ms: None,
         });
 
         assert_eq!(tracker.total_calls, 2);
@@ -592,4 +1013,29 @@ mod tests {
         assert_eq!(tracker.total_completion_tokens, 150);
         assert_eq!(tracker.total_tokens(), 450);
     }
+
+    #[tokio::test]
+    async fn test_usage_tracker_averages_latency() {
+        let provider = MockProvider::new();
+        for ms in [100, 300] {
+            let mut response = MockProvider::completion("ok");
+            response.usage.latency_ms = Some(ms);
+            provider.push_completion(response);
+        }
+        provider.push_response("untimed");
+
+        let mut tracker = UsageTracker::new();
+        for _ in 0..2 {
+            let response = provider.complete(CompletionRequest::new(vec![ChatMessage::user("hi")])).await.unwrap();
+            tracker.track(&response.model, &response.usage);
+        }
+        assert_eq!(tracker.latency, LatencyStats { calls: 2, total_ms: 400 });
+        assert_eq!(tracker.latency.average_ms(), Some(200.0));
+        assert_eq!(tracker.latency_by_model["mock"].average_ms(), Some(200.0));
+        assert_eq!(tracker.by_model["mock"].latency_ms, Some(400));
+
+        let response = provider.complete(CompletionRequest::new(vec![ChatMessage::user("hi")])).await.unwrap();
+        assert!(response.usage.latency_ms.is_some());
+        assert_eq!(UsageTracker::new().latency.average_ms(), None);
+    }
 }
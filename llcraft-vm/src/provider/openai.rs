@@ -1,6 +1,9 @@
 //! OpenAI-compatible provider implementation
 //!
 //! Works with OpenAI, Azure OpenAI, vLLM, Ollama, and other OpenAI-compatible APIs.
+//! For a third-party endpoint (OpenRouter, Together, DeepSeek, ...) build the
+//! config with `ProviderConfig::custom`, plus `with_auth_header` if the service
+//! doesn't take `Authorization: Bearer`.
 
 use super::*;
 use reqwest::Client;
@@ -12,6 +15,9 @@ pub struct OpenAIProvider {
     config: ProviderConfig,
 }
 
+/// The same provider, named for use against non-OpenAI endpoints
+pub type OpenAICompatibleProvider = OpenAIProvider;
+
 impl OpenAIProvider {
     pub fn new(config: ProviderConfig) -> Self {
         let client = Client::builder()
@@ -25,38 +31,55 @@ impl OpenAIProvider {
     fn base_url(&self) -> &str {
         self.config.base_url.as_deref().unwrap_or("https://api.openai.com/v1")
     }
-}
 
-impl LlmProvider for OpenAIProvider {
-    fn name(&self) -> &str {
-        "openai"
-    }
+    /// POST `body` to the chat completions endpoint with auth and extra headers
+    fn post(&self, request: &CompletionRequest, body: &OpenAIRequest) -> reqwest::RequestBuilder {
+        let mut req = self.client
+            .post(format!("{}/chat/completions", self.base_url()))
+            .json(body);
 
-    fn models(&self) -> Vec<String> {
-        vec![
-            "gpt-4o".into(),
-            "gpt-4o-mini".into(),
-            "gpt-4-turbo".into(),
-            "gpt-4".into(),
-            "gpt-3.5-turbo".into(),
-            "o1".into(),
-            "o1-mini".into(),
-        ]
+        if let Some(timeout) = request.timeout {
+            req = req.timeout(timeout);
+        }
+
+        self.authorize(req)
     }
 
-    fn default_model(&self) -> &str {
-        self.config.default_model.as_deref().unwrap_or("gpt-4o")
+    /// Add the API key and configured headers
+    fn authorize(&self, mut req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(api_key) = self.config.api_key.as_deref().filter(|k| !k.is_empty()) {
+            req = match &self.config.auth_header {
+                Some(header) => req.header(header, api_key),
+                None => req.header("Authorization", format!("Bearer {}", api_key)),
+            };
+        }
+
+        for (key, value) in &self.config.headers {
+            req = req.header(key, value);
+        }
+
+        req
     }
 
-    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
-        let model = request.model.as_deref().unwrap_or(self.default_model());
+    /// Build the request body, rejecting what the API would refuse
+    fn api_request(&self, request: &CompletionRequest, stream: bool) -> Result<OpenAIRequest, ProviderError> {
+        if let Some(stop) = &request.stop {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                return Err(ProviderError::InvalidRequest(format!(
+                    "at most {} stop sequences are allowed, got {}",
+                    MAX_STOP_SEQUENCES,
+                    stop.len()
+                )));
+            }
+        }
 
-        let api_request = OpenAIRequest {
-            model: model.to_string(),
+        Ok(OpenAIRequest {
+            model: request.model.as_deref().unwrap_or(self.default_model()).to_string(),
             messages: request.messages.iter().map(|m| OpenAIMessage::from(m.clone())).collect(),
             temperature: request.temperature,
             max_tokens: request.max_tokens,
-            stream: Some(false),
+            stream: Some(stream),
+            stream_options: stream.then(|| serde_json::json!({ "include_usage": true })),
             tools: request.tools.as_ref().map(|tools| {
                 tools.iter().map(|t| OpenAITool {
                     r#type: "function".into(),
@@ -76,24 +99,50 @@ impl LlmProvider for OpenAIProvider {
                     "function": { "name": name }
                 }),
             }),
-            stop: request.stop,
-        };
+            stop: request.stop.clone(),
+            seed: request.seed,
+            response_format: request.response_schema.clone().map(json_schema_format),
+        })
+    }
+}
 
-        let mut req = self.client
-            .post(format!("{}/chat/completions", self.base_url()))
-            .json(&api_request);
+/// Most stop sequences the chat completions API accepts
+pub const MAX_STOP_SEQUENCES: usize = 4;
 
-        if let Some(api_key) = &self.config.api_key {
-            if !api_key.is_empty() {
-                req = req.header("Authorization", format!("Bearer {}", api_key));
-            }
-        }
+impl LlmProvider for OpenAIProvider {
+    fn name(&self) -> &str {
+        "openai"
+    }
 
-        for (key, value) in &self.config.headers {
-            req = req.header(key, value);
+    fn models(&self) -> Vec<String> {
+        if self.config.provider_type != ProviderType::OpenAI {
+            return vec![self.default_model().to_string()];
         }
+        vec![
+            "gpt-4o".into(),
+            "gpt-4o-mini".into(),
+            "gpt-4-turbo".into(),
+            "gpt-4".into(),
+            "gpt-3.5-turbo".into(),
+            "o1".into(),
+            "o1-mini".into(),
+        ]
+    }
+
+    fn default_model(&self) -> &str {
+        self.config.default_model.as_deref().unwrap_or("gpt-4o")
+    }
 
-        let response = req.send().await
+    async fn list_models(&self) -> Result<Vec<String>, ProviderError> {
+        let req = self.authorize(self.client.get(format!("{}/models", self.base_url())));
+        Ok(fetch_models(req).await.unwrap_or_else(|_| self.models()))
+    }
+
+    async fn complete(&self, request: CompletionRequest) -> Result<CompletionResponse, ProviderError> {
+        let started = Instant::now();
+        let api_request = self.api_request(&request, false)?;
+
+        let response = self.post(&request, &api_request).send().await
             .map_err(|e| ProviderError::Network(e.to_string()))?;
 
         let status = response.status().as_u16();
@@ -131,11 +180,7 @@ impl LlmProvider for OpenAIProvider {
             _ => FinishReason::Unknown,
         };
 
-        let usage = api_response.usage.map(|u| Usage {
-            prompt_tokens: u.prompt_tokens,
-            completion_tokens: u.completion_tokens,
-            total_tokens: u.total_tokens,
-        }).unwrap_or_default();
+        let usage = api_response.usage.map(Usage::from).unwrap_or_default();
 
         Ok(CompletionResponse {
             id: api_response.id,
@@ -143,48 +188,16 @@ impl LlmProvider for OpenAIProvider {
             content: choice.message.content.clone(),
             tool_calls,
             finish_reason,
-            usage,
+            usage: usage.with_latency(started),
+            reasoning: None,
         })
     }
 
     async fn stream(&self, request: CompletionRequest) -> Result<StreamReceiver, ProviderError> {
-        let model = request.model.as_deref().unwrap_or(self.default_model());
-
-        let api_request = OpenAIRequest {
-            model: model.to_string(),
-            messages: request.messages.iter().map(|m| OpenAIMessage::from(m.clone())).collect(),
-            temperature: request.temperature,
-            max_tokens: request.max_tokens,
-            stream: Some(true),
-            tools: request.tools.as_ref().map(|tools| {
-                tools.iter().map(|t| OpenAITool {
-                    r#type: "function".into(),
-                    function: OpenAIFunction {
-                        name: t.name.clone(),
-                        description: Some(t.description.clone()),
-                        parameters: Some(t.parameters.clone()),
-                    },
-                }).collect()
-            }),
-            tool_choice: None,
-            stop: request.stop,
-        };
-
-        let mut req = self.client
-            .post(format!("{}/chat/completions", self.base_url()))
-            .json(&api_request);
-
-        if let Some(api_key) = &self.config.api_key {
-            if !api_key.is_empty() {
-                req = req.header("Authorization", format!("Bearer {}", api_key));
-            }
-        }
+        let started = Instant::now();
+        let api_request = self.api_request(&request, true)?;
 
-        for (key, value) in &self.config.headers {
-            req = req.header(key, value);
-        }
-
-        let response = req.send().await
+        let response = self.post(&request, &api_request).send().await
             .map_err(|e| ProviderError::Network(e.to_string()))?;
 
         if !response.status().is_success() {
@@ -199,6 +212,8 @@ impl LlmProvider for OpenAIProvider {
 
             let mut byte_stream = response.bytes_stream();
             let mut buffer = String::new();
+            let mut state = StreamState::default();
+            let mut chunks = Vec::new();
 
             while let Some(chunk_result) = byte_stream.next().await {
                 match chunk_result {
@@ -210,48 +225,13 @@ impl LlmProvider for OpenAIProvider {
                             let event = buffer[..pos].to_string();
                             buffer = buffer[pos + 2..].to_string();
 
-                            for line in event.lines() {
-                                if let Some(data) = line.strip_prefix("data: ") {
-                                    if data == "[DONE]" {
-                                        yield StreamChunk::Done {
-                                            finish_reason: FinishReason::Stop,
-                                            usage: None,
-                                        };
-                                        return;
-                                    }
-
-                                    if let Ok(chunk) = serde_json::from_str::<OpenAIStreamChunk>(data) {
-                                        if let Some(choice) = chunk.choices.first() {
-                                            if let Some(content) = &choice.delta.content {
-                                                yield StreamChunk::Text(content.clone());
-                                            }
-
-                                            if let Some(tool_calls) = &choice.delta.tool_calls {
-                                                for tc in tool_calls {
-                                                    yield StreamChunk::ToolCallDelta {
-                                                        index: tc.index,
-                                                        id: tc.id.clone(),
-                                                        name: tc.function.as_ref().and_then(|f| f.name.clone()),
-                                                        arguments_delta: tc.function.as_ref().and_then(|f| f.arguments.clone()),
-                                                    };
-                                                }
-                                            }
-
-                                            if let Some(reason) = &choice.finish_reason {
-                                                let fr = match reason.as_str() {
-                                                    "stop" => FinishReason::Stop,
-                                                    "length" => FinishReason::Length,
-                                                    "tool_calls" => FinishReason::ToolCalls,
-                                                    _ => FinishReason::Unknown,
-                                                };
-                                                yield StreamChunk::Done {
-                                                    finish_reason: fr,
-                                                    usage: None,
-                                                };
-                                            }
-                                        }
-                                    }
-                                }
+                            let finished = state.event(&event, &mut chunks);
+                            for chunk in chunks.drain(..) {
+                                yield chunk;
+                            }
+                            if finished {
+                                yield state.done(started);
+                                return;
                             }
                         }
                     }
@@ -261,12 +241,75 @@ impl LlmProvider for OpenAIProvider {
                     }
                 }
             }
+
+            // Server closed without `[DONE]`
+            if state.finish_reason.is_some() {
+                yield state.done(started);
+            }
         };
 
         Ok(StreamReceiver::new(stream))
     }
 }
 
+/// Turns SSE events into chunks. `Done` is held back until `[DONE]`,
+/// because the usage chunk arrives after the one with the finish reason.
+#[derive(Default)]
+struct StreamState {
+    finish_reason: Option<FinishReason>,
+    usage: Option<Usage>,
+}
+
+impl StreamState {
+    /// Handle one SSE event, pushing any chunks it produces; returns true on `[DONE]`
+    fn event(&mut self, event: &str, chunks: &mut Vec<StreamChunk>) -> bool {
+        for line in event.lines() {
+            let Some(data) = line.strip_prefix("data: ") else { continue };
+            if data == "[DONE]" {
+                return true;
+            }
+
+            let Ok(chunk) = serde_json::from_str::<OpenAIStreamChunk>(data) else { continue };
+            if let Some(usage) = chunk.usage {
+                self.usage = Some(usage.into());
+            }
+            let Some(choice) = chunk.choices.into_iter().next() else { continue };
+
+            if let Some(content) = choice.delta.content {
+                chunks.push(StreamChunk::Text(content));
+            }
+
+            for tc in choice.delta.tool_calls.unwrap_or_default() {
+                chunks.push(StreamChunk::ToolCallDelta {
+                    index: tc.index,
+                    id: tc.id,
+                    name: tc.function.as_ref().and_then(|f| f.name.clone()),
+                    arguments_delta: tc.function.and_then(|f| f.arguments),
+                });
+            }
+
+            if let Some(reason) = choice.finish_reason {
+                self.finish_reason = Some(match reason.as_str() {
+                    "stop" => FinishReason::Stop,
+                    "length" => FinishReason::Length,
+                    "tool_calls" => FinishReason::ToolCalls,
+                    "content_filter" => FinishReason::ContentFilter,
+                    _ => FinishReason::Unknown,
+                });
+            }
+        }
+        false
+    }
+
+    /// The final `Done` chunk
+    fn done(&mut self, started: Instant) -> StreamChunk {
+        StreamChunk::Done {
+            finish_reason: self.finish_reason.take().unwrap_or(FinishReason::Stop),
+            usage: Some(self.usage.take().unwrap_or_default().with_latency(started)),
+        }
+    }
+}
+
 // ============================================================================
 // OpenAI API Types
 // ============================================================================
@@ -282,11 +325,17 @@ struct OpenAIRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<OpenAITool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_choice: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -298,6 +347,8 @@ struct OpenAIMessage {
     tool_calls: Option<Vec<OpenAIToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_call_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 impl From<ChatMessage> for OpenAIMessage {
@@ -321,6 +372,7 @@ impl From<ChatMessage> for OpenAIMessage {
                 }).collect()
             }),
             tool_call_id: msg.tool_call_id,
+            name: msg.name,
         }
     }
 }
@@ -374,9 +426,24 @@ struct OpenAIUsage {
     total_tokens: usize,
 }
 
+impl From<OpenAIUsage> for Usage {
+    fn from(u: OpenAIUsage) -> Self {
+        Usage {
+            prompt_tokens: u.prompt_tokens,
+            completion_tokens: u.completion_tokens,
+            total_tokens: u.total_tokens,
+            latency_ms: None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct OpenAIStreamChunk {
+    #[serde(default)]
     choices: Vec<OpenAIStreamChoice>,
+    /// Only on the last chunk, when `stream_options.include_usage` is set
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -403,3 +470,290 @@ struct OpenAIFunctionDelta {
     name: Option<String>,
     arguments: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[test]
+    fn test_stop_sequences_forwarded_and_validated() {
+        let provider = OpenAIProvider::new(ProviderConfig::openai("sk-test"));
+
+        let request = CompletionRequest::new(vec![ChatMessage::user("hi")]).with_stop(vec!["END".into()]);
+        let body = serde_json::to_value(provider.api_request(&request, false).unwrap()).unwrap();
+        assert_eq!(body["stop"], serde_json::json!(["END"]));
+
+        let body = serde_json::to_value(provider.api_request(&CompletionRequest::default(), true).unwrap()).unwrap();
+        assert!(body.get("stop").is_none());
+
+        let stops = (0..5).map(|i| i.to_string()).collect();
+        let request = CompletionRequest::new(vec![ChatMessage::user("hi")]).with_stop(stops);
+        match provider.api_request(&request, false) {
+            Err(ProviderError::InvalidRequest(msg)) => assert!(msg.contains("at most 4")),
+            other => panic!("expected InvalidRequest, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_message_name_serialized_when_set() {
+        let provider = OpenAIProvider::new(ProviderConfig::openai("sk-test"));
+        let request = CompletionRequest::new(vec![
+            ChatMessage::user_named("reviewer", "looks good"),
+            ChatMessage::user("thanks"),
+        ]);
+
+        let body = serde_json::to_value(provider.api_request(&request, false).unwrap()).unwrap();
+        assert_eq!(body["messages"][0]["name"], "reviewer");
+        assert!(body["messages"][1].get("name").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_overrides_client_default() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let provider = OpenAIProvider::new(ProviderConfig::local(format!("http://{}", addr), "m").with_timeout(60));
+        let request = CompletionRequest::new(vec![ChatMessage::user("hi")])
+            .with_timeout(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let result = provider.complete(request).await;
+        assert!(matches!(result, Err(ProviderError::Network(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Accepts one connection, optionally writes `reply`, then reads until the
+    /// client hangs up; the receiver fires when it does
+    async fn hang_up_server(reply: &'static str) -> (String, oneshot::Receiver<()>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (closed_tx, closed_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let mut replied = reply.is_empty();
+            loop {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) if !replied => {
+                        replied = true;
+                        socket.write_all(reply.as_bytes()).await.unwrap();
+                    }
+                    Ok(_) => {}
+                }
+            }
+            let _ = closed_tx.send(());
+        });
+        (format!("http://{}", addr), closed_rx)
+    }
+
+    #[tokio::test]
+    async fn test_dropping_complete_future_closes_connection() {
+        let (url, closed) = hang_up_server("").await;
+        let provider = OpenAIProvider::new(ProviderConfig::local(url, "m"));
+        let request = CompletionRequest::new(vec![ChatMessage::user("hi")]);
+
+        let result = tokio::time::timeout(Duration::from_millis(100), provider.complete(request)).await;
+        assert!(result.is_err(), "the server never answers");
+
+        tokio::time::timeout(Duration::from_secs(2), closed)
+            .await
+            .expect("request was not cancelled")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropping_stream_receiver_closes_connection() {
+        const SSE: &str = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n\
+            2f\r\ndata: {\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\n\r\n";
+        let (url, closed) = hang_up_server(SSE).await;
+        let provider = OpenAIProvider::new(ProviderConfig::local(url, "m"));
+        let request = CompletionRequest::new(vec![ChatMessage::user("hi")]);
+
+        let receiver = provider.stream(request).await.unwrap();
+        drop(receiver);
+
+        tokio::time::timeout(Duration::from_secs(2), closed)
+            .await
+            .expect("stream was not cancelled")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_stream_usage_carried_to_done() {
+        let provider = OpenAIProvider::new(ProviderConfig::openai("sk-test"));
+        let request = CompletionRequest::new(vec![ChatMessage::user("hi")]);
+        let body = serde_json::to_value(provider.api_request(&request, true).unwrap()).unwrap();
+        assert_eq!(body["stream_options"]["include_usage"], true);
+        let body = serde_json::to_value(provider.api_request(&request, false).unwrap()).unwrap();
+        assert!(body.get("stream_options").is_none());
+
+        let captured = concat!(
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"finish_reason\":null}],\"usage\":null}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":null}],\"usage\":null}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}],\"usage\":null}\n\n",
+            "data: {\"id\":\"c1\",\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":2,\"total_tokens\":14}}\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        let mut state = StreamState::default();
+        let mut chunks = Vec::new();
+        let finished = captured.split("\n\n").any(|event| state.event(event, &mut chunks));
+        assert!(finished);
+        assert!(matches!(&chunks[..], [StreamChunk::Text(a), StreamChunk::Text(b)] if a == "Hel" && b == "lo"));
+
+        match state.done(Instant::now()) {
+            StreamChunk::Done { finish_reason, usage: Some(usage) } => {
+                assert_eq!(finish_reason, FinishReason::Stop);
+                assert_eq!(usage.prompt_tokens, 12);
+                assert_eq!(usage.completion_tokens, 2);
+                assert_eq!(usage.total_tokens, 14);
+            }
+            other => panic!("expected Done with usage, got {:?}", other),
+        }
+    }
+
+    /// Answers one request with `reply` and hands back the raw request it got
+    async fn capture_server(reply: String) -> (String, oneshot::Receiver<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (captured_tx, captured_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut raw = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                raw.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&raw).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text[..end]
+                        .lines()
+                        .find_map(|l| l.to_lowercase().strip_prefix("content-length: ").map(|v| v.trim().parse::<usize>().unwrap()))
+                        .unwrap_or(0);
+                    if raw.len() >= end + 4 + length || n == 0 {
+                        break;
+                    }
+                }
+            }
+            socket.write_all(reply.as_bytes()).await.unwrap();
+            let _ = captured_tx.send(String::from_utf8_lossy(&raw).to_string());
+        });
+        (format!("http://{}/api/v1", addr), captured_rx)
+    }
+
+    fn http_reply(content_type: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    fn weather_request() -> CompletionRequest {
+        CompletionRequest::new(vec![ChatMessage::user("Weather in Paris?")]).with_tools(vec![
+            ToolDefinition::new("get_weather", "Current weather").with_parameters(serde_json::json!({
+                "type": "object",
+                "properties": { "city": { "type": "string" } }
+            })),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_compatible_endpoint_complete_with_tool_call() {
+        let body = r#"{"id":"gen-1","model":"meta-llama/llama-3-70b","choices":[{"index":0,"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Paris\"}"}}]},"finish_reason":"tool_calls"}],"usage":{"prompt_tokens":20,"completion_tokens":8,"total_tokens":28}}"#;
+        let (url, captured) = capture_server(http_reply("application/json", body)).await;
+
+        let config = ProviderConfig::custom(url, "meta-llama/llama-3-70b")
+            .with_api_key("secret")
+            .with_auth_header("x-api-key")
+            .with_header("HTTP-Referer", "https://example.com");
+        let provider = OpenAICompatibleProvider::new(config);
+        assert_eq!(provider.models(), ["meta-llama/llama-3-70b"]);
+
+        let response = provider.complete(weather_request()).await.unwrap();
+        assert_eq!(response.finish_reason, FinishReason::ToolCalls);
+        assert_eq!(response.tool_calls[0].name, "get_weather");
+        assert_eq!(response.tool_calls[0].arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(response.usage.total_tokens, 28);
+
+        let raw = captured.await.unwrap();
+        let (head, json) = raw.split_once("\r\n\r\n").unwrap();
+        let head = head.to_lowercase();
+        assert!(head.starts_with("post /api/v1/chat/completions "));
+        assert!(head.contains("x-api-key: secret"));
+        assert!(head.contains("http-referer: https://example.com"));
+        assert!(!head.contains("authorization"));
+
+        let json: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(json["model"], "meta-llama/llama-3-70b");
+        assert_eq!(json["tools"][0]["function"]["name"], "get_weather");
+    }
+
+    #[tokio::test]
+    async fn test_compatible_endpoint_streams_tool_call() {
+        let sse = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"type\":\"function\",\"function\":{\"name\":\"get_weather\",\"arguments\":\"\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\":\\\"Paris\\\"}\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let (url, captured) = capture_server(http_reply("text/event-stream", sse)).await;
+
+        let provider = OpenAICompatibleProvider::new(ProviderConfig::custom(url, "deepseek-chat").with_api_key("secret"));
+        let mut receiver = provider.stream(weather_request()).await.unwrap();
+
+        let mut name = None;
+        let mut arguments = String::new();
+        let mut finish = None;
+        while let Some(chunk) = receiver.next_chunk().await {
+            match chunk {
+                StreamChunk::ToolCallDelta { name: n, arguments_delta, .. } => {
+                    name = name.or(n);
+                    arguments.push_str(&arguments_delta.unwrap_or_default());
+                }
+                StreamChunk::Done { finish_reason, .. } => finish = Some(finish_reason),
+                other => panic!("unexpected chunk {:?}", other),
+            }
+        }
+        assert_eq!(name.as_deref(), Some("get_weather"));
+        assert_eq!(arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(finish, Some(FinishReason::ToolCalls));
+
+        let raw = captured.await.unwrap().to_lowercase();
+        assert!(raw.contains("authorization: bearer secret"));
+        assert!(raw.contains("\"stream\":true"));
+    }
+
+    #[tokio::test]
+    async fn test_list_models_queries_endpoint() {
+        let body = r#"{"object":"list","data":[{"id":"gpt-4.1","object":"model","owned_by":"openai"},{"id":"o3","object":"model","owned_by":"openai"}]}"#;
+        let (url, captured) = capture_server(http_reply("application/json", body)).await;
+        let provider = OpenAIProvider::new(ProviderConfig { base_url: Some(url), ..ProviderConfig::openai("sk-test") });
+
+        assert_eq!(provider.list_models().await.unwrap(), ["gpt-4.1", "o3"]);
+        let raw = captured.await.unwrap().to_lowercase();
+        assert!(raw.starts_with("get /api/v1/models "));
+        assert!(raw.contains("authorization: bearer sk-test"));
+
+        // Nothing listening: fall back to the built-in list
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let provider = OpenAIProvider::new(ProviderConfig { base_url: Some(closed), ..ProviderConfig::openai("sk-test") });
+        assert_eq!(provider.list_models().await.unwrap(), provider.models());
+    }
+}
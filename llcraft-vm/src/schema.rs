@@ -64,6 +64,111 @@ pub fn format_trace_section(trace: &[ExecutionStep]) -> String {
     out
 }
 
+// ============================================================================
+// OPCODE REFERENCE - Machine-readable summary of the instruction set
+// ============================================================================
+
+/// Short description of one opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct OpcodeSpec {
+    /// Name as used in the `op` field
+    pub name: &'static str,
+    /// Group the opcode belongs to (memory, control, tools, ...)
+    pub category: &'static str,
+    /// One-line description
+    pub description: &'static str,
+    /// Field names accepted by the opcode
+    pub fields: &'static [&'static str],
+}
+
+/// Every opcode the VM understands, grouped by category
+pub const OPCODES: &[OpcodeSpec] = &[
+    OpcodeSpec { name: "LOAD", category: "memory", description: "Load a page from storage into the heap", fields: &["page_id", "range"] },
+    OpcodeSpec { name: "STORE", category: "memory", description: "Store data to a page", fields: &["page_id", "data"] },
+    OpcodeSpec { name: "APPEND", category: "memory", description: "Append a value to an array page", fields: &["page_id", "value"] },
+    OpcodeSpec { name: "ALLOC", category: "memory", description: "Allocate a new page", fields: &["size_hint", "label"] },
+    OpcodeSpec { name: "FREE", category: "memory", description: "Free a page from the heap", fields: &["page_id"] },
+    OpcodeSpec { name: "COPY", category: "memory", description: "Copy data between pages", fields: &["src", "dst", "range"] },
+    OpcodeSpec { name: "PIN", category: "memory", description: "Pin a page so eviction never drops it", fields: &["page_id"] },
+    OpcodeSpec { name: "CALL", category: "control", description: "Call a subprogram", fields: &["program_id", "args"] },
+    OpcodeSpec { name: "INCLUDE", category: "control", description: "Inline another program's code at load time", fields: &["program"] },
+    OpcodeSpec { name: "RETURN", category: "control", description: "Return from a subprogram", fields: &["value"] },
+    OpcodeSpec { name: "YIELD", category: "control", description: "Yield execution", fields: &[] },
+    OpcodeSpec { name: "COMPLETE", category: "control", description: "Complete the task successfully with a result", fields: &["result"] },
+    OpcodeSpec { name: "FAIL", category: "control", description: "Fail the task with an error", fields: &["error"] },
+    OpcodeSpec { name: "BRANCH", category: "control", description: "Jump to one of two labels depending on a condition", fields: &["condition", "if_true", "if_false"] },
+    OpcodeSpec { name: "SWITCH", category: "control", description: "Jump to the label of the first case matching a value", fields: &["value", "cases", "default"] },
+    OpcodeSpec { name: "JUMP", category: "control", description: "Unconditional jump to a label", fields: &["target"] },
+    OpcodeSpec { name: "LABEL", category: "control", description: "Define a jump target", fields: &["name"] },
+    OpcodeSpec { name: "LOOP", category: "control", description: "Run a body of opcodes for each item", fields: &["var", "over", "body"] },
+    OpcodeSpec { name: "BREAK", category: "control", description: "Stop the innermost LOOP, optionally only when a condition holds", fields: &["condition"] },
+    OpcodeSpec { name: "CONTINUE", category: "control", description: "Skip to the next LOOP item, optionally only when a condition holds", fields: &["condition"] },
+    OpcodeSpec { name: "TRY", category: "control", description: "Run opcodes, falling back to a catch block on error", fields: &["body", "catch"] },
+    OpcodeSpec { name: "READ_FILE", category: "tools", description: "Read a file's contents", fields: &["path", "store_to"] },
+    OpcodeSpec { name: "WRITE_FILE", category: "tools", description: "Write content to a file", fields: &["path", "content", "backup", "store_to"] },
+    OpcodeSpec { name: "LIST_DIR", category: "tools", description: "List files in a directory", fields: &["path", "store_to"] },
+    OpcodeSpec { name: "EXEC", category: "tools", description: "Execute a shell command", fields: &["command", "stdin", "cwd", "env", "store_to"] },
+    OpcodeSpec { name: "HTTP_REQUEST", category: "tools", description: "Make an HTTP request to an allowlisted host", fields: &["method", "url", "headers", "body", "store_to"] },
+    OpcodeSpec { name: "GET_ENV", category: "tools", description: "Read an environment variable into a page", fields: &["name", "store_to", "default"] },
+    OpcodeSpec { name: "GREP", category: "tools", description: "Search for a pattern in files", fields: &["pattern", "path", "store_to"] },
+    OpcodeSpec { name: "WAIT", category: "tools", description: "Wait for an async syscall to complete", fields: &["handle", "timeout_ms"] },
+    OpcodeSpec { name: "SLEEP", category: "tools", description: "Pause execution for some milliseconds", fields: &["ms"] },
+    OpcodeSpec { name: "TIMESTAMP", category: "tools", description: "Store the current unix time in seconds", fields: &["store_to"] },
+    OpcodeSpec { name: "UUID", category: "tools", description: "Store a fresh random UUID string", fields: &["store_to"] },
+    OpcodeSpec { name: "SPAWN", category: "parallel", description: "Spawn a concurrent task", fields: &["task_id", "task"] },
+    OpcodeSpec { name: "JOIN", category: "parallel", description: "Wait for spawned tasks or forked children and collect their results", fields: &["task_ids", "store_to"] },
+    OpcodeSpec { name: "PARALLEL", category: "parallel", description: "Run branches of opcodes concurrently", fields: &["branches", "store_to"] },
+    OpcodeSpec { name: "FORK", category: "parallel", description: "Start a registered program in a concurrent child interpreter and store its pid", fields: &["program_id", "args", "store_to"] },
+    OpcodeSpec { name: "SEND", category: "parallel", description: "Send a message to another process", fields: &["pid", "message"] },
+    OpcodeSpec { name: "RECV", category: "parallel", description: "Receive a message from the message queue", fields: &["timeout_ms", "store_to"] },
+    OpcodeSpec { name: "INFER", category: "llm", description: "Invoke the LLM with a prompt and context pages", fields: &["prompt", "context", "store_to", "params", "history_page"] },
+    OpcodeSpec { name: "PLAN", category: "llm", description: "Ask the LLM to plan the next steps", fields: &["goal", "context", "store_to"] },
+    OpcodeSpec { name: "REFLECT", category: "llm", description: "Ask the LLM to reflect on execution so far", fields: &["question", "include_trace", "store_to"] },
+    OpcodeSpec { name: "INJECT", category: "llm", description: "Have the LLM generate opcodes to insert at runtime", fields: &["goal", "context", "include_trace", "include_memory"] },
+    OpcodeSpec { name: "SUMMARIZE", category: "llm", description: "Summarize one or more pages", fields: &["pages", "target_tokens", "store_to"] },
+    OpcodeSpec { name: "INFER_BATCH", category: "llm", description: "Run several LLM prompts concurrently", fields: &["prompts", "context", "store_prefix", "store_combined", "params"] },
+    OpcodeSpec { name: "MAP", category: "llm", description: "Run a prompt template over every element of an array page", fields: &["over", "prompt_template", "store_to"] },
+    OpcodeSpec { name: "CHUNK", category: "llm", description: "Split a large page into smaller pages", fields: &["source", "chunk_size", "prefix"] },
+    OpcodeSpec { name: "MERGE", category: "llm", description: "Merge multiple pages into one", fields: &["pages", "store_to", "separator"] },
+    OpcodeSpec { name: "JSON_PARSE", category: "data", description: "Parse a JSON string into a structured value", fields: &["source", "store_to"] },
+    OpcodeSpec { name: "FILTER", category: "data", description: "Keep the elements of an array page that satisfy a condition", fields: &["over", "condition", "store_to"] },
+    OpcodeSpec { name: "LEN", category: "data", description: "Store the length of an array, object or string", fields: &["source", "store_to"] },
+    OpcodeSpec { name: "DIFF", category: "data", description: "Unified diff between the text of two pages", fields: &["old", "new", "store_to"] },
+    OpcodeSpec { name: "REGEX_REPLACE", category: "data", description: "Replace regex matches in a string, with $1 capture groups", fields: &["source", "pattern", "replacement", "store_to", "all"] },
+    OpcodeSpec { name: "JSON_STRINGIFY", category: "data", description: "Serialize a value into a JSON string", fields: &["source", "store_to", "pretty"] },
+    OpcodeSpec { name: "NOP", category: "debug", description: "No operation", fields: &[] },
+    OpcodeSpec { name: "LOG", category: "debug", description: "Log a message", fields: &["level", "message"] },
+    OpcodeSpec { name: "PRINT", category: "debug", description: "Print a page or message as user-facing output", fields: &["source", "message"] },
+    OpcodeSpec { name: "CHECKPOINT", category: "debug", description: "Checkpoint the current state", fields: &["name", "persist"] },
+    OpcodeSpec { name: "ROLLBACK", category: "debug", description: "Restore pages, stack and registers from a checkpoint (not pc)", fields: &["name"] },
+    OpcodeSpec { name: "ASSERT", category: "debug", description: "Fail if a condition is false", fields: &["condition", "message"] },
+    OpcodeSpec { name: "SET_REG", category: "registers", description: "Set a register value", fields: &["reg", "value"] },
+    OpcodeSpec { name: "GET_REG", category: "registers", description: "Copy a register value into a page", fields: &["reg", "store_to"] },
+    OpcodeSpec { name: "LOAD_SESSION", category: "session", description: "Load an existing session", fields: &["session_id", "store_to"] },
+    OpcodeSpec { name: "SAVE_SESSION", category: "session", description: "Save the current session state", fields: &["session_id", "store_to"] },
+    OpcodeSpec { name: "LOAD_PAGE", category: "session", description: "Load a page from session storage", fields: &["page_id", "store_to"] },
+    OpcodeSpec { name: "SEARCH_PAGES", category: "session", description: "Find the session pages most relevant to a query", fields: &["query", "top_k", "store_to"] },
+    OpcodeSpec { name: "SAVE_PAGE", category: "session", description: "Save a page to session storage", fields: &["page_id", "summary", "content_type"] },
+    OpcodeSpec { name: "EVICT_PAGE", category: "session", description: "Evict a page from active memory, keeping it in storage", fields: &["page_id"] },
+    OpcodeSpec { name: "GET_PAGE_INDEX", category: "session", description: "Get metadata about all indexed pages", fields: &["store_to"] },
+    OpcodeSpec { name: "SET_SESSION_STATUS", category: "session", description: "Update the session status", fields: &["status", "message"] },
+    OpcodeSpec { name: "GET_TRACE_SUMMARY", category: "session", description: "Get the compressed trace summary", fields: &["store_to", "max_entries"] },
+    OpcodeSpec { name: "PUSH", category: "stack", description: "Push a value onto the stack", fields: &["value"] },
+    OpcodeSpec { name: "PUSH_PAGE", category: "stack", description: "Push the contents of a page onto the stack", fields: &["page_id"] },
+    OpcodeSpec { name: "POP", category: "stack", description: "Pop the top value", fields: &[] },
+    OpcodeSpec { name: "POP_TO", category: "stack", description: "Pop the top value into a page", fields: &["store_to"] },
+    OpcodeSpec { name: "PEEK", category: "stack", description: "Copy the top value into a page", fields: &["store_to"] },
+    OpcodeSpec { name: "PEEK_AT", category: "stack", description: "Copy the value at a depth into a page", fields: &["depth", "store_to"] },
+    OpcodeSpec { name: "DUP", category: "stack", description: "Duplicate the top value", fields: &[] },
+    OpcodeSpec { name: "DUP_N", category: "stack", description: "Duplicate the value at a depth", fields: &["n"] },
+    OpcodeSpec { name: "SWAP", category: "stack", description: "Swap the top two values", fields: &[] },
+    OpcodeSpec { name: "SWAP_N", category: "stack", description: "Swap the top with the value at a depth", fields: &["n"] },
+    OpcodeSpec { name: "ROT", category: "stack", description: "Rotate the top N values", fields: &["n"] },
+    OpcodeSpec { name: "DROP", category: "stack", description: "Drop the top N values", fields: &["n"] },
+    OpcodeSpec { name: "DEPTH", category: "stack", description: "Store the stack depth in a page", fields: &["store_to"] },
+    OpcodeSpec { name: "CLEAR", category: "stack", description: "Clear the stack", fields: &[] },
+];
+
 // ============================================================================
 // VmSchema - Simplified, uses external templates
 // ============================================================================
@@ -94,6 +199,35 @@ impl VmSchema {
             .replace("{{PAGES}}", &format_pages_section(pages))
             .replace("{{TRACE}}", &format_trace_section(trace))
     }
+
+    /// Opcode reference table
+    pub fn opcodes(&self) -> &'static [OpcodeSpec] {
+        OPCODES
+    }
+
+    /// Full VM specification as markdown (the system prompt)
+    pub fn to_prompt(&self) -> String {
+        SYSTEM_PROMPT.to_string()
+    }
+
+    /// Opcode reference as JSON, for tooling
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({ "opcodes": OPCODES })
+    }
+
+    /// Compact listing: one opcode per line with its description
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let mut category = "";
+        for spec in OPCODES {
+            if spec.category != category {
+                category = spec.category;
+                out.push_str(&format!("{}{}:\n", if out.is_empty() { "" } else { "\n" }, category));
+            }
+            out.push_str(&format!("  {:<20} {}\n", spec.name, spec.description));
+        }
+        out
+    }
 }
 
 // ============================================================================
@@ -111,6 +245,38 @@ pub struct ExecutionStep {
     pub result: String,
     /// Any error that occurred
     pub error: Option<String>,
+    /// Wall-clock time spent executing the opcode, in milliseconds
+    #[serde(default)]
+    pub duration_ms: f64,
+    /// Time spent waiting on the LLM for this step, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm_ms: Option<f64>,
+    /// Top of the working stack after the step, top first, with long values
+    /// truncated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stack: Vec<String>,
+    /// Pages the step created, modified or freed, by page id (recorded only
+    /// when the interpreter traces memory)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memory: Vec<PageChange>,
+}
+
+/// What happened to a page during one step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageChangeKind {
+    Created,
+    Modified,
+    Freed,
+}
+
+/// One page's change during a step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageChange {
+    pub page_id: String,
+    pub kind: PageChangeKind,
+    /// Change in the page's size, in tokens
+    pub token_delta: i64,
 }
 
 #[cfg(test)]
@@ -143,6 +309,35 @@ mod tests {
         println!("{}", prompt);
     }
 
+    #[test]
+    fn test_opcode_reference_matches_opcodes() {
+        for spec in OPCODES {
+            // Missing fields are fine; an unknown tag means the table is stale
+            let parsed = serde_json::from_value::<crate::Opcode>(serde_json::json!({"op": spec.name}));
+            if let Err(e) = parsed {
+                assert!(!e.to_string().contains("unknown variant"), "{}: {}", spec.name, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema_json_and_text() {
+        let schema = VmSchema::new();
+        let json: serde_json::Value = serde_json::from_str(&schema.to_json().to_string()).unwrap();
+        let infer = json["opcodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|op| op["name"] == "INFER")
+            .unwrap();
+        assert_eq!(infer["category"], "llm");
+        assert_eq!(infer["fields"][0], "prompt");
+
+        let text = schema.to_text();
+        assert!(text.starts_with("memory:\n"));
+        assert!(text.contains("  READ_FILE "));
+    }
+
     #[test]
     fn test_user_prompt_with_trace() {
         let schema = VmSchema::new();
@@ -152,6 +347,10 @@ mod tests {
                 opcode: "READ_FILE".to_string(),
                 result: "success".to_string(),
                 error: None,
+                duration_ms: 0.0,
+                llm_ms: None,
+                stack: Vec::new(),
+                memory: Vec::new(),
             },
         ];
         let prompt = schema.user_prompt("Continue task", std::iter::empty(), &trace);
@@ -15,11 +15,14 @@
 //!
 //! The LLM sees the page index and can request specific pages via LOAD_PAGE.
 
+use crate::embedding::{cosine_similarity, Embedder, HashEmbedder};
 use crate::error::{self, Result};
 use crate::memory::{Memory, MemoryPage};
+use crate::storage::write_files_atomically;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 // ═══════════════════════════════════════════════════════════════════════════════
 // Progress Log (for learnings across iterations)
@@ -149,6 +152,37 @@ pub struct SessionMetadata {
     pub status: SessionStatus,
 }
 
+/// Pages at or below this size are inlined in the saved working set;
+/// larger pages are only referenced and reloaded from the backend.
+pub const INLINE_PAGE_TOKENS: usize = 512;
+
+/// One page of the saved working set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkingSetEntry {
+    /// Page identifier
+    pub id: String,
+    /// Inlined page (None means load it from the backend)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<MemoryPage>,
+}
+
+/// Serialize active memory as a working-set snapshot
+fn serialize_working_set<S: serde::Serializer>(
+    memory: &Memory,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let mut entries: Vec<WorkingSetEntry> = memory
+        .pages_by_lru()
+        .into_iter()
+        .map(|page| WorkingSetEntry {
+            id: page.id.clone(),
+            page: (page.size_tokens <= INLINE_PAGE_TOKENS).then(|| page.clone()),
+        })
+        .collect();
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    serializer.collect_seq(entries)
+}
+
 /// Session status
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum SessionStatus {
@@ -171,9 +205,16 @@ pub struct Session {
     pub page_index: HashMap<String, PageIndex>,
     /// Compressed execution trace
     pub trace_summary: Vec<TraceSummary>,
-    /// Pages currently loaded in active memory
-    #[serde(skip)]
+    /// Pages currently loaded in active memory (saved as `working_set`)
+    #[serde(
+        rename = "working_set",
+        serialize_with = "serialize_working_set",
+        skip_deserializing
+    )]
     pub active_memory: Memory,
+    /// Working set read back from disk, applied by `restore_working_set`
+    #[serde(rename = "working_set", default, skip_serializing)]
+    pending_working_set: Vec<WorkingSetEntry>,
 }
 
 impl Session {
@@ -193,9 +234,32 @@ impl Session {
             page_index: HashMap::new(),
             trace_summary: Vec::new(),
             active_memory: Memory::new(),
+            pending_working_set: Vec::new(),
         }
     }
 
+    /// Rebuild active memory from the saved working set. Inlined pages are
+    /// restored directly; referenced pages are loaded from `backend`, and any
+    /// that can no longer be found are marked unloaded in the index.
+    pub fn restore_working_set(&mut self, backend: &dyn SessionBackend) -> Result<()> {
+        for entry in std::mem::take(&mut self.pending_working_set) {
+            let page = match entry.page {
+                Some(page) => page,
+                None => match backend.load_page(&self.metadata.id, &entry.id) {
+                    Ok(page) => page,
+                    Err(_) => {
+                        if let Some(idx) = self.page_index.get_mut(&entry.id) {
+                            idx.loaded = false;
+                        }
+                        continue;
+                    }
+                },
+            };
+            self.active_memory.store_page(page)?;
+        }
+        Ok(())
+    }
+
     /// Generate a unique session ID
     pub fn generate_id() -> String {
         use std::time::{SystemTime, UNIX_EPOCH};
@@ -341,6 +405,15 @@ pub trait SessionBackend: Send + Sync {
     /// Save a specific page
     fn save_page(&self, session_id: &str, page: &MemoryPage) -> Result<()>;
 
+    /// Save several pages as one unit, so a failure doesn't leave the
+    /// session with only some of them. The default saves one at a time.
+    fn save_pages(&self, session_id: &str, pages: &[MemoryPage]) -> Result<()> {
+        for page in pages {
+            self.save_page(session_id, page)?;
+        }
+        Ok(())
+    }
+
     /// Load a specific page
     fn load_page(&self, session_id: &str, page_id: &str) -> Result<MemoryPage>;
 
@@ -420,13 +493,18 @@ impl SessionBackend for FileBackend {
         std::fs::create_dir_all(&pages_dir)
             .map_err(|e| error::io_error(format!("Failed to create session dir: {}", e)))?;
 
+        // Large pages are only referenced from the working set, so make sure
+        // their content is on disk
+        for page in session.active_memory.pages_by_lru() {
+            if page.size_tokens > INLINE_PAGE_TOKENS {
+                self.save_page(&session.metadata.id, page)?;
+            }
+        }
+
         let metadata_path = self.metadata_path(&session.metadata.id);
         let json = serde_json::to_string_pretty(session)
             .map_err(|e| error::serialization_error(e.to_string()))?;
-        std::fs::write(&metadata_path, json)
-            .map_err(|e| error::io_error(format!("Failed to write session: {}", e)))?;
-
-        Ok(())
+        write_files_atomically(&[(metadata_path, json)])
     }
 
     fn load_session(&self, session_id: &str) -> Result<Session> {
@@ -435,8 +513,9 @@ impl SessionBackend for FileBackend {
         let json = std::fs::read_to_string(&metadata_path)
             .map_err(|e| error::storage_not_found(format!("Session {}: {}", session_id, e)))?;
 
-        let session: Session = serde_json::from_str(&json)
+        let mut session: Session = serde_json::from_str(&json)
             .map_err(|e| error::parse_error(format!("Failed to parse session: {}", e)))?;
+        session.restore_working_set(self)?;
 
         Ok(session)
     }
@@ -457,6 +536,19 @@ impl SessionBackend for FileBackend {
         Ok(())
     }
 
+    fn save_pages(&self, session_id: &str, pages: &[MemoryPage]) -> Result<()> {
+        std::fs::create_dir_all(self.session_dir(session_id).join("pages"))
+            .map_err(|e| error::io_error(format!("Failed to create pages dir: {}", e)))?;
+
+        let mut files = Vec::new();
+        for page in pages {
+            let json = serde_json::to_string_pretty(page)
+                .map_err(|e| error::serialization_error(e.to_string()))?;
+            files.push((self.page_path(session_id, &page.id), json));
+        }
+        write_files_atomically(&files)
+    }
+
     fn load_page(&self, session_id: &str, page_id: &str) -> Result<MemoryPage> {
         let page_path = self.page_path(session_id, page_id);
 
@@ -551,6 +643,14 @@ impl SessionBackend for MemoryBackend {
         Ok(())
     }
 
+    fn save_pages(&self, session_id: &str, new_pages: &[MemoryPage]) -> Result<()> {
+        let mut pages = self.pages.write().unwrap();
+        for page in new_pages {
+            pages.insert((session_id.to_string(), page.id.clone()), page.clone());
+        }
+        Ok(())
+    }
+
     fn load_page(&self, session_id: &str, page_id: &str) -> Result<MemoryPage> {
         let pages = self.pages.read().unwrap();
         pages.get(&(session_id.to_string(), page_id.to_string()))
@@ -638,6 +738,10 @@ impl SessionBackend for MemoryBackend {
 /// Manages session persistence with pluggable backends
 pub struct SessionManager {
     backend: Box<dyn SessionBackend>,
+    /// Embeds page summaries and queries for `search_pages`
+    embedder: Arc<dyn Embedder>,
+    /// Embeddings already computed, keyed by the embedded text
+    embeddings: Mutex<HashMap<String, Vec<f32>>>,
 }
 
 impl SessionManager {
@@ -645,9 +749,18 @@ impl SessionManager {
     pub fn with_backend(backend: impl SessionBackend + 'static) -> Self {
         Self {
             backend: Box::new(backend),
+            embedder: Arc::new(HashEmbedder::new()),
+            embeddings: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Use a different embedder for `search_pages`
+    pub fn with_embedder(mut self, embedder: impl Embedder + 'static) -> Self {
+        self.embedder = Arc::new(embedder);
+        self.embeddings.lock().unwrap().clear();
+        self
+    }
+
     /// Create a session manager with file backend (default)
     pub fn new(base_path: impl AsRef<Path>) -> Result<Self> {
         let backend = FileBackend::new(base_path)?;
@@ -684,6 +797,11 @@ impl SessionManager {
         self.backend.save_page(session_id, page)
     }
 
+    /// Save several pages; none are written if any fails
+    pub fn save_pages(&self, session_id: &str, pages: &[MemoryPage]) -> Result<()> {
+        self.backend.save_pages(session_id, pages)
+    }
+
     /// Load a page
     pub fn load_page(&self, session_id: &str, page_id: &str) -> Result<MemoryPage> {
         self.backend.load_page(session_id, page_id)
@@ -708,6 +826,54 @@ impl SessionManager {
     pub fn session_exists(&self, session_id: &str) -> bool {
         self.backend.session_exists(session_id)
     }
+
+    /// Save a named checkpoint to the session. Stored as an unindexed page,
+    /// so it never shows up among the session's pages
+    pub fn save_checkpoint(&self, session_id: &str, name: &str, data: serde_json::Value) -> Result<()> {
+        self.backend.save_page(session_id, &MemoryPage::new(format!("_checkpoint:{}", name), data))
+    }
+
+    /// Load a checkpoint saved by `save_checkpoint`
+    pub fn load_checkpoint(&self, session_id: &str, name: &str) -> Result<serde_json::Value> {
+        Ok(self.backend.load_page(session_id, &format!("_checkpoint:{}", name))?.content)
+    }
+
+    /// The `top_k` indexed pages whose summaries are most similar to `query`,
+    /// best first, with their cosine similarity. Pages sharing nothing with
+    /// the query are left out.
+    pub fn search_pages(&self, session_id: &str, query: &str, top_k: usize) -> Result<Vec<(String, f32)>> {
+        let session = self.load_session(session_id)?;
+        self.rank_pages(&session, query, top_k)
+    }
+
+    /// Like `search_pages`, over a session already in hand (including pages
+    /// indexed since it was last saved)
+    pub fn rank_pages(&self, session: &Session, query: &str, top_k: usize) -> Result<Vec<(String, f32)>> {
+        let query = self.embedder.embed(query)?;
+
+        let mut matches = Vec::new();
+        for (id, idx) in &session.page_index {
+            let vector = self.embedding(&format!("{}: {}", id, idx.summary))?;
+            let score = cosine_similarity(&query, &vector);
+            if score > 0.0 {
+                matches.push((id.clone(), score));
+            }
+        }
+
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        matches.truncate(top_k);
+        Ok(matches)
+    }
+
+    /// Embedding of `text`, computed once per distinct text
+    fn embedding(&self, text: &str) -> Result<Vec<f32>> {
+        if let Some(vector) = self.embeddings.lock().unwrap().get(text) {
+            return Ok(vector.clone());
+        }
+        let vector = self.embedder.embed(text)?;
+        self.embeddings.lock().unwrap().insert(text.to_string(), vector.clone());
+        Ok(vector)
+    }
 }
 
 fn current_timestamp() -> u64 {
@@ -742,6 +908,44 @@ mod tests {
         assert_eq!(session.page_index["test_page"].summary, "Test page summary");
     }
 
+    /// Counts embed calls so the cache can be checked
+    struct CountingEmbedder(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl Embedder for CountingEmbedder {
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let text = text.to_lowercase();
+            Ok(["config", "network", "test"].iter().map(|w| text.matches(w).count() as f32).collect())
+        }
+    }
+
+    #[test]
+    fn test_search_pages_ranks_by_summary() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let manager = SessionManager::in_memory().with_embedder(CountingEmbedder(calls.clone()));
+
+        let mut session = manager.create_session("search").unwrap();
+        for (id, summary) in [
+            ("cfg", "Parsed config file and config defaults"),
+            ("net", "Network retry settings, read from config"),
+            ("log", "Test run output"),
+        ] {
+            session.index_page(&MemoryPage::new(id, serde_json::json!(null)), Some(summary.to_string()));
+        }
+        manager.save_session(&session).unwrap();
+
+        let found = manager.search_pages(&session.metadata.id, "config", 5).unwrap();
+        let ids: Vec<_> = found.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["cfg", "net"]);
+        assert!(found[0].1 > found[1].1);
+
+        let found = manager.search_pages(&session.metadata.id, "network", 1).unwrap();
+        assert_eq!(found[0].0, "net");
+
+        // 3 summaries embedded once, plus one embed per query
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 5);
+    }
+
     #[test]
     fn test_session_persistence() {
         let temp_dir = TempDir::new().unwrap();
@@ -764,6 +968,41 @@ mod tests {
         assert_eq!(loaded_page.content["content"], "hello");
     }
 
+    #[test]
+    fn test_working_set_survives_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = SessionManager::new(temp_dir.path()).unwrap();
+
+        let mut session = manager.create_session("Resume task").unwrap();
+        let large = "x".repeat(INLINE_PAGE_TOKENS * 8);
+        for (id, content) in [("goal", serde_json::json!("ship it")), ("source", serde_json::json!(large))] {
+            let page = MemoryPage::new(id, content);
+            session.index_page(&page, None);
+            session.active_memory.store_page(page).unwrap();
+        }
+        session.active_memory.pin("goal").unwrap();
+        manager.save_session(&session).unwrap();
+
+        // Only the small page is inlined
+        let json = std::fs::read_to_string(
+            temp_dir.path().join(&session.metadata.id).join("session.json"),
+        )
+        .unwrap();
+        assert!(json.contains("ship it"));
+        assert!(!json.contains(&large));
+
+        let loaded = manager.load_session(&session.metadata.id).unwrap();
+        let mut ids: Vec<_> = loaded.active_memory.page_ids().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["goal", "source"]);
+        assert!(loaded.active_memory.is_pinned("goal"));
+        assert_eq!(loaded.active_memory.get("source").unwrap().content, serde_json::json!(large));
+
+        let mut loaded_ids = loaded.loaded_page_ids();
+        loaded_ids.sort();
+        assert_eq!(loaded_ids, vec!["goal", "source"]);
+    }
+
     #[test]
     fn test_trace_summary() {
         let mut session = Session::new("test", "task");
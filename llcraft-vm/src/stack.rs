@@ -10,9 +10,22 @@ use serde::{Deserialize, Serialize};
 pub const MAX_STACK_SIZE: usize = 256;
 
 /// LLM-VM Stack - holds JSON values
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stack {
     data: Vec<serde_json::Value>,
+    /// Pushes beyond this many values fail with StackOverflow
+    #[serde(default = "default_max_size")]
+    max_size: usize,
+}
+
+fn default_max_size() -> usize {
+    MAX_STACK_SIZE
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Stack {
@@ -20,9 +33,21 @@ impl Stack {
     pub fn new() -> Self {
         Stack {
             data: Vec::with_capacity(32),
+            max_size: MAX_STACK_SIZE,
         }
     }
 
+    /// Use a different depth cap
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Maximum number of values the stack can hold
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
     /// Current stack size
     pub fn len(&self) -> usize {
         self.data.len()
@@ -35,8 +60,8 @@ impl Stack {
 
     /// Push a value onto the stack
     pub fn push(&mut self, value: serde_json::Value) -> Result<()> {
-        if self.data.len() >= MAX_STACK_SIZE {
-            return Err(error::stack_overflow());
+        if self.data.len() >= self.max_size {
+            return Err(error::stack_full(self.max_size));
         }
         self.data.push(value);
         Ok(())
@@ -44,27 +69,31 @@ impl Stack {
 
     /// Pop a value from the stack
     pub fn pop(&mut self) -> Result<serde_json::Value> {
-        self.data.pop().ok_or_else(error::stack_underflow)
+        self.data.pop().ok_or_else(|| error::stack_too_shallow("POP", 1, 0))
     }
 
     /// Peek at the top value without removing it
     pub fn peek(&self) -> Result<&serde_json::Value> {
-        self.data.last().ok_or_else(error::stack_underflow)
+        self.data.last().ok_or_else(|| error::stack_too_shallow("PEEK", 1, 0))
     }
 
     /// Peek at a value at a specific depth (0 = top)
     pub fn peek_at(&self, depth: usize) -> Result<&serde_json::Value> {
+        self.check_depth("PEEK_AT", depth)?;
+        Ok(&self.data[self.data.len() - 1 - depth])
+    }
+
+    /// Error unless a value exists at `depth` (0 = top)
+    fn check_depth(&self, op: &str, depth: usize) -> Result<()> {
         if depth >= self.data.len() {
-            return Err(error::stack_underflow());
+            return Err(error::stack_too_shallow(op, depth + 1, self.data.len()));
         }
-        Ok(&self.data[self.data.len() - 1 - depth])
+        Ok(())
     }
 
     /// Set a value at a specific depth (0 = top)
     pub fn set_at(&mut self, depth: usize, value: serde_json::Value) -> Result<()> {
-        if depth >= self.data.len() {
-            return Err(error::stack_underflow());
-        }
+        self.check_depth("SET_AT", depth)?;
         let idx = self.data.len() - 1 - depth;
         self.data[idx] = value;
         Ok(())
@@ -72,20 +101,21 @@ impl Stack {
 
     /// Duplicate the top value
     pub fn dup(&mut self) -> Result<()> {
-        let value = self.peek()?.clone();
+        let value = self.data.last().ok_or_else(|| error::stack_too_shallow("DUP", 1, 0))?.clone();
         self.push(value)
     }
 
     /// Duplicate the value at depth N (0 = top)
     pub fn dup_n(&mut self, n: usize) -> Result<()> {
-        let value = self.peek_at(n)?.clone();
+        self.check_depth("DUP_N", n)?;
+        let value = self.data[self.data.len() - 1 - n].clone();
         self.push(value)
     }
 
     /// Swap top two values
     pub fn swap(&mut self) -> Result<()> {
         if self.data.len() < 2 {
-            return Err(error::stack_underflow());
+            return Err(error::stack_too_shallow("SWAP", 2, self.data.len()));
         }
         let len = self.data.len();
         self.data.swap(len - 1, len - 2);
@@ -94,9 +124,10 @@ impl Stack {
 
     /// Swap top with value at depth N (1-indexed)
     pub fn swap_n(&mut self, n: usize) -> Result<()> {
-        if n == 0 || self.data.len() <= n {
-            return Err(error::stack_underflow());
+        if n == 0 {
+            return Err(error::invalid_argument("SWAP_N: n must be at least 1"));
         }
+        self.check_depth("SWAP_N", n)?;
         let top_idx = self.data.len() - 1;
         let swap_idx = self.data.len() - 1 - n;
         self.data.swap(top_idx, swap_idx);
@@ -106,8 +137,11 @@ impl Stack {
     /// Rotate top N values (top moves to Nth position)
     /// e.g., rot(3) on [a, b, c] -> [c, a, b]
     pub fn rot(&mut self, n: usize) -> Result<()> {
-        if n > self.data.len() || n == 0 {
-            return Err(error::stack_underflow());
+        if n == 0 {
+            return Err(error::invalid_argument("ROT: n must be at least 1"));
+        }
+        if n > self.data.len() {
+            return Err(error::stack_too_shallow("ROT", n, self.data.len()));
         }
         let start = self.data.len() - n;
         self.data[start..].rotate_right(1);
@@ -117,7 +151,7 @@ impl Stack {
     /// Drop top N values
     pub fn drop_n(&mut self, n: usize) -> Result<()> {
         if n > self.data.len() {
-            return Err(error::stack_underflow());
+            return Err(error::stack_too_shallow("DROP", n, self.data.len()));
         }
         self.data.truncate(self.data.len() - n);
         Ok(())
@@ -280,5 +314,51 @@ mod tests {
             stack.push(json!(i)).unwrap();
         }
         assert!(stack.push(json!(999)).is_err_and(|e| e.kind() == ErrorKind::StackOverflow));
+
+        let mut small = Stack::new().with_max_size(2);
+        small.push(json!(1)).unwrap();
+        small.dup().unwrap();
+        let err = small.dup().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::StackOverflow);
+        assert!(err.to_string().contains("2 values max"));
+        assert_eq!(small.len(), 2);
+    }
+
+    #[test]
+    fn test_depth_ops_past_bottom() {
+        use crate::error::ErrorKind;
+        let mut stack = Stack::new();
+        for i in 0..3 {
+            stack.push(json!(i)).unwrap();
+        }
+
+        let cases: [(&str, Result<()>); 5] = [
+            ("PEEK_AT needs 4 value(s) but the stack has 3", stack.peek_at(3).map(|_| ())),
+            ("DUP_N needs 4 value(s) but the stack has 3", stack.clone().dup_n(3)),
+            ("SWAP_N needs 4 value(s) but the stack has 3", stack.clone().swap_n(3)),
+            ("ROT needs 4 value(s) but the stack has 3", stack.clone().rot(4)),
+            ("DROP needs 4 value(s) but the stack has 3", stack.clone().drop_n(4)),
+        ];
+        for (message, result) in cases {
+            let err = result.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::StackUnderflow);
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+
+        assert_eq!(stack.clone().rot(0).unwrap_err().kind(), ErrorKind::InvalidArgument);
+        assert_eq!(stack.clone().swap_n(0).unwrap_err().kind(), ErrorKind::InvalidArgument);
+
+        // Failed ops leave the stack untouched
+        assert_eq!(stack.len(), 3);
+    }
+
+    #[test]
+    fn test_underflow_messages() {
+        let mut stack = Stack::new();
+        assert!(stack.pop().unwrap_err().to_string().contains("POP needs 1 value(s) but the stack has 0"));
+        stack.push(json!(1)).unwrap();
+        let err = stack.swap().unwrap_err();
+        assert!(err.to_string().contains("SWAP needs 2 value(s) but the stack has 1"));
+        assert!(err.context().iter().any(|(k, v)| *k == "depth" && v == "1"));
     }
 }
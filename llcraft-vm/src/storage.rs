@@ -8,6 +8,8 @@ use crate::error::{self, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Storage backend trait
 pub trait StorageBackend: Send + Sync {
@@ -17,47 +19,225 @@ pub trait StorageBackend: Send + Sync {
     fn exists(&self, key: &str) -> bool;
     fn keys(&self) -> Vec<String>;
     fn clear(&mut self) -> Result<()>;
+
+    /// Store a value that expires after `ttl`.
+    /// Expired keys read as missing; a later `set` without TTL persists.
+    fn put_with_ttl(&mut self, key: &str, value: serde_json::Value, ttl: Duration) -> Result<()> {
+        let _ = (key, value, ttl);
+        Err(error::not_implemented("put_with_ttl for this storage backend"))
+    }
+
+    /// Write several entries as one unit: if the batch fails, none of it is
+    /// applied. The default falls back to one `set` per entry, which isn't
+    /// atomic; the built-in backends override it.
+    fn put_batch(&mut self, items: Vec<(String, serde_json::Value)>) -> Result<()> {
+        for (key, value) in items {
+            self.set(&key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A path next to `path` that nothing is using yet, e.g. `.notes.txt.123-4.tmp`
+fn unused_sibling(path: &Path, ext: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    loop {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let candidate = path.with_file_name(format!(".{}.{}-{}.{}", name, std::process::id(), n, ext));
+        if std::fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+    }
+}
+
+/// Create `path`, which must not exist yet, holding `content`
+fn write_new(path: &Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    std::fs::OpenOptions::new().write(true).create_new(true).open(path)?.write_all(content.as_bytes())
+}
+
+/// Replace all `files` or none of them. Contents go to temp files next to
+/// their targets first, then each is renamed into place. The old contents are
+/// kept aside (hard link, or copy) until every rename has succeeded, so if one
+/// fails the targets already replaced are put back. Temp files and the kept
+/// copies are removed whatever happens.
+///
+/// A target that is a symlink is written through, replacing the file it
+/// points at, and a replaced file keeps its permissions.
+pub(crate) fn write_files_atomically(files: &[(PathBuf, String)]) -> Result<()> {
+    let files: Vec<(PathBuf, &String)> = files
+        .iter()
+        .map(|(path, content)| {
+            let is_link = std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+            let target = match is_link {
+                true => std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()),
+                false => path.clone(),
+            };
+            (target, content)
+        })
+        .collect();
+    let remove_all = |paths: &[PathBuf]| {
+        for path in paths {
+            let _ = std::fs::remove_file(path);
+        }
+    };
+    let failed = |path: &Path, e: std::io::Error| error::io_error(format!("Failed to write {}: {}", path.display(), e));
+
+    let mut temps = Vec::new();
+    for (path, content) in &files {
+        let temp = unused_sibling(path, "tmp");
+        let written = write_new(&temp, content).and_then(|_| match std::fs::metadata(path) {
+            Ok(meta) if meta.is_file() => std::fs::set_permissions(&temp, meta.permissions()),
+            _ => Ok(()),
+        });
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&temp);
+            remove_all(&temps);
+            return Err(failed(path, e));
+        }
+        temps.push(temp);
+    }
+
+    // Old contents of the targets replaced so far (None: there was no file)
+    let mut saved: Vec<Option<PathBuf>> = Vec::new();
+    for (i, (path, _)) in files.iter().enumerate() {
+        let kept = match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_file() => {
+                let keep = unused_sibling(path, "old");
+                std::fs::hard_link(path, &keep)
+                    .or_else(|_| std::fs::copy(path, &keep).map(|_| ()))
+                    .map(|_| Some(keep))
+            }
+            _ => Ok(None),
+        };
+        let renamed = kept.and_then(|kept| {
+            let result = std::fs::rename(&temps[i], path);
+            saved.push(kept);
+            result
+        });
+
+        if let Err(e) = renamed {
+            // Put back the targets already replaced, newest first
+            for ((path, _), kept) in files[..i].iter().zip(&saved[..i]).rev() {
+                let _ = match kept {
+                    Some(kept) => std::fs::rename(kept, path),
+                    None => std::fs::remove_file(path),
+                };
+            }
+            if let Some(Some(kept)) = saved.get(i) {
+                let _ = std::fs::remove_file(kept);
+            }
+            remove_all(&temps[i..]);
+            return Err(failed(path, e));
+        }
+    }
+
+    let kept: Vec<PathBuf> = saved.into_iter().flatten().collect();
+    remove_all(&kept);
+    Ok(())
+}
+
+/// Current time as milliseconds since the Unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Expiry timestamp for a TTL starting now
+fn expires_at(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
 }
 
 /// In-memory storage (volatile, but useful for testing)
+///
+/// Expired keys are hidden from reads and purged on the next write.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MemoryStorage {
     data: HashMap<String, serde_json::Value>,
+    /// Expiry timestamps (ms since epoch) for keys stored with a TTL
+    #[serde(default)]
+    expiry: HashMap<String, u64>,
 }
 
 impl MemoryStorage {
     pub fn new() -> Self {
-        Self {
-            data: HashMap::new(),
+        Self::default()
+    }
+
+    fn is_expired(&self, key: &str) -> bool {
+        self.expiry.get(key).is_some_and(|&at| at <= now_millis())
+    }
+
+    fn purge_expired(&mut self) {
+        let now = now_millis();
+        let expired: Vec<String> = self
+            .expiry
+            .iter()
+            .filter(|(_, &at)| at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            self.data.remove(&key);
+            self.expiry.remove(&key);
         }
     }
 }
 
 impl StorageBackend for MemoryStorage {
     fn get(&self, key: &str) -> Option<serde_json::Value> {
+        if self.is_expired(key) {
+            return None;
+        }
         self.data.get(key).cloned()
     }
 
     fn set(&mut self, key: &str, value: serde_json::Value) -> Result<()> {
+        self.purge_expired();
+        self.expiry.remove(key);
         self.data.insert(key.to_string(), value);
         Ok(())
     }
 
     fn delete(&mut self, key: &str) -> Result<()> {
+        self.purge_expired();
+        self.expiry.remove(key);
         self.data.remove(key);
         Ok(())
     }
 
     fn exists(&self, key: &str) -> bool {
-        self.data.contains_key(key)
+        self.data.contains_key(key) && !self.is_expired(key)
     }
 
     fn keys(&self) -> Vec<String> {
-        self.data.keys().cloned().collect()
+        self.data
+            .keys()
+            .filter(|key| !self.is_expired(key))
+            .cloned()
+            .collect()
     }
 
     fn clear(&mut self) -> Result<()> {
         self.data.clear();
+        self.expiry.clear();
+        Ok(())
+    }
+
+    fn put_with_ttl(&mut self, key: &str, value: serde_json::Value, ttl: Duration) -> Result<()> {
+        self.set(key, value)?;
+        self.expiry.insert(key.to_string(), expires_at(ttl));
+        Ok(())
+    }
+
+    fn put_batch(&mut self, items: Vec<(String, serde_json::Value)>) -> Result<()> {
+        self.purge_expired();
+        for (key, value) in items {
+            self.expiry.remove(&key);
+            self.data.insert(key, value);
+        }
         Ok(())
     }
 }
@@ -81,10 +261,41 @@ impl FileStorage {
             .replace(['/', '\\', ':', '*', '?', '"', '<', '>', '|'], "_");
         self.base_path.join(format!("{}.json", safe_key))
     }
+
+    /// Sidecar file holding the expiry timestamp (ms since epoch)
+    fn expiry_path(&self, key: &str) -> PathBuf {
+        self.key_to_path(key).with_extension("expires")
+    }
+
+    /// Delete the key if its TTL has passed. Returns true if it expired.
+    fn expire_if_due(&self, key: &str) -> bool {
+        let expiry_path = self.expiry_path(key);
+        let expired = std::fs::read_to_string(&expiry_path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .is_some_and(|at| at <= now_millis());
+        if expired {
+            let _ = std::fs::remove_file(self.key_to_path(key));
+            let _ = std::fs::remove_file(expiry_path);
+        }
+        expired
+    }
+
+    fn remove_expiry(&self, key: &str) -> Result<()> {
+        let path = self.expiry_path(key);
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .map_err(|e| error::io_error(format!("Failed to delete {}: {}", path.display(), e)))?;
+        }
+        Ok(())
+    }
 }
 
 impl StorageBackend for FileStorage {
     fn get(&self, key: &str) -> Option<serde_json::Value> {
+        if self.expire_if_due(key) {
+            return None;
+        }
         let path = self.key_to_path(key);
         let content = std::fs::read_to_string(&path).ok()?;
         serde_json::from_str(&content).ok()
@@ -96,7 +307,7 @@ impl StorageBackend for FileStorage {
             .map_err(|e| error::serialization_error(e.to_string()))?;
         std::fs::write(&path, content)
             .map_err(|e| error::io_error(format!("Failed to write {}: {}", path.display(), e)))?;
-        Ok(())
+        self.remove_expiry(key)
     }
 
     fn delete(&mut self, key: &str) -> Result<()> {
@@ -105,11 +316,18 @@ impl StorageBackend for FileStorage {
             std::fs::remove_file(&path)
                 .map_err(|e| error::io_error(format!("Failed to delete {}: {}", path.display(), e)))?;
         }
-        Ok(())
+        self.remove_expiry(key)
     }
 
     fn exists(&self, key: &str) -> bool {
-        self.key_to_path(key).exists()
+        !self.expire_if_due(key) && self.key_to_path(key).exists()
+    }
+
+    fn put_with_ttl(&mut self, key: &str, value: serde_json::Value, ttl: Duration) -> Result<()> {
+        self.set(key, value)?;
+        let path = self.expiry_path(key);
+        std::fs::write(&path, expires_at(ttl).to_string())
+            .map_err(|e| error::io_error(format!("Failed to write {}: {}", path.display(), e)))
     }
 
     fn keys(&self) -> Vec<String> {
@@ -127,6 +345,7 @@ impl StorageBackend for FileStorage {
                             None
                         }
                     })
+                    .filter(|key| !self.expire_if_due(key))
                     .collect()
             })
             .unwrap_or_default()
@@ -138,6 +357,166 @@ impl StorageBackend for FileStorage {
         }
         Ok(())
     }
+
+    fn put_batch(&mut self, items: Vec<(String, serde_json::Value)>) -> Result<()> {
+        let mut files = Vec::new();
+        for (key, value) in &items {
+            let content = serde_json::to_string_pretty(value)
+                .map_err(|e| error::serialization_error(e.to_string()))?;
+            files.push((self.key_to_path(key), content));
+        }
+        write_files_atomically(&files)?;
+        for (key, _) in &items {
+            self.remove_expiry(key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Redis storage (networked, shared between agent processes)
+///
+/// Keys are stored as `{prefix}:{key}` holding the JSON-encoded value.
+/// Requires the `redis` feature.
+#[cfg(feature = "redis")]
+pub struct RedisStorage {
+    connection: std::sync::Mutex<redis::Connection>,
+    prefix: Option<String>,
+    ttl: Option<Duration>,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStorage {
+    /// Connect to Redis, e.g. `redis://127.0.0.1:6379/0`
+    pub fn connect(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).map_err(|e| {
+            error::storage_failed(format!("Invalid Redis URL {}", url)).set_source(e)
+        })?;
+        let connection = client.get_connection().map_err(|e| {
+            error::storage_failed(format!("Failed to connect to {}", url)).set_source(e)
+        })?;
+        Ok(Self {
+            connection: std::sync::Mutex::new(connection),
+            prefix: None,
+            ttl: None,
+        })
+    }
+
+    /// Prefix every key, so several agents can share one database
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Expire every written key after `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}:{}", prefix, key),
+            None => key.to_string(),
+        }
+    }
+
+    fn query<T>(&self, f: impl FnOnce(&mut redis::Connection) -> redis::RedisResult<T>) -> Result<T> {
+        let mut connection = self
+            .connection
+            .lock()
+            .map_err(|_| error::storage_failed("Redis connection lock poisoned"))?;
+        f(&mut connection).map_err(|e| error::storage_failed("Redis command failed").set_source(e))
+    }
+}
+
+#[cfg(feature = "redis")]
+impl StorageBackend for RedisStorage {
+    fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let key = self.redis_key(key);
+        let raw: Option<String> = self.query(|c| redis::cmd("GET").arg(&key).query(c)).ok()?;
+        serde_json::from_str(&raw?).ok()
+    }
+
+    fn set(&mut self, key: &str, value: serde_json::Value) -> Result<()> {
+        let key = self.redis_key(key);
+        let content = serde_json::to_string(&value)
+            .map_err(|e| error::serialization_error(e.to_string()))?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(&key).arg(content);
+        if let Some(ttl) = self.ttl {
+            cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+        }
+        self.query(|c| cmd.query::<()>(c))
+    }
+
+    fn delete(&mut self, key: &str) -> Result<()> {
+        let key = self.redis_key(key);
+        self.query(|c| redis::cmd("DEL").arg(&key).query::<()>(c))
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        let key = self.redis_key(key);
+        self.query(|c| redis::cmd("EXISTS").arg(&key).query::<bool>(c))
+            .unwrap_or(false)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let pattern = match &self.prefix {
+            Some(prefix) => format!("{}:*", prefix),
+            None => "*".to_string(),
+        };
+        let keys: Vec<String> = self
+            .query(|c| {
+                let iter: redis::Iter<String> = redis::cmd("SCAN")
+                    .cursor_arg(0)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .clone()
+                    .iter(c)?;
+                Ok(iter.collect())
+            })
+            .unwrap_or_default();
+
+        match &self.prefix {
+            Some(prefix) => {
+                let prefix = format!("{}:", prefix);
+                keys.into_iter()
+                    .filter_map(|k| k.strip_prefix(&prefix).map(|s| s.to_string()))
+                    .collect()
+            }
+            None => keys,
+        }
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        for key in self.keys() {
+            self.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    fn put_with_ttl(&mut self, key: &str, value: serde_json::Value, ttl: Duration) -> Result<()> {
+        let key = self.redis_key(key);
+        let content = serde_json::to_string(&value)
+            .map_err(|e| error::serialization_error(e.to_string()))?;
+        let millis = ttl.as_millis().max(1) as u64;
+        self.query(|c| redis::cmd("SET").arg(&key).arg(content).arg("PX").arg(millis).query::<()>(c))
+    }
+
+    fn put_batch(&mut self, items: Vec<(String, serde_json::Value)>) -> Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in &items {
+            let content = serde_json::to_string(value)
+                .map_err(|e| error::serialization_error(e.to_string()))?;
+            let cmd = pipe.cmd("SET").arg(self.redis_key(key)).arg(content);
+            if let Some(ttl) = self.ttl {
+                cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+            }
+            cmd.ignore();
+        }
+        self.query(|c| pipe.query::<()>(c))
+    }
 }
 
 /// LLM-VM Storage - high-level interface
@@ -164,6 +543,15 @@ impl Storage {
         })
     }
 
+    /// Create storage with a Redis backend
+    #[cfg(feature = "redis")]
+    pub fn redis(url: &str) -> Result<Self> {
+        Ok(Self {
+            backend: Box::new(RedisStorage::connect(url)?),
+            namespace: None,
+        })
+    }
+
     /// Create storage with custom backend
     pub fn with_backend(backend: impl StorageBackend + 'static) -> Self {
         Self {
@@ -205,6 +593,17 @@ impl Storage {
         self.backend.set(&self.full_key(key), value)
     }
 
+    /// Set several values at once; none are written if any fails
+    pub fn set_batch(&mut self, items: impl IntoIterator<Item = (String, serde_json::Value)>) -> Result<()> {
+        let items = items.into_iter().map(|(key, value)| (self.full_key(&key), value)).collect();
+        self.backend.put_batch(items)
+    }
+
+    /// Set a value that expires after `ttl`
+    pub fn set_with_ttl(&mut self, key: &str, value: serde_json::Value, ttl: Duration) -> Result<()> {
+        self.backend.put_with_ttl(&self.full_key(key), value, ttl)
+    }
+
     /// Set a typed value in storage
     pub fn set_typed<T: Serialize>(&mut self, key: &str, value: &T) -> Result<()> {
         let json = serde_json::to_value(value)
@@ -347,4 +746,125 @@ mod tests {
         storage.delete_checkpoint("before_change").unwrap();
         assert_eq!(storage.load_checkpoint("before_change"), None);
     }
+
+    #[test]
+    fn test_memory_storage_ttl() {
+        let mut backend = MemoryStorage::new();
+        backend.put_with_ttl("scratch", json!("tmp"), Duration::from_millis(20)).unwrap();
+        backend.set("keep", json!("forever")).unwrap();
+
+        assert_eq!(backend.get("scratch"), Some(json!("tmp")));
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert_eq!(backend.get("scratch"), None);
+        assert!(!backend.exists("scratch"));
+        assert_eq!(backend.keys(), vec!["keep".to_string()]);
+        assert_eq!(backend.get("keep"), Some(json!("forever")));
+
+        // Writes purge expired entries
+        backend.set("other", json!(1)).unwrap();
+        assert!(!backend.data.contains_key("scratch"));
+    }
+
+    #[test]
+    fn test_file_storage_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = Storage::file(dir.path()).unwrap();
+        storage.set_with_ttl("scratch", json!("tmp"), Duration::from_millis(20)).unwrap();
+        storage.set("keep", json!("forever")).unwrap();
+        assert!(dir.path().join("scratch.expires").exists());
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert_eq!(storage.get("scratch"), None);
+        assert!(!dir.path().join("scratch.json").exists());
+        assert!(!dir.path().join("scratch.expires").exists());
+        assert_eq!(storage.keys(), vec!["keep".to_string()]);
+
+        // Overwriting without a TTL makes a key permanent again
+        storage.set_with_ttl("keep", json!("again"), Duration::from_millis(20)).unwrap();
+        storage.set("keep", json!("again")).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(storage.get("keep"), Some(json!("again")));
+    }
+
+    #[test]
+    fn test_file_batch_failure_keeps_prior_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = Storage::file(dir.path()).unwrap();
+        storage.set("a", json!("old a")).unwrap();
+        storage.set("b", json!("old b")).unwrap();
+
+        storage.set_batch([("a".to_string(), json!("new a")), ("c".to_string(), json!("new c"))]).unwrap();
+        assert_eq!(storage.get("a"), Some(json!("new a")));
+        assert_eq!(storage.get("c"), Some(json!("new c")));
+
+        // A directory in the way of x makes the batch fail
+        std::fs::create_dir_all(dir.path().join("x.json").join("in_the_way")).unwrap();
+        let batch = [
+            ("a".to_string(), json!("newer a")),
+            ("b".to_string(), json!("new b")),
+            ("x".to_string(), json!("new x")),
+        ];
+        assert!(storage.set_batch(batch).is_err());
+
+        assert_eq!(storage.get("a"), Some(json!("new a")));
+        assert_eq!(storage.get("b"), Some(json!("old b")));
+        std::fs::remove_dir_all(dir.path().join("x.json")).unwrap();
+
+        // Every temp file is written, but the rename onto d fails after a and
+        // c have been replaced: both get their old contents back
+        std::fs::create_dir_all(dir.path().join("d.json").join("in_the_way")).unwrap();
+        let batch = [
+            ("a".to_string(), json!("newer a")),
+            ("c".to_string(), json!("newer c")),
+            ("d".to_string(), json!("new d")),
+            ("e".to_string(), json!("new e")),
+        ];
+        assert!(storage.set_batch(batch).is_err());
+
+        assert_eq!(storage.get("a"), Some(json!("new a")));
+        assert_eq!(storage.get("c"), Some(json!("new c")));
+        assert!(!dir.path().join("e.json").exists());
+        let mut left: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["a.json", "b.json", "c.json", "d.json"]);
+    }
+
+    /// Needs a local server: `cargo test --features redis -- --ignored`
+    #[cfg(feature = "redis")]
+    #[test]
+    #[ignore]
+    fn test_redis_storage_roundtrip() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into());
+        let mut backend = RedisStorage::connect(&url).unwrap().with_prefix("llcraft-test");
+        backend.clear().unwrap();
+
+        backend.set("page", json!({"content": "hello"})).unwrap();
+        assert!(backend.exists("page"));
+        assert_eq!(backend.get("page"), Some(json!({"content": "hello"})));
+        assert_eq!(backend.keys(), vec!["page".to_string()]);
+
+        backend.delete("page").unwrap();
+        assert_eq!(backend.get("page"), None);
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    #[ignore]
+    fn test_redis_storage_ttl() {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".into());
+        let mut backend = RedisStorage::connect(&url)
+            .unwrap()
+            .with_prefix("llcraft-ttl-test")
+            .with_ttl(Duration::from_millis(50));
+
+        backend.set("scratch", json!(1)).unwrap();
+        assert!(backend.exists("scratch"));
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!backend.exists("scratch"));
+    }
 }
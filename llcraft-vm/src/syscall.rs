@@ -0,0 +1,223 @@
+//! # Syscall handlers
+//!
+//! Every side effect a program has goes through the interpreter's
+//! `SyscallHandler`. `DefaultSyscallHandler` touches the real filesystem,
+//! shell and network; the handlers here wrap or replace it:
+//!
+//! - `RecordingSyscallHandler` records each call and answers from a script,
+//!   so programs can be tested with no real I/O
+//! - `SandboxedSyscallHandler` wraps any handler and refuses paths and
+//!   commands outside its allowlists
+
+use crate::error::{self, Result};
+use crate::interpreter::SyscallHandler;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Syscalls the interpreter issues, with the opcode behind each:
+/// READ_FILE, WRITE_FILE, LIST_DIR, EXEC, GREP, HTTP_REQUEST and GET_ENV
+pub const SYSCALLS: &[&str] = &["read_file", "write_file", "list_dir", "exec", "grep", "http_request", "get_env"];
+
+/// Handler that records every call and returns scripted results
+///
+/// Results are queued per syscall name and handed out in order; once a
+/// name's queue is down to its last result, that result repeats. A call
+/// with nothing scripted fails.
+#[derive(Debug, Default)]
+pub struct RecordingSyscallHandler {
+    results: Mutex<HashMap<String, VecDeque<serde_json::Value>>>,
+    calls: Mutex<Vec<(String, serde_json::Value)>>,
+}
+
+impl RecordingSyscallHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `result` as the answer to the next `name` call
+    pub fn with_result(self, name: impl Into<String>, result: serde_json::Value) -> Self {
+        self.results.lock().unwrap().entry(name.into()).or_default().push_back(result);
+        self
+    }
+
+    /// Calls made so far as `(name, args)`, oldest first
+    pub fn calls(&self) -> Vec<(String, serde_json::Value)> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Args of every call to `name`, oldest first
+    pub fn calls_to(&self, name: &str) -> Vec<serde_json::Value> {
+        self.calls.lock().unwrap().iter().filter(|(n, _)| n == name).map(|(_, args)| args.clone()).collect()
+    }
+}
+
+impl SyscallHandler for RecordingSyscallHandler {
+    fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        self.calls.lock().unwrap().push((name.to_string(), args.clone()));
+
+        let mut results = self.results.lock().unwrap();
+        let queue = results
+            .get_mut(name)
+            .filter(|queue| !queue.is_empty())
+            .ok_or_else(|| error::syscall_failed(name, "no scripted result"))?;
+        Ok(if queue.len() > 1 { queue.pop_front().unwrap() } else { queue[0].clone() })
+    }
+
+    fn available(&self) -> Vec<&str> {
+        SYSCALLS.to_vec()
+    }
+}
+
+/// Wraps a handler, refusing file paths outside the allowed directories and
+/// EXEC commands not on the allowlist before they reach it
+///
+/// Paths are checked lexically: relative paths are taken as relative to the
+/// wrapped handler's root, and any `..` that climbs out of an allowed
+/// directory is refused. A list left unset allows everything.
+pub struct SandboxedSyscallHandler<S> {
+    inner: S,
+    allowed_paths: Option<Vec<PathBuf>>,
+    allowed_commands: Option<Vec<String>>,
+}
+
+impl<S: SyscallHandler> SandboxedSyscallHandler<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, allowed_paths: None, allowed_commands: None }
+    }
+
+    /// Only allow file syscalls under these directories
+    pub fn with_allowed_paths(mut self, paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        self.allowed_paths = Some(paths.into_iter().filter_map(|p| normalize(&p.into())).collect());
+        self
+    }
+
+    /// Only allow EXEC to run these programs (matched against the first word
+    /// of the command). Commands that chain or redirect are refused.
+    pub fn with_allowed_commands(mut self, commands: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.allowed_commands = Some(commands.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The wrapped handler
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    fn check_path(&self, path: &str) -> std::result::Result<(), String> {
+        let Some(allowed) = &self.allowed_paths else {
+            return Ok(());
+        };
+        let normalized = normalize(Path::new(path)).ok_or_else(|| format!("path '{}' escapes its root", path))?;
+        if allowed.iter().any(|root| normalized.starts_with(root)) {
+            Ok(())
+        } else {
+            Err(format!("path '{}' is outside the allowed directories", path))
+        }
+    }
+
+    fn check_command(&self, command: &str) -> std::result::Result<(), String> {
+        let Some(allowed) = &self.allowed_commands else {
+            return Ok(());
+        };
+        if command.contains(['\n', ';', '&', '|', '`', '>', '<']) || command.contains("$(") {
+            return Err(format!("command '{}' chains or redirects", command));
+        }
+        let program = command.split_whitespace().next().unwrap_or_default();
+        if allowed.iter().any(|c| c == program) {
+            Ok(())
+        } else {
+            Err(format!("command '{}' is not in the allowlist", program))
+        }
+    }
+}
+
+impl<S: SyscallHandler> SyscallHandler for SandboxedSyscallHandler<S> {
+    fn call(&self, name: &str, args: &serde_json::Value) -> Result<serde_json::Value> {
+        let arg = |key: &str| args.get(key).and_then(|v| v.as_str());
+        let checked = match name {
+            "read_file" | "write_file" | "grep" => self.check_path(arg("path").unwrap_or_default()),
+            "list_dir" => self.check_path(arg("path").unwrap_or(".")),
+            "exec" => self
+                .check_command(arg("command").unwrap_or_default())
+                .and_then(|_| arg("cwd").map_or(Ok(()), |cwd| self.check_path(cwd))),
+            _ => Ok(()),
+        };
+        checked.map_err(|reason| error::syscall_failed(name, reason))?;
+        self.inner.call(name, args)
+    }
+
+    fn available(&self) -> Vec<&str> {
+        self.inner.available()
+    }
+}
+
+/// `path` with `.` and `..` resolved lexically, or `None` if it climbs above
+/// its start
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            other => out.push(other),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{ExecutionResult, Interpreter};
+    use crate::opcode::{Opcode, Program};
+
+    #[test]
+    fn test_program_runs_against_recording_handler() {
+        let handler = std::sync::Arc::new(RecordingSyscallHandler::new()
+            .with_result("read_file", serde_json::json!({"success": true, "content": "name = \"demo\""}))
+            .with_result("write_file", serde_json::json!({"success": true})));
+        let program = Program::new(
+            "rec",
+            "Recorded",
+            vec![
+                Opcode::ReadFile { path: "Cargo.toml".to_string(), store_to: "manifest".to_string() },
+                Opcode::WriteFile { path: "out.txt".to_string(), content: "done".to_string(), backup: false, store_to: None },
+                Opcode::Complete { result: serde_json::json!({"page": "manifest"}) },
+            ],
+        );
+
+        let mut interp = Interpreter::new(program, handler.clone());
+        assert!(matches!(interp.run().unwrap(), ExecutionResult::Complete(_)));
+        assert_eq!(interp.get_page("manifest").unwrap()["content"], "name = \"demo\"");
+
+        let names: Vec<_> = handler.calls().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["read_file", "write_file"]);
+        assert_eq!(handler.calls_to("write_file")[0]["content"], "done");
+        assert!(handler.call("exec", &serde_json::json!({"command": "ls"})).is_err());
+    }
+
+    #[test]
+    fn test_sandbox_refuses_paths_and_commands() {
+        let recorder = RecordingSyscallHandler::new()
+            .with_result("read_file", serde_json::json!({"success": true, "content": ""}))
+            .with_result("exec", serde_json::json!({"success": true}));
+        let sandbox = SandboxedSyscallHandler::new(&recorder)
+            .with_allowed_paths(["src"])
+            .with_allowed_commands(["cargo"]);
+
+        assert!(sandbox.call("read_file", &serde_json::json!({"path": "src/lib.rs"})).is_ok());
+        assert!(sandbox.call("read_file", &serde_json::json!({"path": "src/../secrets"})).is_err());
+        assert!(sandbox.call("read_file", &serde_json::json!({"path": "../src/lib.rs"})).is_err());
+        assert!(sandbox.call("exec", &serde_json::json!({"command": "cargo test"})).is_ok());
+        assert!(sandbox.call("exec", &serde_json::json!({"command": "cargo test; rm -rf ."})).is_err());
+        assert!(sandbox.call("exec", &serde_json::json!({"command": "rm -rf ."})).is_err());
+
+        // Only the allowed calls reached the wrapped handler
+        assert_eq!(recorder.calls().len(), 2);
+    }
+}
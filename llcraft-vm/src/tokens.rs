@@ -0,0 +1,188 @@
+//! # Token Counting
+//!
+//! Page sizes, token budgets and context trimming all need a token count.
+//! `TokenCounter` abstracts how that count is produced. `ApproxCounter` is
+//! the dependency-free default; with the `tiktoken` feature,
+//! `TiktokenCounter` gives exact counts for OpenAI models.
+
+use std::sync::Arc;
+
+/// Counts tokens for a piece of text
+pub trait TokenCounter: Send + Sync {
+    /// Number of tokens in `text`
+    fn count(&self, text: &str) -> usize;
+
+    /// Number of tokens in a JSON value, as it would appear in a prompt
+    fn count_value(&self, value: &serde_json::Value) -> usize {
+        self.count(&value.to_string())
+    }
+}
+
+/// Byte-length heuristic (about 4 bytes per token for English text)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApproxCounter {
+    bytes_per_token: f64,
+}
+
+impl ApproxCounter {
+    /// Heuristic with the default ratio of 4 bytes per token
+    pub fn new() -> Self {
+        Self { bytes_per_token: 4.0 }
+    }
+
+    /// Heuristic with a custom bytes-per-token ratio
+    pub fn with_ratio(bytes_per_token: f64) -> Self {
+        Self { bytes_per_token: bytes_per_token.max(1.0) }
+    }
+}
+
+impl Default for ApproxCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenCounter for ApproxCounter {
+    fn count(&self, text: &str) -> usize {
+        (text.len() as f64 / self.bytes_per_token) as usize + 1
+    }
+}
+
+/// Exact counts from OpenAI's BPE encodings (needs the `tiktoken` feature)
+#[cfg(feature = "tiktoken")]
+#[derive(Clone, Copy)]
+pub struct TiktokenCounter {
+    bpe: &'static tiktoken_rs::CoreBPE,
+}
+
+#[cfg(feature = "tiktoken")]
+impl TiktokenCounter {
+    /// The cl100k_base encoding (GPT-4, GPT-3.5)
+    pub fn cl100k() -> Self {
+        Self { bpe: tiktoken_rs::cl100k_base_singleton() }
+    }
+
+    /// The o200k_base encoding (GPT-4o, o1)
+    pub fn o200k() -> Self {
+        Self { bpe: tiktoken_rs::o200k_base_singleton() }
+    }
+
+    /// The encoding OpenAI uses for `model`, if it is a known chat model
+    pub fn for_model(model: &str) -> Option<Self> {
+        use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+        match get_tokenizer(model)? {
+            Tokenizer::O200kBase => Some(Self::o200k()),
+            Tokenizer::Cl100kBase => Some(Self::cl100k()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl TokenCounter for TiktokenCounter {
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// Pick a counter for a model name. With the `tiktoken` feature, OpenAI
+/// models get exact counts. Everything else uses the heuristic; Claude
+/// tokenizers split English text a little finer than OpenAI's, so they get
+/// a slightly lower ratio.
+pub fn counter_for_model(model: &str) -> Arc<dyn TokenCounter> {
+    #[cfg(feature = "tiktoken")]
+    if let Some(counter) = TiktokenCounter::for_model(model) {
+        return Arc::new(counter);
+    }
+    let model = model.to_lowercase();
+    if model.contains("claude") {
+        Arc::new(ApproxCounter::with_ratio(3.5))
+    } else {
+        Arc::new(ApproxCounter::new())
+    }
+}
+
+/// Shared counter handle that can live inside `Debug`/`Default` structs
+#[derive(Clone)]
+pub struct SharedCounter(pub Arc<dyn TokenCounter>);
+
+impl Default for SharedCounter {
+    fn default() -> Self {
+        Self(Arc::new(ApproxCounter::new()))
+    }
+}
+
+impl std::fmt::Debug for SharedCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenCounter")
+    }
+}
+
+impl std::ops::Deref for SharedCounter {
+    type Target = dyn TokenCounter;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approx_close_to_real_counts() {
+        // Reference counts from the cl100k_base tokenizer
+        let samples = [
+            ("Hello, world!", 4),
+            ("The quick brown fox jumps over the lazy dog.", 10),
+            ("fn main() { println!(\"hi\"); }", 10),
+        ];
+
+        let counter = ApproxCounter::new();
+        for (text, real) in samples {
+            let approx = counter.count(text);
+            let diff = approx.abs_diff(real);
+            assert!(diff * 2 <= real, "{:?}: approx {} vs real {}", text, approx, real);
+        }
+    }
+
+    #[test]
+    fn test_counter_for_model() {
+        let text = "x".repeat(700);
+        assert_eq!(counter_for_model("mistral-large").count(&text), 176);
+        assert_eq!(counter_for_model("claude-sonnet-4").count(&text), 201);
+        assert_eq!(ApproxCounter::new().count_value(&serde_json::json!("abcd")), 2);
+    }
+
+    /// Run with `cargo test -p llcraft-vm --features tiktoken`
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_approx_against_tiktoken() {
+        let samples = [
+            "Hello, world!",
+            "The quick brown fox jumps over the lazy dog.",
+            "fn main() { println!(\"hi\"); }",
+            "Page-based memory lets the VM keep large tool results out of the prompt until a step asks for them.",
+            r#"{"op": "READ_FILE", "path": "src/main.rs", "store_to": "source"}"#,
+        ];
+
+        let real = TiktokenCounter::cl100k();
+        let approx = ApproxCounter::new();
+        for text in samples {
+            let (real, approx) = (real.count(text), approx.count(text));
+            assert!(real > 0);
+            assert!(approx.abs_diff(real) * 2 <= real, "{:?}: approx {} vs real {}", text, approx, real);
+        }
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_counter_for_model_uses_tiktoken() {
+        let text = "The quick brown fox jumps over the lazy dog.";
+        assert_eq!(counter_for_model("gpt-4o").count(text), TiktokenCounter::o200k().count(text));
+        assert_eq!(counter_for_model("gpt-4").count(text), TiktokenCounter::cl100k().count(text));
+        assert!(TiktokenCounter::for_model("claude-sonnet-4").is_none());
+        assert_eq!(counter_for_model("claude-sonnet-4").count(text), ApproxCounter::with_ratio(3.5).count(text));
+    }
+}